@@ -0,0 +1,320 @@
+//! Android backend: `android_library`/`kt_android_library` targets with Java or Kotlin sources
+//! and a string resource per library.
+
+use crate::backend::EmitBackend;
+use crate::graph::ID;
+use crate::java;
+use crate::layers;
+use crate::starlark::{quote, string_list, BuildFile, Rule, RuleRef};
+use crate::{node_fraction, Args};
+use std::io::{BufWriter, Write};
+use std::path::Path;
+
+pub const LIBRARY_LOAD: &str = "@rules_android//android:rules.bzl";
+pub const LIBRARY_RULE: &str = "android_library";
+
+const KOTLIN_LOAD: &str = "@io_bazel_rules_kotlin//kotlin:android.bzl";
+const KOTLIN_RULE: &str = "kt_android_library";
+
+const KOTLIN_SALT: u64 = 2;
+
+const WORKSPACE: &str = r#"load("@bazel_tools//tools/build_defs/repo:http.bzl", "http_archive")
+
+http_archive(
+    name = "rules_android",
+    sha256 = "cd06d15dd8bb59926e4d65f9003bfc20f9da4b2519985c27e190cddc8b7a7806",
+    strip_prefix = "rules_android-0.1.1",
+    urls = ["https://github.com/bazelbuild/rules_android/archive/v0.1.1.zip"],
+)
+
+load("@rules_android//android:rules.bzl", "android_sdk_repository")
+
+android_sdk_repository(name = "androidsdk")
+
+http_archive(
+    name = "io_bazel_rules_kotlin",
+    urls = ["https://github.com/bazelbuild/rules_kotlin/releases/download/v1.5.0/rules_kotlin_release.tgz"],
+)
+
+load("@io_bazel_rules_kotlin//kotlin:repositories.bzl", "kotlin_repositories")
+
+kotlin_repositories()
+
+load("@io_bazel_rules_kotlin//kotlin:core.bzl", "kt_register_toolchains")
+
+kt_register_toolchains()"#;
+
+/// Resource directory of the strings of `locale`, with a region spelled the Android way, e.g.
+/// `values-pt-rBR` for `pt-BR`.
+fn values_dir(locale: &str) -> String {
+    match locale.split_once(['-', '_']) {
+        Some((language, region)) => format!("values-{}-r{}", language, region),
+        None => format!("values-{}", locale),
+    }
+}
+
+/// Java package of the library generated for `node`.
+fn java_package(node: &ID) -> String {
+    format!(
+        "com.bazel.benchmark.{}",
+        node.lib_path().to_str().unwrap().replace('/', ".")
+    )
+}
+
+fn is_kotlin(node: &ID, args: &Args) -> bool {
+    node_fraction(node.id, KOTLIN_SALT) < args.kotlin_ratio
+}
+
+pub fn handle_root(args: &Args) -> anyhow::Result<()> {
+    let mut build = BuildFile::new();
+    build.load(LIBRARY_LOAD, "android_binary");
+    build.add(
+        Rule::new("android_binary", args.app_name.as_deref().unwrap_or("root"))
+            .attr("custom_package", quote(&args.bundle_id_prefix))
+            .attr("manifest", quote("AndroidManifest.xml"))
+            .attr(
+                "deps",
+                string_list(
+                    (1..=args.targets_per_level)
+                        .map(|i| ID::new(i, args.targets_per_level, args.height as u64).label()),
+                ),
+            ),
+    );
+    build.write(&args.output.join("BUILD.bazel"))?;
+
+    write_manifest(&args.output, &args.bundle_id_prefix, true)?;
+    Ok(())
+}
+
+pub fn handle_node(node: &ID, args: &Args) -> anyhow::Result<()> {
+    let lib_dir = layers::lib_dir(node, args);
+    std::fs::create_dir_all(lib_dir.join("res/values"))?;
+
+    let kotlin = is_kotlin(node, args);
+    let extension = if kotlin { "kt" } else { "java" };
+    let srcs = (1..=args.files_per_target.for_node(node))
+        .map(|i| format!("{}_Src{}.{}", node.lib_name(), i, extension));
+
+    let (load, kind) = if kotlin {
+        (KOTLIN_LOAD, KOTLIN_RULE)
+    } else {
+        (LIBRARY_LOAD, LIBRARY_RULE)
+    };
+    let mut rule = Rule::new(kind, node.target_name())
+        .attr(
+            "srcs",
+            layers::srcs(args, srcs, &[&format!("*.{}", extension)], &[]),
+        )
+        .attr("custom_package", quote(&java_package(node)))
+        .attr("manifest", quote("AndroidManifest.xml"))
+        .attr(
+            "resource_files",
+            string_list(
+                std::iter::once("values".to_string())
+                    .chain(args.locales.iter().map(|locale| values_dir(locale)))
+                    .map(|values| format!("res/{}/strings.xml", values)),
+            ),
+        );
+    // kt_android_library has no javacopts
+    if !kotlin && args.copts_per_target > 0 {
+        let javacopts = layers::generated_flags(args, args.copts_per_target, |i, value| {
+            format!("-Abench_copt_{}={}", i, value)
+        });
+        rule.set("javacopts", string_list(javacopts));
+    }
+
+    let deps = layers::dep_labels(node, &layers::direct_deps(node, args), args);
+    if args.reexport_deps {
+        rule.set("exports", string_list(&deps));
+    }
+
+    let mut build = BuildFile::new();
+    layers::add_library(&mut build, node, args, load, rule, deps)?;
+    build.write(&lib_dir.join("BUILD.bazel"))?;
+
+    write_manifest(&lib_dir, &java_package(node), false)?;
+
+    let mut strings = std::fs::File::create(lib_dir.join("res/values/strings.xml"))?;
+    writeln!(
+        strings,
+        r#"<?xml version="1.0" encoding="utf-8"?>
+<resources>
+    <string name="{}_name">{}</string>
+</resources>"#,
+        node.lib_name().to_lowercase(),
+        node.lib_name()
+    )?;
+    for locale in &args.locales {
+        let dir = lib_dir.join("res").join(values_dir(locale));
+        std::fs::create_dir_all(&dir)?;
+        std::fs::write(
+            dir.join("strings.xml"),
+            format!(
+                r#"<?xml version="1.0" encoding="utf-8"?>
+<resources>
+    <string name="{}_name">{} ({})</string>
+</resources>
+"#,
+                node.lib_name().to_lowercase(),
+                node.lib_name(),
+                locale
+            ),
+        )?;
+    }
+
+    let mut imported = layers::imported_deps(node, node.children(), args);
+    imported.extend(layers::undeclared_import(node, args));
+    for i in 1..=args.files_per_target.for_node(node) {
+        if kotlin {
+            write_kotlin_file(&lib_dir, node, &imported, i)?;
+        } else {
+            write_java_file(&lib_dir, node, &imported, i)?;
+        }
+    }
+    Ok(())
+}
+
+fn write_manifest(dir: &Path, package: &str, application: bool) -> anyhow::Result<()> {
+    let mut f = std::fs::File::create(dir.join("AndroidManifest.xml"))?;
+    writeln!(
+        f,
+        r#"<?xml version="1.0" encoding="utf-8"?>
+<manifest xmlns:android="http://schemas.android.com/apk/res/android"
+    package="{}">
+    <uses-sdk android:minSdkVersion="21" android:targetSdkVersion="30" />{}
+</manifest>"#,
+        package,
+        if application {
+            "\n    <application android:label=\"Benchmark\" />"
+        } else {
+            ""
+        }
+    )?;
+    Ok(())
+}
+
+fn write_java_file(lib_dir: &Path, node: &ID, deps: &[ID], i: u64) -> anyhow::Result<()> {
+    let resource = if i == 1 {
+        Some(format!("R.string.{}_name", node.lib_name().to_lowercase()))
+    } else {
+        None
+    };
+    java::write_java_file(lib_dir, node, deps, i, java_package, resource)?;
+    Ok(())
+}
+
+fn write_kotlin_file(lib_dir: &Path, node: &ID, deps: &[ID], i: u64) -> anyhow::Result<()> {
+    let class = format!("{}_Src{}", node.lib_name(), i);
+    let mut f = BufWriter::new(std::fs::File::create(
+        lib_dir.join(format!("{}.kt", class)),
+    )?);
+
+    writeln!(f, "package {}", java_package(node))?;
+    if !deps.is_empty() {
+        writeln!(f)?;
+    }
+    for dep in deps {
+        writeln!(f, "import {}.{}_Src1", java_package(dep), dep.lib_name())?;
+    }
+
+    let mut value = format!("{}", i);
+    if i == 1 {
+        value.push_str(&format!(
+            " + R.string.{}_name",
+            node.lib_name().to_lowercase()
+        ));
+    }
+    for dep in deps {
+        value.push_str(&format!(" + {}_Src1.value()", dep.lib_name()));
+    }
+
+    writeln!(
+        f,
+        r#"
+object {} {{
+    @JvmStatic
+    fun value(): Int = {}
+}}"#,
+        class, value
+    )?;
+    Ok(())
+}
+
+pub fn write_workspace(args: &Args) -> anyhow::Result<()> {
+    let mut f = std::fs::File::create(args.output.join("WORKSPACE"))?;
+    writeln!(f, "{}", WORKSPACE)?;
+    Ok(())
+}
+
+/// The language dependent flags the android backend implements.
+const SUPPORTED_FLAGS: &[&str] = &[
+    "--external-repos",
+    "--layout nested-workspaces",
+    "--umbrella-per-level",
+    "--locales",
+    "--cc-toolchain-config",
+    "--inject-errors",
+    "--app-name",
+];
+
+pub struct Backend;
+
+impl EmitBackend for Backend {
+    fn name(&self) -> &'static str {
+        "android"
+    }
+
+    fn emit_workspace(&self, args: &Args) -> anyhow::Result<()> {
+        write_workspace(args)
+    }
+
+    fn emit_package(&self, node: &ID, args: &Args) -> anyhow::Result<()> {
+        match node.id {
+            0 => handle_root(args),
+            _ => handle_node(node, args),
+        }
+    }
+
+    fn library_rule(&self, _args: &Args) -> RuleRef {
+        RuleRef {
+            load: LIBRARY_LOAD.to_string(),
+            name: LIBRARY_RULE.to_string(),
+        }
+    }
+
+    fn umbrella(
+        &self,
+        build: &mut BuildFile,
+        _dir: &Path,
+        level: u32,
+        libraries: &[ID],
+        args: &Args,
+    ) -> anyhow::Result<Rule> {
+        let rule = self.library_rule(args);
+        build.load(&rule.load, &rule.name);
+        Ok(Rule::new(&rule.name, format!("level_{}", level)).attr(
+            "exports",
+            string_list(libraries.iter().map(|node| layers::label(node, args))),
+        ))
+    }
+
+    fn supports(&self, flag: &str) -> bool {
+        SUPPORTED_FLAGS.contains(&flag)
+    }
+
+    fn proto_binding(
+        &self,
+        _build: &mut BuildFile,
+        _args: &Args,
+    ) -> Option<(&'static str, &'static str)> {
+        Some(("java_lite_proto_library", "java_proto"))
+    }
+
+    fn ide_languages(&self, args: &Args) -> Vec<&'static str> {
+        if args.kotlin_ratio > 0.0 {
+            vec!["android", "kotlin"]
+        } else {
+            vec!["android"]
+        }
+    }
+}
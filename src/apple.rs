@@ -0,0 +1,1753 @@
+//! The default backend: Objective-C frameworks built with rules_ios, or with the builtin
+//! `objc_library` and rules_apple alone for `--rules native-objc`.
+
+use crate::backend::EmitBackend;
+use crate::graph::ID;
+use crate::layers;
+use crate::starlark::{quote, string_list, BuildFile, Rule, RuleRef};
+use crate::{
+    buck, bzlmod, cmake, fake, node_fraction, third_party, AlsoEmit, Args, HeaderDiscipline,
+    ImportStyle, ObjcRules, Pch, Platform, ProtoBindings, RootRule,
+};
+use anyhow::{bail, format_err, Context};
+use itertools::Itertools;
+use std::io::{BufWriter, Write};
+use std::path::Path;
+use std::str::FromStr;
+
+const FRAMEWORK_IMPORT_LOAD: &str = "@build_bazel_rules_apple//apple:apple.bzl";
+
+/// WORKSPACE of `--rules native-objc`, rules_apple and what it needs without rules_ios.
+const NATIVE_WORKSPACE: &str = r#"load("@bazel_tools//tools/build_defs/repo:http.bzl", "http_archive")
+
+http_archive(
+    name = "build_bazel_rules_apple",
+    url = "https://github.com/bazelbuild/rules_apple/releases/download/2.0.0/rules_apple.2.0.0.tar.gz",
+)
+
+load("@build_bazel_rules_apple//apple:repositories.bzl", "apple_rules_dependencies")
+
+apple_rules_dependencies()
+
+load("@build_bazel_rules_swift//swift:repositories.bzl", "swift_rules_dependencies")
+
+swift_rules_dependencies()
+
+load("@build_bazel_apple_support//lib:repositories.bzl", "apple_support_dependencies")
+
+apple_support_dependencies()"#;
+
+const PREBUILT_SALT: u64 = 4;
+const CODEGEN_SALT: u64 = 13;
+const SDK_FRAMEWORKS_SALT: u64 = 17;
+const TEST_HOSTED_SALT: u64 = 21;
+const PRIVATE_HEADERS_SALT: u64 = 22;
+
+pub const SHARED_PCH: &str = "Prefix.pch";
+
+const ENTITLEMENTS: &str = "app.entitlements";
+const PROVISIONING_PROFILE: &str = "app.mobileprovision";
+
+/// Appended to the WORKSPACE with `--with-xcodeproj`.
+pub const XCODEPROJ_WORKSPACE: &str = r#"
+http_archive(
+    name = "rules_xcodeproj",
+    url = "https://github.com/MobileNativeFoundation/rules_xcodeproj/releases/download/1.13.0/release.tar.gz",
+)
+
+load(
+    "@rules_xcodeproj//xcodeproj:repositories.bzl",
+    "xcodeproj_rules_dependencies",
+)
+
+xcodeproj_rules_dependencies()"#;
+
+/// Appended to the WORKSPACE with `--emit-compile-commands`.
+pub const COMPILE_COMMANDS_WORKSPACE: &str = r#"
+http_archive(
+    name = "hedron_compile_commands",
+    strip_prefix = "bazel-compile-commands-extractor-main",
+    url = "https://github.com/hedronvision/bazel-compile-commands-extractor/archive/main.tar.gz",
+)
+
+load("@hedron_compile_commands//:workspace_setup.bzl", "hedron_compile_commands_setup")
+
+hedron_compile_commands_setup()"#;
+
+const EXTENSION_PLIST: &str = "Extension-Info.plist";
+const WATCH_APP_PLIST: &str = "WatchApp-Info.plist";
+const WATCH_EXTENSION_PLIST: &str = "WatchExtension-Info.plist";
+
+pub const ALL_FRAMEWORKS: [&str; 135] = [
+    "ARKit",
+    "AVFAudio",
+    "AVFoundation",
+    "AVKit",
+    "Accelerate",
+    "Accessibility",
+    "Accounts",
+    "AdServices",
+    "AdSupport",
+    "AddressBook",
+    "AddressBookUI",
+    "AppClip",
+    "AppTrackingTransparency",
+    "AssetsLibrary",
+    "AudioToolbox",
+    "AudioUnit",
+    "AuthenticationServices",
+    "AutomaticAssessmentConfiguration",
+    "BackgroundTasks",
+    "BusinessChat",
+    "CFNetwork",
+    "CallKit",
+    "CarPlay",
+    "ClassKit",
+    "ClockKit",
+    "CloudKit",
+    "Contacts",
+    "ContactsUI",
+    "CoreAudio",
+    "CoreAudioKit",
+    "CoreAudioTypes",
+    "CoreBluetooth",
+    "CoreData",
+    "CoreFoundation",
+    "CoreGraphics",
+    "CoreHaptics",
+    "CoreImage",
+    "CoreLocation",
+    "CoreLocationUI",
+    "CoreMIDI",
+    "CoreML",
+    "CoreMedia",
+    "CoreMotion",
+    "CoreNFC",
+    "CoreServices",
+    "CoreSpotlight",
+    "CoreTelephony",
+    "CoreText",
+    "CoreVideo",
+    "DataDetection",
+    "DeviceCheck",
+    "EventKit",
+    "EventKitUI",
+    "ExposureNotification",
+    "ExternalAccessory",
+    "FileProvider",
+    "FileProviderUI",
+    "Foundation",
+    "GLKit",
+    "GSS",
+    "GameController",
+    "GameKit",
+    "GameplayKit",
+    "GroupActivities",
+    "HealthKit",
+    "HealthKitUI",
+    "HomeKit",
+    "IOSurface",
+    "IdentityLookup",
+    "IdentityLookupUI",
+    "ImageCaptureCore",
+    "ImageIO",
+    "Intents",
+    "IntentsUI",
+    "JavaScriptCore",
+    "LinkPresentation",
+    "LocalAuthentication",
+    "MapKit",
+    "MediaAccessibility",
+    "MediaPlayer",
+    "MediaToolbox",
+    "MessageUI",
+    "Messages",
+    "Metal",
+    "MetalKit",
+    "MetalPerformanceShaders",
+    "MetalPerformanceShadersGraph",
+    "MetricKit",
+    "MobileCoreServices",
+    "ModelIO",
+    "MultipeerConnectivity",
+    "NaturalLanguage",
+    "NearbyInteraction",
+    "Network",
+    "NetworkExtension",
+    "NewsstandKit",
+    "NotificationCenter",
+    "OSLog",
+    "OpenAL",
+    "OpenGLES",
+    "PDFKit",
+    "PHASE",
+    "PassKit",
+    "PencilKit",
+    "Photos",
+    "PhotosUI",
+    "PushKit",
+    "QuartzCore",
+    "QuickLook",
+    "QuickLookThumbnailing",
+    "ReplayKit",
+    "SafariServices",
+    "SceneKit",
+    "ScreenTime",
+    "Security",
+    "SensorKit",
+    "ShazamKit",
+    "Social",
+    "SoundAnalysis",
+    "Speech",
+    "SpriteKit",
+    "StoreKit",
+    "SwiftUI",
+    "SystemConfiguration",
+    "UIKit",
+    "UniformTypeIdentifiers",
+    "UserNotifications",
+    "UserNotificationsUI",
+    "VideoToolbox",
+    "Vision",
+    "VisionKit",
+    "WatchConnectivity",
+    "WebKit",
+    "WidgetKit",
+    "iAd",
+];
+
+/// `--source-template-dir`: templates replacing the generated headers, implementations and BUILD
+/// files of frameworks, read from `header.h.tpl`, `impl.m.tpl` and `BUILD.bazel.tpl` in the
+/// directory. Any of them can be left out to keep the generated file.
+#[derive(Debug, Clone)]
+pub struct SourceTemplates {
+    header: Option<String>,
+    implementation: Option<String>,
+    build: Option<String>,
+}
+
+impl FromStr for SourceTemplates {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let dir = Path::new(s);
+        if !dir.is_dir() {
+            bail!("source template directory {} doesn't exist", s);
+        }
+        let read = |name: &str| {
+            let path = dir.join(name);
+            path.exists()
+                .then(|| std::fs::read_to_string(&path))
+                .transpose()
+                .map_err(|e| format_err!("{}: {}", path.display(), e))
+        };
+        let templates = SourceTemplates {
+            header: read("header.h.tpl")?,
+            implementation: read("impl.m.tpl")?,
+            build: read("BUILD.bazel.tpl")?,
+        };
+        if templates.header.is_none()
+            && templates.implementation.is_none()
+            && templates.build.is_none()
+        {
+            bail!(
+                "{} has none of header.h.tpl, impl.m.tpl and BUILD.bazel.tpl",
+                s
+            );
+        }
+        Ok(templates)
+    }
+}
+
+/// Renders `template` with the `{<name>}` placeholders of `values`, or returns `generated`
+/// without a template. `{generated}` stands for the generated content.
+fn render(template: Option<&String>, generated: String, values: &[(&str, String)]) -> String {
+    match template {
+        Some(template) => values
+            .iter()
+            .fold(template.clone(), |t, (name, value)| {
+                t.replace(&format!("{{{}}}", name), value)
+            })
+            .replace("{generated}", &generated),
+        None => generated,
+    }
+}
+
+/// The placeholders every template can use: the module and target name, package, level and the
+/// modules it imports.
+fn template_values(node: &ID, children: &[ID]) -> Vec<(&'static str, String)> {
+    vec![
+        ("module", node.lib_name()),
+        ("name", node.target_name()),
+        ("package", node.lib_path().display().to_string()),
+        ("level", node.level.to_string()),
+        ("children", children.iter().map(ID::lib_name).join(", ")),
+    ]
+}
+
+/// The rule frameworks are emitted with, `--framework-rule` or the one of `--rules`.
+pub fn framework_rule(args: &Args) -> RuleRef {
+    if args.analysis_only {
+        return fake::rule("apple_framework");
+    }
+    args.framework_rule
+        .clone()
+        .unwrap_or_else(|| match args.rules {
+            ObjcRules::RulesIos => RuleRef {
+                load: "@build_bazel_rules_ios//rules:framework.bzl".to_string(),
+                name: "apple_framework".to_string(),
+            },
+            ObjcRules::NativeObjc => RuleRef {
+                load: String::new(),
+                name: "objc_library".to_string(),
+            },
+        })
+}
+
+/// The `platforms` of rules_ios frameworks built for more than iOS.
+pub fn platforms_attr(args: &Args) -> Option<String> {
+    if args.platforms == [Platform::Ios] || args.rules != ObjcRules::RulesIos {
+        return None;
+    }
+    let platforms = args
+        .platforms
+        .iter()
+        .map(|p| format!("{}: {}", quote(p.name()), quote(p.minimum_os_version())))
+        .join(", ");
+    Some(format!("{{{}}}", platforms))
+}
+
+/// The rule iOS applications are emitted with, `--app-rule` or the one of `--rules`.
+fn app_rule(args: &Args) -> RuleRef {
+    if args.analysis_only {
+        return fake::rule("ios_application");
+    }
+    args.app_rule.clone().unwrap_or_else(|| RuleRef {
+        load: match args.rules {
+            ObjcRules::RulesIos => "@build_bazel_rules_ios//rules:app.bzl".to_string(),
+            ObjcRules::NativeObjc => "@build_bazel_rules_apple//apple:ios.bzl".to_string(),
+        },
+        name: "ios_application".to_string(),
+    })
+}
+
+/// Root BUILD file of `--root-rule none`: a filegroup of the first-level libraries of every island,
+/// which the whole graph hangs off.
+fn write_library_root(args: &Args) -> anyhow::Result<()> {
+    let mut build = BuildFile::new();
+    for island in 1..=args.islands {
+        let name = match args.islands {
+            1 => "libraries".to_string(),
+            _ => format!("libraries_{}", island),
+        };
+        build.add(
+            Rule::new("filegroup", &name).attr(
+                "srcs",
+                string_list(
+                    app_deps(island, args)
+                        .map(|i| ID::new(i, args.targets_per_level, args.height as u64).label()),
+                ),
+            ),
+        );
+    }
+    build.write(&args.output.join("BUILD.bazel"))?;
+    Ok(())
+}
+
+pub fn handle_root(args: &Args) -> anyhow::Result<()> {
+    if args.root_rule == RootRule::None {
+        write_library_root(args)?;
+        return Ok(());
+    }
+    let mut build = BuildFile::new();
+    let mut top_level_targets = vec![];
+    let mut apps = vec![];
+
+    for app in 1..=num_apps(args) {
+        let (name, bundle_id) = app_identity(app, args);
+        let app_libraries: Vec<_> = app_deps(app, args)
+            .map(|i| ID::new(i, args.targets_per_level, args.height as u64))
+            .collect();
+        let deps = string_list(app_libraries.iter().map(ID::label));
+        if args.link_all_roots {
+            write_linking_main(&name, &app_libraries, args)?;
+        }
+        apps.push((name.clone(), app_libraries));
+
+        if args.root_rule == RootRule::MacosCommandLine {
+            build.load(
+                "@build_bazel_rules_apple//apple:macos.bzl",
+                "macos_command_line_application",
+            );
+            let main_name = format!("{}_main", name);
+            build.add(
+                Rule::new("objc_library", &main_name)
+                    .attr("srcs", string_list([main_source(&name, args)]))
+                    .attr("deps", deps.clone()),
+            );
+            top_level_targets.push(format!(":{}", name));
+            build.add(
+                Rule::new("macos_command_line_application", &name)
+                    .attr("bundle_id", quote(&bundle_id))
+                    .attr(
+                        "minimum_os_version",
+                        quote(Platform::Macos.minimum_os_version()),
+                    )
+                    .attr("deps", string_list([format!(":{}", main_name)])),
+            );
+            continue;
+        }
+
+        for platform in &args.platforms {
+            if *platform == Platform::Ios {
+                let rule = app_rule(args);
+                build.load(&rule.load, &rule.name);
+                // Without a provisioning profile rules_apple falls back to ad-hoc signing.
+                let mut app = Rule::new(&rule.name, &name)
+                    .attr("bundle_id", quote(&bundle_id))
+                    .attr("families", string_list(["iphone", "ipad"]));
+                let app_deps = match args.rules {
+                    ObjcRules::RulesIos => {
+                        app.set("srcs", string_list([main_source(&name, args)]));
+                        deps.clone()
+                    }
+                    ObjcRules::NativeObjc => {
+                        let main_name = format!("{}_main", name);
+                        build.add(
+                            Rule::new("objc_library", &main_name)
+                                .attr("srcs", string_list([main_source(&name, args)]))
+                                .attr("deps", deps.clone()),
+                        );
+                        string_list([format!(":{}", main_name)])
+                    }
+                };
+                let mut app = app
+                    .attr("minimum_os_version", quote("15.0"))
+                    .attr("infoplists", string_list(["Info.plist"]))
+                    .attr("entitlements", quote(ENTITLEMENTS));
+                if args.provisioning_profile.is_some() {
+                    app.set("provisioning_profile", quote(PROVISIONING_PROFILE));
+                }
+                // The `--test-hosted` tests of the library packages run in the application.
+                if args.test_hosted > 0.0 {
+                    app.set("visibility", string_list(["//visibility:public"]));
+                }
+                let extensions = add_extensions(&mut build, &name, &bundle_id, args);
+                if !extensions.is_empty() {
+                    app.set("extensions", string_list(extensions));
+                }
+                if args.watch_app {
+                    app.set(
+                        "watch_application",
+                        quote(&add_watch_app(&mut build, &name, &bundle_id, args)),
+                    );
+                }
+                top_level_targets.push(format!(":{}", name));
+                build.add(app.attr("deps", app_deps));
+            } else {
+                let kind = format!("{}_application", platform.name());
+                build.load(
+                    &format!("@build_bazel_rules_apple//apple:{}.bzl", platform.name()),
+                    &kind,
+                );
+
+                // rules_apple applications don't take sources, so main goes through a library
+                let main_name = format!("{}_{}_main", name, platform.name());
+                build.add(
+                    Rule::new("objc_library", &main_name)
+                        .attr("srcs", string_list([main_source(&name, args)]))
+                        .attr("deps", deps.clone()),
+                );
+                top_level_targets.push(format!(":{}_{}", name, platform.name()));
+                build.add(
+                    Rule::new(kind, format!("{}_{}", name, platform.name()))
+                        .attr(
+                            "bundle_id",
+                            quote(&format!("{}.{}", bundle_id, platform.name())),
+                        )
+                        .attr("infoplists", string_list(["Info.plist"]))
+                        .attr("minimum_os_version", quote(platform.minimum_os_version()))
+                        .attr("deps", string_list([format!(":{}", main_name)])),
+                );
+            }
+        }
+    }
+
+    if args.also_emit.contains(&AlsoEmit::Cmake) {
+        cmake::write_root(args, &apps)?;
+    }
+    if args.also_emit.contains(&AlsoEmit::Buck2) {
+        buck::write_root(args, &apps)?;
+    }
+
+    if args.with_xcodeproj {
+        // The libraries end up in the project as the transitive dependencies of the applications
+        build.load("@rules_xcodeproj//xcodeproj:defs.bzl", "xcodeproj");
+        build.add(
+            Rule::new("xcodeproj", "xcodeproj")
+                .attr("project_name", quote("Benchmark"))
+                .attr("top_level_targets", string_list(&top_level_targets)),
+        );
+    }
+
+    if args.emit_compile_commands {
+        // `bazel run //:refresh_compile_commands` writes compile_commands.json to the workspace
+        build.load(
+            "@hedron_compile_commands//:refresh_compile_commands.bzl",
+            "refresh_compile_commands",
+        );
+        build.add(
+            Rule::new("refresh_compile_commands", "refresh_compile_commands").attr(
+                "targets",
+                format!(
+                    "{{{}}}",
+                    top_level_targets
+                        .iter()
+                        .map(|t| format!("{}: \"\"", quote(&format!("//{}", t))))
+                        .join(", ")
+                ),
+            ),
+        );
+    }
+
+    build.write(&args.output.join("BUILD.bazel"))?;
+    Ok(())
+}
+
+/// Source file with the `main` of application `name`. All applications share an empty one unless
+/// `--link-all-roots` gives each its own.
+pub fn main_source(name: &str, args: &Args) -> String {
+    if args.link_all_roots {
+        format!("{}_main.m", name)
+    } else {
+        "main.m".to_string()
+    }
+}
+
+/// Writes the `main` of application `name`, which references a class of every one of its
+/// first-level `libraries` so that the linker can't drop any of them.
+fn write_linking_main(name: &str, libraries: &[ID], args: &Args) -> anyhow::Result<()> {
+    let mut f = BufWriter::new(std::fs::File::create(
+        args.output.join(main_source(name, args)),
+    )?);
+    writeln!(f, "{}", import_foundation(args))?;
+    for library in libraries {
+        writeln!(f, "{}", import(library, args))?;
+    }
+    writeln!(
+        f,
+        "\nint main(int argc, char *argv[]) {{\n    @autoreleasepool {{\n        NSArray *classes = @["
+    )
+    ?;
+    for library in libraries {
+        writeln!(f, "            [{} class],", first_class(library, args))?;
+    }
+    writeln!(
+        f,
+        "        ];\n        return classes.count == {} ? 0 : 1;\n    }}\n}}",
+        libraries.len()
+    )?;
+    Ok(())
+}
+
+/// Label of the first-level library the `i`th (1-based) extension of an application depends on,
+/// so that extensions embed distinct slices of the graph.
+fn extension_dep(i: u64, args: &Args) -> String {
+    ID::new(
+        (i - 1) % args.targets_per_level + 1,
+        args.targets_per_level,
+        args.height as u64,
+    )
+    .label()
+}
+
+/// Adds the `--extensions` app extensions of application `name` and returns their labels.
+fn add_extensions(build: &mut BuildFile, name: &str, bundle_id: &str, args: &Args) -> Vec<String> {
+    (1..=args.extensions)
+        .map(|i| {
+            let ext_name = format!("{}_ext_{}", name, i);
+            build.load("@build_bazel_rules_apple//apple:ios.bzl", "ios_extension");
+            build.add(
+                Rule::new("objc_library", format!("{}_lib", ext_name))
+                    .attr("deps", string_list([extension_dep(i, args)])),
+            );
+            build.add(
+                Rule::new("ios_extension", &ext_name)
+                    .attr("bundle_id", quote(&format!("{}.ext{}", bundle_id, i)))
+                    .attr("families", string_list(["iphone", "ipad"]))
+                    .attr("minimum_os_version", quote("15.0"))
+                    .attr("infoplists", string_list([EXTENSION_PLIST]))
+                    .attr("deps", string_list([format!(":{}_lib", ext_name)])),
+            );
+            format!(":{}", ext_name)
+        })
+        .collect()
+}
+
+/// Adds a watchOS application with its extension for application `name` and returns its label.
+fn add_watch_app(build: &mut BuildFile, name: &str, bundle_id: &str, args: &Args) -> String {
+    let watch_name = format!("{}_watch", name);
+    let watch_bundle_id = format!("{}.watchkitapp", bundle_id);
+    build.load(
+        "@build_bazel_rules_apple//apple:watchos.bzl",
+        "watchos_application",
+    );
+    build.load(
+        "@build_bazel_rules_apple//apple:watchos.bzl",
+        "watchos_extension",
+    );
+    build.add(
+        Rule::new("objc_library", format!("{}_ext_lib", watch_name)).attr(
+            "deps",
+            string_list([extension_dep(args.extensions + 1, args)]),
+        ),
+    );
+    build.add(
+        Rule::new("watchos_extension", format!("{}_ext", watch_name))
+            .attr(
+                "bundle_id",
+                quote(&format!("{}.watchkitextension", watch_bundle_id)),
+            )
+            .attr(
+                "minimum_os_version",
+                quote(Platform::Watchos.minimum_os_version()),
+            )
+            .attr("infoplists", string_list([WATCH_EXTENSION_PLIST]))
+            .attr("deps", string_list([format!(":{}_ext_lib", watch_name)])),
+    );
+    build.add(
+        Rule::new("watchos_application", &watch_name)
+            .attr("bundle_id", quote(&watch_bundle_id))
+            .attr(
+                "minimum_os_version",
+                quote(Platform::Watchos.minimum_os_version()),
+            )
+            .attr("infoplists", string_list([WATCH_APP_PLIST]))
+            .attr("extension", quote(&format!(":{}_ext", watch_name))),
+    );
+    format!(":{}", watch_name)
+}
+
+/// Number of top-level applications, one per island with `--islands`.
+pub fn num_apps(args: &Args) -> u64 {
+    args.apps.max(args.islands)
+}
+
+/// Target name and bundle identifier of application `app` (1-based).
+pub fn app_identity(app: u64, args: &Args) -> (String, String) {
+    let prefix = &args.bundle_id_prefix;
+    if num_apps(args) == 1 {
+        (
+            args.app_name.as_deref().unwrap_or("root").to_string(),
+            prefix.clone(),
+        )
+    } else {
+        (
+            format!("{}_{}", args.app_name.as_deref().unwrap_or("app"), app),
+            format!("{}.app{}", prefix, app),
+        )
+    }
+}
+
+/// Indices of the first-level libraries `app` (1-based) depends on. With `--islands` those of its
+/// island, otherwise the first `app_overlap * targets_per_level` libraries are shared by all apps
+/// and the remaining ones are handed out round-robin.
+pub fn app_deps(app: u64, args: &Args) -> impl Iterator<Item = u64> {
+    let shared = (args.app_overlap * args.targets_per_level as f64).round() as u64;
+    let (apps, islands, k) = (args.apps, args.islands, args.targets_per_level);
+    (1..=k).filter(move |&i| match islands {
+        1 => i <= shared || (i - shared - 1) % apps == app - 1,
+        _ => (i - 1) * islands / k == app - 1,
+    })
+}
+
+/// Ids of the libraries library `id` depends on: its children, with loose header discipline
+/// also their children, its `--skip-level-deps` descendant and its `--sibling-deps` siblings.
+pub fn dependency_ids(id: u64, args: &Args) -> Vec<u64> {
+    let node = ID::new(id, args.targets_per_level, args.height as u64);
+    let mut deps: Vec<_> = node.children().into_iter().map(|n| n.id).collect();
+    deps.extend(indirect_deps(&node, args).iter().map(|n| n.id));
+    deps
+}
+
+/// The libraries past its children `node` depends on and imports in its implementation.
+fn indirect_deps(node: &ID, args: &Args) -> Vec<ID> {
+    // Loose header discipline reaches past the direct dependencies into their dependencies, which
+    // then have to be declared as well to keep the graph correct.
+    let mut deps: Vec<_> = match args.header_discipline {
+        HeaderDiscipline::Strict => vec![],
+        HeaderDiscipline::Loose => node.children().iter().flat_map(ID::children).collect(),
+    };
+    if let Some(dep) = layers::skip_level_dep(node, args) {
+        if !deps.contains(&dep) {
+            deps.push(dep);
+        }
+    }
+    deps.extend(layers::sibling_deps(node, args));
+    deps
+}
+
+/// Whether `node` is replaced by an imported prebuilt framework, only leaves can be.
+pub fn is_prebuilt(node: &ID, args: &Args) -> bool {
+    node.children().is_empty() && node_fraction(node.id, PREBUILT_SALT) < args.prebuilt_ratio
+}
+
+/// Whether the headers of `node` are generated by a genrule, from the checked-in `.h.in` files
+/// they are written as.
+pub fn is_codegen(node: &ID, args: &Args) -> bool {
+    node_fraction(node.id, CODEGEN_SALT) < args.codegen_ratio
+}
+
+/// The genrule generating the headers of a `--codegen-ratio` library from their `.h.in`.
+fn codegen_rule(node: &ID, args: &Args) -> Rule {
+    let headers: Vec<_> = (1..=args.files_per_target.for_node(node))
+        .map(|i| format!("{}_Hdr{}.h", node.lib_name(), i))
+        .collect();
+    Rule::new("genrule", format!("{}_headers", node.target_name()))
+        .attr(
+            "srcs",
+            layers::srcs(
+                args,
+                headers.iter().map(|header| format!("{}.in", header)),
+                &["*.h.in"],
+                &[],
+            ),
+        )
+        .attr("outs", string_list(headers))
+        .attr(
+            "cmd",
+            quote("for f in $(SRCS); do cp $$f $(RULEDIR)/$$(basename $$f .in); done"),
+        )
+}
+
+pub fn handle_node(node: &ID, args: &Args) -> anyhow::Result<()> {
+    let lib_dir = layers::lib_dir(node, args);
+    std::fs::create_dir_all(&lib_dir)?;
+
+    if is_prebuilt(node, args) {
+        handle_prebuilt_node(node, args, &lib_dir)?;
+        return Ok(());
+    }
+
+    // Generated headers are in the outputs of the genrule instead.
+    let codegen = is_codegen(node, args);
+    let generated = codegen.then(|| format!(":{}_headers", node.target_name()));
+    let private: Vec<_> = (1..=args.files_per_target.for_node(node))
+        .filter(|i| is_private_header(node, *i, args))
+        .map(|i| format!("{}_Hdr{}.h", node.lib_name(), i))
+        .collect();
+    let srcs = (1..=args.files_per_target.for_node(node))
+        .flat_map(|i| {
+            let header = format!("{}_Hdr{}.h", node.lib_name(), i);
+            let public = !codegen && !is_private_header(node, i, args);
+            public
+                .then_some(header)
+                .into_iter()
+                .chain([format!("{}_Src{}.m", node.lib_name(), i)])
+        })
+        .chain((1..=args.categories_per_target).flat_map(|i| {
+            vec![
+                format!("{}_Cat{}.h", node.lib_name(), i),
+                format!("{}_Cat{}.m", node.lib_name(), i),
+            ]
+        }));
+
+    let framework_rule = framework_rule(args);
+    let mut rule = Rule::new(&framework_rule.name, node.target_name())
+        .attr("module_name", quote(&node.lib_name()));
+    let umbrella = match emits_modulemaps(args) {
+        true => {
+            let umbrella = write_umbrella_header(&lib_dir, node, args)?;
+            write_module_map(&lib_dir, &node.lib_name(), &umbrella)?;
+            Some(umbrella)
+        }
+        false => None,
+    };
+    // Globs have to leave out the sources of `--with-tests` and the private headers
+    let tests: &[&str] = if args.with_tests { &["*_Tests.m"] } else { &[] };
+    let excluded: Vec<&str> = tests
+        .iter()
+        .copied()
+        .chain(private.iter().map(String::as_str))
+        .collect();
+    match args.rules {
+        ObjcRules::RulesIos => {
+            let srcs = layers::srcs(args, srcs, &["*.h", "*.m"], &excluded);
+            match &generated {
+                Some(generated) => {
+                    rule.set("srcs", format!("{} + {}", srcs, string_list([generated])))
+                }
+                None => rule.set("srcs", srcs),
+            }
+            if !private.is_empty() {
+                rule.set("private_headers", string_list(&private));
+            }
+            if let Some(umbrella) = &umbrella {
+                rule.set("umbrella_header", quote(umbrella));
+            }
+        }
+        // Headers in the srcs of an objc_library are private to it
+        ObjcRules::NativeObjc => {
+            let (hdrs, srcs): (Vec<_>, Vec<_>) = srcs.partition(|src| src.ends_with(".h"));
+            let hdrs = hdrs.into_iter().chain(umbrella.clone());
+            let srcs_attr = layers::srcs(args, srcs.iter().chain(&private), &["*.m"], tests);
+            match args.use_globs && !private.is_empty() {
+                true => rule.set("srcs", format!("{} + {}", srcs_attr, string_list(&private))),
+                false => rule.set("srcs", srcs_attr),
+            }
+            let hdrs = layers::srcs(
+                args,
+                hdrs,
+                &["*.h"],
+                &private.iter().map(String::as_str).collect::<Vec<_>>(),
+            );
+            match &generated {
+                Some(generated) => {
+                    rule.set("hdrs", format!("{} + {}", hdrs, string_list([generated])))
+                }
+                None => rule.set("hdrs", hdrs),
+            }
+            if args.import_style == ImportStyle::Module {
+                rule.set("enable_modules", "True");
+            }
+        }
+    }
+    if umbrella.is_some() {
+        rule.set("module_map", quote("module.modulemap"));
+    }
+    match args.pch {
+        Pch::None => {}
+        Pch::Shared => rule.set(
+            "pch",
+            quote(&format!(
+                "{}//tools/pch:{}",
+                layers::main_repo(node, args),
+                SHARED_PCH
+            )),
+        ),
+        Pch::PerTarget => {
+            let pch = format!("{}_Prefix.pch", node.lib_name());
+            write_pch(&lib_dir.join(&pch), &node.lib_name())?;
+            rule.set("pch", quote(&pch));
+        }
+    }
+    if let Some(platforms) = platforms_attr(args) {
+        rule.set("platforms", platforms);
+    }
+    if args.linkage.is_dynamic(node) {
+        rule.set("link_dynamic", "True");
+    }
+    let mut data = localized_resources(node, args);
+    if has_framework_bundle(node, args) {
+        let plist = format!("{}-Info.plist", node.lib_name());
+        write_framework_bundle(&lib_dir, &plist, &info_plist("FMWK", ""), node)?;
+        rule.set("bundle_id", quote(&framework_bundle_id(node, args)));
+        rule.set("infoplists", string_list([plist]));
+        data.push(framework_resource(node));
+    }
+    if !data.is_empty() {
+        rule.set("data", string_list(data));
+    }
+    if args.sdk_frameworks_per_target > 0 {
+        match args.rules {
+            ObjcRules::RulesIos => {
+                rule.set("sdk_frameworks", string_list(sdk_frameworks(node, args)))
+            }
+            ObjcRules::NativeObjc => rule.set(
+                "linkopts",
+                string_list(
+                    sdk_frameworks(node, args)
+                        .map(|framework| format!("-Wl,-framework,{}", framework)),
+                ),
+            ),
+        }
+    }
+    if let Some(duration) = fake::action_duration(node, args) {
+        rule.set("action_duration_ms", duration.to_string());
+    }
+    let copts = layers::generated_flags(args, args.copts_per_target, |i, value| {
+        format!("-DBENCH_COPT_{}={}", i, value)
+    });
+    let copts = (!copts.is_empty())
+        .then(|| string_list(copts))
+        .into_iter()
+        .chain(layers::copts_selects(node, args))
+        .join(" + ");
+    let (copts_attr, defines_attr) = match args.rules {
+        ObjcRules::RulesIos => ("objc_copts", "objc_defines"),
+        ObjcRules::NativeObjc => ("copts", "defines"),
+    };
+    if !copts.is_empty() {
+        rule.set(copts_attr, copts);
+    }
+    if args.defines_per_target > 0 {
+        let defines = layers::generated_flags(args, args.defines_per_target, |i, value| {
+            format!("BENCH_DEFINE_{}={}", i, value)
+        });
+        rule.set(defines_attr, string_list(defines));
+    }
+
+    let indirect = indirect_deps(node, args);
+    let deps = layers::dep_labels(node, &node.children(), args)
+        .into_iter()
+        .chain(indirect.iter().map(|dep| layers::label(dep, args)))
+        .collect();
+
+    let mut build = BuildFile::new();
+    layers::add_library(&mut build, node, args, &framework_rule.load, rule, deps)?;
+    if codegen {
+        build.add(codegen_rule(node, args));
+    }
+    if args.with_tests {
+        add_unit_test(&mut build, node, args, &lib_dir)?;
+    }
+    let imported = layers::imported_deps(node, node.children(), args);
+    let templates = args.source_templates.as_ref();
+    std::fs::write(
+        lib_dir.join("BUILD.bazel"),
+        render(
+            templates.and_then(|t| t.build.as_ref()),
+            build.render(),
+            &template_values(node, &imported),
+        ),
+    )?;
+
+    write_localizations(&lib_dir, node, args)?;
+    let mut extra_imports = layers::imported_deps(node, indirect.clone(), args);
+    extra_imports.extend(layers::undeclared_import(node, args));
+    write_objc_files(&lib_dir, node, args, &imported, &extra_imports)?;
+    write_categories(&lib_dir, node, args, &imported)?;
+    if codegen {
+        for i in 1..=args.files_per_target.for_node(node) {
+            let header = lib_dir.join(format!("{}_Hdr{}.h", node.lib_name(), i));
+            std::fs::rename(&header, header.with_extension("h.in"))?;
+        }
+    }
+
+    let deps: Vec<_> = node.children().into_iter().chain(indirect).collect();
+    if args.also_emit.contains(&AlsoEmit::Cmake) {
+        cmake::write_library(node, args, &lib_dir, &deps)?;
+    }
+    if args.also_emit.contains(&AlsoEmit::Buck2) {
+        buck::write_library(node, args, &lib_dir, &deps)?;
+    }
+    Ok(())
+}
+
+/// Name of the first class the module of `node` declares.
+fn first_class(node: &ID, args: &Args) -> String {
+    if is_prebuilt(node, args) {
+        format!("{}_Prebuilt", node.lib_name())
+    } else {
+        format!("{}_Hdr1_Class", node.lib_name())
+    }
+}
+
+/// Writes `--categories-per-target` categories of `node`, extending the first class of the
+/// `children` it imports in turn, or `NSObject` when there are none.
+fn write_categories(lib_dir: &Path, node: &ID, args: &Args, children: &[ID]) -> anyhow::Result<()> {
+    for i in 1..=args.categories_per_target {
+        let category = format!("{}_Cat{}", node.lib_name(), i);
+        let (import, class) = match children.get((i as usize - 1) % children.len().max(1)) {
+            Some(child) => (import(child, args), first_class(child, args)),
+            None => (import_foundation(args).to_string(), "NSObject".to_string()),
+        };
+
+        let mut hdr_file = BufWriter::new(std::fs::File::create(
+            lib_dir.join(format!("{}.h", category)),
+        )?);
+        writeln!(
+            hdr_file,
+            "{}\n\n@interface {} ({})\n- (int){}Value;\n@end",
+            import,
+            class,
+            category,
+            category.to_lowercase()
+        )?;
+
+        let mut m_file = BufWriter::new(std::fs::File::create(
+            lib_dir.join(format!("{}.m", category)),
+        )?);
+        writeln!(
+            m_file,
+            "#include \"{}\"\n\n@implementation {} ({})\n- (int){}Value {{\n    return {};\n}}\n@end",
+            header_path(node, &format!("{}.h", category), args),
+            class,
+            category,
+            category.to_lowercase(),
+            i
+        )
+        ?;
+    }
+    Ok(())
+}
+
+/// Writes an umbrella header importing all public headers of `node` and returns its name.
+fn write_umbrella_header(lib_dir: &Path, node: &ID, args: &Args) -> anyhow::Result<String> {
+    let name = format!("{}_Umbrella.h", node.lib_name());
+    let mut f = BufWriter::new(std::fs::File::create(lib_dir.join(&name))?);
+    for i in 1..=args.files_per_target.for_node(node) {
+        if is_private_header(node, i, args) {
+            continue;
+        }
+        writeln!(f, "#import \"{}_Hdr{}.h\"", node.lib_name(), i)?;
+    }
+    Ok(name)
+}
+
+fn write_pch(path: &Path, name: &str) -> anyhow::Result<()> {
+    std::fs::write(
+        path,
+        format!(
+            "#import <Foundation/Foundation.h>\n\n#define {}_PREFIX_HEADER 1\n",
+            name.to_uppercase()
+        ),
+    )?;
+    Ok(())
+}
+
+/// Whether frameworks get their own umbrella header and module map, which explicit module builds
+/// compile their modules from.
+fn emits_modulemaps(args: &Args) -> bool {
+    args.emit_modulemaps || args.explicit_modules
+}
+
+/// `.bazelrc` flags of `--explicit-modules`: every framework's module map gets precompiled by its
+/// own action, consumers load the precompiled modules of their dependencies instead of building
+/// them on demand into the implicit module cache, and layering checks keep them from reaching
+/// modules they don't depend on.
+pub const EXPLICIT_MODULES_BAZELRC: &str = "build --features=swift.emit_c_module
+build --features=swift.use_c_modules
+build --features=layering_check
+build --features=parse_headers";
+
+/// The values of `--ios_multi_cpus` `--archs` takes.
+pub const IOS_ARCHS: [&str; 4] = ["arm64", "arm64e", "sim_arm64", "x86_64"];
+
+/// The simulator architectures of `--archs`.
+fn simulator_archs(args: &Args) -> Vec<&str> {
+    args.archs
+        .iter()
+        .map(String::as_str)
+        .filter(|arch| matches!(*arch, "sim_arm64" | "x86_64"))
+        .collect()
+}
+
+/// `.bazelrc` configs of `--archs`: one per architecture, and the simulator and device ones
+/// together, what a multi-architecture build of either ends up with.
+pub fn archs_bazelrc(args: &Args) -> String {
+    let simulator = simulator_archs(args);
+    let device: Vec<_> = args
+        .archs
+        .iter()
+        .map(String::as_str)
+        .filter(|arch| !simulator.contains(arch))
+        .collect();
+    args.archs
+        .iter()
+        .map(|arch| format!("build:arch_{0} --ios_multi_cpus={0}", arch))
+        .chain(
+            [("simulator", simulator), ("device", device)]
+                .into_iter()
+                .filter(|(_, archs)| !archs.is_empty())
+                .map(|(kind, archs)| {
+                    format!("build:archs_{} --ios_multi_cpus={}", kind, archs.join(","))
+                }),
+        )
+        .join("\n")
+}
+
+/// `--config=simulator` section of the `.bazelrc` of `--with-run-target`: builds the applications
+/// for the simulator, and `bazel run` installs and launches them on the given device.
+pub fn simulator_bazelrc(args: &Args) -> String {
+    let archs = simulator_archs(args);
+    [
+        format!(
+            "build:simulator --ios_multi_cpus={}",
+            if archs.is_empty() {
+                "sim_arm64".to_string()
+            } else {
+                archs.join(",")
+            }
+        ),
+        format!(
+            "run:simulator --ios_simulator_device={}",
+            quote(&args.simulator_device)
+        ),
+        format!(
+            "run:simulator --ios_simulator_version={}",
+            args.simulator_version
+        ),
+    ]
+    .join("\n")
+}
+
+fn write_module_map(dir: &Path, module: &str, umbrella: &str) -> anyhow::Result<()> {
+    std::fs::write(
+        dir.join("module.modulemap"),
+        format!(
+            "framework module {} {{\n    umbrella header \"{}\"\n    export *\n    module * {{ export * }}\n}}\n",
+            module, umbrella
+        ),
+    )
+    ?;
+    Ok(())
+}
+
+/// Emits `node` as an `apple_{static,dynamic}_framework_import` of a generated framework bundle.
+/// The binary is an empty placeholder archive: consumers only import the module, so nothing ever
+/// needs symbols from it.
+fn handle_prebuilt_node(node: &ID, args: &Args, lib_dir: &Path) -> anyhow::Result<()> {
+    let framework = format!("{}.framework", node.lib_name());
+    let framework_dir = lib_dir.join(&framework);
+    std::fs::create_dir_all(framework_dir.join("Headers"))?;
+    std::fs::create_dir_all(framework_dir.join("Modules"))?;
+
+    std::fs::write(framework_dir.join(node.lib_name()), "!<arch>\n")?;
+    std::fs::write(
+        framework_dir.join(format!("Headers/{}.h", node.lib_name())),
+        format!(
+            "{}\n@interface {}_Prebuilt : NSObject\n@end\n",
+            import_foundation(args),
+            node.lib_name()
+        ),
+    )?;
+    write_module_map(
+        &framework_dir.join("Modules"),
+        &node.lib_name(),
+        &format!("{}.h", node.lib_name()),
+    )?;
+    // Imported bundles are copied as they are, so their Info.plist can't be left to the build.
+    if has_framework_bundle(node, args) {
+        let content = info_plist("FMWK", "")
+            .replace("$(EXECUTABLE_NAME)", &node.lib_name())
+            .replace("$(PRODUCT_NAME)", &node.lib_name())
+            .replace(
+                "$(PRODUCT_BUNDLE_IDENTIFIER)",
+                &framework_bundle_id(node, args),
+            );
+        write_framework_bundle(&framework_dir, "Info.plist", &content, node)?;
+    }
+
+    let kind = if args.linkage.is_dynamic(node) {
+        "apple_dynamic_framework_import"
+    } else {
+        "apple_static_framework_import"
+    };
+    let rule = Rule::new(kind, node.target_name()).attr(
+        "framework_imports",
+        format!("glob([{}])", quote(&format!("{}/**", framework))),
+    );
+
+    let mut build = BuildFile::new();
+    layers::add_library(&mut build, node, args, FRAMEWORK_IMPORT_LOAD, rule, vec![])?;
+    if args.with_tests {
+        add_unit_test(&mut build, node, args, lib_dir)?;
+    }
+    build.write(&lib_dir.join("BUILD.bazel"))?;
+
+    if args.also_emit.contains(&AlsoEmit::Cmake) {
+        cmake::write_library(node, args, lib_dir, &[])?;
+    }
+    if args.also_emit.contains(&AlsoEmit::Buck2) {
+        buck::write_library(node, args, lib_dir, &[])?;
+    }
+    Ok(())
+}
+
+/// Whether header `i` of `node` is one of the `--private-headers-ratio` ones, which only the
+/// library itself can import. The first header declares the class other libraries use and stays
+/// public, and the headers generated for `--codegen-ratio` are all public.
+fn is_private_header(node: &ID, i: u64, args: &Args) -> bool {
+    i > 1
+        && !is_codegen(node, args)
+        && node_fraction(node.id << 16 | i, PRIVATE_HEADERS_SALT) < args.private_headers_ratio
+}
+
+/// Whether the test of `node` is one of the `--test-hosted` ones.
+fn is_test_hosted(node: &ID, args: &Args) -> bool {
+    node_fraction(node.id, TEST_HOSTED_SALT) < args.test_hosted
+}
+
+/// Adds the `ios_unit_test` of `node` to `build` and writes its test case into `lib_dir`. The
+/// `--test-hosted` tests get the application of the island of `node` as their `test_host`.
+fn add_unit_test(
+    build: &mut BuildFile,
+    node: &ID,
+    args: &Args,
+    lib_dir: &Path,
+) -> anyhow::Result<()> {
+    let name = node.target_name();
+    let source = format!("{}_Tests.m", node.lib_name());
+    let mut test = Rule::new("ios_unit_test", format!("{}_test", name))
+        .attr("minimum_os_version", quote("15.0"));
+    match args.rules {
+        ObjcRules::RulesIos => {
+            build.load("@build_bazel_rules_ios//rules:test.bzl", "ios_unit_test");
+            test.set("srcs", string_list([&source]));
+            test.set("deps", string_list([format!(":{}", name)]));
+        }
+        // rules_apple tests don't take sources, they go through a library
+        ObjcRules::NativeObjc => {
+            build.load("@build_bazel_rules_apple//apple:ios.bzl", "ios_unit_test");
+            let test_lib = format!("{}_test_lib", name);
+            build.add(
+                Rule::new("objc_library", &test_lib)
+                    .attr("testonly", "True")
+                    .attr("srcs", string_list([&source]))
+                    .attr("deps", string_list([format!(":{}", name)])),
+            );
+            test.set("deps", string_list([format!(":{}", test_lib)]));
+        }
+    }
+    if is_test_hosted(node, args) {
+        let app = match args.islands {
+            1 => 1,
+            _ => node.island(args.islands) + 1,
+        };
+        test.set(
+            "test_host",
+            quote(&format!(
+                "{}//:{}",
+                layers::main_repo(node, args),
+                app_identity(app, args).0
+            )),
+        );
+    }
+    layers::add_test_attrs(&mut test, node, args);
+    build.add(test);
+
+    let xctest = match args.import_style {
+        ImportStyle::Module => "@import XCTest;",
+        ImportStyle::Textual => "#import <XCTest/XCTest.h>",
+    };
+    // Flaky tests fail with the --flake-rate probability
+    let flake = if layers::is_flaky(node, args) {
+        format!(
+            "\n    if (arc4random_uniform(1000) < {}) {{\n        XCTFail(@\"flaked\");\n    }}",
+            (args.flake_rate * 1000.0).round() as u64
+        )
+    } else {
+        String::new()
+    };
+    std::fs::write(
+        lib_dir.join(source),
+        format!(
+            "{xctest}\n{import}\n\n@interface {lib}_Tests : XCTestCase\n@end\n\n\
+             @implementation {lib}_Tests\n- (void)testClass {{\n    XCTAssertNotNil([{class} new]);{flake}\n}}\n@end\n",
+            import = import(node, args),
+            lib = node.lib_name(),
+            class = first_class(node, args),
+        ),
+    )
+    ?;
+    Ok(())
+}
+
+fn import_foundation(args: &Args) -> &'static str {
+    match args.import_style {
+        ImportStyle::Module => "@import Foundation;",
+        ImportStyle::Textual => "#import <Foundation/Foundation.h>",
+    }
+}
+
+/// The `--locales` resources of `node`, relative to its package.
+fn localized_resources(node: &ID, args: &Args) -> Vec<String> {
+    args.locales
+        .iter()
+        .flat_map(|locale| {
+            ["strings", "storyboard"]
+                .map(|extension| format!("{}.lproj/{}.{}", locale, node.lib_name(), extension))
+        })
+        .collect()
+}
+
+/// Writes the `.strings` table and the storyboard of every `--locales` locale of `node`, the
+/// storyboard a single view controller showing the localized title.
+fn write_localizations(lib_dir: &Path, node: &ID, args: &Args) -> anyhow::Result<()> {
+    let name = node.lib_name();
+    for locale in &args.locales {
+        let dir = lib_dir.join(format!("{}.lproj", locale));
+        std::fs::create_dir_all(&dir)?;
+        std::fs::write(
+            dir.join(format!("{}.strings", name)),
+            format!("\"{0}_title\" = \"{0} ({1})\";\n", name, locale),
+        )?;
+        std::fs::write(
+            dir.join(format!("{}.storyboard", name)),
+            format!(
+                r#"<?xml version="1.0" encoding="UTF-8"?>
+<document type="com.apple.InterfaceBuilder3.CocoaTouch.Storyboard.XIB" version="3.0" toolsVersion="21701" targetRuntime="iOS.CocoaTouch" propertyAccessControl="none" useAutolayout="YES" initialViewController="{0}-vc">
+    <scenes>
+        <scene sceneID="{0}-scene">
+            <objects>
+                <viewController id="{0}-vc" sceneMemberID="viewController">
+                    <view key="view" contentMode="scaleToFill" id="{0}-view">
+                        <rect key="frame" x="0.0" y="0.0" width="390" height="844"/>
+                        <subviews>
+                            <label text="{0} ({1})" textAlignment="center" id="{0}-label">
+                                <rect key="frame" x="0.0" y="411" width="390" height="21"/>
+                            </label>
+                        </subviews>
+                    </view>
+                </viewController>
+            </objects>
+        </scene>
+    </scenes>
+</document>
+"#,
+                name, locale
+            ),
+        )?;
+    }
+    Ok(())
+}
+
+/// The `--sdk-frameworks-per-target` SDK frameworks of `node`, consecutive ones of
+/// `ALL_FRAMEWORKS` from an offset picked per framework.
+fn sdk_frameworks<'a>(node: &ID, args: &'a Args) -> impl Iterator<Item = &'static str> + 'a {
+    let offset =
+        (node_fraction(node.id, SDK_FRAMEWORKS_SALT) * ALL_FRAMEWORKS.len() as f64) as usize;
+    (0..args.sdk_frameworks_per_target)
+        .map(move |i| ALL_FRAMEWORKS[(offset + i) % ALL_FRAMEWORKS.len()])
+}
+
+/// Import of the module of `node`, either as a module import or as textual includes of its
+/// public headers.
+fn import(node: &ID, args: &Args) -> String {
+    let name = node.lib_name();
+    match args.import_style {
+        ImportStyle::Module => format!("@import {};", name),
+        ImportStyle::Textual if is_prebuilt(node, args) => format!("#import <{0}/{0}.h>", name),
+        ImportStyle::Textual if emits_modulemaps(args) => {
+            header_import(node, &format!("{}_Umbrella.h", name), args)
+        }
+        ImportStyle::Textual => (1..=args.files_per_target.for_node(node))
+            .filter(|i| !is_private_header(node, *i, args))
+            .map(|i| header_import(node, &format!("{}_Hdr{}.h", name, i), args))
+            .join("\n"),
+    }
+}
+
+/// The `--umbrella-per-level` framework `name` of module `module` of the `libraries` of a level,
+/// written into `dir`: a header importing all of their modules and a dependency on each one.
+pub fn umbrella_framework(
+    dir: &Path,
+    name: &str,
+    module: &str,
+    libraries: &[ID],
+    args: &Args,
+) -> anyhow::Result<Rule> {
+    let header = format!("{}.h", module);
+    let imports = libraries.iter().map(|node| import(node, args)).join("\n");
+    std::fs::write(dir.join(&header), format!("{}\n", imports))?;
+    let rule = Rule::new(framework_rule(args).name, name);
+    let rule = match args.rules {
+        ObjcRules::RulesIos => rule
+            .attr("module_name", quote(module))
+            .attr("srcs", string_list([&header])),
+        ObjcRules::NativeObjc => rule.attr("hdrs", string_list([&header])),
+    };
+    Ok(rule.attr(
+        "deps",
+        string_list(libraries.iter().map(|node| layers::label(node, args))),
+    ))
+}
+
+/// Path `header` of `node` is included with. rules_ios maps the headers of a framework under its
+/// module name, objc_library only has them at their path in the workspace.
+fn header_path(node: &ID, header: &str, args: &Args) -> String {
+    match args.rules {
+        ObjcRules::RulesIos => format!("{}/{}", node.lib_name(), header),
+        ObjcRules::NativeObjc => format!("{}/{}", node.lib_path().display(), header),
+    }
+}
+
+fn header_import(node: &ID, header: &str, args: &Args) -> String {
+    match args.rules {
+        ObjcRules::RulesIos => format!("#import <{}>", header_path(node, header, args)),
+        ObjcRules::NativeObjc => format!("#import \"{}\"", header_path(node, header, args)),
+    }
+}
+
+/// Writes the header and implementation files of `node`. Headers import the modules of
+/// `children`, implementations additionally import `extra_imports`.
+/// Headers and implementations can be replaced with `--source-template-dir` templates, which
+/// additionally get the `{index}` of the file, its `{class}` and `{imports}`, and `{include}` of
+/// the header for implementations.
+fn write_objc_files(
+    lib_dir: &Path,
+    node: &ID,
+    args: &Args,
+    children: &[ID],
+    extra_imports: &[ID],
+) -> anyhow::Result<()> {
+    let templates = args.source_templates.as_ref();
+    for i in 1..=args.files_per_target.for_node(node) {
+        let imports = std::iter::once(import_foundation(args).to_string())
+            .chain(
+                sdk_frameworks(node, args).map(|framework| match args.import_style {
+                    ImportStyle::Module => format!("@import {};", framework),
+                    ImportStyle::Textual => format!("#import <{0}/{0}.h>", framework),
+                }),
+            )
+            .chain(children.iter().map(|child| import(child, args)))
+            .join("\n");
+        let class = format!("{}_Hdr{}_Class", node.lib_name(), i);
+        let mut values = template_values(node, children);
+        values.extend([("index", i.to_string()), ("class", class.clone())]);
+
+        let header = format!("{}\n@interface {} : NSObject\n@end\n", imports, class);
+        let mut header_values = values.clone();
+        header_values.push(("imports", imports));
+        std::fs::write(
+            lib_dir.join(format!("{}_Hdr{}.h", node.lib_name(), i)),
+            render(
+                templates.and_then(|t| t.header.as_ref()),
+                header,
+                &header_values,
+            ),
+        )?;
+
+        let include = format!(
+            "#include \"{}\"",
+            header_path(node, &format!("{}_Hdr{}.h", node.lib_name(), i), args)
+        );
+        let imports: String = extra_imports
+            .iter()
+            .map(|module| format!("{}\n", import(module, args)))
+            .collect();
+        let implementation = format!("{}\n{}@implementation {}\n@end\n", include, imports, class);
+        values.extend([("include", include), ("imports", imports)]);
+        std::fs::write(
+            lib_dir.join(format!("{}_Src{}.m", node.lib_name(), i)),
+            render(
+                templates.and_then(|t| t.implementation.as_ref()),
+                implementation,
+                &values,
+            ),
+        )?;
+    }
+    Ok(())
+}
+
+pub fn write_workspace(args: &Args) -> anyhow::Result<()> {
+    if args.pch == Pch::Shared {
+        let pch_dir = args.output.join("tools/pch");
+        std::fs::create_dir_all(&pch_dir)?;
+        write_pch(&pch_dir.join(SHARED_PCH), "BENCH_SHARED")?;
+        std::fs::write(
+            pch_dir.join("BUILD.bazel"),
+            format!(
+                "exports_files(\n    [{}],\n    visibility = [\"//visibility:public\"],\n)\n",
+                quote(SHARED_PCH)
+            ),
+        )?;
+    }
+
+    match args.rules {
+        _ if args.analysis_only => {
+            std::fs::write(args.output.join("WORKSPACE"), fake::WORKSPACE)?;
+            fake::write_rules(args)?;
+        }
+        ObjcRules::RulesIos => {
+            std::fs::copy(Path::new("GEN_WORKSPACE"), args.output.join("WORKSPACE"))?;
+        }
+        ObjcRules::NativeObjc => {
+            std::fs::write(
+                args.output.join("WORKSPACE"),
+                format!("{}\n", NATIVE_WORKSPACE),
+            )?;
+        }
+    }
+
+    if args.root_rule == RootRule::None {
+        return Ok(());
+    }
+    let mut f = std::fs::File::create(args.output.join("main.m"))?;
+    writeln!(f, "int main(int, char*[]){{return  0;}}")?;
+    if args.root_rule == RootRule::MacosCommandLine {
+        return Ok(());
+    }
+
+    if let Some(profile) = &args.provisioning_profile {
+        std::fs::copy(profile, args.output.join(PROVISIONING_PROFILE))
+            .with_context(|| format!("copying {}", profile.display()))?;
+    }
+
+    std::fs::write(
+        args.output.join(ENTITLEMENTS),
+        format!(
+            r#"<?xml version="1.0" encoding="UTF-8"?>
+<!DOCTYPE plist PUBLIC "-//Apple//DTD PLIST 1.0//EN" "http://www.apple.com/DTDs/PropertyList-1.0.dtd">
+<plist version="1.0">
+<dict>
+    <key>com.apple.security.application-groups</key>
+    <array>
+        <string>group.{}</string>
+    </array>
+</dict>
+</plist>
+"#,
+            args.bundle_id_prefix
+        ),
+    )?;
+
+    write_info_plist(&args.output.join("Info.plist"), "APPL", "")?;
+    if args.extensions > 0 {
+        write_info_plist(
+            &args.output.join(EXTENSION_PLIST),
+            "XPC!",
+            r#"
+    <key>NSExtension</key>
+    <dict>
+        <key>NSExtensionPointIdentifier</key>
+        <string>com.apple.widgetkit-extension</string>
+    </dict>"#,
+        )?;
+    }
+    if args.watch_app {
+        write_info_plist(
+            &args.output.join(WATCH_APP_PLIST),
+            "APPL",
+            r#"
+    <key>WKWatchKitApp</key>
+    <true/>"#,
+        )?;
+        write_info_plist(
+            &args.output.join(WATCH_EXTENSION_PLIST),
+            "XPC!",
+            r#"
+    <key>NSExtension</key>
+    <dict>
+        <key>NSExtensionPointIdentifier</key>
+        <string>com.apple.watchkit</string>
+    </dict>"#,
+        )?;
+    }
+    Ok(())
+}
+
+/// Writes a bundle Info.plist of `package_type` with `extra` appended to the top level dict.
+fn write_info_plist(path: &Path, package_type: &str, extra: &str) -> anyhow::Result<()> {
+    std::fs::write(path, info_plist(package_type, extra))?;
+    Ok(())
+}
+
+/// A bundle Info.plist of `package_type` with `extra` appended to the top level dict, the
+/// bundle name and identifier left to the build.
+fn info_plist(package_type: &str, extra: &str) -> String {
+    format!(
+        r#"<?xml version="1.0" encoding="UTF-8"?>
+<!DOCTYPE plist PUBLIC "-//Apple//DTD PLIST 1.0//EN" "http://www.apple.com/DTDs/PropertyList-1.0.dtd">
+<plist version="1.0">
+<dict>
+    <key>CFBundleExecutable</key>
+    <string>$(EXECUTABLE_NAME)</string>
+    <key>CFBundleIdentifier</key>
+    <string>$(PRODUCT_BUNDLE_IDENTIFIER)</string>
+    <key>CFBundleName</key>
+    <string>$(PRODUCT_NAME)</string>
+    <key>CFBundlePackageType</key>
+    <string>{}</string>
+    <key>CFBundleShortVersionString</key>
+    <string>1.0</string>
+    <key>CFBundleVersion</key>
+    <string>1</string>{}
+</dict>
+</plist>
+"#,
+        package_type, extra
+    )
+}
+
+/// Whether `node` gets an Info.plist and resources of its own with `--framework-bundles`.
+fn has_framework_bundle(node: &ID, args: &Args) -> bool {
+    args.framework_bundles && args.linkage.is_dynamic(node)
+}
+
+/// Bundle identifier of the framework of `node`, without the underscores identifiers can't have.
+fn framework_bundle_id(node: &ID, args: &Args) -> String {
+    format!(
+        "{}.{}",
+        args.bundle_id_prefix,
+        node.lib_name().replace('_', "-")
+    )
+}
+
+/// The bundle resource of the framework of `node`, relative to its package.
+fn framework_resource(node: &ID) -> String {
+    format!("{}_Resource.json", node.lib_name())
+}
+
+/// Writes the `--framework-bundles` Info.plist `plist` with `content` and the resource of the
+/// framework of `node` into `dir`.
+fn write_framework_bundle(dir: &Path, plist: &str, content: &str, node: &ID) -> anyhow::Result<()> {
+    std::fs::write(dir.join(plist), content)?;
+    std::fs::write(
+        dir.join(framework_resource(node)),
+        format!("{{\"framework\": \"{}\"}}\n", node.lib_name()),
+    )?;
+    Ok(())
+}
+
+/// The language dependent flags the objc backend implements.
+const SUPPORTED_FLAGS: &[&str] = &[
+    "--external-repos",
+    "--layout nested-workspaces",
+    "--umbrella-per-level",
+    "--with-xcodeproj",
+    "--emit-compile-commands",
+    "--also-emit",
+    "--locales",
+    "--archs",
+    "--sdk-frameworks-per-target",
+    "--explicit-modules",
+    "--emit-env",
+    "--test-hosted",
+    "--rules native-objc",
+    "--framework-bundles",
+    "--edit-kind implementation",
+    "--edit-kind interface",
+    "--source-template-dir",
+    "--root-rule",
+    "--third-party-packages",
+    "--islands",
+    "--hermetic-apple-toolchain",
+    "--stamped-targets",
+    "--filegroups-per-package",
+    "--emit-both-dep-systems",
+    "--inject-errors",
+    "--private-headers-ratio",
+    "--codegen-ratio",
+    "--analysis-only",
+    "--with-run-target",
+    "--app-name",
+    "--naming-scheme mixed",
+    "--naming-scheme hashed",
+];
+
+pub struct Backend;
+
+impl EmitBackend for Backend {
+    fn name(&self) -> &'static str {
+        "objc"
+    }
+
+    fn emit_workspace(&self, args: &Args) -> anyhow::Result<()> {
+        write_workspace(args)
+    }
+
+    fn emit_package(&self, node: &ID, args: &Args) -> anyhow::Result<()> {
+        match node.id {
+            0 => handle_root(args),
+            _ => handle_node(node, args),
+        }
+    }
+
+    fn library_rule(&self, args: &Args) -> RuleRef {
+        framework_rule(args)
+    }
+
+    fn umbrella(
+        &self,
+        build: &mut BuildFile,
+        dir: &Path,
+        level: u32,
+        libraries: &[ID],
+        args: &Args,
+    ) -> anyhow::Result<Rule> {
+        let rule = framework_rule(args);
+        build.load(&rule.load, &rule.name);
+        umbrella_framework(
+            dir,
+            &format!("level_{}", level),
+            &format!("Level{}Umbrella", level),
+            libraries,
+            args,
+        )
+    }
+
+    fn supports(&self, flag: &str) -> bool {
+        SUPPORTED_FLAGS.contains(&flag)
+    }
+
+    fn dependency_ids(&self, node: &ID, args: &Args) -> Vec<u64> {
+        dependency_ids(node.id, args)
+    }
+
+    fn has_proto_rules(&self) -> bool {
+        true
+    }
+
+    fn proto_binding(
+        &self,
+        build: &mut BuildFile,
+        args: &Args,
+    ) -> Option<(&'static str, &'static str)> {
+        Some(match args.proto_bindings {
+            ProtoBindings::Cc => ("cc_proto_library", "cc_proto"),
+            ProtoBindings::Swift => {
+                build.load(
+                    "@build_bazel_rules_swift//swift:swift.bzl",
+                    "swift_proto_library",
+                );
+                ("swift_proto_library", "swift_proto")
+            }
+        })
+    }
+
+    fn has_library_tests(&self) -> bool {
+        true
+    }
+
+    fn third_party_library(&self, index: u64, args: &Args) -> Option<third_party::Library> {
+        let src = format!("ThirdParty{}.h", index);
+        let framework = framework_rule(args);
+        let mut rule = Rule::new(framework.name.clone(), third_party::name(index));
+        match args.rules {
+            ObjcRules::RulesIos => {
+                rule.set("module_name", quote(&format!("ThirdParty{}", index)));
+                rule.set("srcs", string_list([&src]));
+            }
+            ObjcRules::NativeObjc => rule.set("hdrs", string_list([&src])),
+        }
+        if let Some(platforms) = platforms_attr(args) {
+            rule.set("platforms", platforms);
+        }
+        Some(third_party::Library {
+            content: format!(
+                "#import <Foundation/Foundation.h>\n\nenum {{ THIRD_PARTY_{}_VERSION = 1 }};\n",
+                index
+            ),
+            src,
+            load: framework.load,
+            rule,
+        })
+    }
+
+    fn modules(&self, args: &Args) -> Vec<bzlmod::Module> {
+        let apple = vec![
+            ("rules_apple", "3.3.0", "build_bazel_rules_apple"),
+            ("rules_swift", "1.16.0", "build_bazel_rules_swift"),
+            ("apple_support", "1.13.0", "build_bazel_apple_support"),
+        ];
+        match args.rules {
+            _ if args.analysis_only => vec![],
+            ObjcRules::NativeObjc => apple,
+            ObjcRules::RulesIos => [
+                vec![
+                    ("rules_proto", "4.0.0", "rules_proto"),
+                    ("rules_ios", "4.3.1", "build_bazel_rules_ios"),
+                    ("protobuf", "21.7", "com_google_protobuf"),
+                ],
+                apple,
+            ]
+            .concat(),
+        }
+    }
+
+    fn ide_languages(&self, _args: &Args) -> Vec<&'static str> {
+        vec!["c"]
+    }
+}
@@ -0,0 +1,342 @@
+//! Archive output: the workspace is staged next to the archive, packed into it and removed, so
+//! only a single file has to be shipped to the benchmark machines. Tar archives take the
+//! libraries as they are written, so the staging directory only holds the libraries being written
+//! and the workspace files, unless a later step needs the whole tree. Filesystem images are
+//! packed from the whole tree, by `image`. Both directories and archives get the same `--mtime`
+//! and permissions on every machine, so identical flags give identical inputs.
+
+use crate::graph::ID;
+use crate::{benchmarks, generate, image, layers, self_bench, summary, AlsoEmit, Args};
+use anyhow::bail;
+use std::collections::{BTreeSet, HashSet};
+use std::ffi::CString;
+use std::io::Write;
+use std::os::unix::ffi::OsStrExt;
+use std::os::unix::fs::PermissionsExt;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::sync::{mpsc, Arc};
+use std::time::Instant;
+use tracing::info;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum OutputFormat {
+    Dir,
+    Tar,
+    TarZst,
+    Ext4Image,
+    SparseImage,
+}
+
+impl FromStr for OutputFormat {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "dir" => Ok(OutputFormat::Dir),
+            "tar" => Ok(OutputFormat::Tar),
+            "tar.zst" => Ok(OutputFormat::TarZst),
+            "ext4-image" => Ok(OutputFormat::Ext4Image),
+            "sparse-image" => Ok(OutputFormat::SparseImage),
+            _ => bail!(
+                "unknown output format {}, expected dir, tar, tar.zst, ext4-image or sparse-image",
+                s
+            ),
+        }
+    }
+}
+
+/// The entries below `dir`, but not in a `.git` directory, sorted by path, so every directory
+/// comes before its content.
+fn entries(dir: &Path) -> anyhow::Result<Vec<PathBuf>> {
+    let mut entries = vec![];
+    let mut dirs = vec![dir.to_path_buf()];
+    while let Some(dir) = dirs.pop() {
+        for entry in std::fs::read_dir(dir)? {
+            let entry = entry?;
+            if entry.file_type()?.is_dir() {
+                if entry.file_name() == ".git" {
+                    continue;
+                }
+                dirs.push(entry.path());
+            }
+            entries.push(entry.path());
+        }
+    }
+    entries.sort();
+    Ok(entries)
+}
+
+/// Permissions every generated file gets: read-only for others, executable only if it was
+/// generated executable.
+fn normalized_mode(metadata: &std::fs::Metadata) -> u32 {
+    if metadata.is_dir() || metadata.permissions().mode() & 0o111 != 0 {
+        0o755
+    } else {
+        0o644
+    }
+}
+
+/// Gives the content of `dir` the modification time `mtime`, in seconds since the epoch, and
+/// normalized permissions. Symlinks keep theirs, only their own time is set.
+pub fn normalize_metadata(dir: &Path, mtime: u64) -> anyhow::Result<()> {
+    let time = libc::timespec {
+        tv_sec: mtime as libc::time_t,
+        tv_nsec: 0,
+    };
+    // Content first, so that nothing touches a directory after its time is set.
+    for path in entries(dir)?.into_iter().rev() {
+        let metadata = std::fs::symlink_metadata(&path)?;
+        if !metadata.file_type().is_symlink() {
+            std::fs::set_permissions(
+                &path,
+                std::fs::Permissions::from_mode(normalized_mode(&metadata)),
+            )?;
+        }
+        let c_path = CString::new(path.as_os_str().as_bytes())?;
+        // SAFETY: `c_path` is NUL terminated and utimensat only reads the two times.
+        let result = unsafe {
+            libc::utimensat(
+                libc::AT_FDCWD,
+                c_path.as_ptr(),
+                [time, time].as_ptr(),
+                libc::AT_SYMLINK_NOFOLLOW,
+            )
+        };
+        if result != 0 {
+            bail!(
+                "setting the time of {}: {}",
+                path.display(),
+                std::io::Error::last_os_error()
+            );
+        }
+    }
+    Ok(())
+}
+
+/// Appends `path` to `builder` as `name`. Symlinks are kept as such. Entries carry no owners and
+/// `mtime` as their time, so the archive only depends on the content.
+fn append(
+    builder: &mut tar::Builder<impl Write>,
+    path: &Path,
+    name: &Path,
+    mtime: u64,
+) -> anyhow::Result<()> {
+    let metadata = std::fs::symlink_metadata(path)?;
+    let mut header = tar::Header::new_gnu();
+    header.set_metadata_in_mode(&metadata, tar::HeaderMode::Deterministic);
+    header.set_mtime(mtime);
+    if metadata.file_type().is_symlink() {
+        builder.append_link(&mut header, name, std::fs::read_link(path)?)?;
+    } else if metadata.is_dir() {
+        builder.append_data(&mut header, name, std::io::empty())?;
+    } else {
+        builder.append_data(&mut header, name, std::fs::File::open(path)?)?;
+    }
+    Ok(())
+}
+
+/// Appends the entries below `dir` to `builder`, sorted and with the workspace at the top level,
+/// leaving out the directories already in `packed`.
+fn append_tree(
+    builder: &mut tar::Builder<impl Write>,
+    dir: &Path,
+    mtime: u64,
+    packed: &HashSet<PathBuf>,
+) -> anyhow::Result<()> {
+    for path in entries(dir)? {
+        let name = Path::new(".").join(path.strip_prefix(dir)?);
+        if !packed.contains(&name) {
+            append(builder, &path, &name, mtime)?;
+        }
+    }
+    Ok(())
+}
+
+/// Packs `dir` into a tar stream written to `out`.
+fn pack(dir: &Path, mtime: u64, out: impl Write) -> anyhow::Result<()> {
+    let mut builder = tar::Builder::new(out);
+    append_tree(&mut builder, dir, mtime, &HashSet::new())?;
+    builder.into_inner()?.flush()?;
+    Ok(())
+}
+
+/// Whether the libraries of a tar archive can be packed as they are written: nothing after the
+/// writers reads or rewrites them, and the staging directory isn't kept for `--resume` or
+/// `--keep-partial`. The library of the benchmark script's incremental scenario stays until the
+/// script picks its file.
+fn streams(args: &Args) -> bool {
+    matches!(args.output_format, OutputFormat::Tar | OutputFormat::TarZst)
+        && !args.resume
+        && !args.keep_partial
+        && args.shard_output.is_empty()
+        && args.layout != layers::Layout::Symlinked
+        && args.inject_errors == 0
+        && args.inject_cycles == 0
+        && !args.buildifier
+        && args.post_hook.is_empty()
+        && !args.init_git
+        && args.emit_summary.is_none()
+        && !args.self_bench
+        && !args.also_emit.contains(&AlsoEmit::Xcodeproj)
+        && !args.also_emit.contains(&AlsoEmit::Spm)
+}
+
+type Builder = tar::Builder<Box<dyn Write + Send>>;
+
+/// Packs the libraries into a tar archive while the writers are still writing others. A single
+/// packer thread takes the ids the writers completed, appends the libraries in id order
+/// whichever writer finishes first, so the archive only depends on the content, and removes them
+/// from the staging directory.
+pub struct Packer {
+    completed: mpsc::Sender<u64>,
+    thread: std::thread::JoinHandle<anyhow::Result<(Builder, HashSet<PathBuf>)>>,
+}
+
+impl Packer {
+    fn start(args: Arc<Args>, out: Box<dyn Write + Send>) -> Self {
+        let (completed, ids) = mpsc::channel();
+        let thread = std::thread::spawn(move || {
+            let mut builder = tar::Builder::new(out);
+            let mut packed = HashSet::new();
+            let mut result = Ok(());
+            let mut pending = BTreeSet::new();
+            // The root package is one of the workspace files.
+            let mut next = 1;
+            for id in ids {
+                pending.insert(id);
+                while result.is_ok() && pending.remove(&next) {
+                    result = pack_library(&mut builder, &mut packed, next, &args);
+                    next += 1;
+                }
+                // After an error the library ids are still taken, so the writers don't fail on it
+                // and the error the generation ran into, if any, comes first.
+            }
+            result.map(|()| (builder, packed))
+        });
+        Packer { completed, thread }
+    }
+
+    /// Hands the library of `id` to the packer once it is completely written.
+    pub fn completed(&self, id: u64) {
+        if id != 0 {
+            // The packer thread only stops once `finish` drops the sender.
+            self.completed.send(id).unwrap();
+        }
+    }
+
+    /// Waits for the libraries to be packed and, if the workspace was `generated`, appends the
+    /// rest of the staging `dir`.
+    fn finish(self, generated: anyhow::Result<()>, dir: &Path, mtime: u64) -> anyhow::Result<()> {
+        drop(self.completed);
+        let libraries = self.thread.join().unwrap();
+        generated?;
+        let (mut builder, packed) = libraries?;
+        append_tree(&mut builder, dir, mtime, &packed)?;
+        builder.into_inner()?.flush()?;
+        Ok(())
+    }
+}
+
+/// Appends the library of `id` to `builder`, after the directories above it not in `packed` yet,
+/// and removes it from the staging directory.
+fn pack_library(
+    builder: &mut Builder,
+    packed: &mut HashSet<PathBuf>,
+    id: u64,
+    args: &Args,
+) -> anyhow::Result<()> {
+    let node = ID::new(id, args.targets_per_level, args.height as u64);
+    if node.id == benchmarks::mutated_library(args).id {
+        return Ok(());
+    }
+    let dir = layers::lib_dir(&node, args);
+    let path = dir.strip_prefix(&args.output)?;
+    let mut above: Vec<_> = path
+        .ancestors()
+        .skip(1)
+        .filter(|ancestor| !ancestor.as_os_str().is_empty())
+        .collect();
+    above.reverse();
+    for ancestor in above {
+        let name = Path::new(".").join(ancestor);
+        if packed.insert(name.clone()) {
+            append(builder, &args.output.join(ancestor), &name, args.mtime)?;
+        }
+    }
+    let name = Path::new(".").join(path);
+    append(builder, &dir, &name, args.mtime)?;
+    for path in entries(&dir)? {
+        append(
+            builder,
+            &path,
+            &name.join(path.strip_prefix(&dir)?),
+            args.mtime,
+        )?;
+    }
+    std::fs::remove_dir_all(&dir)?;
+    Ok(())
+}
+
+/// Generates the workspace into `<output>.staging` and packs it into the `args.output` archive,
+/// tar archives while it is generated when it `streams`. Otherwise the staging directory is only
+/// removed once packed, so `--resume` also works for archives.
+pub async fn generate_archive(mut args: Args) -> anyhow::Result<()> {
+    let start = Instant::now();
+    let archive = args.output.clone();
+    let mut staging = archive.clone().into_os_string();
+    staging.push(".staging");
+    args.output = staging.into();
+    let args = Arc::new(args);
+    let create =
+        || -> anyhow::Result<_> { Ok(std::io::BufWriter::new(std::fs::File::create(&archive)?)) };
+
+    if streams(&args) {
+        info!("packing {} while generating", archive.display());
+        let out: Box<dyn Write + Send> = match args.output_format {
+            OutputFormat::Tar => Box::new(create()?),
+            _ => Box::new(zstd::Encoder::new(create()?, 3)?.auto_finish()),
+        };
+        let packer = Packer::start(args.clone(), out);
+        let generated = generate(args.clone(), Some(&packer)).await;
+        let packing = Instant::now();
+        let packed = packer.finish(generated, &args.output, args.mtime);
+        if packed.is_err() {
+            std::fs::remove_file(&archive).unwrap_or(());
+        }
+        packed?;
+        self_bench::phase("archive", packing);
+        std::fs::remove_dir_all(&args.output)?;
+        return Ok(());
+    }
+
+    generate(args.clone(), None).await?;
+
+    let packing = Instant::now();
+    info!("packing {}", archive.display());
+    match args.output_format {
+        OutputFormat::Dir => unreachable!("directories aren't archived"),
+        OutputFormat::Tar => pack(&args.output, args.mtime, create()?)?,
+        OutputFormat::TarZst => pack(
+            &args.output,
+            args.mtime,
+            zstd::Encoder::new(create()?, 3)?.auto_finish(),
+        )?,
+        OutputFormat::Ext4Image => {
+            image::write_ext4(&args.output, &archive, args.image_size, args.mtime)?
+        }
+        OutputFormat::SparseImage => {
+            let mut raw = archive.clone().into_os_string();
+            raw.push(".raw");
+            let raw = PathBuf::from(raw);
+            image::write_ext4(&args.output, &raw, args.image_size, args.mtime)?;
+            let sparse = image::write_sparse(&raw, &archive);
+            std::fs::remove_file(&raw)?;
+            sparse?
+        }
+    }
+    self_bench::phase("archive", packing);
+    summary::emit(&args, &args.output, &archive, start.elapsed())?;
+    std::fs::remove_dir_all(&args.output)?;
+    Ok(())
+}
@@ -0,0 +1,169 @@
+//! The interface between the language independent generation and the language backends. A
+//! backend implements `EmitBackend` and is registered in `BACKENDS`, which `--language` selects it
+//! from by its name. Besides the workspace and its packages, the trait covers everything else
+//! that depends on the language: the flags the backend supports, its protos, tests and
+//! third-party libraries, the modules of its bzlmod variant and its IDE and environment files.
+//! Everything but the emission has a default, so a new backend starts out supporting none of the
+//! language dependent flags and opts into the ones it implements.
+
+use crate::graph::ID;
+use crate::starlark::{string_list, BuildFile, Rule, RuleRef};
+use crate::{android, apple, bzlmod, go, java, layers, python, rust, third_party, Args, Language};
+use anyhow::bail;
+use std::path::Path;
+
+pub trait EmitBackend: Sync {
+    /// The `--language` the backend is selected with.
+    fn name(&self) -> &'static str;
+
+    /// Writes the `WORKSPACE` with the rule sets and toolchains the backend builds on. Runs after
+    /// all packages are written.
+    fn emit_workspace(&self, args: &Args) -> anyhow::Result<()>;
+
+    /// Writes the package of `node`: the root package with the top-level targets for the root,
+    /// and the `BUILD.bazel` and sources of its library otherwise. Called concurrently for many
+    /// nodes.
+    fn emit_package(&self, node: &ID, args: &Args) -> anyhow::Result<()>;
+
+    /// The library rule the backend emits, which the language independent layers refer to.
+    fn library_rule(&self, args: &Args) -> RuleRef;
+
+    /// Whether the backend implements the language dependent generate flag `flag`, written as on
+    /// the command line and with the value for flags whose values differ, e.g. `--locales` or
+    /// `--naming-scheme mixed`. `prepare` rejects the flags of `require` the backend doesn't.
+    fn supports(&self, _flag: &str) -> bool {
+        false
+    }
+
+    /// The `--umbrella-per-level` library `level_<level>` in `dir`, which depends on and re-exports
+    /// the `libraries` of the level, loading its rule into `build`.
+    fn umbrella(
+        &self,
+        build: &mut BuildFile,
+        _dir: &Path,
+        level: u32,
+        libraries: &[ID],
+        args: &Args,
+    ) -> anyhow::Result<Rule> {
+        let rule = self.library_rule(args);
+        build.load(&rule.load, &rule.name);
+        Ok(Rule::new(&rule.name, format!("level_{}", level)).attr(
+            "deps",
+            string_list(libraries.iter().map(|node| layers::label(node, args))),
+        ))
+    }
+
+    /// Ids of the libraries library `node` depends on.
+    fn dependency_ids(&self, node: &ID, args: &Args) -> Vec<u64> {
+        layers::direct_deps(node, args)
+            .iter()
+            .map(|dep| dep.id)
+            .collect()
+    }
+
+    /// Whether the `WORKSPACE` already pulls in what `proto_library` needs for
+    /// `--protos-per-package`, without `layers::PROTO_WORKSPACE`.
+    fn has_proto_rules(&self) -> bool {
+        false
+    }
+
+    /// The rule and target name suffix of the language binding of the `--protos-per-package`
+    /// proto libraries, loading what it needs into `build`, or `None` without one.
+    fn proto_binding(
+        &self,
+        _build: &mut BuildFile,
+        _args: &Args,
+    ) -> Option<(&'static str, &'static str)> {
+        None
+    }
+
+    /// Whether `--with-tests` gives every library a `<name>_test` target in its package, which
+    /// the target lists and impact reports include.
+    fn has_library_tests(&self) -> bool {
+        false
+    }
+
+    /// The `--third-party-packages` library `index` (1-based), `None` if the backend supports
+    /// none.
+    fn third_party_library(&self, _index: u64, _args: &Args) -> Option<third_party::Library> {
+        None
+    }
+
+    /// The Bazel Central Registry modules of the rulesets of the `WORKSPACE`, for the
+    /// `--emit-both-dep-systems` variant.
+    fn modules(&self, _args: &Args) -> Vec<bzlmod::Module> {
+        vec![]
+    }
+
+    /// The module extensions registering the toolchains the `WORKSPACE` registers, as they go
+    /// into `MODULE.bazel`.
+    fn module_extensions(&self, _args: &Args) -> &'static str {
+        ""
+    }
+
+    /// Bazel plugin languages of the `--ide intellij` project view, on top of the default (java)
+    /// one.
+    fn ide_languages(&self, _args: &Args) -> Vec<&'static str> {
+        vec![]
+    }
+
+    /// Tools of the language the `--emit-env` environments install, as nixpkgs attributes and
+    /// Debian packages.
+    fn env_tools(&self, _args: &Args) -> Vec<(&'static str, &'static str)> {
+        vec![]
+    }
+}
+
+/// The backends `--language` selects from.
+pub static BACKENDS: [&dyn EmitBackend; 6] = [
+    &apple::Backend,
+    &android::Backend,
+    &java::Backend,
+    &go::Backend,
+    &rust::Backend,
+    &python::Backend,
+];
+
+/// The backend `--language` selects.
+pub fn backend(language: Language) -> &'static dyn EmitBackend {
+    *BACKENDS
+        .iter()
+        .find(|backend| backend.name() == language.as_str())
+        .unwrap()
+}
+
+/// `names` listed as in a sentence, the last one joined with `conjunction`, e.g. `objc, android
+/// and java`.
+pub fn enumerate(names: &[&str], conjunction: &str) -> String {
+    match names {
+        [] => String::new(),
+        [name] => name.to_string(),
+        [rest @ .., last] => format!("{} {} {}", rest.join(", "), conjunction, last),
+    }
+}
+
+/// Fails when `flag` is `set` but isn't supported by the `--language` backend, naming the ones
+/// that support it.
+pub fn require(args: &Args, flag: &str, set: bool) -> anyhow::Result<()> {
+    if !set || backend(args.language).supports(flag) {
+        return Ok(());
+    }
+    let supporting: Vec<_> = BACKENDS
+        .iter()
+        .filter(|backend| backend.supports(flag))
+        .map(|backend| backend.name())
+        .collect();
+    match supporting.len() {
+        0 => bail!("{} isn't supported by any backend", flag),
+        1 => bail!(
+            "{} is only supported by the {} backend",
+            flag,
+            supporting[0]
+        ),
+        _ => bail!(
+            "{} is only supported by the {} backends",
+            flag,
+            enumerate(&supporting, "and")
+        ),
+    }
+}
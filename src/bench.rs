@@ -0,0 +1,717 @@
+//! `bench` subcommand: times clean builds of a generated workspace. With `--jobs-sweep` the builds
+//! repeat at every given parallelism, limiting Bazel's local CPU resources to match, and the
+//! report shows how the build time scales with it, with `--config-sweep` at every given `.bazelrc`
+//! config and with `--arch-sweep` for every given set of architectures. With `--launch` it times installing and
+//! launching an application on the simulator instead, from a booted and a shut down one, and with
+//! `--populate-cache` rebuilding from a remote cache filled by a first build. `--execution-log`
+//! adds a summary of the actions of every configuration, `--build-events` one of the build event
+//! stream of its builds, and `--ab` compares builds with and without some flags.
+
+use crate::benchmarks::CACHE_DIR;
+use crate::report::{print_table, summarize_bep};
+use crate::{exec_log, summary};
+use anyhow::{bail, format_err};
+use clap::Parser;
+use serde_json::{json, Value};
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+use std::time::Instant;
+
+/// Time clean builds of a generated workspace, optionally at several levels of parallelism
+#[derive(Parser, Debug)]
+pub struct BenchArgs {
+    /// Generated workspace to build
+    #[clap(long)]
+    workspace: PathBuf,
+
+    /// Bazel binary to run
+    #[clap(long, default_value = "bazel")]
+    bazel: String,
+
+    /// Targets to build
+    #[clap(long, default_value = "//:all")]
+    targets: String,
+
+    /// Number of timed clean builds per configuration, after the `--warmup` ones
+    #[clap(long, default_value = "3")]
+    runs: usize,
+
+    /// Number of untimed clean builds per configuration before the timed ones, fetching external
+    /// repositories and warming up the server and disk caches
+    #[clap(long, default_value = "1")]
+    warmup: usize,
+
+    /// Leave the times outside of 1.5 interquartile ranges of the quartiles, e.g. of a build that
+    /// ran into a background job, out of the statistics. Needs at least 4 runs
+    #[clap(long)]
+    drop_outliers: bool,
+
+    /// Shut the Bazel server down before every build, so that no build profits from the JIT, file
+    /// system caches or memory of the server of the one before
+    #[clap(long)]
+    shutdown_between_runs: bool,
+
+    /// Expunge the whole output base before every build instead of only cleaning the outputs, so
+    /// that external repositories are fetched and extracted again each time
+    #[clap(long)]
+    clean_output_base: bool,
+
+    /// Comma separated `--jobs` values to repeat the builds at, each also passed as
+    /// `--local_cpu_resources`
+    #[clap(long, use_delimiter = true)]
+    jobs_sweep: Vec<u64>,
+
+    /// Comma separated `.bazelrc` configs (e.g. `debug,dsym` of `--debug-configs`) to repeat the
+    /// builds with, after the builds without any, reporting each one's overhead over those
+    #[clap(long, use_delimiter = true)]
+    config_sweep: Vec<String>,
+
+    /// Comma separated `--ios_multi_cpus` values to repeat the builds with, `+` joining the
+    /// architectures of a multi-architecture build, e.g. `sim_arm64,sim_arm64+x86_64`, reporting
+    /// each one's overhead over the first
+    #[clap(long, use_delimiter = true)]
+    arch_sweep: Vec<String>,
+
+    /// Comma separated `--spawn_strategy` values to repeat the builds with, e.g.
+    /// `sandboxed,local,worker`, reporting each one's overhead over the first. `worker` falls back
+    /// to `sandboxed` for actions without a worker, and `+reuse` reuses sandbox directories and
+    /// `+tmpfs` puts the sandboxes on `/dev/shm`, e.g. `sandboxed+reuse+tmpfs`
+    #[clap(long, use_delimiter = true)]
+    strategy_sweep: Vec<String>,
+
+    /// Bazel flags to A/B test, space separated in one argument (e.g.
+    /// `--ab="--features=swift.use_global_module_cache"`): clean builds without (A) and with (B)
+    /// them alternate, each variant in an output base of its own, and the report compares their
+    /// mean, standard deviation and minimum
+    #[clap(long, allow_hyphen_values = true)]
+    ab: Option<String>,
+
+    /// Application (e.g. `//:root`) to time `bazel run --config=simulator` of instead of clean
+    /// builds, cold with all simulators shut down first and warm right after. The workspace has to
+    /// be generated with `--with-run-target`
+    #[clap(long)]
+    launch: Option<String>,
+
+    /// Remote cache (e.g. `grpc://localhost:9092`) to fill with a first build, timing clean
+    /// rebuilds served from it instead and reporting their remote cache hit rates from the
+    /// execution log
+    #[clap(long)]
+    populate_cache: Option<String>,
+
+    /// After the timed builds of every configuration, one more that writes a JSON execution log,
+    /// summarized by mnemonic with cache hits, input bytes and the slowest actions. Writing the log
+    /// slows the build down, so that one isn't timed
+    #[clap(long)]
+    execution_log: bool,
+
+    /// Have the clean builds write their build event protocol stream with
+    /// `--build_event_json_file`, which the times then include, and summarize the stream of every
+    /// configuration: events by kind, bytes and the time to the last event
+    #[clap(long)]
+    build_events: bool,
+
+    /// Number of the slowest actions the `--execution-log` summary lists
+    #[clap(long, default_value = "10")]
+    slowest: usize,
+
+    /// Also write the results as JSON to this file, along with the `BENCHMARK_METADATA.json` of
+    /// the workspace
+    #[clap(long)]
+    json: Option<PathBuf>,
+}
+
+fn bazel(args: &BenchArgs, bazel_args: &[String]) -> anyhow::Result<()> {
+    let status = Command::new(&args.bazel)
+        .args(bazel_args)
+        .current_dir(&args.workspace)
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .status()?;
+    if !status.success() {
+        bail!(
+            "{} {} failed with {}",
+            args.bazel,
+            bazel_args.join(" "),
+            status
+        );
+    }
+    Ok(())
+}
+
+/// Cleans and builds with `flags`, returning the wall time of the build in milliseconds.
+fn clean_build(args: &BenchArgs, flags: &[String]) -> anyhow::Result<f64> {
+    clean_build_with(args, &[], flags)
+}
+
+/// `clean_build` with Bazel startup options, e.g. an `--output_base`. The server is shut down
+/// first with `--shutdown-between-runs`, and the output base expunged with
+/// `--clean-output-base`.
+fn clean_build_with(args: &BenchArgs, startup: &[String], flags: &[String]) -> anyhow::Result<f64> {
+    let command = |command: &[&str]| -> Vec<String> {
+        startup
+            .iter()
+            .cloned()
+            .chain(command.iter().map(|arg| arg.to_string()))
+            .collect()
+    };
+    if args.shutdown_between_runs {
+        bazel(args, &command(&["shutdown"]))?;
+    }
+    if args.clean_output_base {
+        bazel(args, &command(&["clean", "--expunge"]))?;
+    } else {
+        bazel(args, &command(&["clean"]))?;
+    }
+    let build: Vec<_> = command(&["build"])
+        .into_iter()
+        .chain(flags.iter().cloned())
+        .chain(
+            args.build_events
+                .then(|| format!("--build_event_json_file={}", build_events_path())),
+        )
+        .chain([args.targets.clone()])
+        .collect();
+    timed(args, &build)
+}
+
+/// Runs `bazel_args` and returns its wall time in milliseconds.
+fn timed(args: &BenchArgs, bazel_args: &[String]) -> anyhow::Result<f64> {
+    let start = Instant::now();
+    bazel(args, bazel_args)?;
+    Ok(start.elapsed().as_secs_f64() * 1000.0)
+}
+
+/// Times cold and warm launches of `app` on the simulator.
+fn launch(args: &BenchArgs, app: &str) -> anyhow::Result<()> {
+    let config = "--config=simulator".to_string();
+    let run = ["run".to_string(), config.clone(), app.to_string()];
+    println!("building {} {}", app, config);
+    bazel(args, &["build".to_string(), config, app.to_string()])?;
+
+    let (mut cold, mut warm) = (vec![], vec![]);
+    for _ in 0..args.runs {
+        // Failing only means no simulator was booted.
+        Command::new("xcrun")
+            .args(["simctl", "shutdown", "all"])
+            .stderr(Stdio::null())
+            .status()
+            .map_err(|e| format_err!("xcrun: {}, --launch needs Xcode", e))?;
+        cold.push(timed(args, &run)?);
+        warm.push(timed(args, &run)?);
+    }
+
+    let mut rows = vec![];
+    let mut results = vec![];
+    for (name, mut times) in [("cold launch", cold), ("warm launch", warm)] {
+        times.sort_by(|a, b| a.partial_cmp(b).unwrap());
+        rows.push(vec![
+            name.to_string(),
+            format!("{:.0}", times[0]),
+            format!("{:.0}", times[times.len() / 2]),
+        ]);
+        results.push(json!({
+            "name": name,
+            "command": run,
+            "times_ms": times,
+        }));
+    }
+    let header = ["scenario", "min (ms)", "median (ms)"].map(str::to_string);
+    print_table(&header, &rows);
+    write_json(args, json!(results))?;
+    Ok(())
+}
+
+/// Fraction of the spawns in the JSON execution log at `path` that hit the remote cache, with
+/// their number.
+fn remote_cache_hit_rate(path: &Path) -> anyhow::Result<(f64, usize)> {
+    let spawns = exec_log::read(path)?;
+    let hits = spawns
+        .iter()
+        .filter(|spawn| spawn["remoteCacheHit"].as_bool() == Some(true))
+        .count();
+    Ok((hits as f64 / spawns.len().max(1) as f64, spawns.len()))
+}
+
+/// Where the builds write their JSON execution log, relative to the workspace. This is the JSON
+/// log of `--execution_log_json_file` rather than the compact one of
+/// `--execution_log_compact_file`: that one is zstd compressed protobuf, and decoding its spawns
+/// needs the message definitions of the Bazel version that wrote it, see `exec_log`.
+fn execution_log_path() -> String {
+    format!("{}/execution_log.json", CACHE_DIR)
+}
+
+/// Where the builds write their build event stream with `--build-events`, relative to the
+/// workspace.
+fn build_events_path() -> String {
+    format!("{}/build_events.json", CACHE_DIR)
+}
+
+/// Drops the times outside of 1.5 interquartile ranges below the first and above the third
+/// quartile with `--drop-outliers`, returning how many it dropped. Fewer than 4 times have no
+/// meaningful quartiles and are kept as they are.
+fn drop_outliers(args: &BenchArgs, times: &mut Vec<f64>) -> usize {
+    if !args.drop_outliers || times.len() < 4 {
+        return 0;
+    }
+    let mut sorted = times.clone();
+    sorted.sort_by(|a, b| a.partial_cmp(b).unwrap());
+    let quartile = |q: f64| {
+        let position = q * (sorted.len() - 1) as f64;
+        let (low, high) = (position.floor() as usize, position.ceil() as usize);
+        sorted[low] + (sorted[high] - sorted[low]) * (position - low as f64)
+    };
+    let (q1, q3) = (quartile(0.25), quartile(0.75));
+    let fence = 1.5 * (q3 - q1);
+    let before = times.len();
+    times.retain(|time| (q1 - fence..=q3 + fence).contains(time));
+    before - times.len()
+}
+
+/// Mean, sample standard deviation and minimum of `times`.
+fn statistics(times: &[f64]) -> (f64, f64, f64) {
+    let mean = times.iter().sum::<f64>() / times.len() as f64;
+    let variance = times.iter().map(|time| (time - mean).powi(2)).sum::<f64>()
+        / (times.len().max(2) - 1) as f64;
+    let min = times.iter().copied().fold(f64::INFINITY, f64::min);
+    (mean, variance.sqrt(), min)
+}
+
+/// Times clean builds without and with `flags`, alternating between them so that drift of the
+/// machine affects both alike. Every variant has an output base of its own, so neither sees the
+/// other's outputs or server state.
+fn ab(args: &BenchArgs, flags: &str) -> anyhow::Result<()> {
+    let flags: Vec<String> = flags.split_whitespace().map(str::to_string).collect();
+    if flags.is_empty() {
+        bail!("--ab needs the flags to compare");
+    }
+    let variants = [("A", vec![]), ("B", flags.clone())];
+    let output_base = |name: &str| {
+        format!(
+            "--output_base={}",
+            args.workspace
+                .join(CACHE_DIR)
+                .join(format!("ab_{}", name.to_lowercase()))
+                .display()
+        )
+    };
+    let build = |name: &str, flags: &[String]| clean_build_with(args, &[output_base(name)], flags);
+
+    println!("A/B testing {} on {}", flags.join(" "), args.targets);
+    for _ in 0..args.warmup {
+        for (name, flags) in &variants {
+            build(name, flags)?;
+        }
+    }
+    let mut times = [vec![], vec![]];
+    for _ in 0..args.runs {
+        for (i, (name, flags)) in variants.iter().enumerate() {
+            times[i].push(build(name, flags)?);
+        }
+    }
+    for (name, _) in &variants {
+        bazel(args, &[output_base(name), "shutdown".to_string()])?;
+    }
+
+    let mut dropped = [0, 0];
+    for (i, (name, _)) in variants.iter().enumerate() {
+        dropped[i] = drop_outliers(args, &mut times[i]);
+        if dropped[i] > 0 {
+            println!("{}: dropped {} outliers", name, dropped[i]);
+        }
+    }
+    let stats = times.each_ref().map(|times| statistics(times));
+    let rows: Vec<_> = variants
+        .iter()
+        .zip(&stats)
+        .map(|((name, flags), (mean, stddev, min))| {
+            vec![
+                name.to_string(),
+                if flags.is_empty() {
+                    "-".to_string()
+                } else {
+                    flags.join(" ")
+                },
+                format!("{:.0}", mean),
+                format!("{:.0}", stddev),
+                format!("{:.0}", min),
+            ]
+        })
+        .collect();
+    let header = ["variant", "flags", "mean (ms)", "stddev (ms)", "min (ms)"].map(str::to_string);
+    print_table(&header, &rows);
+    let delta = stats[1].0 - stats[0].0;
+    let delta_percent = delta / stats[0].0 * 100.0;
+    println!("B - A: {:+.0} ms ({:+.1}%) mean", delta, delta_percent);
+
+    let variant_json = |i: usize| {
+        let (mean, stddev, min) = stats[i];
+        json!({
+            "flags": variants[i].1,
+            "times_ms": times[i],
+            "dropped_outliers": dropped[i],
+            "mean_ms": mean,
+            "stddev_ms": stddev,
+            "min_ms": min,
+        })
+    };
+    write_json(
+        args,
+        json!({
+            "a": variant_json(0),
+            "b": variant_json(1),
+            "delta_mean_ms": delta,
+            "delta_mean_percent": delta_percent,
+        }),
+    )
+}
+
+/// One configuration of a sweep: its name in the report and the flags its builds add.
+struct Configuration {
+    name: String,
+    jobs: Option<u64>,
+    flags: Vec<String>,
+}
+
+/// Flags of the `--strategy-sweep` entry `strategy`: a spawn strategy followed by `+` separated
+/// sandbox options.
+fn strategy_flags(strategy: &str) -> anyhow::Result<Vec<String>> {
+    let mut parts = strategy.split('+');
+    let mut flags = match parts.next().unwrap_or_default() {
+        "" => bail!(
+            "--strategy-sweep entry {:?} has no spawn strategy",
+            strategy
+        ),
+        "worker" => vec!["--spawn_strategy=worker,sandboxed".to_string()],
+        name => vec![format!("--spawn_strategy={}", name)],
+    };
+    for option in parts {
+        flags.push(match option {
+            "reuse" => "--experimental_reuse_sandbox_directories".to_string(),
+            "tmpfs" => "--sandbox_base=/dev/shm".to_string(),
+            _ => bail!(
+                "unknown sandbox option {} of --strategy-sweep entry {}, expected reuse or tmpfs",
+                option,
+                strategy
+            ),
+        });
+    }
+    Ok(flags)
+}
+
+/// Fills the remote cache at `url` with one build, then times clean rebuilds from it.
+fn populate_cache(args: &BenchArgs, url: &str) -> anyhow::Result<()> {
+    let log = execution_log_path();
+    let flags = [
+        format!("--remote_cache={}", url),
+        format!("--execution_log_json_file={}", log),
+    ];
+    std::fs::create_dir_all(args.workspace.join(CACHE_DIR))?;
+    println!("populating {} with {}", url, args.targets);
+    let populate = clean_build(args, &flags)?;
+
+    let mut runs = vec![];
+    for _ in 0..args.runs {
+        let time = clean_build(args, &flags)?;
+        let (hit_rate, spawns) = remote_cache_hit_rate(&args.workspace.join(&log))?;
+        runs.push((time, hit_rate, spawns));
+    }
+    runs.sort_by(|a, b| a.0.partial_cmp(&b.0).unwrap());
+    let median = runs[runs.len() / 2];
+
+    let rows = vec![
+        vec![
+            "populate".to_string(),
+            format!("{:.0}", populate),
+            format!("{:.0}", populate),
+            "-".to_string(),
+        ],
+        vec![
+            "cached rebuild".to_string(),
+            format!("{:.0}", runs[0].0),
+            format!("{:.0}", median.0),
+            format!("{:.1}% of {}", median.1 * 100.0, median.2),
+        ],
+    ];
+    let header = ["scenario", "min (ms)", "median (ms)", "remote hits"].map(str::to_string);
+    print_table(&header, &rows);
+    let results = json!({
+        "remote_cache": url,
+        "populate_ms": populate,
+        "rebuilds": runs
+            .iter()
+            .map(|(time, hit_rate, spawns)| json!({
+                "time_ms": time,
+                "remote_cache_hit_rate": hit_rate,
+                "spawns": spawns,
+            }))
+            .collect::<Vec<_>>(),
+    });
+    write_json(args, results)
+}
+
+/// The `BENCHMARK_METADATA.json` of the workspace, `None` if it's older than that file.
+fn workspace_metadata(args: &BenchArgs) -> anyhow::Result<Option<Value>> {
+    let path = args.workspace.join(summary::METADATA_FILE);
+    if !path.exists() {
+        return Ok(None);
+    }
+    let content = std::fs::read_to_string(&path)?;
+    let metadata = serde_json::from_str(&content)
+        .map_err(|e| format_err!("{} isn't valid JSON: {}", path.display(), e))?;
+    Ok(Some(metadata))
+}
+
+/// Writes `results` to `--json`, next to the metadata of the workspace they were measured on.
+fn write_json(args: &BenchArgs, results: Value) -> anyhow::Result<()> {
+    if let Some(path) = &args.json {
+        let report = json!({
+            "workspace_metadata": workspace_metadata(args)?,
+            "results": results,
+        });
+        std::fs::write(path, serde_json::to_string_pretty(&report)?)?;
+    }
+    Ok(())
+}
+
+pub fn run(args: &BenchArgs) -> anyhow::Result<()> {
+    if args.runs == 0 {
+        bail!("--runs must be at least 1");
+    }
+    if args.drop_outliers && args.runs < 4 {
+        bail!("--drop-outliers needs at least 4 --runs");
+    }
+    match workspace_metadata(args)? {
+        Some(metadata) => println!(
+            "workspace generated by gen_bazel_benchmark {} ({}) at {}: {}",
+            metadata["version"].as_str().unwrap_or("?"),
+            metadata["commit"].as_str().unwrap_or("?"),
+            metadata["generated_at_unix"],
+            metadata["command_line"]
+                .as_array()
+                .into_iter()
+                .flatten()
+                .filter_map(Value::as_str)
+                .collect::<Vec<_>>()
+                .join(" ")
+        ),
+        None => println!(
+            "{} has no {}, its parameters are unknown",
+            args.workspace.display(),
+            summary::METADATA_FILE
+        ),
+    }
+    if args.execution_log && (args.launch.is_some() || args.populate_cache.is_some()) {
+        bail!("--execution-log summarizes clean builds, it doesn't combine with --launch or --populate-cache");
+    }
+    if args.build_events
+        && (args.launch.is_some() || args.populate_cache.is_some() || args.ab.is_some())
+    {
+        bail!("--build-events summarizes the builds of every configuration, it doesn't combine with --launch, --populate-cache or --ab");
+    }
+    if args.build_events {
+        std::fs::create_dir_all(args.workspace.join(CACHE_DIR))?;
+    }
+    let sweeps = [
+        !args.jobs_sweep.is_empty(),
+        !args.config_sweep.is_empty(),
+        !args.arch_sweep.is_empty(),
+        !args.strategy_sweep.is_empty(),
+    ];
+    if let Some(flags) = &args.ab {
+        if sweeps.contains(&true)
+            || args.launch.is_some()
+            || args.populate_cache.is_some()
+            || args.execution_log
+        {
+            bail!("--ab compares two variants of clean builds, it doesn't combine with sweeps, --launch, --populate-cache or --execution-log");
+        }
+        return ab(args, flags);
+    }
+    if let Some(url) = &args.populate_cache {
+        if sweeps.contains(&true) || args.launch.is_some() {
+            bail!("--populate-cache times cached rebuilds, it doesn't combine with sweeps or --launch");
+        }
+        return populate_cache(args, url);
+    }
+    if let Some(app) = &args.launch {
+        if sweeps.contains(&true) {
+            bail!("--launch times launches instead of builds, it doesn't combine with sweeps");
+        }
+        return launch(args, app);
+    }
+    if args.jobs_sweep.contains(&0) {
+        bail!("--jobs-sweep values must be at least 1");
+    }
+    if sweeps.iter().filter(|sweep| **sweep).count() > 1 {
+        bail!("--jobs-sweep, --config-sweep, --arch-sweep and --strategy-sweep each sweep configurations, pick one");
+    }
+    // Without a sweep a single configuration with Bazel's default parallelism. A config sweep
+    // starts with the builds without a config the others are compared to.
+    let (column, configurations): (_, Vec<Configuration>) = if !args.config_sweep.is_empty() {
+        let configurations = std::iter::once(Configuration {
+            name: "none".to_string(),
+            jobs: None,
+            flags: vec![],
+        })
+        .chain(args.config_sweep.iter().map(|config| Configuration {
+            name: config.clone(),
+            jobs: None,
+            flags: vec![format!("--config={}", config)],
+        }))
+        .collect();
+        ("config", configurations)
+    } else if !args.arch_sweep.is_empty() {
+        let configurations = args
+            .arch_sweep
+            .iter()
+            .map(|archs| Configuration {
+                name: archs.clone(),
+                jobs: None,
+                flags: vec![format!("--ios_multi_cpus={}", archs.replace('+', ","))],
+            })
+            .collect();
+        ("archs", configurations)
+    } else if !args.strategy_sweep.is_empty() {
+        let configurations = args
+            .strategy_sweep
+            .iter()
+            .map(|strategy| {
+                Ok(Configuration {
+                    name: strategy.clone(),
+                    jobs: None,
+                    flags: strategy_flags(strategy)?,
+                })
+            })
+            .collect::<anyhow::Result<_>>()?;
+        ("strategy", configurations)
+    } else if args.jobs_sweep.is_empty() {
+        let configuration = Configuration {
+            name: "default".to_string(),
+            jobs: None,
+            flags: vec![],
+        };
+        ("jobs", vec![configuration])
+    } else {
+        let configurations = args
+            .jobs_sweep
+            .iter()
+            .map(|jobs| Configuration {
+                name: jobs.to_string(),
+                jobs: Some(*jobs),
+                flags: vec![
+                    format!("--jobs={}", jobs),
+                    format!("--local_cpu_resources={}", jobs),
+                ],
+            })
+            .collect();
+        ("jobs", configurations)
+    };
+
+    let mut medians = vec![];
+    let mut results = vec![];
+    let mut summaries = vec![];
+    let mut build_events = vec![];
+    for configuration in &configurations {
+        let flags = &configuration.flags;
+        println!("building {} {}", args.targets, flags.join(" "));
+        for _ in 0..args.warmup {
+            clean_build(args, flags)?;
+        }
+        let mut times = (0..args.runs)
+            .map(|_| clean_build(args, flags))
+            .collect::<anyhow::Result<Vec<_>>>()?;
+        let dropped = drop_outliers(args, &mut times);
+        if dropped > 0 {
+            println!("dropped {} outliers", dropped);
+        }
+        times.sort_by(|a, b| a.partial_cmp(b).unwrap());
+        medians.push((configuration, times[0], times[times.len() / 2]));
+        let mut result = json!({
+            "name": configuration.name,
+            "jobs": configuration.jobs,
+            "flags": flags,
+            "times_ms": times,
+            "dropped_outliers": dropped,
+        });
+        if args.build_events {
+            // The stream of the last timed build, every build of a configuration has the same.
+            let summary = summarize_bep(&args.workspace.join(build_events_path()))?;
+            result["build_events"] = json!({
+                "events": summary.events,
+                "bytes": summary.bytes,
+                "to_last_event_ms": summary.to_last_event,
+            });
+            build_events.push((configuration, summary));
+        }
+        if args.execution_log {
+            let log = execution_log_path();
+            std::fs::create_dir_all(args.workspace.join(CACHE_DIR))?;
+            let mut log_flags = flags.clone();
+            log_flags.push(format!("--execution_log_json_file={}", log));
+            clean_build(args, &log_flags)?;
+            let summary =
+                exec_log::Summary::new(&exec_log::read(&args.workspace.join(&log))?, args.slowest);
+            result["execution_log"] = summary.to_json();
+            summaries.push((configuration, summary));
+        }
+        results.push(result);
+    }
+
+    // Speedup, efficiency and overhead are relative to the first configuration of the sweep.
+    let (base, _, base_median) = medians[0];
+    let rows: Vec<_> = medians
+        .iter()
+        .map(|(configuration, min, median)| {
+            let mut row = vec![
+                configuration.name.clone(),
+                format!("{:.0}", min),
+                format!("{:.0}", median),
+            ];
+            if column != "jobs" {
+                row.push(format!("{:+.1}%", (median / base_median - 1.0) * 100.0));
+                return row;
+            }
+            let speedup = base_median / median;
+            row.push(format!("{:.2}", speedup));
+            row.push(match (base.jobs, configuration.jobs) {
+                (Some(base), Some(jobs)) => format!("{:.2}", speedup * base as f64 / jobs as f64),
+                _ => "-".to_string(),
+            });
+            row
+        })
+        .collect();
+    let mut header = vec![column, "min (ms)", "median (ms)"];
+    if column == "jobs" {
+        header.extend(["speedup", "efficiency"]);
+    } else {
+        header.push("overhead");
+    }
+    print_table(
+        &header
+            .iter()
+            .map(|title| title.to_string())
+            .collect::<Vec<_>>(),
+        &rows,
+    );
+    for (configuration, summary) in &build_events {
+        println!(
+            "\nbuild events, {} {}: {} events, {} bytes, last event after {}",
+            column,
+            configuration.name,
+            summary.events.values().sum::<u64>(),
+            summary.bytes,
+            summary
+                .to_last_event
+                .map_or("-".to_string(), |ms| format!("{:.0} ms", ms))
+        );
+        for (kind, count) in &summary.events {
+            println!("  {:<32} {}", kind, count);
+        }
+    }
+    for (configuration, summary) in &summaries {
+        println!("\nexecution log, {} {}:", column, configuration.name);
+        summary.print();
+    }
+    write_json(args, json!(results))?;
+    Ok(())
+}
@@ -0,0 +1,196 @@
+//! Canonical benchmark scenarios shipped with every generated workspace, so the same workspace
+//! gets measured the same way no matter who runs it.
+
+use crate::graph::{num_nodes_in_ntree, ID};
+use crate::{backend, layers, mutate, third_party, Args, Language};
+use std::io::{BufWriter, Write};
+use std::os::unix::fs::PermissionsExt;
+use std::path::PathBuf;
+
+/// Workspace directory of the caches of `--config=diskcache`, ignored by Bazel and git.
+pub const CACHE_DIR: &str = ".cache";
+
+/// `--config=diskcache` section of the `.bazelrc`: a disk cache and a repository cache in
+/// `CACHE_DIR`, so that their size and state are part of the workspace being measured.
+pub fn cache_bazelrc() -> String {
+    [
+        format!(
+            "build:diskcache --disk_cache=%workspace%/{}/disk",
+            CACHE_DIR
+        ),
+        format!(
+            "build:diskcache --repository_cache=%workspace%/{}/repository",
+            CACHE_DIR
+        ),
+        format!(
+            "fetch:diskcache --repository_cache=%workspace%/{}/repository",
+            CACHE_DIR
+        ),
+    ]
+    .join("\n")
+}
+
+/// `.bazelrc` sections of `--debug-configs`: `debug` builds with debug info, which `dsym` (objc
+/// backend) and `fission` add their debug artifacts on top of, so comparing them with `debug`
+/// measures generating those alone.
+pub fn debug_bazelrc(args: &Args) -> String {
+    let mut lines = vec!["build:debug --compilation_mode=dbg"];
+    if args.language == Language::OBJC {
+        lines.extend([
+            "build:dsym --config=debug",
+            "build:dsym --apple_generate_dsym",
+            "build:dsym --output_groups=+dsyms",
+        ]);
+    }
+    lines.extend([
+        "build:fission --config=debug",
+        "build:fission --fission=yes",
+        "build:fission --features=per_object_debug_info",
+    ]);
+    lines.join("\n")
+}
+
+/// Library touched by the incremental scenario: the first library on the last level, so that the
+/// rebuild invalidates the longest chain of dependents.
+pub fn mutated_library(args: &Args) -> ID {
+    ID::new(
+        num_nodes_in_ntree(args.targets_per_level, args.height - 1),
+        args.targets_per_level,
+        args.height as u64,
+    )
+}
+
+/// Source file touched by the incremental scenario: the first source file of the
+/// `mutated_library`.
+fn mutated_file(args: &Args) -> anyhow::Result<PathBuf> {
+    Ok(
+        mutate::source_files(&layers::lib_dir(&mutated_library(args), args))?[0]
+            .strip_prefix(&args.output)
+            .unwrap()
+            .to_path_buf(),
+    )
+}
+
+/// Writes `all_targets.txt` with the libraries, and their tests with `--with-tests`, level by
+/// level, and `leaf_targets.txt` with the libraries of the last level, one label per line, for
+/// drivers to sample targets from without querying the workspace first.
+pub fn write_target_lists(args: &Args) -> anyhow::Result<()> {
+    let mut all = BufWriter::new(std::fs::File::create(args.output.join("all_targets.txt"))?);
+    let mut leaves = BufWriter::new(std::fs::File::create(args.output.join("leaf_targets.txt"))?);
+    for id in 1..num_nodes_in_ntree(args.targets_per_level, args.height) {
+        let node = ID::new(id, args.targets_per_level, args.height as u64);
+        let label = layers::label(&node, args);
+        writeln!(all, "{}", label)?;
+        if args.with_tests && backend::backend(args.language).has_library_tests() {
+            writeln!(all, "{}:{}_test", label, node.target_name())?;
+        }
+        if node.is_leaf() {
+            writeln!(leaves, "{}", label)?;
+        }
+    }
+    Ok(())
+}
+
+pub fn write_benchmark_script(args: &Args) -> anyhow::Result<()> {
+    let (third_party_comment, third_party) = if args.third_party_packages > 0 {
+        let file = third_party::bumped_file(args);
+        let file = file.to_str().unwrap();
+        (
+            format!(
+                "\n# third_party_bump, also only run when asked for, bumps the version of a vendored library\n\
+                 # and rebuilds what {} lists.",
+                third_party::BUMP_REPORT
+            ),
+            format!(
+                r#"    third_party_bump)
+        sed -i.bak -e "s/\(VERSION[^=]*= \)[0-9]*/\1$(date +%s)/" {file}
+        rm {file}.bak
+        scenario third_party_bump build "$TARGETS"
+        ;;
+"#,
+                file = file
+            ),
+        )
+    } else {
+        (String::new(), String::new())
+    };
+    let path = args.output.join("run_benchmarks.sh");
+    let mut f = std::fs::File::create(&path)?;
+    writeln!(
+        f,
+        r#"#!/bin/bash
+# Canonical benchmark scenarios for this workspace. Every scenario writes a JSON trace profile to
+# $PROFILE_DIR/<scenario>.json.gz, and the build scenarios their build event protocol stream to
+# $PROFILE_DIR/<scenario>.bep.json unless BEP=0. Pass scenario names to only run some of them, the
+# cquery, aquery and disk_cache scenarios only run when asked for. disk_cache builds from scratch
+# with --config=diskcache twice, first with an empty cache (disk_cache_cold) and then with the one
+# the first build filled (disk_cache_warm).{third_party_comment}
+set -euo pipefail
+cd "$(dirname "$0")"
+
+BAZEL="${{BAZEL:-bazel}}"
+PROFILE_DIR="${{PROFILE_DIR:-profiles}}"
+BEP="${{BEP:-1}}"
+TARGETS="//:all"
+SCENARIOS="${{*:-clean_build null_build analysis_only query incremental}}"
+
+mkdir -p "$PROFILE_DIR"
+
+scenario() {{
+    local name="$1" command="$2"
+    shift 2
+    local flags=(--profile="$PROFILE_DIR/$name.json.gz")
+    if [ "$command" = build ] && [ "$BEP" != 0 ]; then
+        flags+=(--build_event_json_file="$PROFILE_DIR/$name.bep.json")
+    fi
+    echo "== $name"
+    "$BAZEL" "$command" "${{flags[@]}}" "$@"
+}}
+
+for s in $SCENARIOS; do
+    case "$s" in
+    clean_build)
+        "$BAZEL" clean
+        scenario clean_build build "$TARGETS"
+        ;;
+    null_build)
+        scenario null_build build "$TARGETS"
+        ;;
+    analysis_only)
+        scenario analysis_only build --nobuild "$TARGETS"
+        ;;
+    query)
+        scenario query query "deps($TARGETS)" > /dev/null
+        ;;
+    cquery)
+        scenario cquery cquery --output=jsonproto "deps($TARGETS)" > /dev/null
+        ;;
+    aquery)
+        scenario aquery aquery --output=jsonproto "deps($TARGETS)" > /dev/null
+        ;;
+    disk_cache)
+        rm -rf {cache}/disk
+        "$BAZEL" clean
+        scenario disk_cache_cold build --config=diskcache "$TARGETS"
+        "$BAZEL" clean
+        scenario disk_cache_warm build --config=diskcache "$TARGETS"
+        ;;
+{third_party}    incremental)
+        echo >> {mutated}
+        scenario incremental build "$TARGETS"
+        ;;
+    *)
+        echo "unknown scenario $s" >&2
+        exit 1
+        ;;
+    esac
+done"#,
+        mutated = mutated_file(args)?.to_str().unwrap(),
+        cache = CACHE_DIR,
+        third_party_comment = third_party_comment,
+        third_party = third_party,
+    )?;
+
+    std::fs::set_permissions(&path, std::fs::Permissions::from_mode(0o755))?;
+    Ok(())
+}
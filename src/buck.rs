@@ -0,0 +1,109 @@
+//! Buck2 build of the objc library graph, so the same workspace can be built with Buck2 as well
+//! as Bazel. Every library gets a `BUCK` file next to its BUILD file.
+
+use crate::graph::ID;
+use crate::starlark::{quote, string_list, BuildFile, Rule};
+use crate::{apple, layers, Args};
+use std::path::Path;
+
+/// Label of the library of `node`. Buck2 sees a single cell, external repositories are plain
+/// directories of it.
+fn label(node: &ID, args: &Args) -> String {
+    format!(
+        "//{}:{}",
+        layers::lib_dir(node, args)
+            .strip_prefix(&args.output)
+            .unwrap()
+            .to_str()
+            .unwrap(),
+        node.target_name()
+    )
+}
+
+/// Writes the `BUCK` file of the library of `node`, depending on `deps`.
+pub fn write_library(node: &ID, args: &Args, lib_dir: &Path, deps: &[ID]) -> anyhow::Result<()> {
+    let mut build = BuildFile::new();
+    let prebuilt = format!("{}.framework", node.lib_name());
+    let rule = if lib_dir.join(&prebuilt).exists() {
+        Rule::new("prebuilt_apple_framework", node.target_name())
+            .attr("framework", quote(&prebuilt))
+            .attr(
+                "preferred_linkage",
+                quote(if args.linkage.is_dynamic(node) {
+                    "shared"
+                } else {
+                    "static"
+                }),
+            )
+    } else {
+        let mut rule = Rule::new("apple_library", node.target_name())
+            .attr("header_path_prefix", quote(&node.lib_name()))
+            .attr("modular", "True")
+            .attr("srcs", format!("glob([{}])", quote("*.m")))
+            .attr("exported_headers", format!("glob([{}])", quote("*.h")));
+        let copts = layers::generated_flags(args, args.copts_per_target, |i, value| {
+            format!("-DBENCH_COPT_{}={}", i, value)
+        });
+        if !copts.is_empty() {
+            rule.set("compiler_flags", string_list(copts));
+        }
+        let defines = layers::generated_flags(args, args.defines_per_target, |i, value| {
+            format!("-DBENCH_DEFINE_{}={}", i, value)
+        });
+        if !defines.is_empty() {
+            rule.set("exported_preprocessor_flags", string_list(defines));
+        }
+        if args.linkage.is_dynamic(node) {
+            rule.set("preferred_linkage", quote("shared"));
+        }
+        rule.attr("deps", string_list(deps.iter().map(|dep| label(dep, args))))
+    };
+    build.add(rule.attr("visibility", string_list(["PUBLIC"])));
+    build.write(&lib_dir.join("BUCK"))?;
+    Ok(())
+}
+
+/// Writes the root `BUCK` file with an `apple_binary` for every application, and the cell
+/// configuration.
+pub fn write_root(args: &Args, apps: &[(String, Vec<ID>)]) -> anyhow::Result<()> {
+    let mut build = BuildFile::new();
+    for (name, deps) in apps {
+        build.add(
+            Rule::new("apple_binary", name)
+                .attr("srcs", string_list([apple::main_source(name, args)]))
+                .attr("deps", string_list(deps.iter().map(|dep| label(dep, args)))),
+        );
+    }
+    build.write(&args.output.join("BUCK"))?;
+
+    std::fs::write(
+        args.output.join(".buckconfig"),
+        "[cells]\n\
+         \x20 root = .\n\
+         \x20 prelude = prelude\n\
+         \x20 toolchains = toolchains\n\
+         \x20 none = none\n\
+         \n\
+         [cell_aliases]\n\
+         \x20 config = prelude\n\
+         \x20 fbcode = none\n\
+         \x20 fbsource = none\n\
+         \x20 buck = none\n\
+         \n\
+         [external_cells]\n\
+         \x20 prelude = bundled\n\
+         \n\
+         [parser]\n\
+         \x20 target_platform_detector_spec = target:root//...->prelude//platforms:default\n\
+         \n\
+         [project]\n\
+         \x20 ignore = bazel-*\n",
+    )?;
+    std::fs::create_dir_all(args.output.join("toolchains"))?;
+    std::fs::write(
+        args.output.join("toolchains/BUCK"),
+        "load(\"@prelude//toolchains:demo.bzl\", \"system_demo_toolchains\")\n\nsystem_demo_toolchains()\n",
+    )
+    ?;
+    Ok(())
+}
@@ -0,0 +1,129 @@
+//! `--max-files` and `--max-bytes`: safety budgets for generating on machines whose disk a
+//! mistyped `--height` would fill. The graph is written level by level into a scratch directory
+//! first, until a level takes the libraries over budget; that level still gets completed, and
+//! the workspace is then generated with the height cut to it, so that the shape stays a regular
+//! tree that `regen`, the metrics and the benchmark scripts agree on.
+
+use crate::graph::num_nodes_in_ntree;
+use crate::{emit_build_file, layers, prepare, presets, regen, Args, Cli, Command, WRITERS};
+use anyhow::bail;
+use clap::Parser;
+use serde_json::{json, Value};
+use std::path::Path;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Mutex;
+use tracing::{info, warn};
+
+/// Number of files, symlinks included, and bytes below `dir`.
+fn totals(dir: &Path) -> anyhow::Result<(u64, u64)> {
+    let (mut files, mut bytes) = (0, 0);
+    let mut dirs = vec![dir.to_path_buf()];
+    while let Some(dir) = dirs.pop() {
+        for entry in std::fs::read_dir(dir)? {
+            let entry = entry?;
+            let file_type = entry.file_type()?;
+            if file_type.is_dir() {
+                dirs.push(entry.path());
+            } else {
+                files += 1;
+                if file_type.is_file() {
+                    bytes += entry.metadata()?.len();
+                }
+            }
+        }
+    }
+    Ok((files, bytes))
+}
+
+/// Writes the nodes with ids in `start..end`, stopping at the first that fails.
+fn emit_nodes(args: &Args, start: u64, end: u64) -> anyhow::Result<()> {
+    let next = AtomicU64::new(start);
+    let error = Mutex::new(None);
+    std::thread::scope(|scope| {
+        for _ in 0..WRITERS {
+            scope.spawn(|| loop {
+                let node_id = next.fetch_add(1, Ordering::Relaxed);
+                if node_id >= end || error.lock().unwrap().is_some() {
+                    break;
+                }
+                if let Err(e) = emit_build_file(node_id, args, false) {
+                    error.lock().unwrap().get_or_insert(e);
+                }
+            });
+        }
+    });
+    error.into_inner().unwrap().map_or(Ok(()), Err)
+}
+
+/// The height the graph of `args` fits the budgets in, writing it into `args.output`.
+fn probe(args: &Args) -> anyhow::Result<u32> {
+    prepare(args)?;
+    std::fs::remove_dir_all(&args.output).unwrap_or(());
+    std::fs::create_dir_all(&args.output)?;
+    if args.layout == layers::Layout::Symlinked {
+        layers::write_level_symlinks(args)?;
+    }
+    let mut start = 0;
+    for level in 0..=args.height {
+        let end = num_nodes_in_ntree(args.targets_per_level, level);
+        emit_nodes(args, start, end)?;
+        start = end;
+        let (files, bytes) = totals(&args.output)?;
+        if args.max_files.is_some_and(|max| files > max)
+            || args.max_bytes.is_some_and(|max| bytes > max)
+        {
+            info!(
+                "{} files and {} bytes after level {} of {}, over the budget",
+                files, bytes, level, args.height
+            );
+            return Ok(level.max(1));
+        }
+    }
+    Ok(args.height)
+}
+
+/// `args` with the height cut to what fits `--max-files` and `--max-bytes`, or as they are if
+/// the whole graph fits.
+pub fn fit(mut args: Args) -> anyhow::Result<Args> {
+    let output = args.output.clone();
+    let mut scratch = output.clone().into_os_string();
+    scratch.push(".budget");
+    args.output = scratch.into();
+    let height = probe(&args);
+    std::fs::remove_dir_all(&args.output).unwrap_or(());
+    args.output = output;
+    let height = height?;
+    if height == args.height {
+        return Ok(args);
+    }
+
+    warn!(
+        "the budget of --max-files and --max-bytes stops the graph at height {} of {}",
+        height, args.height
+    );
+    let mut argv = regen::without_flags(&args.command_line, &["height"]);
+    argv.push("--height".into());
+    argv.push(height.to_string().into());
+    let mut truncated = match Cli::try_parse_from(presets::expand(argv.clone())?)?.command {
+        Command::Generate(args) => args,
+        _ => bail!("--max-files and --max-bytes only apply to generate"),
+    };
+    truncated.command_line = argv;
+    truncated.requested_height = Some(args.height);
+    Ok(truncated)
+}
+
+/// The budgets of `args` and the graph they left, `None` without budgets.
+pub fn manifest(args: &Args) -> Option<Value> {
+    if args.max_files.is_none() && args.max_bytes.is_none() {
+        return None;
+    }
+    Some(json!({
+        "max_files": args.max_files,
+        "max_bytes": args.max_bytes,
+        "requested_height": args.requested_height.unwrap_or(args.height),
+        "height": args.height,
+        "truncated": args.requested_height.is_some(),
+        "libraries": num_nodes_in_ntree(args.targets_per_level, args.height) - 1,
+    }))
+}
@@ -0,0 +1,112 @@
+//! `--emit-both-dep-systems`: a sibling of the workspace declaring its rulesets in a
+//! `MODULE.bazel` instead of the `WORKSPACE`, to measure a bzlmod migration on the same code.
+//! Every other file of the sibling is a hardlink to the one of the workspace, so the second
+//! variant costs directories and inodes rather than another copy of the sources. Each variant
+//! pins its dependency system in its `.bazelrc`, whatever the default of the Bazel version, and
+//! its Bazel in its `.bazelversion`.
+
+use crate::{backend, starlark::quote, Args};
+use std::path::{Path, PathBuf};
+use tracing::info;
+
+/// Suffix of the sibling directory.
+const SIBLING_SUFFIX: &str = "_bzlmod";
+
+/// Bazel of the sibling, pinned apart from the one of the workspace because the registry versions
+/// of `modules` need Bazel 7.
+const BAZEL_VERSION: &str = "7.1.1";
+
+/// A Bazel Central Registry module of a ruleset of the `WORKSPACE`, as the module name, version
+/// and the repository name the `BUILD` files load it by.
+pub type Module = (&'static str, &'static str, &'static str);
+
+/// The `MODULE.bazel` of the sibling of the workspace of `args`.
+fn module_file(args: &Args) -> String {
+    let name = args.workspace_name.as_deref().unwrap_or("bazel_benchmark");
+    let mut content = format!(
+        "# The rulesets of the WORKSPACE of the other variant, at their Bazel Central Registry versions.\n\
+         module(name = {}, version = \"0.0.0\")\n\n",
+        quote(name)
+    );
+    let backend = backend::backend(args.language);
+    for (module, version, repo_name) in backend.modules(args) {
+        if module == repo_name {
+            content += &format!(
+                "bazel_dep(name = {}, version = {})\n",
+                quote(module),
+                quote(version)
+            );
+        } else {
+            content += &format!(
+                "bazel_dep(name = {}, version = {}, repo_name = {})\n",
+                quote(module),
+                quote(version),
+                quote(repo_name)
+            );
+        }
+    }
+    format!(
+        "{}\n",
+        (content + backend.module_extensions(args)).trim_end()
+    )
+}
+
+/// Recreates the tree `from` in `to` with hardlinks, copying symlinks as they are and leaving out
+/// the files `skip` names relative to the root.
+fn link_tree(from: &Path, to: &Path, root: &Path, skip: &[&str]) -> anyhow::Result<()> {
+    std::fs::create_dir_all(to)?;
+    for entry in std::fs::read_dir(from)? {
+        let entry = entry?;
+        let file_type = entry.file_type()?;
+        let target = to.join(entry.file_name());
+        if file_type.is_dir() {
+            link_tree(&entry.path(), &target, root, skip)?;
+        } else if file_type.is_symlink() {
+            std::os::unix::fs::symlink(std::fs::read_link(entry.path())?, &target)?;
+        } else if !skip.iter().any(|name| entry.path() == root.join(name)) {
+            std::fs::hard_link(entry.path(), &target)?;
+        }
+    }
+    Ok(())
+}
+
+/// Pins the WORKSPACE dependency system in the `.bazelrc` of the workspace of `args` and writes
+/// its bzlmod sibling next to it, returning where.
+pub fn write_sibling(args: &Args) -> anyhow::Result<PathBuf> {
+    let mut sibling = args.output.clone().into_os_string();
+    sibling.push(SIBLING_SUFFIX);
+    let sibling = PathBuf::from(sibling);
+    std::fs::remove_dir_all(&sibling).unwrap_or(());
+
+    let bazelrc = std::fs::read_to_string(args.output.join(".bazelrc"))?;
+    link_tree(
+        &args.output,
+        &sibling,
+        &args.output,
+        &[".bazelrc", ".bazelversion", "WORKSPACE"],
+    )?;
+    std::fs::write(
+        args.output.join(".bazelrc"),
+        format!("{}common --noexperimental_enable_bzlmod\n", bazelrc),
+    )?;
+    std::fs::write(
+        sibling.join(".bazelrc"),
+        format!("{}common --enable_bzlmod\n", bazelrc),
+    )?;
+    std::fs::write(
+        sibling.join(".bazelversion"),
+        format!("{}\n", BAZEL_VERSION),
+    )?;
+    // Older Bazel versions only find the root of the workspace by its WORKSPACE.
+    std::fs::write(
+        sibling.join("WORKSPACE"),
+        "# Dependencies are declared in MODULE.bazel.\n",
+    )?;
+    std::fs::write(sibling.join("MODULE.bazel"), module_file(args))?;
+    info!(
+        "wrote the bzlmod variant of {} to {}",
+        args.output.display(),
+        sibling.display()
+    );
+    Ok(sibling)
+}
@@ -0,0 +1,220 @@
+//! `calibrate` subcommand: repeatedly generates and cleanly builds a workspace, tuning its size
+//! and per-action cost until the build profile roughly matches the one of a real repository, so
+//! the generated workspace can stand in for it.
+
+use crate::archive::OutputFormat;
+use crate::graph::num_nodes_in_ntree;
+use crate::report::{print_table, summarize, Summary};
+use crate::rust::CompileCost;
+use crate::{generate, regen, Args, Language};
+use anyhow::bail;
+use clap::{IntoApp, Parser};
+use std::ffi::OsString;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use std::sync::Arc;
+
+/// Tune the generation parameters until a clean build matches a real Bazel JSON trace profile
+#[derive(Parser, Debug)]
+pub struct CalibrateArgs {
+    /// Parameters to start from. Everything but the targets per level, the files per target and
+    /// the rust compile cost is kept as given
+    #[clap(flatten)]
+    pub generate: Args,
+
+    /// Profile of a clean build of the real repository, optionally gzipped
+    #[clap(long)]
+    target_profile: PathBuf,
+
+    /// Bazel binary to build the generated workspace with
+    #[clap(long, default_value = "bazel")]
+    bazel: String,
+
+    /// Target pattern to build
+    #[clap(long, default_value = "//...")]
+    targets: String,
+
+    /// Maximum number of generate and build rounds
+    #[clap(long, default_value = "5")]
+    iterations: u64,
+
+    /// Relative difference of the action count and the execution phase to the target profile
+    /// below which calibration stops
+    #[clap(long, default_value = "0.1")]
+    tolerance: f64,
+}
+
+/// Indices of the analysis and execution phases in `Summary::phases`.
+const ANALYSIS: usize = 2;
+const EXECUTION: usize = 4;
+
+fn run_bazel(bazel: &str, workspace: &Path, bazel_args: &[&str]) -> anyhow::Result<()> {
+    println!("running {} {}", bazel, bazel_args.join(" "));
+    let status = Command::new(bazel)
+        .args(bazel_args)
+        .current_dir(workspace)
+        .status()?;
+    if !status.success() {
+        bail!("{} {} failed with {}", bazel, bazel_args[0], status);
+    }
+    Ok(())
+}
+
+/// Execution time per action, the part of the execution phase that doesn't scale with the
+/// number of libraries.
+fn per_action(summary: &Summary) -> f64 {
+    summary.phases[EXECUTION].1 / summary.actions.max(1) as f64
+}
+
+/// The `generate` flags calibration tunes, as given on the command line.
+fn tuned_flags(args: &Args) -> String {
+    let files = match &args.files_per_target {
+        crate::FilesPerTarget::PerLevel(counts) => counts
+            .iter()
+            .map(u64::to_string)
+            .collect::<Vec<_>>()
+            .join(","),
+        crate::FilesPerTarget::Roles { root, mid, leaf } => {
+            format!("root:{},mid:{},leaf:{}", root, mid, leaf)
+        }
+    };
+    let mut flags = format!(
+        "--targets-per-level {} --files-per-target {}",
+        args.targets_per_level, files
+    );
+    if args.language == Language::RUST {
+        let cost = match args.compile_cost {
+            CompileCost::Low => "low",
+            CompileCost::Medium => "medium",
+            CompileCost::High => "high",
+        };
+        flags.push_str(&format!(" --compile-cost {}", cost));
+    }
+    flags
+}
+
+/// The `generate` command line of the workspaces of the `calibrate` command line `argv`, which the
+/// metadata records.
+pub fn generate_command_line(argv: &[OsString]) -> Vec<OsString> {
+    let mut argv = regen::without_flags_of(
+        &CalibrateArgs::into_app(),
+        argv,
+        &[
+            "target-profile",
+            "bazel",
+            "targets",
+            "iterations",
+            "tolerance",
+        ],
+    );
+    if let Some(command) = argv.iter_mut().find(|arg| *arg == "calibrate") {
+        *command = "generate".into();
+    }
+    argv
+}
+
+/// Moves the parameters of `args` towards `target` from the `measured` build of them: the number
+/// of libraries follows the action count, and the cost of every action first the rust compile
+/// cost and then the files per target. The command line of `args` gets the tuned flags.
+fn tune(args: &mut Args, measured: &Summary, target: &Summary) {
+    let actions = target.actions as f64 / measured.actions.max(1) as f64;
+    let targets_per_level =
+        (args.targets_per_level as f64 * actions.powf(1.0 / args.height as f64)).round();
+    args.targets_per_level = (targets_per_level as u64).max(2);
+
+    let cost = per_action(target) / per_action(measured).max(f64::MIN_POSITIVE);
+    let next_cost = match args.compile_cost {
+        CompileCost::Low => Some(CompileCost::Medium),
+        CompileCost::Medium => Some(CompileCost::High),
+        CompileCost::High => None,
+    };
+    match next_cost {
+        Some(next_cost) if args.language == Language::RUST && cost > 2.0 => {
+            args.compile_cost = next_cost;
+        }
+        _ => args.files_per_target = args.files_per_target.scaled(cost),
+    }
+
+    let mut argv = regen::without_flags(
+        &args.command_line,
+        &["targets-per-level", "files-per-target", "compile-cost"],
+    );
+    argv.extend(tuned_flags(args).split(' ').map(OsString::from));
+    args.command_line = argv;
+}
+
+pub async fn run(args: CalibrateArgs) -> anyhow::Result<()> {
+    if args.generate.output_format != OutputFormat::Dir {
+        bail!("calibrate needs the workspace as a directory, --output-format has to be dir");
+    }
+    let target = summarize(&args.target_profile)?;
+    let profiles_dir = std::env::temp_dir().join("gen_bazel_benchmark_calibrate");
+    std::fs::create_dir_all(&profiles_dir)?;
+
+    let mut header = vec!["".to_string()];
+    let mut rows = vec![
+        vec!["libraries".to_string()],
+        vec!["actions".to_string()],
+        vec!["analysis (ms)".to_string()],
+        vec!["execution (ms)".to_string()],
+        vec!["per action (ms)".to_string()],
+    ];
+    let mut add_column = |title: String, libraries: String, summary: &Summary| {
+        header.push(title);
+        let cells = [
+            libraries,
+            summary.actions.to_string(),
+            format!("{:.0}", summary.phases[ANALYSIS].1),
+            format!("{:.0}", summary.phases[EXECUTION].1),
+            format!("{:.2}", per_action(summary)),
+        ];
+        for (row, cell) in rows.iter_mut().zip(cells) {
+            row.push(cell);
+        }
+    };
+    add_column("target".to_string(), "".to_string(), &target);
+
+    let mut generate_args = Arc::new(args.generate);
+    let mut calibrated = None;
+    for iteration in 1..=args.iterations {
+        generate(generate_args.clone(), None).await?;
+        let workspace = generate_args.output.canonicalize()?;
+        let profile = profiles_dir.join(format!("iteration_{}.profile.gz", iteration));
+        let profile_flag = format!("--profile={}", profile.display());
+        run_bazel(&args.bazel, &workspace, &["clean", "--expunge"])?;
+        run_bazel(
+            &args.bazel,
+            &workspace,
+            &["build", &profile_flag, "--", &args.targets],
+        )?;
+        let measured = summarize(&profile)?;
+
+        let libraries =
+            num_nodes_in_ntree(generate_args.targets_per_level, generate_args.height) - 1;
+        add_column(format!("#{}", iteration), libraries.to_string(), &measured);
+        println!("{}: {}", iteration, tuned_flags(&generate_args));
+
+        let off = |measured: f64, target: f64| (measured - target).abs() / target.max(1.0);
+        if off(measured.actions as f64, target.actions as f64) <= args.tolerance
+            && off(measured.phases[EXECUTION].1, target.phases[EXECUTION].1) <= args.tolerance
+        {
+            calibrated = Some(tuned_flags(&generate_args));
+            break;
+        }
+        let params = Arc::get_mut(&mut generate_args).unwrap();
+        tune(params, &measured, &target);
+    }
+    run_bazel(&args.bazel, &generate_args.output, &["shutdown"])?;
+
+    print_table(&header, &rows);
+    match calibrated {
+        Some(flags) => println!("calibrated within {}: {}", args.tolerance, flags),
+        None => println!(
+            "not within {} after {} iterations, continue from {}",
+            args.tolerance,
+            args.iterations,
+            tuned_flags(&generate_args)
+        ),
+    }
+    Ok(())
+}
@@ -0,0 +1,79 @@
+//! `churn` subcommand: keeps modifying seeded random sources of a generated workspace at a fixed
+//! rate, for benchmarks of file watchers like watchman, `--watchfs` or the ones of IDEs. The same
+//! seed, rate and duration modify the same files in the same order.
+
+use crate::{mutate, node_fraction};
+use anyhow::bail;
+use clap::Parser;
+use std::io::Write;
+use std::path::PathBuf;
+use std::time::{Duration, Instant};
+
+const CHURN_SALT: u64 = 15;
+
+/// Continuously modify random sources of a generated workspace
+#[derive(Parser, Debug)]
+pub struct ChurnArgs {
+    /// Generated workspace to modify
+    #[clap(long)]
+    workspace: PathBuf,
+
+    /// Files to modify per second
+    #[clap(long, default_value = "10")]
+    rate: f64,
+
+    /// Seconds to keep modifying files for
+    #[clap(long, default_value = "60")]
+    duration: u64,
+
+    /// Seed of the files picked
+    #[clap(long, default_value = "0")]
+    seed: u64,
+}
+
+pub fn run(args: &ChurnArgs) -> anyhow::Result<()> {
+    if args.rate <= 0.0 || !args.rate.is_finite() {
+        bail!("--rate must be positive, got {}", args.rate);
+    }
+    let files = mutate::source_files(&args.workspace)?;
+    if files.is_empty() {
+        bail!(
+            "{} has no sources, is it a generated workspace?",
+            args.workspace.display()
+        );
+    }
+    println!(
+        "modifying {} files per second out of {} for {}s",
+        args.rate,
+        files.len(),
+        args.duration
+    );
+
+    let start = Instant::now();
+    let end = start + Duration::from_secs(args.duration);
+    let mut count = 0u64;
+    loop {
+        // Every modification has its own slot, so slow ones don't lower the rate.
+        let due = start + Duration::from_secs_f64(count as f64 / args.rate);
+        if due >= end {
+            break;
+        }
+        std::thread::sleep(due.saturating_duration_since(Instant::now()));
+        let pick = node_fraction(count ^ args.seed.wrapping_mul(0x1000_0000_01B3), CHURN_SALT);
+        let file = &files[(pick * files.len() as f64) as usize];
+        let mut f = std::fs::OpenOptions::new().append(true).open(file)?;
+        writeln!(
+            f,
+            "{} churn {}",
+            mutate::comment_prefix(file).unwrap(),
+            count
+        )?;
+        count += 1;
+    }
+    println!(
+        "modified {} files in {:.1}s",
+        count,
+        start.elapsed().as_secs_f64()
+    );
+    Ok(())
+}
@@ -0,0 +1,182 @@
+//! CMake build of the objc library graph, so the same sources can be built with CMake (and its
+//! Ninja or Xcode generators) for comparison. Every library gets a `CMakeLists.txt` next to its
+//! BUILD file, adding the directories of its dependencies before its own target.
+
+use crate::graph::ID;
+use crate::{apple, layers, Args, Pch};
+use itertools::Itertools;
+use std::io::{BufWriter, Write};
+use std::path::Path;
+
+/// Directory, relative to the output, holding the `<Module>/<Module>/` header trees that CMake
+/// targets use as include directories, mirroring the header layout rules_ios provides.
+pub const HEADERS_DIR: &str = "cmake_include";
+
+fn relative(path: &Path, args: &Args) -> String {
+    path.strip_prefix(&args.output)
+        .unwrap()
+        .to_str()
+        .unwrap()
+        .to_string()
+}
+
+/// Links the headers of the library in `lib_dir` into its include directory and gives them a
+/// module map, so that both `@import` and `#import <Module/Header.h>` resolve. Returns the
+/// include directory relative to the output.
+fn write_headers(node: &ID, args: &Args, lib_dir: &Path) -> anyhow::Result<String> {
+    let include_dir = Path::new(HEADERS_DIR).join(node.lib_name());
+    let module_dir = args.output.join(&include_dir).join(node.lib_name());
+    // Left over by an interrupted run when resuming
+    std::fs::remove_dir_all(&module_dir).unwrap_or(());
+    std::fs::create_dir_all(&module_dir)?;
+
+    let to_root = "../".repeat(include_dir.components().count() + 1);
+    for header in layers::files_with_extension(lib_dir, "h")? {
+        std::os::unix::fs::symlink(
+            Path::new(&to_root).join(relative(&header, args)),
+            module_dir.join(header.file_name().unwrap()),
+        )?;
+    }
+    std::fs::write(
+        module_dir.join("module.modulemap"),
+        format!(
+            "module {} {{\n    umbrella \".\"\n    export *\n    module * {{ export * }}\n}}\n",
+            node.lib_name()
+        ),
+    )?;
+
+    Ok(include_dir.to_str().unwrap().to_string())
+}
+
+fn add_subdirectories(f: &mut impl Write, nodes: &[ID], args: &Args) -> anyhow::Result<()> {
+    for node in nodes {
+        let dir = relative(&layers::lib_dir(node, args), args);
+        writeln!(
+            f,
+            "add_subdirectory(\"${{CMAKE_SOURCE_DIR}}/{0}\" \"${{CMAKE_BINARY_DIR}}/{0}\")",
+            dir
+        )?;
+    }
+    Ok(())
+}
+
+/// Writes the `CMakeLists.txt` of the library of `node`, linking against `deps`. Has to run after
+/// the sources of the library are written.
+pub fn write_library(node: &ID, args: &Args, lib_dir: &Path, deps: &[ID]) -> anyhow::Result<()> {
+    let mut f = BufWriter::new(std::fs::File::create(lib_dir.join("CMakeLists.txt"))?);
+    let name = node.lib_name();
+    add_subdirectories(&mut f, &node.children(), args)?;
+
+    let sources = layers::files_with_extension(lib_dir, "m")?;
+    if sources.is_empty() {
+        // Prebuilt framework
+        writeln!(
+            f,
+            "add_library({0} INTERFACE)\n\
+             target_compile_options({0} INTERFACE \"-F${{CMAKE_CURRENT_SOURCE_DIR}}\")\n\
+             target_link_options({0} INTERFACE \"-F${{CMAKE_CURRENT_SOURCE_DIR}}\" \"SHELL:-framework {0}\")",
+            name
+        )
+        ?;
+        return Ok(());
+    }
+
+    let kind = if args.linkage.is_dynamic(node) {
+        "SHARED"
+    } else {
+        "STATIC"
+    };
+    writeln!(
+        f,
+        "add_library({} {}\n{}\n)",
+        name,
+        kind,
+        sources
+            .iter()
+            .map(|s| format!("    \"{}\"", s.file_name().unwrap().to_str().unwrap()))
+            .join("\n")
+    )?;
+    writeln!(
+        f,
+        "target_include_directories({} PUBLIC \"${{CMAKE_SOURCE_DIR}}/{}\")",
+        name,
+        write_headers(node, args, lib_dir)?
+    )?;
+    if !deps.is_empty() {
+        writeln!(
+            f,
+            "target_link_libraries({} PUBLIC {})",
+            name,
+            deps.iter().map(ID::lib_name).join(" ")
+        )?;
+    }
+
+    match args.pch {
+        Pch::None => {}
+        Pch::Shared => writeln!(
+            f,
+            "target_precompile_headers({} PRIVATE \"${{CMAKE_SOURCE_DIR}}/tools/pch/{}\")",
+            name,
+            apple::SHARED_PCH
+        )
+        .unwrap(),
+        Pch::PerTarget => writeln!(
+            f,
+            "target_precompile_headers({0} PRIVATE \"{0}_Prefix.pch\")",
+            name
+        )
+        .unwrap(),
+    }
+    let copts = layers::generated_flags(args, args.copts_per_target, |i, value| {
+        format!("-DBENCH_COPT_{}={}", i, value)
+    });
+    if !copts.is_empty() {
+        writeln!(
+            f,
+            "target_compile_options({} PRIVATE {})",
+            name,
+            copts.join(" ")
+        )?;
+    }
+    let defines = layers::generated_flags(args, args.defines_per_target, |i, value| {
+        format!("BENCH_DEFINE_{}={}", i, value)
+    });
+    if !defines.is_empty() {
+        writeln!(
+            f,
+            "target_compile_definitions({} PUBLIC {})",
+            name,
+            defines.join(" ")
+        )?;
+    }
+    Ok(())
+}
+
+/// Writes the top level `CMakeLists.txt`, with an executable for every application linking its
+/// first-level libraries.
+pub fn write_root(args: &Args, apps: &[(String, Vec<ID>)]) -> anyhow::Result<()> {
+    let mut f = BufWriter::new(std::fs::File::create(args.output.join("CMakeLists.txt"))?);
+    writeln!(
+        f,
+        "cmake_minimum_required(VERSION 3.16)\n\
+         project(Benchmark LANGUAGES OBJC)\n\
+         \n\
+         set(CMAKE_OBJC_FLAGS \"${{CMAKE_OBJC_FLAGS}} -fmodules -fobjc-arc\")\n"
+    )?;
+
+    let first_level: Vec<_> = (1..=args.targets_per_level)
+        .map(|i| ID::new(i, args.targets_per_level, args.height as u64))
+        .collect();
+    add_subdirectories(&mut f, &first_level, args)?;
+
+    for (name, deps) in apps {
+        writeln!(
+            f,
+            "\nadd_executable({0} {1})\ntarget_link_libraries({0} PRIVATE {2})",
+            name,
+            apple::main_source(name, args),
+            deps.iter().map(ID::lib_name).join(" ")
+        )?;
+    }
+    Ok(())
+}
@@ -0,0 +1,171 @@
+//! `--emit-env`: a definition of the environment to benchmark the workspace in, so the machines
+//! running it agree on more than the workspace. Bazel is pinned by the `.bazelversion` that
+//! bazelisk reads, the rest of the tools are the ones the workspace and its scripts run: a nix
+//! flake shell, whose `flake.lock` pins the packages once it's committed, or a devcontainer
+//! image. Xcode can't be installed by either, the nix shell checks for the selected one on macOS.
+
+use crate::{backend, Args, Language};
+use anyhow::bail;
+use std::fmt::Write;
+use std::str::FromStr;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum EnvKind {
+    Nix,
+    Devcontainer,
+}
+
+impl FromStr for EnvKind {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "nix" => Ok(EnvKind::Nix),
+            "devcontainer" => Ok(EnvKind::Devcontainer),
+            _ => bail!("unknown environment {}, expected nix or devcontainer", s),
+        }
+    }
+}
+
+/// Release of bazelisk the devcontainer installs as `bazel`.
+const BAZELISK_VERSION: &str = "v1.19.0";
+
+/// Release of buildtools the devcontainer takes buildifier from.
+const BUILDTOOLS_VERSION: &str = "v6.4.0";
+
+/// Tools the workspace of `args` needs besides Bazel, as nixpkgs attributes and Debian packages.
+fn tools(args: &Args) -> Vec<(&'static str, &'static str)> {
+    // run_benchmarks.sh and the scenarios.
+    let mut tools = vec![
+        ("bash", "bash"),
+        ("coreutils", "coreutils"),
+        ("gnused", "sed"),
+    ];
+    tools.extend(backend::backend(args.language).env_tools(args));
+    if args.init_git {
+        tools.push(("git", "git"));
+    }
+    if args.vendor_deps.is_some() {
+        tools.push(("curl", "curl"));
+    }
+    tools
+}
+
+/// The `flake.nix` of the workspace of `args`.
+fn flake(args: &Args) -> String {
+    let mut packages = String::new();
+    for (package, _) in tools(args) {
+        writeln!(packages, "            {}", package).unwrap();
+    }
+    if args.buildifier {
+        packages += "            bazel-buildtools\n";
+    }
+    let mut shell_hook = String::new();
+    if args.language == Language::OBJC && !args.analysis_only {
+        let wanted = match &args.hermetic_apple_toolchain {
+            Some(version) => format!(
+                r#"
+            case "$(xcodebuild -version 2>/dev/null | head -n 1)" in
+              "Xcode {version}"*) ;;
+              *) echo "the workspace pins Xcode {version}, select it with xcode-select" >&2 ;;
+            esac"#,
+                version = version
+            ),
+            None => String::new(),
+        };
+        shell_hook = format!(
+            r#"
+          shellHook = ''
+            if ! xcode-select -p >/dev/null 2>&1; then
+              echo "the workspace builds with Xcode, install it and select it with xcode-select" >&2
+            fi{}
+          '';"#,
+            wanted
+        );
+    }
+    format!(
+        r#"# The environment to benchmark the workspace in: `nix develop`. Commit the flake.lock of
+# the first run to pin the packages, Bazel is pinned by .bazelversion.
+{{
+  inputs.nixpkgs.url = "github:NixOS/nixpkgs/nixos-23.11";
+  inputs.flake-utils.url = "github:numtide/flake-utils";
+
+  outputs = {{ nixpkgs, flake-utils, ... }}:
+    flake-utils.lib.eachDefaultSystem (system:
+      let
+        pkgs = nixpkgs.legacyPackages.${{system}};
+        bazel = pkgs.writeShellScriptBin "bazel" ''exec ${{pkgs.bazelisk}}/bin/bazelisk "$@"'';
+      in
+      {{
+        devShells.default = pkgs.mkShell {{
+          packages = with pkgs; [
+            bazel
+{packages}          ];{shell_hook}
+        }};
+      }});
+}}
+"#,
+        packages = packages,
+        shell_hook = shell_hook
+    )
+}
+
+/// The `Dockerfile` of the devcontainer of the workspace of `args`.
+fn dockerfile(args: &Args) -> String {
+    let mut packages = vec!["build-essential", "ca-certificates", "curl"];
+    packages.extend(tools(args).into_iter().map(|(_, package)| package));
+    packages.sort();
+    packages.dedup();
+    let mut content = format!(
+        r#"# The environment to benchmark the workspace in. Bazel is pinned by .bazelversion.
+FROM ubuntu:22.04
+RUN apt-get update \
+    && DEBIAN_FRONTEND=noninteractive apt-get install -y --no-install-recommends \
+       {} \
+    && rm -rf /var/lib/apt/lists/*
+RUN curl -fL -o /usr/local/bin/bazel \
+       https://github.com/bazelbuild/bazelisk/releases/download/{}/bazelisk-linux-amd64 \
+    && chmod +x /usr/local/bin/bazel
+"#,
+        packages.join(" "),
+        BAZELISK_VERSION
+    );
+    if args.buildifier {
+        content += &format!(
+            r#"RUN curl -fL -o /usr/local/bin/buildifier \
+       https://github.com/bazelbuild/buildtools/releases/download/{}/buildifier-linux-amd64 \
+    && chmod +x /usr/local/bin/buildifier
+"#,
+            BUILDTOOLS_VERSION
+        );
+    }
+    content
+}
+
+/// The `devcontainer.json` of the workspace of `args`.
+fn devcontainer(args: &Args) -> String {
+    let name = args.workspace_name.as_deref().unwrap_or("bazel_benchmark");
+    let config = serde_json::json!({
+        "name": name,
+        "build": { "dockerfile": "Dockerfile" },
+        // Downloads the Bazel of .bazelversion into the image's cache.
+        "postCreateCommand": "bazel version",
+    });
+    serde_json::to_string_pretty(&config).unwrap() + "\n"
+}
+
+/// Writes the `--emit-env` environment definitions of `args`.
+pub fn write(args: &Args) -> anyhow::Result<()> {
+    for kind in &args.emit_env {
+        match kind {
+            EnvKind::Nix => std::fs::write(args.output.join("flake.nix"), flake(args))?,
+            EnvKind::Devcontainer => {
+                let dir = args.output.join(".devcontainer");
+                std::fs::create_dir_all(&dir)?;
+                std::fs::write(dir.join("Dockerfile"), dockerfile(args))?;
+                std::fs::write(dir.join("devcontainer.json"), devcontainer(args))?;
+            }
+        }
+    }
+    Ok(())
+}
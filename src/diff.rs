@@ -0,0 +1,367 @@
+//! `diff` subcommand: how the workload changed between two generated workspaces or two
+//! `--export-graph` files, as the targets, dependency edges and files only one of them has or the
+//! two have differently. After tweaking the flags of the generation it tells what a benchmark
+//! delta may come from before it gets attributed to Bazel. Workspaces are compared through the
+//! model rebuilt from their metadata, like for `regen`, and their files; graph files only have
+//! targets and edges.
+
+use crate::graph::{num_nodes_in_ntree, ID};
+use crate::{layers, metrics, prepare, regen, summary};
+use anyhow::format_err;
+use clap::Parser;
+use serde_json::{json, Map, Value};
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt::Display;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+
+/// Compare the targets, dependency edges and files of two generated workspaces or
+/// `--export-graph` files
+#[derive(Parser, Debug)]
+pub struct DiffArgs {
+    /// Workspace before the change, with its `BENCHMARK_METADATA.json`, or `--export-graph` file
+    before: PathBuf,
+
+    /// Workspace or `--export-graph` file after the change. Files are only compared between two
+    /// workspaces
+    after: PathBuf,
+
+    /// Print the differences as JSON instead, every one of them listed
+    #[clap(long)]
+    json: bool,
+
+    /// Number of differences listed of every kind in the text output, the others are only
+    /// counted
+    #[clap(long, default_value = "20")]
+    max_listed: usize,
+}
+
+/// The workload of a workspace or graph file.
+struct Workload {
+    /// Generate flags by long name, the output left out.
+    parameters: Map<String, Value>,
+    /// Dependencies of every target by label, the root as `//`.
+    targets: BTreeMap<String, BTreeSet<String>>,
+    /// Labels of the libraries by their package directory, relative to the workspace.
+    packages: BTreeMap<PathBuf, String>,
+    /// Digests of the files by path relative to the workspace, `None` for graph files.
+    files: Option<BTreeMap<PathBuf, u64>>,
+}
+
+impl Workload {
+    fn read(path: &Path) -> anyhow::Result<Workload> {
+        if path.is_dir() {
+            Self::read_workspace(path)
+        } else {
+            Self::read_graph(path)
+        }
+    }
+
+    fn read_workspace(workspace: &Path) -> anyhow::Result<Workload> {
+        let (metadata, _) = summary::read_metadata(workspace)?;
+        let mut parameters = metadata["parameters"]
+            .as_object()
+            .cloned()
+            .unwrap_or_default();
+        parameters.remove("output");
+        let mut args = regen::workspace_args(workspace)?;
+        args.quiet = true;
+        prepare(&args)?;
+
+        let label = |id: u64| match id {
+            0 => "//".to_string(),
+            _ => layers::label(
+                &ID::new(id, args.targets_per_level, args.height as u64),
+                &args,
+            ),
+        };
+        let mut targets = BTreeMap::new();
+        let mut packages = BTreeMap::new();
+        for id in 0..num_nodes_in_ntree(args.targets_per_level, args.height) {
+            let deps = metrics::dependencies(id, &args).into_iter().map(label);
+            targets.insert(label(id), deps.collect());
+            if id > 0 {
+                let node = ID::new(id, args.targets_per_level, args.height as u64);
+                let dir = layers::lib_dir(&node, &args);
+                packages.insert(dir.strip_prefix(&args.output)?.to_path_buf(), label(id));
+            }
+        }
+        Ok(Workload {
+            parameters,
+            targets,
+            packages,
+            files: Some(summary::file_digests(workspace)?),
+        })
+    }
+
+    fn read_graph(path: &Path) -> anyhow::Result<Workload> {
+        let content =
+            std::fs::read_to_string(path).map_err(|e| format_err!("{}: {}", path.display(), e))?;
+        let graph: Value = serde_json::from_str(&content)
+            .map_err(|e| format_err!("{} isn't valid JSON: {}", path.display(), e))?;
+        let nodes = graph["nodes"].as_array().ok_or_else(|| {
+            format_err!(
+                "{} is neither a workspace nor an --export-graph file",
+                path.display()
+            )
+        })?;
+        let labels: Vec<String> = nodes
+            .iter()
+            .map(|node| node["label"].as_str().unwrap_or("//").to_string())
+            .collect();
+        let mut targets = BTreeMap::new();
+        for (node, label) in nodes.iter().zip(&labels) {
+            let deps = node["deps"].as_array().into_iter().flatten();
+            let deps = deps
+                .map(|dep| {
+                    dep.as_u64()
+                        .and_then(|id| labels.get(id as usize))
+                        .cloned()
+                        .ok_or_else(|| {
+                            format_err!("{} has a bad dependency {}", path.display(), dep)
+                        })
+                })
+                .collect::<anyhow::Result<_>>()?;
+            targets.insert(label.clone(), deps);
+        }
+        let mut parameters = Map::new();
+        parameters.insert("height".to_string(), graph["height"].clone());
+        parameters.insert(
+            "targets-per-level".to_string(),
+            graph["targets_per_level"].clone(),
+        );
+        Ok(Workload {
+            parameters,
+            targets,
+            packages: BTreeMap::new(),
+            files: None,
+        })
+    }
+
+    /// The library whose package has `file`, `None` for files outside of the libraries.
+    fn owner(&self, file: &Path) -> Option<&String> {
+        file.ancestors()
+            .skip(1)
+            .find_map(|dir| self.packages.get(dir))
+    }
+}
+
+/// What only `before` has, what only `after` has and the keys both have with different values.
+struct Changes<T> {
+    removed: Vec<T>,
+    added: Vec<T>,
+    changed: Vec<T>,
+}
+
+impl<T: Ord + Clone> Changes<T> {
+    fn of<V: PartialEq>(before: &BTreeMap<T, V>, after: &BTreeMap<T, V>) -> Self {
+        Changes {
+            removed: before
+                .keys()
+                .filter(|k| !after.contains_key(k))
+                .cloned()
+                .collect(),
+            added: after
+                .keys()
+                .filter(|k| !before.contains_key(k))
+                .cloned()
+                .collect(),
+            changed: before
+                .iter()
+                .filter(|(k, v)| after.get(k).is_some_and(|other| other != *v))
+                .map(|(k, _)| k.clone())
+                .collect(),
+        }
+    }
+}
+
+/// Edges of `targets` as `(from, to)` labels.
+fn edges(targets: &BTreeMap<String, BTreeSet<String>>) -> BTreeSet<(String, String)> {
+    targets
+        .iter()
+        .flat_map(|(from, deps)| deps.iter().map(move |to| (from.clone(), to.clone())))
+        .collect()
+}
+
+/// Writes at most `max` of `entries` to `out`, marked with `sign`.
+fn list<T: Display>(
+    out: &mut impl Write,
+    sign: char,
+    entries: &[T],
+    max: usize,
+) -> std::io::Result<()> {
+    for entry in entries.iter().take(max) {
+        writeln!(out, "  {} {}", sign, entry)?;
+    }
+    if entries.len() > max {
+        writeln!(out, "  {} ... and {} more", sign, entries.len() - max)?;
+    }
+    Ok(())
+}
+
+/// Prints the differences of `diff`. A reader closing the output early, like `head`, ends the
+/// listing without an error.
+pub fn run(diff: &DiffArgs) -> anyhow::Result<()> {
+    let mut out = std::io::stdout().lock();
+    match compare(diff, &mut out) {
+        Err(e)
+            if e.downcast_ref::<std::io::Error>()
+                .is_some_and(|e| e.kind() == std::io::ErrorKind::BrokenPipe) =>
+        {
+            Ok(())
+        }
+        result => result,
+    }
+}
+
+fn compare(diff: &DiffArgs, out: &mut impl Write) -> anyhow::Result<()> {
+    let before = Workload::read(&diff.before)?;
+    let after = Workload::read(&diff.after)?;
+
+    let parameters = Changes::of(
+        &before.parameters.clone().into_iter().collect(),
+        &after.parameters.clone().into_iter().collect(),
+    );
+    let mut targets = Changes::of(&before.targets, &after.targets);
+    let (before_edges, after_edges) = (edges(&before.targets), edges(&after.targets));
+    let removed_edges: Vec<_> = before_edges.difference(&after_edges).collect();
+    let added_edges: Vec<_> = after_edges.difference(&before_edges).collect();
+    let files = match (&before.files, &after.files) {
+        (Some(before_files), Some(after_files)) => {
+            let files = Changes::of(before_files, after_files);
+            // A library whose files changed changed too, even with the same dependencies.
+            let edited: BTreeSet<_> = files
+                .changed
+                .iter()
+                .filter_map(|file| before.owner(file))
+                .chain(files.removed.iter().filter_map(|file| before.owner(file)))
+                .chain(files.added.iter().filter_map(|file| after.owner(file)))
+                .filter(|label| {
+                    before.targets.contains_key(*label) && after.targets.contains_key(*label)
+                })
+                .cloned()
+                .collect();
+            targets.changed = targets
+                .changed
+                .into_iter()
+                .chain(edited)
+                .collect::<BTreeSet<_>>()
+                .into_iter()
+                .collect();
+            Some(files)
+        }
+        _ => None,
+    };
+
+    if diff.json {
+        let value = |name: &String, parameters: &Map<String, Value>| {
+            parameters.get(name).cloned().unwrap_or(Value::Null)
+        };
+        let paths = |paths: &[PathBuf]| -> Vec<String> {
+            paths.iter().map(|p| p.display().to_string()).collect()
+        };
+        let report = json!({
+            "parameters": parameters
+                .removed
+                .iter()
+                .chain(&parameters.added)
+                .chain(&parameters.changed)
+                .map(|name| (name.clone(), json!({
+                    "before": value(name, &before.parameters),
+                    "after": value(name, &after.parameters),
+                })))
+                .collect::<Map<_, _>>(),
+            "targets": {
+                "added": targets.added,
+                "removed": targets.removed,
+                "changed": targets.changed,
+            },
+            "edges": {
+                "added": added_edges,
+                "removed": removed_edges,
+            },
+            "files": files.as_ref().map(|files| json!({
+                "added": paths(&files.added),
+                "removed": paths(&files.removed),
+                "changed": paths(&files.changed),
+            })),
+        });
+        writeln!(out, "{}", serde_json::to_string_pretty(&report)?)?;
+        return Ok(());
+    }
+
+    let max = diff.max_listed;
+    let mut names: Vec<_> = parameters
+        .removed
+        .iter()
+        .chain(&parameters.added)
+        .chain(&parameters.changed)
+        .collect();
+    names.sort();
+    writeln!(out, "parameters: {} changed", names.len())?;
+    let lines: Vec<_> = names
+        .iter()
+        .map(|name| {
+            let value = |parameters: &Map<String, Value>| {
+                parameters
+                    .get(*name)
+                    .map_or("unset".to_string(), Value::to_string)
+            };
+            format!(
+                "--{}: {} -> {}",
+                name,
+                value(&before.parameters),
+                value(&after.parameters)
+            )
+        })
+        .collect();
+    list(out, '~', &lines, max)?;
+    writeln!(
+        out,
+        "targets: {} added, {} removed, {} changed",
+        targets.added.len(),
+        targets.removed.len(),
+        targets.changed.len()
+    )?;
+    list(out, '+', &targets.added, max)?;
+    list(out, '-', &targets.removed, max)?;
+    list(out, '~', &targets.changed, max)?;
+    writeln!(
+        out,
+        "edges: {} added, {} removed",
+        added_edges.len(),
+        removed_edges.len()
+    )?;
+    let edge = |(from, to): &&(String, String)| format!("{} -> {}", from, to);
+    list(
+        out,
+        '+',
+        &added_edges.iter().map(edge).collect::<Vec<_>>(),
+        max,
+    )?;
+    list(
+        out,
+        '-',
+        &removed_edges.iter().map(edge).collect::<Vec<_>>(),
+        max,
+    )?;
+    match files {
+        Some(files) => {
+            writeln!(
+                out,
+                "files: {} added, {} removed, {} changed",
+                files.added.len(),
+                files.removed.len(),
+                files.changed.len()
+            )?;
+            let paths = |paths: &[PathBuf]| -> Vec<_> {
+                paths.iter().map(|p| p.display().to_string()).collect()
+            };
+            list(out, '+', &paths(&files.added), max)?;
+            list(out, '-', &paths(&files.removed), max)?;
+            list(out, '~', &paths(&files.changed), max)?;
+        }
+        None => writeln!(out, "files: not compared, only workspaces have them")?,
+    }
+    Ok(())
+}
@@ -0,0 +1,219 @@
+//! `diff-bench` subcommand: generates a workspace, mutates it, and compares the targets a target
+//! determination tool (bazel-diff) reports as impacted against the generator's own graph model.
+
+use crate::archive::OutputFormat;
+use crate::graph::level_path;
+use crate::mutate::impacted_libraries;
+use crate::{budgeted, generate, layers, mutate, regen, Args};
+use anyhow::bail;
+use clap::{IntoApp, Parser};
+use itertools::Itertools;
+use std::collections::BTreeSet;
+use std::ffi::OsString;
+use std::path::Path;
+use std::process::Command;
+use std::sync::Arc;
+
+/// Measure a target determination tool against the known impacted targets of a mutation
+#[derive(Parser, Debug)]
+pub struct DiffBenchArgs {
+    #[clap(flatten)]
+    pub generate: Args,
+
+    /// Number of libraries to mutate between the base and the mutated state
+    #[clap(long, default_value = "1")]
+    mutations: u64,
+
+    /// bazel-diff executable. Without it only the ground truth is written to the output, in
+    /// `expected_impacted_targets.txt` and `impacted_targets.json`
+    #[clap(long)]
+    bazel_diff: Option<String>,
+
+    /// Bazel binary bazel-diff runs
+    #[clap(long, default_value = "bazel")]
+    bazel: String,
+}
+
+/// Normalizes a label to the short form `layers::label` uses, e.g. `@//pkg_1/lib_1:lib_1` to
+/// `//pkg_1/lib_1`. The repository is the apparent name `layers::label` gives it, which is found
+/// by comparing with the canonical names Bazel reports, e.g. `@@ext_1~` or
+/// `@@_main~_repo_rules~ext_1` for `@ext_1`, out of the `repos` of the workspace
+/// `workspace_name`.
+fn normalize_label(label: &str, repos: &[String], workspace_name: Option<&str>) -> String {
+    let label = label.trim();
+    let label = match label.trim_start_matches('@').split_once("//") {
+        Some((repo, rest)) if label.starts_with('@') => {
+            let apparent = repos.iter().find(|name| {
+                repo == name.as_str()
+                    || ['~', '+'].iter().any(|separator| {
+                        repo.ends_with(&format!("{}{}", separator, name))
+                            || repo.starts_with(&format!("{}{}", name, separator))
+                    })
+            });
+            if repo.is_empty() || repo == "_main" || Some(repo) == workspace_name {
+                format!("//{}", rest)
+            } else if let Some(name) = apparent {
+                format!("@{}//{}", name, rest)
+            } else {
+                label.to_string()
+            }
+        }
+        _ => label.to_string(),
+    };
+    match label.rsplit_once(':') {
+        Some((package, name)) if package.rsplit('/').next() == Some(name) => package.to_string(),
+        _ => label.to_string(),
+    }
+}
+
+/// The `generate` command line of the workspace of the `diff-bench` command line `argv`, which
+/// `--max-files` and `--max-bytes` derive the flags of the truncated graph from and the metadata
+/// records.
+pub fn generate_command_line(argv: &[OsString]) -> Vec<OsString> {
+    let mut argv = regen::without_flags_of(
+        &DiffBenchArgs::into_app(),
+        argv,
+        &["mutations", "bazel-diff", "bazel"],
+    );
+    if let Some(command) = argv.iter_mut().find(|arg| *arg == "diff-bench") {
+        *command = "generate".into();
+    }
+    argv
+}
+
+fn run_bazel_diff(bazel_diff: &str, workspace: &Path, tool_args: &[&str]) -> anyhow::Result<()> {
+    println!("running {} {}", bazel_diff, tool_args.join(" "));
+    let status = Command::new(bazel_diff)
+        .args(tool_args)
+        .current_dir(workspace)
+        .status()?;
+    if !status.success() {
+        bail!("{} {} failed with {}", bazel_diff, tool_args[0], status);
+    }
+    Ok(())
+}
+
+pub async fn run(args: DiffBenchArgs) -> anyhow::Result<()> {
+    if args.generate.output_format != OutputFormat::Dir {
+        bail!("diff-bench needs the workspace as a directory, --output-format has to be dir");
+    }
+    let generate_args = Arc::new(budgeted(args.generate)?);
+    generate(generate_args.clone(), None).await?;
+    let generate_args = &*generate_args;
+    let workspace = generate_args.output.canonicalize()?;
+    let workspace_str = workspace.to_str().unwrap();
+    let hashes_dir = std::env::temp_dir().join("gen_bazel_benchmark_diff_bench");
+    std::fs::create_dir_all(&hashes_dir)?;
+    let base_hashes = hashes_dir.join("base_hashes.json");
+    let mutated_hashes = hashes_dir.join("mutated_hashes.json");
+    let impacted_file = hashes_dir.join("impacted_targets.txt");
+
+    if let Some(bazel_diff) = &args.bazel_diff {
+        run_bazel_diff(
+            bazel_diff,
+            &workspace,
+            &[
+                "generate-hashes",
+                "-w",
+                workspace_str,
+                "-b",
+                &args.bazel,
+                base_hashes.to_str().unwrap(),
+            ],
+        )?;
+    }
+
+    let mutated = mutate::pick_nodes(generate_args, generate_args.seed, 0, args.mutations);
+    let files = mutated
+        .iter()
+        .map(|node| mutate::mutate_node(generate_args, node, "diff-bench"))
+        .collect::<anyhow::Result<Vec<_>>>()?
+        .concat();
+    for file in &files {
+        println!("mutated {}", file.display());
+    }
+    mutate::write_impact_report(
+        generate_args,
+        &[mutate::impact(
+            "diff-bench",
+            &mutated,
+            &files,
+            generate_args,
+        )],
+    )?;
+
+    let expected = impacted_libraries(&mutated, generate_args);
+    std::fs::write(
+        workspace.join("expected_impacted_targets.txt"),
+        expected.iter().map(|l| format!("{}\n", l)).join(""),
+    )?;
+    println!("expected impacted libraries: {}", expected.len());
+
+    let bazel_diff = match &args.bazel_diff {
+        Some(bazel_diff) => bazel_diff,
+        None => return Ok(()),
+    };
+    run_bazel_diff(
+        bazel_diff,
+        &workspace,
+        &[
+            "generate-hashes",
+            "-w",
+            workspace_str,
+            "-b",
+            &args.bazel,
+            mutated_hashes.to_str().unwrap(),
+        ],
+    )?;
+    run_bazel_diff(
+        bazel_diff,
+        &workspace,
+        &[
+            "get-impacted-targets",
+            "-w",
+            workspace_str,
+            "-b",
+            &args.bazel,
+            "-sh",
+            base_hashes.to_str().unwrap(),
+            "-fh",
+            mutated_hashes.to_str().unwrap(),
+            "-o",
+            impacted_file.to_str().unwrap(),
+        ],
+    )?;
+
+    let repos: Vec<_> = layers::repositories(generate_args)
+        .into_iter()
+        .map(|(name, _)| name)
+        .collect();
+    let reported: BTreeSet<_> = std::fs::read_to_string(&impacted_file)?
+        .lines()
+        .filter(|l| !l.trim().is_empty())
+        .map(|l| normalize_label(l, &repos, generate_args.workspace_name.as_deref()))
+        .collect();
+    // Libraries of every repository are below the first level of its package tree.
+    let first_level = level_path(1);
+    let libraries: BTreeSet<_> = reported
+        .iter()
+        .filter(|l| {
+            let path = l.split_once("//").map_or(l.as_str(), |(_, path)| path);
+            !l.contains(':') && Path::new(path).starts_with(&first_level)
+        })
+        .cloned()
+        .collect();
+
+    println!("reported impacted targets: {}", reported.len());
+    println!("reported impacted libraries: {}", libraries.len());
+    let missing: Vec<_> = expected.difference(&libraries).collect();
+    let extra: Vec<_> = libraries.difference(&expected).collect();
+    println!("missing libraries: {}", missing.len());
+    for label in &missing {
+        println!("  {}", label);
+    }
+    println!("unexpected libraries: {}", extra.len());
+    for label in &extra {
+        println!("  {}", label);
+    }
+    Ok(())
+}
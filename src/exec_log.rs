@@ -0,0 +1,136 @@
+//! Summaries of Bazel's JSON execution logs: the spawns of a build by mnemonic, how many hit a
+//! cache, how many bytes they read and which took longest. The compact execution log has the
+//! same spawns, but is protobuf that would need the message definitions of the Bazel version
+//! that wrote it.
+
+use crate::report::print_table;
+use serde_json::{json, Value};
+use std::collections::BTreeMap;
+use std::path::Path;
+
+/// The spawns of the JSON execution log at `path`.
+pub fn read(path: &Path) -> anyhow::Result<Vec<Value>> {
+    let log = std::fs::read_to_string(path)?;
+    // The log is a stream of JSON objects, one per spawn, rather than a single document.
+    Ok(serde_json::Deserializer::from_str(&log)
+        .into_iter::<Value>()
+        .collect::<Result<Vec<_>, _>>()?)
+}
+
+/// Whether `spawn` was served by the remote or disk cache instead of running.
+pub fn is_cache_hit(spawn: &Value) -> bool {
+    spawn["remoteCacheHit"].as_bool() == Some(true) || spawn["cacheHit"].as_bool() == Some(true)
+}
+
+/// An int64 field, which the protobuf JSON mapping writes as a string.
+fn int64(value: &Value) -> u64 {
+    value
+        .as_u64()
+        .or_else(|| value.as_str()?.parse().ok())
+        .unwrap_or(0)
+}
+
+/// Milliseconds of a duration field, written like `1.250s`.
+fn duration_ms(value: &Value) -> f64 {
+    value
+        .as_str()
+        .and_then(|d| d.strip_suffix('s')?.parse::<f64>().ok())
+        .map_or(0.0, |secs| secs * 1000.0)
+}
+
+pub struct Summary {
+    spawns: usize,
+    cache_hits: usize,
+    input_bytes: u64,
+    /// Spawns and cache hits per mnemonic.
+    mnemonics: BTreeMap<String, (usize, usize)>,
+    /// Milliseconds, mnemonic and target of the slowest spawns, slowest first.
+    slowest: Vec<(f64, String, String)>,
+}
+
+impl Summary {
+    /// Summarizes `spawns`, keeping the `slowest` that took longest.
+    pub fn new(spawns: &[Value], slowest: usize) -> Summary {
+        let mut mnemonics = BTreeMap::new();
+        let mut times = vec![];
+        let mut input_bytes = 0;
+        for spawn in spawns {
+            let mnemonic = spawn["mnemonic"].as_str().unwrap_or("unknown").to_string();
+            let counts = mnemonics.entry(mnemonic.clone()).or_insert((0, 0));
+            counts.0 += 1;
+            counts.1 += is_cache_hit(spawn) as usize;
+            input_bytes += spawn["inputs"]
+                .as_array()
+                .into_iter()
+                .flatten()
+                .map(|input| int64(&input["digest"]["sizeBytes"]))
+                .sum::<u64>();
+            let target = spawn["targetLabel"].as_str().unwrap_or("-").to_string();
+            times.push((
+                duration_ms(&spawn["metrics"]["totalTime"]),
+                mnemonic,
+                target,
+            ));
+        }
+        times.sort_by(|a, b| b.0.partial_cmp(&a.0).unwrap());
+        times.truncate(slowest);
+        Summary {
+            spawns: spawns.len(),
+            cache_hits: spawns.iter().filter(|spawn| is_cache_hit(spawn)).count(),
+            input_bytes,
+            mnemonics,
+            slowest: times,
+        }
+    }
+
+    pub fn print(&self) {
+        println!(
+            "{} spawns, {} cache hits, {} input bytes",
+            self.spawns, self.cache_hits, self.input_bytes
+        );
+        let rows: Vec<_> = self
+            .mnemonics
+            .iter()
+            .map(|(mnemonic, (spawns, hits))| {
+                vec![mnemonic.clone(), spawns.to_string(), hits.to_string()]
+            })
+            .collect();
+        print_table(
+            &["mnemonic", "spawns", "cache hits"].map(str::to_string),
+            &rows,
+        );
+        let rows: Vec<_> = self
+            .slowest
+            .iter()
+            .map(|(ms, mnemonic, target)| {
+                vec![format!("{:.0}", ms), mnemonic.clone(), target.clone()]
+            })
+            .collect();
+        print_table(
+            &["time (ms)", "mnemonic", "target"].map(str::to_string),
+            &rows,
+        );
+    }
+
+    pub fn to_json(&self) -> Value {
+        json!({
+            "spawns": self.spawns,
+            "cache_hits": self.cache_hits,
+            "input_bytes": self.input_bytes,
+            "mnemonics": self
+                .mnemonics
+                .iter()
+                .map(|(mnemonic, (spawns, hits))| {
+                    (mnemonic.clone(), json!({"spawns": spawns, "cache_hits": hits}))
+                })
+                .collect::<serde_json::Map<_, _>>(),
+            "slowest": self
+                .slowest
+                .iter()
+                .map(|(ms, mnemonic, target)| {
+                    json!({"time_ms": ms, "mnemonic": mnemonic, "target": target})
+                })
+                .collect::<Vec<_>>(),
+        })
+    }
+}
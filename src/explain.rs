@@ -0,0 +1,179 @@
+//! `explain` subcommand: what the model of a generated workspace says about one of its libraries,
+//! its place in the tree, its dependencies and dependents, its files, and which of the mutations
+//! the workspace comes with edit or invalidate it. The model is rebuilt from the command line in
+//! the `BENCHMARK_METADATA.json` of the workspace, like for `regen`.
+
+use crate::graph::{num_nodes_in_ntree, ID};
+use crate::{layers, metrics, mutate, prepare, regen, third_party, Args};
+use anyhow::format_err;
+use clap::Parser;
+use itertools::Itertools;
+use std::collections::BTreeSet;
+use std::path::{Path, PathBuf};
+
+/// Explain a library of a generated workspace: its depth, dependencies, closure, files and the
+/// mutations touching it
+#[derive(Parser, Debug)]
+pub struct ExplainArgs {
+    /// Label of the library, e.g. `//pkg_1/lib_3`
+    label: String,
+
+    /// Generated workspace the library is in, with its `BENCHMARK_METADATA.json`
+    #[clap(long, default_value = ".")]
+    workspace: PathBuf,
+}
+
+/// `label` without the name of the target when it repeats the package, `//a/b:b` for `//a/b`.
+fn normalize(label: &str) -> &str {
+    match label.rsplit_once(':') {
+        Some((package, name)) if package.rsplit('/').next() == Some(name) => package,
+        _ => label,
+    }
+}
+
+/// The labels of `ids`, the root as `//`.
+fn labels(ids: impl IntoIterator<Item = u64>, args: &Args) -> String {
+    let labels = ids
+        .into_iter()
+        .map(|id| match id {
+            0 => "//".to_string(),
+            _ => layers::label(
+                &ID::new(id, args.targets_per_level, args.height as u64),
+                args,
+            ),
+        })
+        .join(" ");
+    if labels.is_empty() {
+        "none".to_string()
+    } else {
+        labels
+    }
+}
+
+/// Files of the package in `lib_dir`, relative to `workspace` and sorted.
+fn files(lib_dir: &Path, workspace: &Path) -> anyhow::Result<Vec<PathBuf>> {
+    let mut files = vec![];
+    let mut dirs = vec![lib_dir.to_path_buf()];
+    while let Some(dir) = dirs.pop() {
+        for entry in std::fs::read_dir(dir)? {
+            let entry = entry?;
+            // Deeper levels are packages of their own
+            if entry.file_type()?.is_dir() {
+                if !entry.path().join("BUILD.bazel").exists() {
+                    dirs.push(entry.path());
+                }
+            } else {
+                files.push(entry.path().strip_prefix(workspace)?.to_path_buf());
+            }
+        }
+    }
+    files.sort();
+    Ok(files)
+}
+
+/// How a mutation of the libraries `mutated` affects `node`, `None` if it doesn't.
+fn touch(node: &ID, mutated: &[ID], args: &Args) -> Option<&'static str> {
+    if mutated.iter().any(|m| m.id == node.id) {
+        Some("edits it")
+    } else if mutate::impacted_libraries(mutated, args).contains(&layers::label(node, args)) {
+        Some("invalidates it")
+    } else {
+        None
+    }
+}
+
+pub fn run(explain: &ExplainArgs) -> anyhow::Result<()> {
+    let mut args = regen::workspace_args(&explain.workspace)?;
+    args.quiet = true;
+    prepare(&args)?;
+
+    let num_nodes = num_nodes_in_ntree(args.targets_per_level, args.height);
+    let wanted = normalize(&explain.label);
+    let node = (1..num_nodes)
+        .map(|id| ID::new(id, args.targets_per_level, args.height as u64))
+        .find(|node| layers::label(node, &args) == wanted)
+        .ok_or_else(|| {
+            format_err!(
+                "{} isn't a library of {}",
+                explain.label,
+                explain.workspace.display()
+            )
+        })?;
+
+    let dependencies = metrics::dependencies(node.id, &args);
+    let dependents: Vec<u64> = (0..num_nodes)
+        .filter(|id| metrics::dependencies(*id, &args).contains(&node.id))
+        .collect();
+    let mut closure = BTreeSet::new();
+    let mut stack = dependencies.clone();
+    while let Some(id) = stack.pop() {
+        if closure.insert(id) {
+            stack.extend(metrics::dependencies(id, &args));
+        }
+    }
+    let lib_dir = layers::lib_dir(&node, &args);
+
+    println!("{}", layers::label(&node, &args));
+    println!("  library {}, module {}", node.id, node.lib_name());
+    println!(
+        "  level {} of {}, index {} on its level, {}",
+        node.level,
+        args.height,
+        node.package_relative_index,
+        if node.is_leaf() { "a leaf" } else { "inner" }
+    );
+    println!(
+        "  dependencies ({}): {}",
+        dependencies.len(),
+        labels(dependencies.iter().copied(), &args)
+    );
+    println!(
+        "  dependents ({}): {}",
+        dependents.len(),
+        labels(dependents.iter().copied(), &args)
+    );
+    println!(
+        "  transitive closure: {} libraries, {:.1}% of the graph",
+        closure.len(),
+        100.0 * closure.len() as f64 / (num_nodes - 1) as f64
+    );
+    if let Some(label) = third_party::dependency_label(&node, &args) {
+        println!("  third-party library: {}", label);
+    }
+    let files = files(&lib_dir, &explain.workspace)?;
+    println!("  files ({}):", files.len());
+    for file in &files {
+        println!("    {}", file.display());
+    }
+
+    let leaf = ID::new(
+        num_nodes_in_ntree(args.targets_per_level, args.height - 1),
+        args.targets_per_level,
+        args.height as u64,
+    );
+    let mut mutations = vec![];
+    mutations.extend(touch(&node, &[leaf], &args).map(|how| ("incremental scenario", how)));
+    if args.third_party_packages > 0 {
+        let how =
+            touch(&node, &third_party::bump_dependents(&args), &args).map(|_| "invalidates it");
+        mutations.extend(how.map(|how| ("third_party_bump scenario", how)));
+    }
+    let commits: Vec<_> = (1..=args.commits)
+        .filter(|_| args.init_git)
+        .filter_map(|round| {
+            let nodes = mutate::pick_nodes(&args, args.seed, round, args.mutations_per_commit);
+            touch(&node, &nodes, &args).map(|how| (format!("commit {}", round), how))
+        })
+        .collect();
+    println!("  mutations:");
+    if mutations.is_empty() && commits.is_empty() {
+        println!("    none touch it");
+    }
+    for (name, how) in mutations {
+        println!("    the {} {}", name, how);
+    }
+    for (name, how) in commits {
+        println!("    {} of the git history {}", name, how);
+    }
+    Ok(())
+}
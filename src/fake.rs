@@ -0,0 +1,143 @@
+//! `--analysis-only`: stand-ins for the rules_ios rules the objc backend emits, with the same
+//! attributes but no actions. The generated graph loads and analyzes like the real one, without
+//! Xcode or the Apple rule sets, so analysis benchmarks run on any machine. With
+//! `--action-duration-ms` every framework runs one action of a known duration instead, for
+//! benchmarks of the scheduler decoupled from a real compiler.
+
+use crate::graph::ID;
+use crate::starlark::RuleRef;
+use crate::{node_fraction, Args};
+use std::os::unix::fs::PermissionsExt;
+
+const ACTION_DURATION_SALT: u64 = 14;
+
+const RULES_DIR: &str = "tools/fake";
+
+/// Label of the `.bzl` file defining the fake rules.
+pub const RULES_LOAD: &str = "//tools/fake:apple.bzl";
+
+pub const WORKSPACE: &str = r#"workspace(name = "bench")
+
+# --analysis-only: the rules in //tools/fake stand in for rules_ios and need no rule sets.
+"#;
+
+const RULES_BZL: &str = r#""""No-op rules with the attributes of the rules_ios rules the workspace was generated for. They
+pass their sources and the modules of their dependencies up the graph, which keeps loading and
+analysis close to the real rules. Frameworks with an action_duration_ms run a single action
+taking that long, which depends on the ones of their dependencies, and nothing else."""
+
+FakeFrameworkInfo = provider(
+    doc = "Modules, sources and action outputs of a fake framework and its transitive dependencies.",
+    fields = ["modules", "outputs", "srcs"],
+)
+
+def _dependencies(ctx):
+    return [dep[FakeFrameworkInfo] for dep in ctx.attr.deps if FakeFrameworkInfo in dep]
+
+def _apple_framework_impl(ctx):
+    deps = _dependencies(ctx)
+    srcs = depset(ctx.files.srcs, transitive = [dep.srcs for dep in deps])
+    outputs = []
+    if ctx.attr.action_duration_ms > 0:
+        output = ctx.actions.declare_file(ctx.label.name + ".fake")
+        ctx.actions.run(
+            executable = ctx.executable._sleep,
+            arguments = [output.path, str(ctx.attr.action_duration_ms)],
+            inputs = depset(ctx.files.srcs, transitive = [dep.outputs for dep in deps]),
+            outputs = [output],
+            mnemonic = "FakeCompile",
+        )
+        outputs.append(output)
+    return [
+        FakeFrameworkInfo(
+            modules = depset([ctx.attr.module_name], transitive = [dep.modules for dep in deps]),
+            outputs = depset(outputs, transitive = [dep.outputs for dep in deps]),
+            srcs = srcs,
+        ),
+        DefaultInfo(files = depset(outputs or ctx.files.srcs)),
+    ]
+
+apple_framework = rule(
+    implementation = _apple_framework_impl,
+    attrs = {
+        "action_duration_ms": attr.int(),
+        "bundle_id": attr.string(),
+        "data": attr.label_list(allow_files = True),
+        "deps": attr.label_list(),
+        "infoplists": attr.label_list(allow_files = True),
+        "link_dynamic": attr.bool(),
+        "module_map": attr.label(allow_single_file = True),
+        "module_name": attr.string(mandatory = True),
+        "objc_copts": attr.string_list(),
+        "objc_defines": attr.string_list(),
+        "pch": attr.label(allow_single_file = True),
+        "platforms": attr.string_dict(),
+        "private_headers": attr.label_list(allow_files = True),
+        "sdk_frameworks": attr.string_list(),
+        "srcs": attr.label_list(allow_files = True),
+        "umbrella_header": attr.label(allow_single_file = True),
+        "_sleep": attr.label(
+            default = "//tools/fake:sleep.sh",
+            allow_single_file = True,
+            executable = True,
+            cfg = "exec",
+        ),
+    },
+)
+
+def _ios_application_impl(ctx):
+    deps = _dependencies(ctx)
+    return [DefaultInfo(files = depset(
+        ctx.files.srcs + ctx.files.infoplists + ctx.files.entitlements,
+        transitive = [dep.srcs for dep in deps] + [dep.outputs for dep in deps],
+    ))]
+
+ios_application = rule(
+    implementation = _ios_application_impl,
+    attrs = {
+        "bundle_id": attr.string(mandatory = True),
+        "deps": attr.label_list(),
+        "entitlements": attr.label(allow_single_file = True),
+        "families": attr.string_list(),
+        "infoplists": attr.label_list(allow_files = True),
+        "minimum_os_version": attr.string(),
+        "provisioning_profile": attr.label(allow_single_file = True),
+        "srcs": attr.label_list(allow_files = True),
+    },
+)
+"#;
+
+/// The tool of the fake actions: sleeps for the milliseconds of its second argument, then writes
+/// the output of its first.
+const SLEEP_SH: &str = r#"#!/bin/bash
+set -euo pipefail
+ms="$2"
+sleep "$((ms / 1000)).$(printf '%03d' $((ms % 1000)))"
+echo "$ms" > "$1"
+"#;
+
+/// Milliseconds the action of the framework of `node` takes with `--action-duration-ms`.
+pub fn action_duration(node: &ID, args: &Args) -> Option<u64> {
+    let durations = args.action_duration_ms.as_ref()?;
+    Some(*durations.pick(node_fraction(node.id, ACTION_DURATION_SALT)))
+}
+
+/// The fake rule standing in for the rules_ios rule `name`.
+pub fn rule(name: &str) -> RuleRef {
+    RuleRef {
+        load: RULES_LOAD.to_string(),
+        name: name.to_string(),
+    }
+}
+
+/// Writes the package defining the fake rules.
+pub fn write_rules(args: &Args) -> anyhow::Result<()> {
+    let dir = args.output.join(RULES_DIR);
+    std::fs::create_dir_all(&dir)?;
+    std::fs::write(dir.join("BUILD.bazel"), "exports_files([\"sleep.sh\"])\n")?;
+    std::fs::write(dir.join("apple.bzl"), RULES_BZL)?;
+    let sleep = dir.join("sleep.sh");
+    std::fs::write(&sleep, SLEEP_SH)?;
+    std::fs::set_permissions(&sleep, std::fs::Permissions::from_mode(0o755))?;
+    Ok(())
+}
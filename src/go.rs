@@ -0,0 +1,227 @@
+//! Go backend: `go_library` packages with real import paths, either with hand-written BUILD files
+//! or, with `--gazelle-compatible`, as a plain Go module that Gazelle generates BUILD files for.
+
+use crate::backend::EmitBackend;
+use crate::graph::ID;
+use crate::layers;
+use crate::starlark::{quote, string_list, BuildFile, Rule, RuleRef};
+use crate::{bzlmod, third_party, Args};
+use std::io::{BufWriter, Write};
+use std::path::Path;
+
+pub const LIBRARY_LOAD: &str = "@io_bazel_rules_go//go:def.bzl";
+pub const LIBRARY_RULE: &str = "go_library";
+
+pub const MODULE_PATH: &str = "github.com/bazel-benchmark/workspace";
+
+const WORKSPACE: &str = r#"load("@bazel_tools//tools/build_defs/repo:http.bzl", "http_archive")
+
+http_archive(
+    name = "io_bazel_rules_go",
+    urls = ["https://github.com/bazelbuild/rules_go/releases/download/v0.35.0/rules_go-v0.35.0.zip"],
+)
+
+http_archive(
+    name = "bazel_gazelle",
+    urls = ["https://github.com/bazelbuild/bazel-gazelle/releases/download/v0.28.0/bazel-gazelle-v0.28.0.tar.gz"],
+)
+
+load("@io_bazel_rules_go//go:deps.bzl", "go_register_toolchains", "go_rules_dependencies")
+load("@bazel_gazelle//:deps.bzl", "gazelle_dependencies")
+
+go_rules_dependencies()
+
+go_register_toolchains(version = "1.19.1")
+
+gazelle_dependencies()"#;
+
+fn import_path(node: &ID) -> String {
+    format!("{}/{}", MODULE_PATH, node.lib_path().to_str().unwrap())
+}
+
+/// Import alias for `node`, package names alone (`lib_1`) aren't unique across levels.
+fn import_alias(node: &ID) -> String {
+    node.lib_name().to_lowercase()
+}
+
+pub fn handle_root(args: &Args) -> anyhow::Result<()> {
+    let first_level: Vec<_> = (1..=args.targets_per_level)
+        .map(|i| ID::new(i, args.targets_per_level, args.height as u64))
+        .collect();
+
+    let mut build = BuildFile::new();
+    if args.gazelle_compatible {
+        build.comment(&format!("gazelle:prefix {}", MODULE_PATH));
+        build.load("@bazel_gazelle//:def.bzl", "gazelle");
+        build.add(Rule::new("gazelle", "gazelle"));
+    } else {
+        build.load(LIBRARY_LOAD, "go_binary");
+        let mut binary = Rule::new("go_binary", "root")
+            .attr("srcs", string_list(["main.go"]))
+            .attr("deps", string_list(first_level.iter().map(ID::label)));
+        if let Some(data) = layers::runfiles(&args.output, args)? {
+            binary.set("data", data);
+        }
+        build.add(binary);
+    }
+    build.write(&args.output.join("BUILD.bazel"))?;
+
+    write_go_file(
+        &args.output.join("main.go"),
+        "main",
+        &first_level,
+        "func main() {\n\tprintln(0",
+        ")\n}",
+    )?;
+    Ok(())
+}
+
+pub fn handle_node(node: &ID, args: &Args) -> anyhow::Result<()> {
+    let lib_dir = args.output.join(node.lib_path());
+    std::fs::create_dir_all(&lib_dir)?;
+
+    let deps = layers::direct_deps(node, args);
+
+    if !args.gazelle_compatible {
+        let srcs = (1..=args.files_per_target.for_node(node)).map(|i| format!("src{}.go", i));
+        let mut rule = Rule::new(LIBRARY_RULE, node.target_name())
+            .attr("srcs", layers::srcs(args, srcs, &["*.go"], &[]))
+            .attr("importpath", quote(&import_path(node)));
+        if args.defines_per_target > 0 {
+            let x_defs = layers::generated_flags(args, args.defines_per_target, |i, value| {
+                format!("{}: {}", quote(&format!("BenchDefine{}", i)), quote(value))
+            });
+            rule.set("x_defs", format!("{{{}}}", x_defs.join(", ")));
+        }
+
+        let labels = deps.iter().map(ID::label).collect();
+
+        let mut build = BuildFile::new();
+        layers::add_library(&mut build, node, args, LIBRARY_LOAD, rule, labels)?;
+        build.write(&lib_dir.join("BUILD.bazel"))?;
+    }
+
+    let package = node.target_name();
+    let mut imported = layers::imported_deps(node, deps, args);
+    imported.extend(layers::undeclared_import(node, args));
+    for i in 1..=args.files_per_target.for_node(node) {
+        write_go_file(
+            &lib_dir.join(format!("src{}.go", i)),
+            &package,
+            &imported,
+            &format!("func Value{}() int {{\n\treturn {}", i, i),
+            "\n}",
+        )?;
+    }
+    Ok(())
+}
+
+/// Writes a Go file importing all of `deps` whose body is `head`, followed by a call to each
+/// dep's `Value1`, followed by `tail`. Go rejects unused imports, so every import is used.
+fn write_go_file(
+    path: &Path,
+    package: &str,
+    deps: &[ID],
+    head: &str,
+    tail: &str,
+) -> anyhow::Result<()> {
+    let mut f = BufWriter::new(std::fs::File::create(path)?);
+    writeln!(f, "package {}", package)?;
+
+    if !deps.is_empty() {
+        writeln!(f, "\nimport (")?;
+        for dep in deps {
+            writeln!(f, "\t{} \"{}\"", import_alias(dep), import_path(dep))?;
+        }
+        writeln!(f, ")")?;
+    }
+
+    write!(f, "\n{}", head)?;
+    for dep in deps {
+        write!(f, " + {}.Value1()", import_alias(dep))?;
+    }
+    writeln!(f, "{}", tail)?;
+    Ok(())
+}
+
+pub fn write_workspace(args: &Args) -> anyhow::Result<()> {
+    let mut f = std::fs::File::create(args.output.join("WORKSPACE"))?;
+    writeln!(f, "{}", WORKSPACE)?;
+
+    let mut f = std::fs::File::create(args.output.join("go.mod"))?;
+    writeln!(f, "module {}\n\ngo 1.19", MODULE_PATH)?;
+    Ok(())
+}
+
+/// The language dependent flags the go backend implements.
+const SUPPORTED_FLAGS: &[&str] = &[
+    "--emit-env",
+    "--runfiles-per-test",
+    "--third-party-packages",
+    "--cc-toolchain-config",
+    "--stamped-targets",
+    "--filegroups-per-package",
+    "--emit-both-dep-systems",
+    "--inject-errors",
+];
+
+pub struct Backend;
+
+impl EmitBackend for Backend {
+    fn name(&self) -> &'static str {
+        "go"
+    }
+
+    fn emit_workspace(&self, args: &Args) -> anyhow::Result<()> {
+        write_workspace(args)
+    }
+
+    fn emit_package(&self, node: &ID, args: &Args) -> anyhow::Result<()> {
+        match node.id {
+            0 => handle_root(args),
+            _ => handle_node(node, args),
+        }
+    }
+
+    fn library_rule(&self, _args: &Args) -> RuleRef {
+        RuleRef {
+            load: LIBRARY_LOAD.to_string(),
+            name: LIBRARY_RULE.to_string(),
+        }
+    }
+
+    fn supports(&self, flag: &str) -> bool {
+        SUPPORTED_FLAGS.contains(&flag)
+    }
+
+    fn third_party_library(&self, index: u64, _args: &Args) -> Option<third_party::Library> {
+        let name = third_party::name(index);
+        let import_path = format!("{}/{}/{}", MODULE_PATH, third_party::DIR, name);
+        Some(third_party::Library {
+            src: "version.go".to_string(),
+            content: format!("package tp_{}\n\nconst VERSION = 1\n", index),
+            load: LIBRARY_LOAD.to_string(),
+            rule: Rule::new(LIBRARY_RULE, name)
+                .attr("srcs", string_list(["version.go"]))
+                .attr("importpath", quote(&import_path)),
+        })
+    }
+
+    fn modules(&self, _args: &Args) -> Vec<bzlmod::Module> {
+        vec![
+            ("rules_go", "0.46.0", "io_bazel_rules_go"),
+            ("gazelle", "0.35.0", "bazel_gazelle"),
+        ]
+    }
+
+    fn module_extensions(&self, _args: &Args) -> &'static str {
+        r#"
+go_sdk = use_extension("@io_bazel_rules_go//go:extensions.bzl", "go_sdk")
+go_sdk.download(version = "1.19.1")
+"#
+    }
+
+    fn ide_languages(&self, _args: &Args) -> Vec<&'static str> {
+        vec!["go"]
+    }
+}
@@ -0,0 +1,535 @@
+use anyhow::{bail, format_err};
+use itertools::Itertools;
+use std::fmt::Display;
+use std::path::PathBuf;
+use std::str::FromStr;
+use std::sync::{Arc, RwLock};
+
+#[derive(Debug, Default, Clone, PartialEq)]
+pub enum NamingScheme {
+    #[default]
+    Ascii,
+    /// Non-ASCII letters, which are still valid in identifiers of all backends.
+    Unicode,
+    /// Alternates ASCII and Unicode package names per level and adds characters that are legal
+    /// in labels but not in identifiers to target names.
+    Mixed,
+    /// Module and target names rendered from a template with `{package}`, `{level}`, `{index}`
+    /// and `{id}` placeholders.
+    Template(String),
+    /// Module and target names made of dictionary words, like the ones of a real code base.
+    Dictionary,
+    /// Module and target names derived from a hash of the id, every pair of consecutive libraries
+    /// differs only in case.
+    Hashed,
+}
+
+impl NamingScheme {
+    /// Whether names depend on more than the position of the node, so `Pkg1_Lib1` style module
+    /// names and `lib_1` style target names aren't used.
+    pub fn replaces_names(&self) -> bool {
+        matches!(
+            self,
+            NamingScheme::Template(_) | NamingScheme::Dictionary | NamingScheme::Hashed
+        )
+    }
+}
+
+const PLACEHOLDERS: [&str; 4] = ["{package}", "{level}", "{index}", "{id}"];
+
+impl FromStr for NamingScheme {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "ascii" => Ok(NamingScheme::Ascii),
+            "unicode" => Ok(NamingScheme::Unicode),
+            "mixed" => Ok(NamingScheme::Mixed),
+            "dictionary" => Ok(NamingScheme::Dictionary),
+            "hashed" => Ok(NamingScheme::Hashed),
+            _ => {
+                let template = s.strip_prefix("template:").ok_or_else(|| {
+                    format_err!(
+                        "unknown naming scheme {}, expected ascii, unicode, mixed, dictionary, \
+                         hashed or template:<template>",
+                        s
+                    )
+                })?;
+                let literal = PLACEHOLDERS
+                    .iter()
+                    .fold(template.to_string(), |t, p| t.replace(p, ""));
+                if !literal
+                    .chars()
+                    .all(|c| c.is_ascii_alphanumeric() || c == '_')
+                {
+                    bail!(
+                        "naming template {} may only contain letters, digits, underscores and {}",
+                        template,
+                        PLACEHOLDERS.join(", ")
+                    );
+                }
+                if !template.starts_with(|c: char| c.is_ascii_alphabetic() || c == '_')
+                    && !template.starts_with("{package}")
+                {
+                    bail!("naming template {} must start with a letter", template);
+                }
+                let unique = template.contains("{id}")
+                    || template.contains("{index}")
+                        && (template.contains("{package}") || template.contains("{level}"));
+                if !unique {
+                    bail!(
+                        "naming template {} must contain {{id}}, or {{index}} and {{package}} or \
+                         {{level}}, to name every library differently",
+                        template
+                    );
+                }
+                Ok(NamingScheme::Template(template.to_string()))
+            }
+        }
+    }
+}
+
+/// Words module names of the `dictionary` naming scheme are combined from.
+const DOMAINS: [&str; 32] = [
+    "Account",
+    "Analytics",
+    "Auth",
+    "Billing",
+    "Camera",
+    "Cart",
+    "Chat",
+    "Checkout",
+    "Contacts",
+    "Feed",
+    "Inbox",
+    "Location",
+    "Login",
+    "Maps",
+    "Media",
+    "Messaging",
+    "Network",
+    "Notification",
+    "Onboarding",
+    "Payments",
+    "Photos",
+    "Player",
+    "Profile",
+    "Search",
+    "Settings",
+    "Sharing",
+    "Storage",
+    "Stories",
+    "Sync",
+    "Theme",
+    "Video",
+    "Wallet",
+];
+const LAYERS: [&str; 16] = [
+    "Core",
+    "UI",
+    "Kit",
+    "Service",
+    "Model",
+    "Store",
+    "Client",
+    "Utils",
+    "Foundation",
+    "Views",
+    "Logic",
+    "API",
+    "Cache",
+    "Components",
+    "Domain",
+    "Testing",
+];
+
+/// Name of library `id` for the `dictionary` scheme, e.g. `PaymentsUI`. Ids beyond the number of
+/// word combinations get a numeric suffix, which keeps names unique.
+fn dictionary_name(id: u64) -> String {
+    let combinations = (DOMAINS.len() * LAYERS.len()) as u64;
+    let index = (id - 1) % combinations;
+    let name = format!(
+        "{}{}",
+        DOMAINS[(index % DOMAINS.len() as u64) as usize],
+        LAYERS[(index / DOMAINS.len() as u64) as usize]
+    );
+    match (id - 1) / combinations {
+        0 => name,
+        round => format!("{}{}", name, round + 1),
+    }
+}
+
+/// Name of library `id` for the `hashed` scheme. Both ids of a pair `2k`, `2k + 1` share the
+/// letters of the hash of `k`, the odd one with the first letter upper cased, so that case
+/// insensitive file systems and tools see collisions.
+fn hashed_name(id: u64) -> String {
+    let mut z = (id >> 1).wrapping_add(0x9E37_79B9_7F4A_7C15);
+    z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+    z ^= z >> 31;
+    // The mix is a bijection, so 14 base 26 digits of it are unique per pair.
+    let mut name: String = (0..14)
+        .map(|_| {
+            let c = (b'a' + (z % 26) as u8) as char;
+            z /= 26;
+            c
+        })
+        .collect();
+    if id % 2 == 1 {
+        name[..1].make_ascii_uppercase();
+    }
+    name
+}
+
+/// How generated packages and targets are named, configured once from the command line.
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct Naming {
+    /// Minimum length package and target names get padded to.
+    pub label_length: usize,
+    /// Number of extra directories nested below every package level.
+    pub path_depth_padding: u64,
+    pub scheme: NamingScheme,
+    /// Package directories named `p1` instead of `pkg_1`.
+    pub short_packages: bool,
+    /// Prefixes of the module names per level, starting at the first library level, the last one
+    /// repeating for deeper levels.
+    pub module_prefixes: Vec<String>,
+}
+
+impl Naming {
+    /// Pads `name` to `--label-length` characters.
+    fn pad(&self, name: String) -> String {
+        let length = self.label_length;
+        let current = name.chars().count();
+        if current >= length {
+            name
+        } else {
+            format!("{}_{}", name, "x".repeat(length - current - 1))
+        }
+    }
+
+    fn package_name(&self, level: usize) -> String {
+        let prefix = if self.short_packages { "p" } else { "pkg_" };
+        match self.scheme {
+            NamingScheme::Unicode => format!("{}{}_ü", prefix, level),
+            NamingScheme::Mixed if level % 2 == 1 => format!("{}{}_ü", prefix, level),
+            _ => format!("{}{}", prefix, level),
+        }
+    }
+
+    /// `level_path` with this naming.
+    pub fn level_path(&self, level: usize) -> PathBuf {
+        let res = (1..=level)
+            .flat_map(|i| {
+                std::iter::once(self.pad(self.package_name(i)))
+                    .chain((1..=self.path_depth_padding).map(|j| format!("nest_{}", j)))
+            })
+            .join("/");
+
+        PathBuf::from(res)
+    }
+
+    /// `ID::target_name` with this naming.
+    pub fn target_name(&self, node: &ID) -> String {
+        if self.scheme.replaces_names() {
+            return self.pad(self.lib_name(node));
+        }
+        let name = match self.scheme {
+            NamingScheme::Ascii => format!("lib_{}", node.package_relative_index),
+            NamingScheme::Unicode => format!("lib_{}_é", node.package_relative_index),
+            NamingScheme::Mixed => format!("lib_{}-é+~", node.package_relative_index),
+            NamingScheme::Template(_) | NamingScheme::Dictionary | NamingScheme::Hashed => {
+                unreachable!()
+            }
+        };
+        self.pad(name)
+    }
+
+    /// `ID::lib_name` with this naming, unless the symbol pool renamed the library.
+    pub fn lib_name(&self, node: &ID) -> String {
+        if let Some(name) = crate::symbols::renamed(node.id) {
+            return name;
+        }
+        let prefix = match self.module_prefixes.len() {
+            0 => "",
+            len => &self.module_prefixes[(node.level.max(1) as usize - 1).min(len - 1)],
+        };
+        format!("{}{}", prefix, self.unprefixed_lib_name(node))
+    }
+
+    fn unprefixed_lib_name(&self, node: &ID) -> String {
+        let package = (1..=node.level).map(|i| format!("Pkg{}", i)).join("_");
+        match &self.scheme {
+            NamingScheme::Template(template) => template
+                .replace("{package}", &package)
+                .replace("{level}", &node.level.to_string())
+                .replace("{index}", &node.package_relative_index.to_string())
+                .replace("{id}", &node.id.to_string()),
+            NamingScheme::Dictionary => dictionary_name(node.id),
+            NamingScheme::Hashed => hashed_name(node.id),
+            _ => format!("{}_Lib{}", package, node.package_relative_index),
+        }
+    }
+}
+
+static NAMING: RwLock<Option<Arc<Naming>>> = RwLock::new(None);
+
+/// Configures the naming of the next generation, which like the topology can change between the
+/// generations of one process, e.g. the cells of `--matrix label-length=0,12`.
+pub fn set_naming(naming: Naming) {
+    *NAMING.write().unwrap() = Some(Arc::new(naming));
+}
+
+fn naming() -> Arc<Naming> {
+    NAMING.read().unwrap().clone().unwrap_or_default()
+}
+
+const DEP_PRUNE_SALT: u64 = 11;
+const SHUFFLE_SALT: u64 = 18;
+
+/// The tree edges of a graph whose parent to child edges only exist with some probability, every
+/// child that loses its edge is attached to a random library on a level above instead, so that it
+/// stays reachable, within its island. Parents are indexed by id, children in compressed rows.
+pub struct Topology {
+    parents: Vec<u64>,
+    child_offsets: Vec<usize>,
+    children: Vec<u64>,
+}
+
+impl Topology {
+    /// The tree where every edge of the full `targets_per_level`-ary tree of `height` is kept
+    /// with `keep_probability`, split into `islands`. The first level always hangs off the root,
+    /// which has no other library above it. A `shuffle` fraction of the other libraries, picked
+    /// by `seed`, moves to a random parent as well; the rest keep the parents they have without
+    /// it, whatever the seed.
+    pub fn pruned(
+        targets_per_level: u64,
+        height: u32,
+        keep_probability: f64,
+        islands: u64,
+        shuffle: f64,
+        seed: u64,
+    ) -> Self {
+        let num_nodes = num_nodes_in_ntree(targets_per_level, height);
+        let mut parents = vec![0; num_nodes as usize];
+        // The libraries of every island in id order, and how many of them are on upper levels.
+        let mut members = vec![vec![]; islands as usize];
+        let mut upper = vec![0; islands as usize];
+        let mut level = 0;
+        for id in 1..num_nodes {
+            let node = ID::new(id, targets_per_level, height as u64);
+            if node.level != level {
+                level = node.level;
+                for (upper, members) in upper.iter_mut().zip(&members) {
+                    *upper = members.len();
+                }
+            }
+            let island = node.island(islands) as usize;
+            let seeded = id ^ seed.wrapping_mul(0x1000_0000_01B3);
+            let parent = if node.level == 1 {
+                0
+            } else if crate::node_fraction(seeded, SHUFFLE_SALT) < shuffle {
+                let pick =
+                    crate::node_fraction(seeded | 1 << 61, SHUFFLE_SALT) * upper[island] as f64;
+                members[island][pick as usize]
+            } else if crate::node_fraction(id, DEP_PRUNE_SALT) < keep_probability {
+                (id - 1) / targets_per_level
+            } else {
+                let pick =
+                    crate::node_fraction(id | 1 << 61, DEP_PRUNE_SALT) * upper[island] as f64;
+                members[island][pick as usize]
+            };
+            members[island].push(id);
+            parents[id as usize] = parent;
+        }
+        Topology::from_parents(parents)
+    }
+
+    /// The tree where every library hangs off `parents[id]`, which has to be on a level above.
+    /// Index 0 is the root's and ignored.
+    pub fn from_parents(parents: Vec<u64>) -> Self {
+        let num_nodes = parents.len() as u64;
+        let mut counts = vec![0; num_nodes as usize + 1];
+        for parent in &parents[1..] {
+            counts[*parent as usize + 1] += 1;
+        }
+        let child_offsets: Vec<usize> = counts
+            .iter()
+            .scan(0, |offset, count| {
+                *offset += count;
+                Some(*offset)
+            })
+            .collect();
+        let mut next = child_offsets.clone();
+        let mut children = vec![0; num_nodes as usize - 1];
+        for id in 1..num_nodes {
+            let parent = parents[id as usize] as usize;
+            children[next[parent]] = id;
+            next[parent] += 1;
+        }
+        Topology {
+            parents,
+            child_offsets,
+            children,
+        }
+    }
+}
+
+static TOPOLOGY: RwLock<Option<Arc<Topology>>> = RwLock::new(None);
+
+/// Replaces the full tree with `topology`, or restores it with `None`. It can change between
+/// generations in one process, like the ones of `--matrix`.
+pub fn set_topology(topology: Option<Topology>) {
+    *TOPOLOGY.write().unwrap() = topology.map(Arc::new);
+}
+
+fn topology() -> Option<Arc<Topology>> {
+    TOPOLOGY.read().unwrap().clone()
+}
+
+/// Directory holding the packages of the libraries `level` levels below the root, e.g.
+/// `pkg_1/pkg_2` for the second level.
+pub fn level_path(level: usize) -> PathBuf {
+    naming().level_path(level)
+}
+
+/// A node of the graph. Everything about it is derived arithmetically from its id, which numbers
+/// the nodes level by level starting with 0 for the root, so nodes are cheap to create and copy.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct ID {
+    pub id: u64,
+    /// Number of edges between the node and the root.
+    pub level: u32,
+    /// 1-based index of the node within its level, 0 for the root.
+    pub package_relative_index: u64,
+    targets_per_level: u64,
+    max_depth: u64,
+}
+
+impl ID {
+    pub fn new(id: u64, targets_per_level: u64, max_depth: u64) -> Self {
+        let mut level = 0;
+        let mut ancestor = id;
+        while ancestor != 0 {
+            ancestor = (ancestor - 1) / targets_per_level;
+            level += 1;
+        }
+
+        let package_relative_index = if id > 0 {
+            1 + id - num_nodes_in_ntree(targets_per_level, level - 1)
+        } else {
+            0
+        };
+
+        ID {
+            id,
+            level,
+            package_relative_index,
+            targets_per_level,
+            max_depth,
+        }
+    }
+
+    /// Which of `islands` this library belongs to: the first-level libraries are split into
+    /// contiguous blocks, and the ones below belong to the block of their first-level ancestor in
+    /// the full tree.
+    pub fn island(&self, islands: u64) -> u64 {
+        let mut id = self.id;
+        while id > self.targets_per_level {
+            id = (id - 1) / self.targets_per_level;
+        }
+        (id.max(1) - 1) * islands / self.targets_per_level
+    }
+
+    /// The node directly above this one, `None` for the root.
+    pub fn parent(&self) -> Option<ID> {
+        if self.id == 0 {
+            return None;
+        }
+        if let Some(topology) = topology() {
+            let parent = topology.parents[self.id as usize];
+            return Some(ID::new(parent, self.targets_per_level, self.max_depth));
+        }
+        Some(ID {
+            id: (self.id - 1) / self.targets_per_level,
+            level: self.level - 1,
+            package_relative_index: if self.level == 1 {
+                0
+            } else {
+                (self.package_relative_index - 1) / self.targets_per_level + 1
+            },
+            targets_per_level: self.targets_per_level,
+            max_depth: self.max_depth,
+        })
+    }
+
+    /// Whether this node is on the last level of the graph.
+    pub fn is_leaf(&self) -> bool {
+        self.level as u64 >= self.max_depth
+    }
+
+    pub fn package_path(&self) -> PathBuf {
+        level_path(self.level as usize)
+    }
+
+    /// Name of the library target of this node, which is also the name of its directory.
+    pub fn target_name(&self) -> String {
+        naming().target_name(self)
+    }
+
+    pub fn lib_path(&self) -> PathBuf {
+        self.package_path().join(self.target_name())
+    }
+
+    /// Label of the library target of this node.
+    pub fn label(&self) -> String {
+        format!("//{}", self.lib_path().to_str().unwrap())
+    }
+
+    /// Module name of the library of this node, a valid identifier for every backend.
+    pub fn lib_name(&self) -> String {
+        naming().lib_name(self)
+    }
+
+    pub fn children(&self) -> Vec<ID> {
+        if self.is_leaf() {
+            return vec![];
+        }
+        if let Some(topology) = topology() {
+            let id = self.id as usize;
+            return topology.children[topology.child_offsets[id]..topology.child_offsets[id + 1]]
+                .iter()
+                .map(|child| ID::new(*child, self.targets_per_level, self.max_depth))
+                .collect();
+        }
+
+        (0..self.targets_per_level)
+            .map(|i| ID {
+                id: self.id * self.targets_per_level + i + 1,
+                level: self.level + 1,
+                package_relative_index: if self.level == 0 {
+                    i + 1
+                } else {
+                    self.targets_per_level * (self.package_relative_index - 1) + i + 1
+                },
+                targets_per_level: self.targets_per_level,
+                max_depth: self.max_depth,
+            })
+            .collect()
+    }
+}
+
+impl Display for ID {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{:?}", self.lib_path())
+    }
+}
+
+/// Number of nodes of a complete tree of `height` levels below the root, the root included. With a
+/// single target per level the tree is a chain.
+pub fn num_nodes_in_ntree(targets_per_level: u64, height: u32) -> u64 {
+    if targets_per_level == 1 {
+        return height as u64 + 1;
+    }
+    (targets_per_level.pow(height + 1) - 1) / (targets_per_level - 1)
+}
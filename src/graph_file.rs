@@ -0,0 +1,166 @@
+//! `--export-graph` and `--from-graph`: the generator's model of the graph as JSON, to replay the
+//! same graph later, with another backend, or after editing it by hand.
+//!
+//! Every node is listed by id with its level, index within the level, module name, label, the
+//! children it hangs off in the tree and all of its dependencies. The children and names are read
+//! back. The dependencies follow from the tree and `--skip-level-deps`, `--sibling-deps` and the
+//! other flags of the generation, so they are only checked against what those flags give:
+//! replaying with other flags or editing them fails rather than silently generating other edges.
+//! Moving a library to another parent or renaming it edits the graph, its id, level and index stay
+//! what the shape implies.
+
+use crate::graph::{num_nodes_in_ntree, Topology, ID};
+use crate::{metrics, Args};
+use anyhow::{bail, format_err};
+use serde_json::{json, Value};
+use std::collections::BTreeSet;
+use std::path::Path;
+
+/// Version of the file layout, bumped on incompatible changes.
+const FORMAT_VERSION: u64 = 1;
+
+/// Writes the graph of `args` to `path`, with the topology and symbol pool already set up.
+pub fn export(args: &Args, path: &Path) -> anyhow::Result<()> {
+    let num_nodes = num_nodes_in_ntree(args.targets_per_level, args.height);
+    let nodes: Vec<_> = (0..num_nodes)
+        .map(|id| {
+            let node = ID::new(id, args.targets_per_level, args.height as u64);
+            json!({
+                "id": id,
+                "level": node.level,
+                "index": node.package_relative_index,
+                "name": (id > 0).then(|| node.lib_name()),
+                "label": (id > 0).then(|| node.label()),
+                "children": node.children().iter().map(|child| child.id).collect::<Vec<_>>(),
+                "deps": metrics::dependencies(id, args),
+            })
+        })
+        .collect();
+    let graph = json!({
+        "format_version": FORMAT_VERSION,
+        "height": args.height,
+        "targets_per_level": args.targets_per_level,
+        "nodes": nodes,
+    });
+    std::fs::write(path, serde_json::to_string_pretty(&graph)?)
+        .map_err(|e| format_err!("{}: {}", path.display(), e))
+}
+
+/// Reads the graph at `path` for `args`, returning its tree, the module names and the
+/// dependencies by id. The dependencies take `check_deps` once the tree is in place.
+pub fn import(
+    args: &Args,
+    path: &Path,
+) -> anyhow::Result<(Topology, Vec<String>, Vec<BTreeSet<u64>>)> {
+    let content =
+        std::fs::read_to_string(path).map_err(|e| format_err!("{}: {}", path.display(), e))?;
+    let graph: Value = serde_json::from_str(&content)
+        .map_err(|e| format_err!("{} isn't valid JSON: {}", path.display(), e))?;
+    if graph["format_version"].as_u64() != Some(FORMAT_VERSION) {
+        bail!(
+            "{} has format version {}, expected {}",
+            path.display(),
+            graph["format_version"],
+            FORMAT_VERSION
+        );
+    }
+    if graph["height"].as_u64() != Some(args.height as u64)
+        || graph["targets_per_level"].as_u64() != Some(args.targets_per_level)
+    {
+        bail!(
+            "{} has height {} and {} targets per level, pass the same --height and --targets-per-level",
+            path.display(),
+            graph["height"],
+            graph["targets_per_level"]
+        );
+    }
+
+    let num_nodes = num_nodes_in_ntree(args.targets_per_level, args.height) as usize;
+    let nodes = graph["nodes"]
+        .as_array()
+        .filter(|nodes| nodes.len() == num_nodes)
+        .ok_or_else(|| format_err!("{} has to list all {} nodes", path.display(), num_nodes))?;
+    let mut parents: Vec<Option<u64>> = vec![None; num_nodes];
+    let mut names = vec![String::new(); num_nodes];
+    let mut deps = vec![BTreeSet::new(); num_nodes];
+    for (id, node) in nodes.iter().enumerate() {
+        if node["id"].as_u64() != Some(id as u64) {
+            bail!("node {} of {} has id {}", id, path.display(), node["id"]);
+        }
+        let level = ID::new(id as u64, args.targets_per_level, args.height as u64).level;
+        if id > 0 {
+            let name = node["name"]
+                .as_str()
+                .ok_or_else(|| format_err!("library {} has no name", id))?;
+            if name.starts_with(|c: char| c.is_ascii_digit())
+                || !name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_')
+            {
+                bail!(
+                    "library {} is named {}, names must be letters, digits and underscores, not starting with a digit",
+                    id,
+                    name
+                );
+            }
+            names[id] = name.to_string();
+        }
+        deps[id] = node["deps"]
+            .as_array()
+            .and_then(|deps| deps.iter().map(Value::as_u64).collect::<Option<_>>())
+            .ok_or_else(|| format_err!("node {} has no deps list of ids", id))?;
+        let children = node["children"]
+            .as_array()
+            .ok_or_else(|| format_err!("node {} has no children list", id))?;
+        for child in children {
+            let child = child
+                .as_u64()
+                .filter(|child| (1..num_nodes as u64).contains(child))
+                .ok_or_else(|| format_err!("node {} has child {}, not a library", id, child))?;
+            let child_level = ID::new(child, args.targets_per_level, args.height as u64).level;
+            if child_level <= level {
+                bail!(
+                    "library {} on level {} can't be a child of node {} on level {}, children have to be on a level below",
+                    child,
+                    child_level,
+                    id,
+                    level
+                );
+            }
+            if let Some(parent) = parents[child as usize].replace(id as u64) {
+                bail!(
+                    "library {} is a child of both {} and {}, every library has one parent",
+                    child,
+                    parent,
+                    id
+                );
+            }
+        }
+    }
+    let parents = parents
+        .iter()
+        .enumerate()
+        .map(|(id, parent)| match parent {
+            _ if id == 0 => Ok(0),
+            Some(parent) => Ok(*parent),
+            None => Err(format_err!("library {} isn't a child of any node", id)),
+        })
+        .collect::<anyhow::Result<Vec<_>>>()?;
+    Ok((Topology::from_parents(parents), names, deps))
+}
+
+/// Fails unless the `deps` of the graph at `path` are the dependencies the imported tree and the
+/// flags of `args` give every node.
+pub fn check_deps(args: &Args, path: &Path, deps: &[BTreeSet<u64>]) -> anyhow::Result<()> {
+    for (id, deps) in deps.iter().enumerate() {
+        let implied: BTreeSet<_> = metrics::dependencies(id as u64, args).into_iter().collect();
+        if *deps != implied {
+            bail!(
+                "node {} of {} depends on {:?}, the flags of this generation give it {:?}: the deps follow from the tree, pass the --skip-level-deps, --sibling-deps and other dependency flags the graph was exported with",
+                id,
+                path.display(),
+                deps,
+                implied
+            );
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,117 @@
+//! `--post-hook`: commands run over the generated workspace before its metadata is written, so
+//! formatters, license header inserters and generators of extra files plug into the generation
+//! without patching it. A hook runs with `sh -c`, either once in the workspace or once in the
+//! directory of every library, and finds what it runs on in its environment.
+
+use crate::graph::{num_nodes_in_ntree, ID};
+use crate::{layers, Args, WRITERS};
+use anyhow::{bail, format_err};
+use std::path::Path;
+use std::process::Command;
+use std::str::FromStr;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Mutex;
+use tracing::info;
+
+/// Where the `--post-hook` commands run.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum HookScope {
+    /// Once, in the workspace.
+    Once,
+    /// Once per library, in its package.
+    Package,
+}
+
+impl FromStr for HookScope {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "once" => Ok(HookScope::Once),
+            "package" => Ok(HookScope::Package),
+            _ => bail!("unknown hook scope {}, expected once or package", s),
+        }
+    }
+}
+
+/// Runs `hook` in `dir` with the variables of the workspace of `args` and `vars`.
+fn run_hook(
+    hook: &str,
+    dir: &Path,
+    workspace: &Path,
+    args: &Args,
+    vars: &[(&str, String)],
+) -> anyhow::Result<()> {
+    let status = Command::new("sh")
+        .arg("-c")
+        .arg(hook)
+        .current_dir(dir)
+        .env("BENCHMARK_OUTPUT", workspace)
+        .env("BENCHMARK_LANGUAGE", args.language.as_str())
+        .env("BENCHMARK_HEIGHT", args.height.to_string())
+        .env(
+            "BENCHMARK_TARGETS_PER_LEVEL",
+            args.targets_per_level.to_string(),
+        )
+        .envs(vars.iter().map(|(name, value)| (name, value)))
+        .status()
+        .map_err(|e| format_err!("running --post-hook {}: {}", hook, e))?;
+    if !status.success() {
+        bail!(
+            "--post-hook {} failed in {} with {}",
+            hook,
+            dir.display(),
+            status
+        );
+    }
+    Ok(())
+}
+
+/// Runs the `--post-hook` commands of `args` over its workspace, in order.
+pub fn run(args: &Args) -> anyhow::Result<()> {
+    let workspace = args.output.canonicalize()?;
+    for hook in &args.post_hook {
+        info!("running --post-hook {}", hook);
+        if args.post_hook_scope == HookScope::Once {
+            run_hook(hook, &workspace, &workspace, args, &[])?;
+            continue;
+        }
+
+        // Libraries don't share packages, so their hooks can run side by side.
+        let num_nodes = num_nodes_in_ntree(args.targets_per_level, args.height);
+        let next = AtomicU64::new(1);
+        let error = Mutex::new(None);
+        std::thread::scope(|scope| {
+            for _ in 0..WRITERS {
+                scope.spawn(|| loop {
+                    let id = next.fetch_add(1, Ordering::Relaxed);
+                    if id >= num_nodes || error.lock().unwrap().is_some() {
+                        break;
+                    }
+                    let node = ID::new(id, args.targets_per_level, args.height as u64);
+                    let dir = workspace.join(
+                        layers::lib_dir(&node, args)
+                            .strip_prefix(&args.output)
+                            .unwrap(),
+                    );
+                    let vars = [
+                        (
+                            "BENCHMARK_PACKAGE",
+                            dir.strip_prefix(&workspace).unwrap().display().to_string(),
+                        ),
+                        ("BENCHMARK_LABEL", layers::label(&node, args)),
+                        ("BENCHMARK_MODULE", node.lib_name()),
+                        ("BENCHMARK_LEVEL", node.level.to_string()),
+                    ];
+                    if let Err(e) = run_hook(hook, &dir, &workspace, args, &vars) {
+                        error.lock().unwrap().get_or_insert(e);
+                    }
+                });
+            }
+        });
+        if let Some(e) = error.into_inner().unwrap() {
+            return Err(e);
+        }
+    }
+    Ok(())
+}
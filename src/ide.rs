@@ -0,0 +1,96 @@
+//! IDE project files for the generated workspace, so that IDE sync can be benchmarked without
+//! hand writing a project view for every graph size.
+
+use crate::graph::level_path;
+use crate::layers;
+use crate::{backend, Args};
+use anyhow::bail;
+use std::io::Write;
+use std::str::FromStr;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Ide {
+    Intellij,
+    Vscode,
+}
+
+impl FromStr for Ide {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "intellij" => Ok(Ide::Intellij),
+            "vscode" => Ok(Ide::Vscode),
+            _ => bail!("unknown ide {}, expected intellij or vscode", s),
+        }
+    }
+}
+
+/// Bazel plugin languages to enable on top of the default (java) one.
+fn additional_languages(args: &Args) -> Vec<&'static str> {
+    backend::backend(args.language).ide_languages(args)
+}
+
+/// Writes a `.bazelproject` project view importing every package of the graph, with one target
+/// pattern per repository the libraries are spread over.
+fn write_bazelproject(args: &Args) -> anyhow::Result<()> {
+    let mut f = std::fs::File::create(args.output.join(".bazelproject"))?;
+    let top = level_path(1);
+    let top = top.iter().next().unwrap().to_str().unwrap();
+
+    writeln!(f, "directories:\n  {}", top)?;
+    if args.output.join("tools").exists() {
+        writeln!(f, "  tools")?;
+    }
+    let repositories = layers::repositories(args);
+    if let Some((_, dir)) = repositories.first() {
+        let root = dir.iter().next().unwrap().to_str().unwrap();
+        writeln!(f, "  {}", root)?;
+    }
+
+    writeln!(f, "\ntargets:\n  //:all\n  //{}/...", top)?;
+    for (name, _) in &repositories {
+        writeln!(f, "  @{}//...", name)?;
+    }
+
+    writeln!(f, "\nderive_targets_from_directories: false")?;
+    let languages = additional_languages(args);
+    if !languages.is_empty() {
+        writeln!(f, "\nadditional_languages:")?;
+        for language in languages {
+            writeln!(f, "  {}", language)?;
+        }
+    }
+    Ok(())
+}
+
+/// Writes `.vscode/settings.json`, keeping the editor from watching and indexing the Bazel output
+/// trees.
+fn write_vscode_settings(args: &Args) -> anyhow::Result<()> {
+    let dir = args.output.join(".vscode");
+    std::fs::create_dir_all(&dir)?;
+    std::fs::write(
+        dir.join("settings.json"),
+        r#"{
+    "files.watcherExclude": {
+        "**/bazel-*/**": true
+    },
+    "search.exclude": {
+        "**/bazel-*": true
+    },
+    "bazel.enableCodeLens": true
+}
+"#,
+    )?;
+    Ok(())
+}
+
+pub fn write_ide_files(args: &Args) -> anyhow::Result<()> {
+    for ide in &args.ide {
+        match ide {
+            Ide::Intellij => write_bazelproject(args)?,
+            Ide::Vscode => write_vscode_settings(args)?,
+        }
+    }
+    Ok(())
+}
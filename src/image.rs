@@ -0,0 +1,174 @@
+//! `--output-format ext4-image` and `sparse-image`: the workspace as a filesystem image to mount
+//! on the benchmark machines, since one image copies much faster than the millions of files in
+//! it. The staged workspace is packed by `mke2fs -d` with a fixed UUID, hash seed and time, so
+//! identical flags give identical images. `sparse-image` then rewrites the image in the Android
+//! sparse format, without its unused blocks, for transfers that don't keep the holes of a raw
+//! image; `simg2img` turns it back into a mountable one.
+
+use anyhow::{bail, format_err};
+use std::io::{BufWriter, Read, Seek, SeekFrom, Write};
+use std::path::Path;
+use std::process::Command;
+use std::str::FromStr;
+use tracing::info;
+
+/// UUID and directory hash seed of every image.
+const IMAGE_UUID: &str = "0ba2e1b0-0000-4000-8000-000000000000";
+
+const BLOCK_SIZE: u64 = 4096;
+
+/// Most blocks of a sparse chunk, whose size in bytes has to fit 32 bits.
+const MAX_CHUNK_BLOCKS: u32 = 1 << 16;
+
+/// A size in bytes, with an optional `K`, `M`, `G` or `T` binary suffix, e.g. `512M`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ImageSize(pub u64);
+
+impl FromStr for ImageSize {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (value, shift) = match s.char_indices().last() {
+            Some((i, 'K')) => (&s[..i], 10),
+            Some((i, 'M')) => (&s[..i], 20),
+            Some((i, 'G')) => (&s[..i], 30),
+            Some((i, 'T')) => (&s[..i], 40),
+            _ => (s, 0),
+        };
+        let value: u64 = value
+            .parse()
+            .map_err(|_| format_err!("{} isn't a size, e.g. 512M or 4G", s))?;
+        value
+            .checked_mul(1 << shift)
+            .map(ImageSize)
+            .ok_or_else(|| format_err!("{} is too large", s))
+    }
+}
+
+/// Size and number of inodes of an image with room for the content of `dir`: the blocks of its
+/// files and directories with a quarter more for the filesystem metadata, and the journal.
+fn estimate(dir: &Path) -> anyhow::Result<(u64, u64)> {
+    let (mut blocks, mut inodes) = (0, 0);
+    let mut dirs = vec![dir.to_path_buf()];
+    while let Some(dir) = dirs.pop() {
+        blocks += 1;
+        for entry in std::fs::read_dir(dir)? {
+            let entry = entry?;
+            let file_type = entry.file_type()?;
+            inodes += 1;
+            if file_type.is_dir() {
+                dirs.push(entry.path());
+            } else if file_type.is_file() {
+                blocks += entry.metadata()?.len().div_ceil(BLOCK_SIZE);
+            }
+        }
+    }
+    let size = (blocks + blocks / 4) * BLOCK_SIZE + (64 << 20);
+    Ok((size, inodes + inodes / 8 + 1024))
+}
+
+/// Writes the ext4 image `image` of `dir`, of `size` bytes or as large as its content needs,
+/// with `mtime` as the time of the filesystem and `dir` already normalized to it.
+pub fn write_ext4(
+    dir: &Path,
+    image: &Path,
+    size: Option<ImageSize>,
+    mtime: u64,
+) -> anyhow::Result<()> {
+    let (estimate, inodes) = estimate(dir)?;
+    let size = size.map_or(estimate, |size| size.0);
+    std::fs::remove_file(image).unwrap_or(());
+    let output = Command::new("mke2fs")
+        .args([
+            "-q", "-F", "-t", "ext4", "-b", "4096", "-m", "0", "-I", "256",
+        ])
+        .arg("-N")
+        .arg(inodes.to_string())
+        .arg("-U")
+        .arg(IMAGE_UUID)
+        .arg("-E")
+        .arg(format!("hash_seed={},root_owner=0:0", IMAGE_UUID))
+        .arg("-d")
+        .arg(dir)
+        .arg(image)
+        .arg(format!(
+            "{}k",
+            size.div_ceil(BLOCK_SIZE) * BLOCK_SIZE / 1024
+        ))
+        .env("E2FSPROGS_FAKE_TIME", mtime.to_string())
+        .output()
+        .map_err(|e| {
+            format_err!(
+                "running mke2fs: {}, ext4 images need e2fsprogs 1.43 or later",
+                e
+            )
+        })?;
+    if !output.status.success() {
+        bail!(
+            "mke2fs failed with {}, pass a larger --image-size if the image is full: {}",
+            output.status,
+            String::from_utf8_lossy(&output.stderr).trim()
+        );
+    }
+    info!("wrote a {} byte ext4 image", size);
+    Ok(())
+}
+
+/// Rewrites the raw image `raw` in the Android sparse format to `sparse`, every run of zero
+/// blocks left out as a "don't care" chunk.
+pub fn write_sparse(raw: &Path, sparse: &Path) -> anyhow::Result<()> {
+    const RAW: u16 = 0xcac1;
+    const DONT_CARE: u16 = 0xcac3;
+
+    let len = std::fs::metadata(raw)?.len();
+    if len % BLOCK_SIZE != 0 {
+        bail!("{} isn't a whole number of blocks", raw.display());
+    }
+    let blocks = len / BLOCK_SIZE;
+
+    // The header counts the chunks, so they are found before anything is written.
+    let mut chunks: Vec<(bool, u32)> = vec![];
+    let mut file = std::fs::File::open(raw)?;
+    let mut block = vec![0; BLOCK_SIZE as usize];
+    for _ in 0..blocks {
+        file.read_exact(&mut block)?;
+        let data = block.iter().any(|&b| b != 0);
+        match chunks.last_mut() {
+            Some((kind, count)) if *kind == data && *count < MAX_CHUNK_BLOCKS => *count += 1,
+            _ => chunks.push((data, 1)),
+        }
+    }
+
+    let mut out = BufWriter::new(std::fs::File::create(sparse)?);
+    out.write_all(&0xed26_ff3a_u32.to_le_bytes())?;
+    out.write_all(&1u16.to_le_bytes())?;
+    out.write_all(&0u16.to_le_bytes())?;
+    out.write_all(&28u16.to_le_bytes())?;
+    out.write_all(&12u16.to_le_bytes())?;
+    out.write_all(&(BLOCK_SIZE as u32).to_le_bytes())?;
+    out.write_all(&u32::try_from(blocks)?.to_le_bytes())?;
+    out.write_all(&u32::try_from(chunks.len())?.to_le_bytes())?;
+    out.write_all(&0u32.to_le_bytes())?;
+    let mut file = std::fs::File::open(raw)?;
+    for (data, count) in chunks {
+        let (kind, payload) = if data {
+            (RAW, count as u64 * BLOCK_SIZE)
+        } else {
+            (DONT_CARE, 0)
+        };
+        out.write_all(&kind.to_le_bytes())?;
+        out.write_all(&0u16.to_le_bytes())?;
+        out.write_all(&count.to_le_bytes())?;
+        out.write_all(&u32::try_from(12 + payload)?.to_le_bytes())?;
+        if data {
+            for _ in 0..count {
+                file.read_exact(&mut block)?;
+                out.write_all(&block)?;
+            }
+        } else {
+            file.seek(SeekFrom::Current((count as u64 * BLOCK_SIZE) as i64))?;
+        }
+    }
+    out.flush()?;
+    Ok(())
+}
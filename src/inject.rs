@@ -0,0 +1,161 @@
+//! `--inject-errors`: breaks a seeded set of libraries of the generated workspace on purpose, with
+//! a compile error in a source or, with `--inject-analysis-errors`, a dependency that fails the
+//! analysis of the library. `injected_errors.json` lists where, for benchmarks of how fast and
+//! how completely the errors get reported. `--inject-cycles` likewise closes dependency cycles,
+//! listed in `injected_cycles.json`.
+
+use crate::graph::{num_nodes_in_ntree, ID};
+use crate::starlark::{self, quote};
+use crate::{apple, layers, mutate, node_fraction, Args};
+use anyhow::Context;
+use serde_json::json;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+
+const INJECT_SALT: u64 = 12;
+const CYCLE_SALT: u64 = 19;
+
+pub const MANIFEST: &str = "injected_errors.json";
+pub const CYCLES_MANIFEST: &str = "injected_cycles.json";
+
+/// The `count` libraries to break for `seed`, in id order. Prebuilt frameworks have no sources
+/// to break, so they are never picked.
+fn pick_libraries(args: &Args, seed: u64, count: u64) -> Vec<ID> {
+    let mut libraries: Vec<_> = (1..num_nodes_in_ntree(args.targets_per_level, args.height))
+        .map(|id| ID::new(id, args.targets_per_level, args.height as u64))
+        .filter(|node| !apple::is_prebuilt(node, args))
+        .collect();
+    let key = |node: &ID| node_fraction(node.id ^ seed.wrapping_mul(0x1000_0000_01B3), INJECT_SALT);
+    libraries.sort_by(|a, b| key(a).partial_cmp(&key(b)).unwrap());
+    libraries.truncate(count as usize);
+    libraries.sort_by_key(|node| node.id);
+    libraries
+}
+
+/// A line that doesn't compile in the language of `path`, `None` for headers and languages
+/// that aren't compiled.
+fn error_line(path: &Path) -> Option<String> {
+    let stem = path.file_stem()?.to_str()?;
+    match path.extension()?.to_str()? {
+        "m" => Some("#error injected error".to_string()),
+        "java" => Some(format!(
+            "class {}InjectedError {{ int value = \"injected error\"; }}",
+            stem
+        )),
+        "kt" => Some("private val injectedError: Int = \"injected error\"".to_string()),
+        "go" => Some("var _ int = \"injected error\"".to_string()),
+        "rs" => Some("const _: u32 = \"injected error\";".to_string()),
+        _ => None,
+    }
+}
+
+/// Appends a compile error to the first source of `node` that compiles, returning the file.
+fn inject_compile_error(node: &ID, args: &Args) -> anyhow::Result<PathBuf> {
+    let (file, line) = mutate::source_files(&layers::lib_dir(node, args))?
+        .into_iter()
+        .find_map(|file| error_line(&file).map(|line| (file, line)))
+        .unwrap();
+    let mut f = std::fs::OpenOptions::new()
+        .append(true)
+        .open(&file)
+        .with_context(|| format!("opening {}", file.display()))?;
+    writeln!(f, "{}", line)?;
+    Ok(file)
+}
+
+/// Adds the `BUILD.bazel` of `node` to the dependencies of its library. A source file has none of
+/// the providers a library dependency needs, which fails the analysis of the library. It goes
+/// through `add_dep` like the edges of `--inject-cycles`, so `select()` valued deps stay valid
+/// Starlark and `--alias-layers` break the library rule rather than its alias.
+fn inject_analysis_error(node: &ID, args: &Args) -> anyhow::Result<PathBuf> {
+    add_dep(node, args, "BUILD.bazel", "")
+}
+
+/// Appends `dep` to the dependencies of the library of `node` in its `BUILD.bazel`, followed by
+/// `comment` on the line, and returns the file.
+fn add_dep(node: &ID, args: &Args, dep: &str, comment: &str) -> anyhow::Result<PathBuf> {
+    let file = layers::lib_dir(node, args).join("BUILD.bazel");
+    let rule = layers::library_rule_name(&node.target_name(), args);
+    starlark::append_deps(&file, &rule, &format!("[{}]", quote(dep)), comment)?;
+    Ok(file)
+}
+
+/// Breaks `--inject-errors` libraries and writes the manifest of what broke where.
+pub fn inject_errors(args: &Args) -> anyhow::Result<()> {
+    let kind = if args.inject_analysis_errors {
+        "analysis"
+    } else {
+        "compile"
+    };
+    let errors = pick_libraries(args, args.seed, args.inject_errors)
+        .iter()
+        .map(|node| {
+            let file = if args.inject_analysis_errors {
+                inject_analysis_error(node, args)?
+            } else {
+                inject_compile_error(node, args)?
+            };
+            Ok(json!({
+                "label": layers::label(node, args),
+                "kind": kind,
+                "file": file.strip_prefix(&args.output).unwrap(),
+            }))
+        })
+        .collect::<anyhow::Result<Vec<_>>>()?;
+    std::fs::write(
+        args.output.join(MANIFEST),
+        serde_json::to_string_pretty(&errors)?,
+    )?;
+    Ok(())
+}
+
+/// Closes `--inject-cycles` dependency cycles, each from a seeded library below the first level
+/// back to its ancestor on the first level, which depends on it through the tree, and writes the
+/// manifest of every cycle's libraries in dependency order. The edges closing them are marked with
+/// a comment in the `BUILD.bazel` files.
+pub fn inject_cycles(args: &Args) -> anyhow::Result<()> {
+    let mut libraries: Vec<_> = (1..num_nodes_in_ntree(args.targets_per_level, args.height))
+        .map(|id| ID::new(id, args.targets_per_level, args.height as u64))
+        .filter(|node| node.level > 1 && !apple::is_prebuilt(node, args))
+        .collect();
+    let key = |node: &ID| {
+        node_fraction(
+            node.id ^ args.seed.wrapping_mul(0x1000_0000_01B3),
+            CYCLE_SALT,
+        )
+    };
+    libraries.sort_by(|a, b| key(a).partial_cmp(&key(b)).unwrap());
+    libraries.truncate(args.inject_cycles as usize);
+    libraries.sort_by_key(|node| node.id);
+
+    let cycles = libraries
+        .iter()
+        .map(|node| {
+            let mut path: Vec<_> = std::iter::successors(Some(*node), ID::parent)
+                .take_while(|ancestor| ancestor.level > 0)
+                .collect();
+            path.reverse();
+            let file = add_dep(
+                node,
+                args,
+                &layers::label(&path[0], args),
+                "  # injected cycle",
+            )?;
+            let labels: Vec<_> = path
+                .iter()
+                .chain([&path[0]])
+                .map(|library| layers::label(library, args))
+                .collect();
+            Ok(json!({
+                "label": layers::label(node, args),
+                "cycle": labels,
+                "file": file.strip_prefix(&args.output).unwrap(),
+            }))
+        })
+        .collect::<anyhow::Result<Vec<_>>>()?;
+    std::fs::write(
+        args.output.join(CYCLES_MANIFEST),
+        serde_json::to_string_pretty(&cycles)?,
+    )?;
+    Ok(())
+}
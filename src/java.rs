@@ -0,0 +1,223 @@
+//! Plain JVM backend: `java_library` targets whose Java packages mirror their directories.
+
+use crate::backend::EmitBackend;
+use crate::graph::ID;
+use crate::layers;
+use crate::starlark::{quote, string_list, BuildFile, Rule, RuleRef};
+use crate::{third_party, Args};
+use itertools::Itertools;
+use std::io::{BufWriter, Write};
+use std::path::Path;
+
+pub const LIBRARY_LOAD: &str = "@rules_java//java:defs.bzl";
+pub const LIBRARY_RULE: &str = "java_library";
+
+/// Java package of the library generated for `node`, matching its path relative to the
+/// workspace root.
+fn java_package(node: &ID) -> String {
+    node.lib_path().to_str().unwrap().replace('/', ".")
+}
+
+pub fn handle_root(args: &Args) -> anyhow::Result<()> {
+    let mut build = BuildFile::new();
+    build.load(LIBRARY_LOAD, "java_binary");
+    let mut binary = Rule::new("java_binary", "root")
+        .attr("srcs", string_list(["Main.java"]))
+        .attr("main_class", quote("Main"))
+        .attr("deps", string_list(first_level(args).map(|n| n.label())));
+    if let Some(data) = layers::runfiles(&args.output, args)? {
+        binary.set("data", data);
+    }
+    build.add(binary);
+    build.write(&args.output.join("BUILD.bazel"))?;
+
+    let mut f = BufWriter::new(std::fs::File::create(args.output.join("Main.java"))?);
+    let value = first_level(args)
+        .map(|n| format!("{}.{}_Src1.value()", java_package(&n), n.lib_name()))
+        .join(" + ");
+    writeln!(
+        f,
+        r#"public class Main {{
+    public static void main(String[] args) {{
+        System.out.println({});
+    }}
+}}"#,
+        value
+    )?;
+    Ok(())
+}
+
+fn first_level(args: &Args) -> impl Iterator<Item = ID> {
+    let (targets_per_level, height) = (args.targets_per_level, args.height as u64);
+    (1..=targets_per_level).map(move |i| ID::new(i, targets_per_level, height))
+}
+
+pub fn handle_node(node: &ID, args: &Args) -> anyhow::Result<()> {
+    let lib_dir = layers::lib_dir(node, args);
+    std::fs::create_dir_all(&lib_dir)?;
+
+    let srcs = (1..=args.files_per_target.for_node(node))
+        .map(|i| format!("{}_Src{}.java", node.lib_name(), i));
+    let mut rule = Rule::new(LIBRARY_RULE, node.target_name())
+        .attr("srcs", layers::srcs(args, srcs, &["*.java"], &[]));
+    if args.copts_per_target > 0 {
+        let javacopts = layers::generated_flags(args, args.copts_per_target, |i, value| {
+            format!("-Abench_copt_{}={}", i, value)
+        });
+        rule.set("javacopts", string_list(javacopts));
+    }
+
+    let deps = layers::dep_labels(node, &layers::direct_deps(node, args), args);
+    if args.reexport_deps {
+        rule.set("exports", string_list(&deps));
+    }
+
+    let mut build = BuildFile::new();
+    layers::add_library(&mut build, node, args, LIBRARY_LOAD, rule, deps)?;
+    build.write(&lib_dir.join("BUILD.bazel"))?;
+
+    let mut imported = layers::imported_deps(node, node.children(), args);
+    imported.extend(layers::undeclared_import(node, args));
+    for i in 1..=args.files_per_target.for_node(node) {
+        write_java_file(&lib_dir, node, &imported, i, java_package, None)?;
+    }
+    Ok(())
+}
+
+/// Writes the `i`th Java source of `node`. Each source file references the first class of every
+/// library of `deps`, so the dependency edges are real at the language level and not just declared
+/// in the BUILD files. `extra_term` is added to the computed value, e.g. to reference resources.
+pub fn write_java_file(
+    lib_dir: &Path,
+    node: &ID,
+    deps: &[ID],
+    i: u64,
+    java_package: fn(&ID) -> String,
+    extra_term: Option<String>,
+) -> anyhow::Result<()> {
+    let class = format!("{}_Src{}", node.lib_name(), i);
+    let mut f = BufWriter::new(std::fs::File::create(
+        lib_dir.join(format!("{}.java", class)),
+    )?);
+
+    writeln!(f, "package {};", java_package(node))?;
+    if !deps.is_empty() {
+        writeln!(f)?;
+    }
+    for dep in deps {
+        writeln!(f, "import {}.{}_Src1;", java_package(dep), dep.lib_name())?;
+    }
+
+    let mut value = format!("{}", i);
+    if let Some(term) = extra_term {
+        value.push_str(&format!(" + {}", term));
+    }
+    for dep in deps {
+        value.push_str(&format!(" + {}_Src1.value()", dep.lib_name()));
+    }
+
+    writeln!(
+        f,
+        r#"
+public class {} {{
+    public static int value() {{
+        return {};
+    }}
+}}"#,
+        class, value
+    )?;
+    Ok(())
+}
+
+pub fn write_workspace(args: &Args) -> anyhow::Result<()> {
+    let mut f = std::fs::File::create(args.output.join("WORKSPACE"))?;
+    writeln!(f, r#"workspace(name = "bazel_benchmark")"#)?;
+    Ok(())
+}
+
+/// The language dependent flags the java backend implements.
+const SUPPORTED_FLAGS: &[&str] = &[
+    "--external-repos",
+    "--layout nested-workspaces",
+    "--umbrella-per-level",
+    "--emit-env",
+    "--runfiles-per-test",
+    "--third-party-packages",
+    "--cc-toolchain-config",
+    "--stamped-targets",
+    "--filegroups-per-package",
+    "--emit-both-dep-systems",
+    "--inject-errors",
+    "--naming-scheme hashed",
+];
+
+pub struct Backend;
+
+impl EmitBackend for Backend {
+    fn name(&self) -> &'static str {
+        "java"
+    }
+
+    fn emit_workspace(&self, args: &Args) -> anyhow::Result<()> {
+        write_workspace(args)
+    }
+
+    fn emit_package(&self, node: &ID, args: &Args) -> anyhow::Result<()> {
+        match node.id {
+            0 => handle_root(args),
+            _ => handle_node(node, args),
+        }
+    }
+
+    fn library_rule(&self, _args: &Args) -> RuleRef {
+        RuleRef {
+            load: LIBRARY_LOAD.to_string(),
+            name: LIBRARY_RULE.to_string(),
+        }
+    }
+
+    fn umbrella(
+        &self,
+        build: &mut BuildFile,
+        _dir: &Path,
+        level: u32,
+        libraries: &[ID],
+        args: &Args,
+    ) -> anyhow::Result<Rule> {
+        let rule = self.library_rule(args);
+        build.load(&rule.load, &rule.name);
+        Ok(Rule::new(&rule.name, format!("level_{}", level)).attr(
+            "exports",
+            string_list(libraries.iter().map(|node| layers::label(node, args))),
+        ))
+    }
+
+    fn supports(&self, flag: &str) -> bool {
+        SUPPORTED_FLAGS.contains(&flag)
+    }
+
+    fn proto_binding(
+        &self,
+        _build: &mut BuildFile,
+        _args: &Args,
+    ) -> Option<(&'static str, &'static str)> {
+        Some(("java_proto_library", "java_proto"))
+    }
+
+    fn third_party_library(&self, index: u64, _args: &Args) -> Option<third_party::Library> {
+        let src = format!("ThirdParty{}.java", index);
+        Some(third_party::Library {
+            content: format!(
+                "package {}.tp_{};\n\npublic class ThirdParty{} {{\n    public static final int VERSION = 1;\n}}\n",
+                third_party::DIR, index, index
+            ),
+            load: LIBRARY_LOAD.to_string(),
+            rule: Rule::new(LIBRARY_RULE, third_party::name(index)).attr("srcs", string_list([&src])),
+            src,
+        })
+    }
+
+    fn env_tools(&self, _args: &Args) -> Vec<(&'static str, &'static str)> {
+        vec![("jdk11", "openjdk-11-jdk-headless")]
+    }
+}
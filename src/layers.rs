@@ -0,0 +1,1715 @@
+//! Language independent layers added on top of the generated library graph: aliases, visibility,
+//! genrules, selects, protos, the `.bzl` macro chain, aspects and genqueries.
+
+use crate::graph::{level_path, num_nodes_in_ntree, ID};
+use crate::starlark::{self, quote, string_list, BuildFile, Rule};
+use crate::{node_fraction, third_party, Args, HeaderDiscipline, Language};
+use anyhow::bail;
+use itertools::Itertools;
+use std::io::{BufWriter, Write};
+use std::os::unix::fs::PermissionsExt;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+
+/// Directory external repositories get generated in, relative to the output.
+pub const EXTERNAL_REPOS_DIR: &str = "repos";
+
+/// Directory the levels of `--layout symlinked` are generated in, relative to the output.
+pub const SYMLINKED_LEVELS_DIR: &str = "levels";
+
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub enum Layout {
+    /// All packages in the main repository, in one directory tree.
+    #[default]
+    Monolith,
+    /// Every level below the first in a repository of its own, whose directory is nested in the
+    /// one of the level above.
+    NestedWorkspaces,
+    /// Every level in a directory of its own, linked into the package tree of the level above.
+    Symlinked,
+}
+
+impl FromStr for Layout {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "monolith" => Ok(Layout::Monolith),
+            "nested-workspaces" => Ok(Layout::NestedWorkspaces),
+            "symlinked" => Ok(Layout::Symlinked),
+            _ => bail!(
+                "unknown layout {}, expected monolith, nested-workspaces or symlinked",
+                s
+            ),
+        }
+    }
+}
+
+/// Local repository holding `node` with `--external-repos` or `--layout nested-workspaces`. The
+/// first level stays in the main repository. External repositories get the levels below it
+/// round-robin, nested ones a level each, so every dependency edge below the first level crosses
+/// a repository boundary.
+pub fn repo(node: &ID, args: &Args) -> Option<String> {
+    if node.level < 2 {
+        return None;
+    }
+    if args.layout == Layout::NestedWorkspaces {
+        return Some(format!("nested_{}", node.level));
+    }
+    if args.external_repos == 0 {
+        return None;
+    }
+    Some(format!(
+        "ext_{}",
+        (node.level as u64 - 2) % args.external_repos + 1
+    ))
+}
+
+/// Directory of the repository of level `level` below the main one, e.g. `nested_2/nested_3`.
+fn nested_repo_dir(level: u32) -> PathBuf {
+    (2..=level)
+        .map(|level| format!("nested_{}", level))
+        .collect()
+}
+
+/// The repositories besides the main one, with their directories relative to the output.
+pub fn repositories(args: &Args) -> Vec<(String, PathBuf)> {
+    if args.layout == Layout::NestedWorkspaces {
+        return (2..=args.height)
+            .map(|level| (format!("nested_{}", level), nested_repo_dir(level)))
+            .collect();
+    }
+    (1..=args.external_repos)
+        .map(|i| {
+            let name = format!("ext_{}", i);
+            let dir = Path::new(EXTERNAL_REPOS_DIR).join(&name);
+            (name, dir)
+        })
+        .collect()
+}
+
+/// Directory of the repository holding `node`, relative to the output, `None` for the main one.
+pub fn repo_dir(node: &ID, args: &Args) -> Option<PathBuf> {
+    let repo = repo(node, args)?;
+    if args.layout == Layout::NestedWorkspaces {
+        return Some(nested_repo_dir(node.level));
+    }
+    Some(Path::new(EXTERNAL_REPOS_DIR).join(repo))
+}
+
+/// Label of the library of `node`, qualified with its repository if it isn't in the main one.
+pub fn label(node: &ID, args: &Args) -> String {
+    match repo(node, args) {
+        Some(repo) => format!("@{}{}", repo, node.label()),
+        None => node.label(),
+    }
+}
+
+/// Directory the library of `node` gets generated in.
+pub fn lib_dir(node: &ID, args: &Args) -> PathBuf {
+    match repo_dir(node, args) {
+        Some(dir) => args.output.join(dir).join(node.lib_path()),
+        None => args.output.join(node.lib_path()),
+    }
+}
+
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub enum Visibility {
+    #[default]
+    Public,
+    /// Visible to the packages below the level above, like the `--package-group-visibility`
+    /// package groups but without them.
+    Package,
+    /// Visible to exactly the packages that depend on the library.
+    ParentOnly,
+}
+
+impl FromStr for Visibility {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "public" => Ok(Visibility::Public),
+            "package" => Ok(Visibility::Package),
+            "parent-only" => Ok(Visibility::ParentOnly),
+            _ => bail!(
+                "unknown visibility {}, expected public, package or parent-only",
+                s
+            ),
+        }
+    }
+}
+
+/// The nodes whose targets depend on the library of `node`, the root standing for the root
+/// package.
+fn dependents(node: &ID, args: &Args) -> Vec<ID> {
+    let mut dependents: Vec<_> = node.parent().into_iter().collect();
+    let ancestors = std::iter::successors(node.parent(), ID::parent);
+    if args.header_discipline == HeaderDiscipline::Loose {
+        dependents.extend(ancestors.clone().nth(1));
+    }
+    dependents.extend(ancestors.filter(|ancestor| skip_level_dep(ancestor, args) == Some(*node)));
+    dependents.extend(sibling_dependents(node, args));
+    dependents
+}
+
+/// Value of the `visibility` of the library of `node`. Restricted visibility only covers packages
+/// of the main repository, libraries in `--external-repos` stay public.
+fn visibility(node: &ID, args: &Args) -> Vec<String> {
+    if repo(node, args).is_some() {
+        return vec!["//visibility:public".to_string()];
+    }
+    let pkg = |dependent: &ID| match dependent.id {
+        0 => "//:__pkg__".to_string(),
+        _ => format!("{}:__pkg__", dependent.label()),
+    };
+    let mut visibility = match args.visibility {
+        _ if args.package_group_visibility => {
+            vec![format!("//tools/visibility:level_{}", node.level)]
+        }
+        Visibility::Public => return vec!["//visibility:public".to_string()],
+        Visibility::Package => {
+            let consumers = level_path(node.level as usize - 1);
+            let mut visibility = vec![format!("//{}:__subpackages__", consumers.display())];
+            visibility.extend(
+                dependents(node, args)
+                    .iter()
+                    .filter(|dependent| dependent.level + 1 < node.level)
+                    .map(pkg),
+            );
+            visibility
+        }
+        Visibility::ParentOnly => {
+            let mut packages: Vec<_> = dependents(node, args).iter().map(pkg).collect();
+            packages.sort();
+            packages.dedup();
+            packages
+        }
+    };
+    if args.genquery_per_level > 0 && !args.package_group_visibility {
+        visibility.push("//tools/queries:__pkg__".to_string());
+    }
+    if args.level_targets && !args.package_group_visibility {
+        visibility.push("//tools/levels:__pkg__".to_string());
+    }
+    visibility
+}
+
+/// A `--tag` or `--exec-property` value, optionally restricted to a fraction of the libraries
+/// with an `@<ratio>` suffix, e.g. `no-remote@0.1`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Stamp {
+    pub value: String,
+    pub ratio: f64,
+}
+
+impl FromStr for Stamp {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (value, ratio) = match s.rsplit_once('@') {
+            Some((value, ratio)) => (value, ratio.parse()?),
+            None => (s, 1.0),
+        };
+        if !(0.0..=1.0).contains(&ratio) {
+            bail!("stamp ratio must be between 0 and 1, got {}", ratio);
+        }
+        Ok(Stamp {
+            value: value.to_string(),
+            ratio,
+        })
+    }
+}
+
+const STAMP_SALT: u64 = 6;
+
+/// Values of the `stamps` that apply to `node`. Every stamp picks its libraries independently,
+/// `offset` keeps the picks of different flags apart.
+fn stamps_for<'a>(node: &ID, stamps: &'a [Stamp], offset: usize) -> impl Iterator<Item = &'a str> {
+    let id = node.id;
+    stamps.iter().enumerate().filter_map(move |(i, stamp)| {
+        let key = id.wrapping_add(((offset + i) as u64) << 48);
+        (node_fraction(key, STAMP_SALT) < stamp.ratio).then_some(stamp.value.as_str())
+    })
+}
+
+const SKIP_LEVEL_SALT: u64 = 7;
+
+/// The `--skip-level-deps` edge of `node`, if it has one: a descendant two or more levels below,
+/// at a depth and position picked per node. The root isn't a library and has none. Descending to
+/// the position child by child keeps it a descendant with `--dep-keep-probability`, where it has
+/// none if a node on the way is childless.
+pub fn skip_level_dep(node: &ID, args: &Args) -> Option<ID> {
+    let levels_below = args.height.checked_sub(node.level)?;
+    if node.id == 0
+        || levels_below < 2
+        || node_fraction(node.id, SKIP_LEVEL_SALT) >= args.skip_level_deps
+    {
+        return None;
+    }
+    let depth =
+        2 + (node_fraction(node.id | 1 << 62, SKIP_LEVEL_SALT) * (levels_below - 1) as f64) as u32;
+    let width = args.targets_per_level.pow(depth);
+    let offset = (node_fraction(node.id | 1 << 63, SKIP_LEVEL_SALT) * width as f64) as u64;
+    (0..depth).rev().try_fold(*node, |ancestor, step| {
+        let children = ancestor.children();
+        let digit = (offset / args.targets_per_level.pow(step)) % args.targets_per_level;
+        children
+            .get(digit as usize % children.len().max(1))
+            .copied()
+    })
+}
+
+/// The `--sibling-deps` libraries of `node`: the ones right before it on its level, in its
+/// island. Only depending on lower indices keeps the graph acyclic.
+pub fn sibling_deps(node: &ID, args: &Args) -> Vec<ID> {
+    (1..=args
+        .sibling_deps
+        .min(node.package_relative_index.saturating_sub(1)))
+        .map(|k| ID::new(node.id - k, args.targets_per_level, args.height as u64))
+        .filter(|sibling| {
+            args.islands == 1 || sibling.island(args.islands) == node.island(args.islands)
+        })
+        .collect()
+}
+
+/// The libraries depending on `node` through `--sibling-deps`, the ones right after it on its
+/// level in its island.
+pub fn sibling_dependents(node: &ID, args: &Args) -> Vec<ID> {
+    let width = args.targets_per_level.pow(node.level);
+    (1..=args.sibling_deps.min(width - node.package_relative_index))
+        .map(|k| ID::new(node.id + k, args.targets_per_level, args.height as u64))
+        .filter(|sibling| {
+            args.islands == 1 || sibling.island(args.islands) == node.island(args.islands)
+        })
+        .collect()
+}
+
+/// The libraries `node` depends on directly: its children, its `--skip-level-deps` descendant and
+/// its `--sibling-deps` siblings.
+pub fn direct_deps(node: &ID, args: &Args) -> Vec<ID> {
+    let mut deps = node.children();
+    deps.extend(skip_level_dep(node, args));
+    deps.extend(sibling_deps(node, args));
+    deps
+}
+
+/// Package of the `--umbrella-per-level` libraries.
+const UMBRELLAS_DIR: &str = "umbrellas";
+
+/// Labels of the `deps` of `node`. With `--umbrella-per-level` its children are replaced by the
+/// umbrellas of their levels, the other libraries stay direct dependencies.
+pub fn dep_labels(node: &ID, deps: &[ID], args: &Args) -> Vec<String> {
+    if !args.umbrella_per_level {
+        return deps.iter().map(|dep| label(dep, args)).collect();
+    }
+    let children = node.children();
+    deps.iter()
+        .map(|dep| {
+            if children.contains(dep) {
+                format!("//{}:level_{}", UMBRELLAS_DIR, dep.level)
+            } else {
+                label(dep, args)
+            }
+        })
+        .unique()
+        .collect()
+}
+
+/// Writes the `--umbrella-per-level` library of every level below the first into
+/// `//umbrellas`, which depends on and re-exports all libraries of its level.
+pub fn write_umbrellas(args: &Args) -> anyhow::Result<()> {
+    let dir = args.output.join(UMBRELLAS_DIR);
+    std::fs::create_dir_all(&dir)?;
+    let backend = crate::backend::backend(args.language);
+    let mut build = BuildFile::new();
+    for level in 2..=args.height {
+        let first = num_nodes_in_ntree(args.targets_per_level, level - 1);
+        let libraries: Vec<_> = (first..first + args.targets_per_level.pow(level))
+            .map(|id| ID::new(id, args.targets_per_level, args.height as u64))
+            .collect();
+        let rule = backend.umbrella(&mut build, &dir, level, &libraries, args)?;
+        build.add(rule.attr("visibility", string_list(["//visibility:public"])));
+    }
+    build.write(&dir.join("BUILD.bazel"))?;
+    Ok(())
+}
+
+const UNUSED_DEPS_SALT: u64 = 8;
+
+/// The `deps` of `node` its sources actually import, all but the `--unused-deps` fraction which
+/// stay declared only.
+pub fn imported_deps(node: &ID, deps: Vec<ID>, args: &Args) -> Vec<ID> {
+    deps.into_iter()
+        .filter(|dep| {
+            let edge = node.id.wrapping_mul(0x1000_0000_01B3) ^ dep.id;
+            node_fraction(edge, UNUSED_DEPS_SALT) >= args.unused_deps
+        })
+        .collect()
+}
+
+const UNDECLARED_IMPORTS_SALT: u64 = 9;
+
+/// The `--undeclared-imports` library the sources of `node` import without declaring it: one of
+/// its grandchildren that isn't among its deps, which is reachable transitively but violates
+/// strict deps.
+pub fn undeclared_import(node: &ID, args: &Args) -> Option<ID> {
+    if node_fraction(node.id, UNDECLARED_IMPORTS_SALT) >= args.undeclared_imports {
+        return None;
+    }
+    let declared = direct_deps(node, args);
+    let candidates: Vec<_> = node
+        .children()
+        .iter()
+        .flat_map(ID::children)
+        .filter(|grandchild| !declared.contains(grandchild))
+        .collect();
+    let pick = node_fraction(node.id | 1 << 62, UNDECLARED_IMPORTS_SALT) * candidates.len() as f64;
+    candidates.get(pick as usize).copied()
+}
+
+/// Writes `undeclared_imports.txt`, the ground truth of `--undeclared-imports` with a line
+/// `<importing library> <undeclared library>` per violation.
+pub fn write_undeclared_imports(args: &Args) -> anyhow::Result<()> {
+    let mut f = BufWriter::new(std::fs::File::create(
+        args.output.join("undeclared_imports.txt"),
+    )?);
+    for id in 1..num_nodes_in_ntree(args.targets_per_level, args.height) {
+        let node = ID::new(id, args.targets_per_level, args.height as u64);
+        if let Some(import) = undeclared_import(&node, args) {
+            writeln!(f, "{} {}", label(&node, args), label(&import, args))?;
+        }
+    }
+    Ok(())
+}
+
+/// A weighted choice between values, given as `<value>@<weight>` separated by commas, e.g.
+/// `small@0.8,medium@0.2`. Weights are relative to each other, a value without one weighs 1.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Distribution<T>(Vec<(T, f64)>);
+
+impl<T: FromStr> FromStr for Distribution<T>
+where
+    anyhow::Error: From<T::Err>,
+{
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let choices = s
+            .split(',')
+            .map(|choice| {
+                let (value, weight) = match choice.rsplit_once('@') {
+                    Some((value, weight)) => (value, weight.parse::<f64>()?),
+                    None => (choice, 1.0),
+                };
+                if weight <= 0.0 {
+                    bail!("weights must be positive, got {}", weight);
+                }
+                Ok((value.parse()?, weight))
+            })
+            .collect::<anyhow::Result<_>>()?;
+        Ok(Distribution(choices))
+    }
+}
+
+impl<T> Distribution<T> {
+    pub fn values(&self) -> impl Iterator<Item = &T> {
+        self.0.iter().map(|(value, _)| value)
+    }
+
+    /// The value `fraction`, in `[0, 1)`, falls on when the values are laid out by weight.
+    pub fn pick(&self, fraction: f64) -> &T {
+        let total: f64 = self.0.iter().map(|(_, weight)| weight).sum();
+        let mut remaining = fraction * total;
+        for (value, weight) in &self.0 {
+            if remaining < *weight {
+                return value;
+            }
+            remaining -= weight;
+        }
+        &self.0.last().unwrap().0
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum TestSize {
+    Small,
+    Medium,
+    Large,
+    Enormous,
+}
+
+impl TestSize {
+    fn as_str(self) -> &'static str {
+        match self {
+            TestSize::Small => "small",
+            TestSize::Medium => "medium",
+            TestSize::Large => "large",
+            TestSize::Enormous => "enormous",
+        }
+    }
+}
+
+impl FromStr for TestSize {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "small" => Ok(TestSize::Small),
+            "medium" => Ok(TestSize::Medium),
+            "large" => Ok(TestSize::Large),
+            "enormous" => Ok(TestSize::Enormous),
+            _ => bail!(
+                "unknown test size {}, expected small, medium, large or enormous",
+                s
+            ),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum TestTimeout {
+    Short,
+    Moderate,
+    Long,
+    Eternal,
+}
+
+impl TestTimeout {
+    fn as_str(self) -> &'static str {
+        match self {
+            TestTimeout::Short => "short",
+            TestTimeout::Moderate => "moderate",
+            TestTimeout::Long => "long",
+            TestTimeout::Eternal => "eternal",
+        }
+    }
+}
+
+impl FromStr for TestTimeout {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "short" => Ok(TestTimeout::Short),
+            "moderate" => Ok(TestTimeout::Moderate),
+            "long" => Ok(TestTimeout::Long),
+            "eternal" => Ok(TestTimeout::Eternal),
+            _ => bail!(
+                "unknown test timeout {}, expected short, moderate, long or eternal",
+                s
+            ),
+        }
+    }
+}
+
+const TEST_SALT: u64 = 10;
+
+/// Sets the `size`, `timeout` and `shard_count` the `--test-*` distributions pick for the test of
+/// `node`, leaving out the ones without a distribution.
+pub fn add_test_attrs(test: &mut Rule, node: &ID, args: &Args) {
+    if let Some(sizes) = &args.test_sizes {
+        test.set(
+            "size",
+            quote(sizes.pick(node_fraction(node.id, TEST_SALT)).as_str()),
+        );
+    }
+    if let Some(timeouts) = &args.test_timeouts {
+        let timeout = timeouts.pick(node_fraction(node.id | 1 << 62, TEST_SALT));
+        test.set("timeout", quote(timeout.as_str()));
+    }
+    if shard_count(node, args) > 1 {
+        test.set("shard_count", shard_count(node, args).to_string());
+    }
+}
+
+/// Number of shards of the test of `node`, 1 without `--test-shard-counts`.
+pub fn shard_count(node: &ID, args: &Args) -> u64 {
+    args.test_shard_counts.as_ref().map_or(1, |counts| {
+        *counts.pick(node_fraction(node.id | 1 << 63, TEST_SALT))
+    })
+}
+
+/// Whether the test of `node` is one of the `--flaky-ratio` tests that fail nondeterministically.
+pub fn is_flaky(node: &ID, args: &Args) -> bool {
+    node_fraction(node.id | 1 << 61, TEST_SALT) < args.flaky_ratio
+}
+
+/// Writes `flaky_tests.txt`, the ground truth of `--flaky-ratio` with the label of a flaky test
+/// per line.
+pub fn write_flaky_tests(args: &Args) -> anyhow::Result<()> {
+    let mut f = BufWriter::new(std::fs::File::create(args.output.join("flaky_tests.txt"))?);
+    for id in 1..num_nodes_in_ntree(args.targets_per_level, args.height) {
+        let node = ID::new(id, args.targets_per_level, args.height as u64);
+        if is_flaky(&node, args) {
+            writeln!(f, "{}:{}_test", label(&node, args), node.target_name())?;
+        }
+    }
+    Ok(())
+}
+
+/// Directory the `--runfiles-per-test` data files go into, in the package of their test or
+/// binary.
+const RUNFILES_DIR: &str = "runfiles";
+
+/// Writes the `--runfiles-per-test` data files of a test or binary into its package `dir` and
+/// returns the value of the `data` attribute listing them, `None` if there are none.
+pub fn runfiles(dir: &Path, args: &Args) -> anyhow::Result<Option<String>> {
+    if args.runfiles_per_test == 0 {
+        return Ok(None);
+    }
+    std::fs::create_dir_all(dir.join(RUNFILES_DIR))?;
+    let files: Vec<_> = (1..=args.runfiles_per_test)
+        .map(|i| format!("{}/data_{}.txt", RUNFILES_DIR, i))
+        .collect();
+    for file in &files {
+        std::fs::write(dir.join(file), format!("{}\n", file))?;
+    }
+    Ok(Some(srcs(
+        args,
+        &files,
+        &[&format!("{}/**", RUNFILES_DIR)],
+        &[],
+    )))
+}
+
+/// Files of `dir` with `extension`, not descending into subdirectories, sorted by name.
+pub fn files_with_extension(dir: &Path, extension: &str) -> anyhow::Result<Vec<PathBuf>> {
+    let mut files = vec![];
+    for entry in std::fs::read_dir(dir)? {
+        let path = entry?.path();
+        if path.is_file() && path.extension().is_some_and(|e| e == extension) {
+            files.push(path);
+        }
+    }
+    files.sort();
+    Ok(files)
+}
+
+/// Prefix for labels of the generated `//tools` packages, which only exist in the main repository.
+pub fn main_repo(node: &ID, args: &Args) -> &'static str {
+    if repo(node, args).is_some() {
+        "@"
+    } else {
+        ""
+    }
+}
+
+/// Writes a `WORKSPACE` for every repository besides the main one and returns the
+/// `local_repository` declarations for the main `WORKSPACE`. A nested repository ignores the one
+/// nested in it, like the main repository ignores the first.
+pub fn write_external_repos(args: &Args) -> anyhow::Result<String> {
+    let repositories = repositories(args);
+    let mut declarations = vec![];
+    for (i, (name, path)) in repositories.iter().enumerate() {
+        let dir = args.output.join(path);
+        std::fs::create_dir_all(&dir)?;
+        std::fs::write(
+            dir.join("WORKSPACE"),
+            format!("workspace(name = {})\n", quote(name)),
+        )?;
+        if let Some((nested, _)) = repositories
+            .get(i + 1)
+            .filter(|_| args.layout == Layout::NestedWorkspaces)
+        {
+            std::fs::write(dir.join(".bazelignore"), format!("{}\n", nested))?;
+        }
+        declarations.push(format!(
+            "\nlocal_repository(\n    name = {},\n    path = {},\n)",
+            quote(name),
+            quote(path.to_str().unwrap())
+        ));
+    }
+    Ok(declarations.join("\n"))
+}
+
+/// Creates the directories of the levels of `--layout symlinked` and links them into the package
+/// tree, `pkg_1` to `levels/level_1`, `levels/level_1/pkg_2` to `levels/level_2` and so on. The
+/// links are relative, so the workspace can be moved.
+pub fn write_level_symlinks(args: &Args) -> anyhow::Result<()> {
+    for level in 1..=args.height as usize {
+        let dir = args
+            .output
+            .join(SYMLINKED_LEVELS_DIR)
+            .join(format!("level_{}", level));
+        std::fs::create_dir_all(&dir)?;
+        let link = args.output.join(level_path(level));
+        if link.symlink_metadata().is_ok() {
+            continue;
+        }
+        // The link sits in the directory of the level above, as many components below it as the
+        // level adds to the path.
+        let components =
+            level_path(level).components().count() - level_path(level - 1).components().count();
+        let up = if level == 1 {
+            components - 1
+        } else {
+            components
+        };
+        let mut target = PathBuf::new();
+        for _ in 0..up {
+            target.push("..");
+        }
+        if level == 1 {
+            target.push(SYMLINKED_LEVELS_DIR);
+        }
+        target.push(format!("level_{}", level));
+        std::fs::create_dir_all(link.parent().unwrap())?;
+        std::os::unix::fs::symlink(target, &link)?;
+    }
+    Ok(())
+}
+
+/// Links the package of `node` to the `--shard-output` directory it gets generated in, replacing
+/// what an earlier generation left there unless the link already exists.
+pub fn link_shard(node: &ID, args: &Args) -> anyhow::Result<()> {
+    if args.shard_output.is_empty() {
+        return Ok(());
+    }
+    let link = args.output.join(node.lib_path());
+    if link.symlink_metadata().is_ok() {
+        return Ok(());
+    }
+    let shard = &args.shard_output[(node.id - 1) as usize % args.shard_output.len()];
+    let dir = std::path::absolute(shard.join(node.lib_path()))?;
+    std::fs::remove_dir_all(&dir).unwrap_or(());
+    std::fs::create_dir_all(&dir)?;
+    std::fs::create_dir_all(link.parent().unwrap())?;
+    std::os::unix::fs::symlink(dir, &link)?;
+    Ok(())
+}
+
+/// File holding the `DEPS` of the library of a package with `--deps-in-bzl`.
+const DEPS_BZL: &str = "deps.bzl";
+
+/// Adds the library target of `node` to `build`. `rule` is the backend specific library rule
+/// loaded from `load`, this takes care of wiring in `deps` and visibility, routing it through the
+/// `--bzl-depth` macro chain and `--alias-layers`, and appending the per package genrules.
+pub fn add_library(
+    build: &mut BuildFile,
+    node: &ID,
+    args: &Args,
+    load: &str,
+    mut rule: Rule,
+    mut deps: Vec<String>,
+) -> anyhow::Result<()> {
+    if let Some(binding) = add_protos(build, node, args)? {
+        deps.push(binding);
+    }
+    deps.extend(third_party::dependency_label(node, args));
+    if is_stamped(node, args) {
+        add_stamping(build, node, args, &mut rule, &mut deps)?;
+    }
+    if args.filegroups_per_package > 0 && !crate::apple::is_prebuilt(node, args) {
+        rule.append("data", string_list(dependency_data(node, args)));
+    }
+
+    // The macro chain ends in the language's main library rule, anything else (e.g. Kotlin
+    // libraries in an Android graph) is instantiated directly.
+    if args.bzl_depth > 0 && rule.kind == args.language.library_rule(args).name {
+        build.load(
+            &format!("{}//tools/macros:library.bzl", main_repo(node, args)),
+            "bench_library",
+        );
+        rule.kind = "bench_library".to_string();
+    } else {
+        build.load(load, &rule.kind);
+    }
+
+    let mut deps = string_list(deps);
+    if args.deps_in_bzl {
+        std::fs::write(
+            lib_dir(node, args).join(DEPS_BZL),
+            format!(
+                "\"\"\"Dependencies of {}.\"\"\"\n\nDEPS = {}\n",
+                label(node, args),
+                starlark::format_assigned_value("deps", &deps)
+            ),
+        )?;
+        build.load(&format!(":{}", DEPS_BZL), "DEPS");
+        deps = "DEPS".to_string();
+    }
+    if args.selects_per_target > 0 {
+        rule.set(
+            "deps",
+            format!(
+                r#"select({{
+        "{1}//tools/config:select_1": {0},
+        "//conditions:default": {0},
+    }})"#,
+                deps,
+                main_repo(node, args)
+            ),
+        );
+    } else {
+        rule.set("deps", deps);
+    }
+
+    let tags: Vec<_> = stamps_for(node, &args.tags, 0).collect();
+    if !tags.is_empty() {
+        rule.set("tags", string_list(tags));
+    }
+    let exec_properties: Vec<_> = stamps_for(node, &args.exec_properties, args.tags.len())
+        .map(|property| {
+            let (key, value) = property.split_once('=').unwrap();
+            format!("{}: {}", quote(key), quote(value))
+        })
+        .collect();
+    if !exec_properties.is_empty() {
+        rule.set(
+            "exec_properties",
+            format!("{{{}}}", exec_properties.join(", ")),
+        );
+    }
+
+    let visibility = string_list(visibility(node, args));
+    rule.set("visibility", visibility.clone());
+    if args.package_metadata {
+        add_package_license(build, node, args, &visibility);
+    }
+
+    let target_name = rule.name.clone();
+    let library_name = library_rule_name(&target_name, args);
+    rule.name = library_name.clone();
+    build.add(rule);
+
+    for layer in 0..args.alias_layers {
+        let name = if layer == 0 {
+            target_name.clone()
+        } else {
+            format!("{}_alias_{}", target_name, layer)
+        };
+        let actual = if layer + 1 == args.alias_layers {
+            library_name.clone()
+        } else {
+            format!("{}_alias_{}", target_name, layer + 1)
+        };
+        build.add(
+            Rule::new("alias", name)
+                .attr("actual", quote(&format!(":{}", actual)))
+                .attr("visibility", visibility.clone()),
+        );
+    }
+
+    add_genrules(build, node, args);
+    add_worker_actions(build, node, args);
+    add_filegroups(build, node, args, &visibility)?;
+    Ok(())
+}
+
+/// Name of the library rule of the target `target_name`, which `--alias-layers` put behind
+/// aliases of the target's name.
+pub fn library_rule_name(target_name: &str, args: &Args) -> String {
+    if args.alias_layers > 0 {
+        format!("{}_impl", target_name)
+    } else {
+        target_name.to_string()
+    }
+}
+
+const STAMPED_SALT: u64 = 20;
+
+/// Whether the library of `node` is one of the `--stamped-targets`, which prebuilt frameworks
+/// never are.
+pub fn is_stamped(node: &ID, args: &Args) -> bool {
+    node.id > 0
+        && !crate::apple::is_prebuilt(node, args)
+        && node_fraction(node.id, STAMPED_SALT) < args.stamped_targets
+}
+
+/// Stamps the library `rule` of `node` with the workspace status: a stamped genrule copies it
+/// into a file the library carries as data, and objc libraries also depend on a linkstamp, which
+/// every binary linking them compiles with the status of the link.
+fn add_stamping(
+    build: &mut BuildFile,
+    node: &ID,
+    args: &Args,
+    rule: &mut Rule,
+    deps: &mut Vec<String>,
+) -> anyhow::Result<()> {
+    let name = node.target_name();
+    build.add(
+        Rule::new("genrule", format!("{}_build_info", name))
+            .attr("outs", string_list([format!("{}_build_info.txt", name)]))
+            .attr(
+                "cmd",
+                quote("cat bazel-out/stable-status.txt bazel-out/volatile-status.txt > $@"),
+            )
+            .attr("stamp", "1"),
+    );
+    rule.append("data", string_list([format!(":{}_build_info", name)]));
+
+    if args.language == Language::OBJC {
+        let linkstamp = format!("{}_linkstamp.cc", name);
+        std::fs::write(
+            lib_dir(node, args).join(&linkstamp),
+            format!(
+                "// Compiled into every binary linking {} with the workspace status of the link.\n\
+                 #ifndef BUILD_TIMESTAMP\n\
+                 #define BUILD_TIMESTAMP 0\n\
+                 #endif\n\
+                 extern const long {1}_build_timestamp;\n\
+                 const long {1}_build_timestamp = BUILD_TIMESTAMP;\n",
+                label(node, args),
+                node.lib_name()
+            ),
+        )?;
+        build.add(
+            Rule::new("cc_library", format!("{}_linkstamp", name))
+                .attr("linkstamp", quote(&linkstamp)),
+        );
+        deps.push(format!(":{}_linkstamp", name));
+    }
+    Ok(())
+}
+
+/// `tools/workspace_status.sh` of `--stamped-targets`, reporting the revision of the workspace
+/// as a stable key, which reruns the stamped actions when it changes, and the time of the build
+/// as a volatile one.
+pub fn write_workspace_status(args: &Args) -> anyhow::Result<()> {
+    let tools_dir = args.output.join("tools");
+    std::fs::create_dir_all(&tools_dir)?;
+    let path = tools_dir.join("workspace_status.sh");
+    std::fs::write(
+        &path,
+        "#!/bin/sh\n\
+         echo \"STABLE_BENCH_REVISION $(git rev-parse HEAD 2>/dev/null || echo unversioned)\"\n\
+         echo \"BENCH_BUILD_TIMESTAMP $(date +%s)\"\n",
+    )?;
+    std::fs::set_permissions(&path, std::fs::Permissions::from_mode(0o755))?;
+    Ok(())
+}
+
+/// `.bazelrc` lines running `write_workspace_status`'s script on every build, with a `stamp`
+/// config that also stamps the binaries.
+pub fn stamping_bazelrc() -> String {
+    "build --workspace_status_command=tools/workspace_status.sh\nbuild:stamp --stamp".to_string()
+}
+
+/// Value of a `srcs` attribute listing `files`, or with `--use-globs` a `glob()` of `include`
+/// minus `exclude` matching the same files.
+pub fn srcs<I, S>(args: &Args, files: I, include: &[&str], exclude: &[&str]) -> String
+where
+    I: IntoIterator<Item = S>,
+    S: AsRef<str>,
+{
+    if !args.use_globs {
+        return string_list(files);
+    }
+    if exclude.is_empty() {
+        format!("glob({})", string_list(include))
+    } else {
+        format!(
+            "glob({}, exclude = {})",
+            string_list(include),
+            string_list(exclude)
+        )
+    }
+}
+
+/// `count` generated flags rendered by `flag` from their index and value. Values are padded to
+/// `--flag-length` characters so command lines can be pushed past the param file threshold.
+pub fn generated_flags(args: &Args, count: u64, flag: impl Fn(u64, &str) -> String) -> Vec<String> {
+    let value = format!("{:x<width$}", "1", width = args.flag_length as usize);
+    (1..=count).map(|i| flag(i, &value)).collect()
+}
+
+/// Concatenation of `--selects-per-target` `select()` expressions that add one generated define
+/// each, for use as a copts style attribute.
+pub fn copts_selects(node: &ID, args: &Args) -> Option<String> {
+    if args.selects_per_target == 0 {
+        return None;
+    }
+
+    Some(
+        (1..=args.selects_per_target)
+            .map(|i| {
+                format!(
+                    r#"select({{
+        "{1}//tools/config:select_{0}": ["-DBENCH_SELECT_{0}=1"],
+        "//conditions:default": [],
+    }})"#,
+                    i,
+                    main_repo(node, args)
+                )
+            })
+            .join(" + "),
+    )
+}
+
+/// Adds the `--protos-per-package` `proto_library` of `node`, depending on the proto libraries of
+/// its children, plus the language binding for it. Returns the label of the binding if the
+/// backend has one, for the library to depend on.
+fn add_protos(build: &mut BuildFile, node: &ID, args: &Args) -> anyhow::Result<Option<String>> {
+    if args.protos_per_package == 0 {
+        return Ok(None);
+    }
+
+    let lib_dir = lib_dir(node, args);
+    let children = node.children();
+    let proto_name = format!("{}_proto", node.target_name());
+    let proto_file = |node: &ID, i: u64| format!("{}_Msg{}.proto", node.lib_name(), i);
+    let package = node.lib_path().to_str().unwrap().replace('/', ".");
+
+    for i in 1..=args.protos_per_package {
+        let mut f = BufWriter::new(std::fs::File::create(lib_dir.join(proto_file(node, i)))?);
+        writeln!(f, "syntax = \"proto3\";\n\npackage {};\n", package)?;
+        for child in &children {
+            writeln!(
+                f,
+                "import \"{}/{}\";",
+                child.lib_path().to_str().unwrap(),
+                proto_file(child, 1)
+            )?;
+        }
+        writeln!(f, "\nmessage {}_Msg{} {{", node.lib_name(), i)?;
+        writeln!(f, "  string name = 1;")?;
+        for (field, child) in children.iter().enumerate() {
+            writeln!(
+                f,
+                "  {}.{}_Msg1 child_{} = {};",
+                child.lib_path().to_str().unwrap().replace('/', "."),
+                child.lib_name(),
+                field + 1,
+                field + 2
+            )?;
+        }
+        writeln!(f, "}}")?;
+    }
+
+    build.load("@rules_proto//proto:defs.bzl", "proto_library");
+    build.add(
+        Rule::new("proto_library", &proto_name)
+            .attr(
+                "srcs",
+                srcs(
+                    args,
+                    (1..=args.protos_per_package).map(|i| proto_file(node, i)),
+                    &["*.proto"],
+                    &[],
+                ),
+            )
+            .attr(
+                "deps",
+                string_list(
+                    children.iter().map(|child| {
+                        format!("{}:{}_proto", label(child, args), child.target_name())
+                    }),
+                ),
+            )
+            .attr("visibility", string_list(["//visibility:public"])),
+    );
+
+    let (kind, suffix) = match crate::backend::backend(args.language).proto_binding(build, args) {
+        Some(binding) => binding,
+        None => return Ok(None),
+    };
+    let binding = format!("{}_{}", node.target_name(), suffix);
+    build.add(Rule::new(kind, &binding).attr("deps", string_list([format!(":{}", proto_name)])));
+
+    Ok(Some(format!(":{}", binding)))
+}
+
+/// WORKSPACE setup needed for `proto_library`, for backends whose WORKSPACE doesn't already pull
+/// in rules_proto.
+pub const PROTO_WORKSPACE: &str = r#"
+load("@bazel_tools//tools/build_defs/repo:http.bzl", "http_archive")
+
+http_archive(
+    name = "rules_proto",
+    sha256 = "66bfdf8782796239d3875d37e7de19b1d94301e8972b3cbd2446b332429b4df1",
+    strip_prefix = "rules_proto-4.0.0",
+    urls = [
+        "https://mirror.bazel.build/github.com/bazelbuild/rules_proto/archive/refs/tags/4.0.0.tar.gz",
+        "https://github.com/bazelbuild/rules_proto/archive/refs/tags/4.0.0.tar.gz",
+    ],
+)
+
+load("@rules_proto//proto:repositories.bzl", "rules_proto_dependencies", "rules_proto_toolchains")
+
+rules_proto_dependencies()
+
+rules_proto_toolchains()"#;
+
+fn add_genrules(build: &mut BuildFile, node: &ID, args: &Args) {
+    if args.genrules_per_package > 0 && args.genrule_tree_artifacts {
+        build.load(
+            &format!("{}//tools:tree_artifact.bzl", main_repo(node, args)),
+            "tree_artifact",
+        );
+    }
+
+    for i in 1..=args.genrules_per_package {
+        let srcs = if i > 1 {
+            string_list([format!(":gen_{}", i - 1)])
+        } else {
+            "[]".to_string()
+        };
+
+        if args.genrule_tree_artifacts {
+            build.add(
+                Rule::new("tree_artifact", format!("gen_{}", i))
+                    .attr("srcs", srcs)
+                    .attr("file_count", args.genrule_outputs.to_string()),
+            );
+        } else {
+            let outs = (1..=args.genrule_outputs)
+                .map(|o| format!("{}_Gen{}_Out{}.txt", node.lib_name(), i, o));
+            build.add(
+                Rule::new("genrule", format!("gen_{}", i))
+                    .attr("srcs", srcs)
+                    .attr("outs", string_list(outs))
+                    .attr(
+                        "cmd",
+                        quote(&format!(
+                            "for f in $(OUTS); do echo {}_Gen{} > $$f; done",
+                            node.lib_name(),
+                            i
+                        )),
+                    ),
+            );
+        }
+    }
+}
+
+/// Name of the `i`th `--filegroups-per-package` data file of `node`.
+fn data_file(node: &ID, i: u64) -> String {
+    format!("{}_Data{}.txt", node.lib_name(), i)
+}
+
+/// Adds the `--filegroups-per-package` filegroups of `node` and exports their files with the
+/// `visibility` of its library, so the libraries depending on it can reference both.
+fn add_filegroups(
+    build: &mut BuildFile,
+    node: &ID,
+    args: &Args,
+    visibility: &str,
+) -> anyhow::Result<()> {
+    if args.filegroups_per_package == 0 {
+        return Ok(());
+    }
+    let files: Vec<_> = (1..=args.filegroups_per_package)
+        .map(|i| data_file(node, i))
+        .collect();
+    for file in &files {
+        std::fs::write(lib_dir(node, args).join(file), format!("{}\n", file))?;
+    }
+    build.exports_files(&string_list(&files), visibility);
+    for (i, file) in (1..).zip(&files) {
+        build.add(
+            Rule::new("filegroup", format!("data_{}", i))
+                .attr("srcs", string_list([file]))
+                .attr("visibility", visibility.to_string()),
+        );
+    }
+    Ok(())
+}
+
+/// Data of the library of `node` with `--filegroups-per-package`: the filegroups of the
+/// libraries it depends on, and the first of their files by its own label.
+fn dependency_data(node: &ID, args: &Args) -> Vec<String> {
+    let mut data = vec![];
+    for dep in crate::metrics::dependencies(node.id, args) {
+        let dep = ID::new(dep, args.targets_per_level, args.height as u64);
+        let label = label(&dep, args);
+        let package = label.split_once(':').map_or(label.as_str(), |(p, _)| p);
+        data.extend((1..=args.filegroups_per_package).map(|i| format!("{}:data_{}", package, i)));
+        data.push(format!("{}:{}", package, data_file(&dep, 1)));
+    }
+    data
+}
+
+/// Adds the `--worker-actions-per-package` actions of `node`, each hashing the package's files
+/// and the output of the previous one through the persistent worker.
+fn add_worker_actions(build: &mut BuildFile, node: &ID, args: &Args) {
+    if args.worker_actions_per_package == 0 {
+        return;
+    }
+    build.load(
+        &format!("{}//tools/worker:worker.bzl", main_repo(node, args)),
+        "worker_action",
+    );
+    for i in 1..=args.worker_actions_per_package {
+        let srcs = if i > 1 {
+            format!(
+                r#"glob(["**"], exclude = ["BUILD.bazel"]) + [":work_{}"]"#,
+                i - 1
+            )
+        } else {
+            r#"glob(["**"], exclude = ["BUILD.bazel"])"#.to_string()
+        };
+        build.add(Rule::new("worker_action", format!("work_{}", i)).attr("srcs", srcs));
+    }
+}
+
+/// Writes the `.bzl` macro chain used by `--bzl-depth`. Every file at level `d` loads all
+/// `bzl_fanout` files of level `d + 1` and forwards to one of their macros, the last level finally
+/// instantiates the library rule `kind` loaded from `load`. Each file also does a bit of busy work
+/// at load time so that Starlark evaluation shows up in the profile and not just file loading.
+pub fn write_bzl_macros(
+    root_dir: &Path,
+    depth: u64,
+    fanout: u64,
+    load: &str,
+    kind: &str,
+) -> anyhow::Result<()> {
+    let macros_dir = root_dir.join("tools/macros");
+    std::fs::create_dir_all(&macros_dir)?;
+    std::fs::File::create(macros_dir.join("BUILD.bazel"))?;
+
+    for level in 1..=depth {
+        for j in 1..=fanout {
+            let mut f =
+                std::fs::File::create(macros_dir.join(format!("level_{}_{}.bzl", level, j)))?;
+
+            let next = if level < depth {
+                for k in 1..=fanout {
+                    writeln!(
+                        f,
+                        r#"load("//tools/macros:level_{0}_{1}.bzl", "macro_{0}_{1}")"#,
+                        level + 1,
+                        k
+                    )?;
+                }
+                format!("macro_{}_{}", level + 1, j)
+            } else {
+                if load.is_empty() {
+                    format!("native.{}", kind)
+                } else {
+                    writeln!(f, "load({}, {})", quote(load), quote(kind))?;
+                    kind.to_string()
+                }
+            };
+
+            writeln!(
+                f,
+                r#"
+_VALUES = [i * {} for i in range(100)]
+_TABLE = {{str(v): v for v in _VALUES}}
+
+def macro_{}_{}(**kwargs):
+    kwargs.setdefault("tags", [])
+    if len(_TABLE) != len(_VALUES):
+        fail("unreachable")
+    {}(**kwargs)"#,
+                level * fanout + j,
+                level,
+                j,
+                next
+            )?;
+        }
+    }
+
+    let mut f = std::fs::File::create(macros_dir.join("library.bzl"))?;
+    for j in 1..=fanout {
+        writeln!(
+            f,
+            r#"load("//tools/macros:level_1_{0}.bzl", "macro_1_{0}")"#,
+            j
+        )?;
+    }
+    writeln!(
+        f,
+        r#"
+def bench_library(**kwargs):
+    macro_1_1(**kwargs)"#
+    )?;
+    Ok(())
+}
+
+pub fn write_config_settings(root_dir: &Path, count: u64) -> anyhow::Result<()> {
+    let config_dir = root_dir.join("tools/config");
+    std::fs::create_dir_all(&config_dir)?;
+
+    let mut f = BufWriter::new(std::fs::File::create(config_dir.join("BUILD.bazel"))?);
+    for i in 1..=count {
+        writeln!(
+            f,
+            r#"config_setting(
+    name = "select_{0}",
+    define_values = {{"bench_select_{0}": "on"}},
+    visibility = ["//visibility:public"],
+)"#,
+            i
+        )?;
+    }
+    Ok(())
+}
+
+/// A `--metadata-file` written to every package, given as `<file name>=<template>`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct MetadataFile {
+    pub name: String,
+    pub template: String,
+}
+
+impl FromStr for MetadataFile {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (name, template) = match s.split_once('=') {
+            Some((name, template)) if !name.is_empty() => (name, template),
+            _ => bail!("expected <file name>=<template>, got {}", s),
+        };
+        if name.contains('/') || name == "BUILD" || name == "BUILD.bazel" {
+            bail!(
+                "metadata file name {} has to be a plain file name other than BUILD",
+                name
+            );
+        }
+        Ok(MetadataFile {
+            name: name.to_string(),
+            template: template.replace("\\n", "\n"),
+        })
+    }
+}
+
+/// Owner of the package of `node`: one team per library of the first level and everything
+/// below it, the root owned by all of them.
+fn owner(node: &ID) -> String {
+    match std::iter::successors(Some(*node), ID::parent).find(|n| n.level == 1) {
+        Some(top) => format!("team-{}@example.com", top.package_relative_index),
+        None => "benchmark-admins@example.com".to_string(),
+    }
+}
+
+/// Writes the `--emit-owners` OWNERS file and the `--metadata-file`s of the package of `node`,
+/// the root package for the root.
+pub fn write_metadata_files(node: &ID, args: &Args) -> anyhow::Result<()> {
+    if !args.emit_owners && args.metadata_files.is_empty() {
+        return Ok(());
+    }
+    let (dir, package, name) = match node.id {
+        0 => (args.output.clone(), String::new(), "root".to_string()),
+        _ => (
+            lib_dir(node, args),
+            node.lib_path().display().to_string(),
+            node.target_name(),
+        ),
+    };
+    if args.emit_owners {
+        std::fs::write(dir.join("OWNERS"), format!("{}\n", owner(node)))?;
+    }
+    for file in &args.metadata_files {
+        let content = file
+            .template
+            .replace("{package}", &package)
+            .replace("{label}", &format!("//{}", package))
+            .replace("{name}", &name)
+            .replace("{level}", &node.level.to_string())
+            .replace("{owner}", &owner(node));
+        std::fs::write(dir.join(&file.name), format!("{}\n", content))?;
+    }
+    Ok(())
+}
+
+/// Writes the `--noise-files-per-package` files of the package of `node`, cycling through
+/// Markdown, JSON and YAML and through the package directory and its `docs` and `config`
+/// subdirectories, a few lines each. None of them is referenced by a target.
+pub fn write_noise_files(node: &ID, args: &Args) -> anyhow::Result<()> {
+    let dir = lib_dir(node, args);
+    let name = node.target_name();
+    for i in 1..=args.noise_files_per_package {
+        let subdir = match i % 3 {
+            0 => dir.join("config"),
+            1 => dir.clone(),
+            _ => dir.join("docs"),
+        };
+        let lines = 1 + (node.id + i) % 5;
+        let (file, content) = match i % 3 {
+            0 => (
+                format!("settings_{}.yaml", i),
+                (1..=lines)
+                    .map(|line| format!("key_{}: value {} of {}\n", line, line, name))
+                    .collect::<String>(),
+            ),
+            1 => (
+                format!("NOTES_{}.md", i),
+                format!("# {} notes {}\n\n", name, i)
+                    + &(1..=lines)
+                        .map(|line| {
+                            format!("- note {} about //{}\n", line, node.lib_path().display())
+                        })
+                        .collect::<String>(),
+            ),
+            _ => (
+                format!("data_{}.json", i),
+                format!(
+                    "{{\n  \"library\": \"{}\",\n  \"entries\": [{}]\n}}\n",
+                    name,
+                    (1..=lines)
+                        .map(|line| line.to_string())
+                        .collect::<Vec<_>>()
+                        .join(", ")
+                ),
+            ),
+        };
+        std::fs::create_dir_all(&subdir)?;
+        std::fs::write(subdir.join(file), content)?;
+    }
+    Ok(())
+}
+
+/// Writes one `package_group` per level of the graph, containing the packages of the level above
+/// it, i.e. exactly the packages that are allowed to depend on libraries of that level, and the
+/// `--level-targets` package.
+pub fn write_package_groups(args: &Args) -> anyhow::Result<()> {
+    let visibility_dir = args.output.join("tools/visibility");
+    std::fs::create_dir_all(&visibility_dir)?;
+
+    let mut f = BufWriter::new(std::fs::File::create(visibility_dir.join("BUILD.bazel"))?);
+    for level in 1..=args.height {
+        let mut consumers = vec![if level == 1 {
+            "//...".to_string()
+        } else {
+            format!("//{}/...", level_path(level as usize - 1).display())
+        }];
+        if args.level_targets && level > 1 {
+            consumers.push("//tools/levels".to_string());
+        }
+        writeln!(
+            f,
+            r#"package_group(
+    name = "level_{}",
+    packages = {},
+)"#,
+            level,
+            string_list(consumers)
+        )?;
+    }
+    Ok(())
+}
+
+/// Writes a `level_<n>` filegroup of all libraries of every level of the graph into
+/// `//tools/levels`.
+pub fn write_level_targets(args: &Args) -> anyhow::Result<()> {
+    let levels_dir = args.output.join("tools/levels");
+    std::fs::create_dir_all(&levels_dir)?;
+
+    let mut build = BuildFile::new();
+    for level in 1..=args.height {
+        let first = num_nodes_in_ntree(args.targets_per_level, level - 1);
+        let libraries = (first..first + args.targets_per_level.pow(level)).map(|id| {
+            label(
+                &ID::new(id, args.targets_per_level, args.height as u64),
+                args,
+            )
+        });
+        build.add(
+            Rule::new("filegroup", format!("level_{}", level)).attr("srcs", string_list(libraries)),
+        );
+    }
+    build.write(&levels_dir.join("BUILD.bazel"))?;
+    Ok(())
+}
+
+pub fn write_tree_artifact_rule(root_dir: &Path) -> anyhow::Result<()> {
+    let tools_dir = root_dir.join("tools");
+    std::fs::create_dir_all(&tools_dir)?;
+    std::fs::File::create(tools_dir.join("BUILD.bazel"))?;
+
+    let mut f = std::fs::File::create(tools_dir.join("tree_artifact.bzl"))?;
+    writeln!(
+        f,
+        r#"def _tree_artifact_impl(ctx):
+    out = ctx.actions.declare_directory(ctx.label.name)
+    ctx.actions.run_shell(
+        inputs = ctx.files.srcs,
+        outputs = [out],
+        command = "for i in $(seq 1 {{}}); do echo {{}} > {{}}/file_$i.txt; done".format(
+            ctx.attr.file_count,
+            ctx.label.name,
+            out.path,
+        ),
+    )
+    return [DefaultInfo(files = depset([out]))]
+
+tree_artifact = rule(
+    implementation = _tree_artifact_impl,
+    attrs = {{
+        "srcs": attr.label_list(allow_files = True),
+        "file_count": attr.int(default = 1),
+    }},
+)"#
+    )?;
+    Ok(())
+}
+
+/// License kinds the `--package-metadata` licenses cycle through.
+const LICENSE_KINDS: [&str; 3] = ["Apache-2.0", "MIT", "BSD-3-Clause"];
+
+/// Package of the `license` rule stub and of the license of the root package.
+const LICENSE_DIR: &str = "tools/license";
+
+/// Sets the default visibility of the package of `node` and makes a `license` of its own the
+/// default package metadata of its targets.
+fn add_package_license(build: &mut BuildFile, node: &ID, args: &Args, visibility: &str) {
+    build.load(
+        &format!("{}//{}:license.bzl", main_repo(node, args), LICENSE_DIR),
+        "license",
+    );
+    build.package("default_visibility", visibility);
+    build.package("default_package_metadata", string_list([":license"]));
+    build.add(
+        Rule::new("license", "license")
+            .attr(
+                "license_kinds",
+                string_list([LICENSE_KINDS[node.id as usize % LICENSE_KINDS.len()]]),
+            )
+            .attr("package_name", quote(&node.lib_name()))
+            // Like the rules_license macro, a license isn't licensed by itself.
+            .attr("applicable_licenses", "[]"),
+    );
+}
+
+/// Writes the `REPO.bazel` of `--package-metadata`, with the defaults of packages that don't set
+/// their own, and a stub of the `license` rule of rules_license with the license of the root
+/// package. Like the real one the rule only provides a `LicenseInfo`, for license aspects to
+/// collect.
+pub fn write_package_metadata(args: &Args) -> anyhow::Result<()> {
+    std::fs::write(
+        args.output.join("REPO.bazel"),
+        format!(
+            "repo(\n    default_package_metadata = [\"//{}\"],\n    default_visibility = [\"//visibility:public\"],\n)\n",
+            LICENSE_DIR
+        ),
+    )
+    ?;
+    let dir = args.output.join(LICENSE_DIR);
+    std::fs::create_dir_all(&dir)?;
+    std::fs::write(
+        dir.join("license.bzl"),
+        r#""""Stub of the license rule of rules_license, with the same attributes."""
+
+LicenseInfo = provider(
+    doc = "License of the targets of a package.",
+    fields = ["label", "license_kinds", "package_name", "package_url"],
+)
+
+def _license_impl(ctx):
+    return [LicenseInfo(
+        label = ctx.label,
+        license_kinds = ctx.attr.license_kinds,
+        package_name = ctx.attr.package_name,
+        package_url = ctx.attr.package_url,
+    )]
+
+license = rule(
+    implementation = _license_impl,
+    attrs = {
+        "license_kinds": attr.string_list(),
+        "license_text": attr.label(allow_single_file = True),
+        "package_name": attr.string(),
+        "package_url": attr.string(),
+    },
+)
+"#,
+    )?;
+    std::fs::write(
+        dir.join("BUILD.bazel"),
+        format!(
+            r#"load(":license.bzl", "license")
+
+license(
+    name = "license",
+    license_kinds = ["{}"],
+    package_name = "root",
+    applicable_licenses = [],
+    visibility = ["//visibility:public"],
+)
+"#,
+            LICENSE_KINDS[0]
+        ),
+    )?;
+    Ok(())
+}
+
+/// Writes the persistent worker used by `--worker-actions-per-package`, a Python tool speaking
+/// the JSON worker protocol, and the `worker_action` rule running it. Actions fall back to
+/// spawning the tool once per action with `--strategy=BenchWork=local`, which is what the worker
+/// pool gets compared against.
+pub fn write_worker(root_dir: &Path) -> anyhow::Result<()> {
+    let worker_dir = root_dir.join("tools/worker");
+    std::fs::create_dir_all(&worker_dir)?;
+    std::fs::write(
+        worker_dir.join("BUILD.bazel"),
+        r#"py_binary(
+    name = "worker",
+    srcs = ["worker.py"],
+    python_version = "PY3",
+    visibility = ["//visibility:public"],
+)
+"#,
+    )?;
+
+    std::fs::write(
+        worker_dir.join("worker.py"),
+        r#"import hashlib
+import json
+import sys
+
+
+def expand(arguments):
+    for argument in arguments:
+        if argument.startswith("@"):
+            with open(argument[1:]) as f:
+                yield from f.read().splitlines()
+        else:
+            yield argument
+
+
+def work(arguments):
+    arguments = list(expand(arguments))
+    out = arguments[arguments.index("--out") + 1]
+    digest = hashlib.sha256()
+    for path in arguments[arguments.index("--out") + 2:]:
+        with open(path, "rb") as f:
+            digest.update(f.read())
+    with open(out, "w") as f:
+        f.write(digest.hexdigest() + "\n")
+
+
+def main():
+    if "--persistent_worker" not in sys.argv:
+        work(sys.argv[1:])
+        return
+    for line in sys.stdin:
+        request = json.loads(line)
+        try:
+            work(request.get("arguments", []))
+            response = {"exitCode": 0}
+        except Exception as e:
+            response = {"exitCode": 1, "output": str(e)}
+        response["requestId"] = request.get("requestId", 0)
+        print(json.dumps(response), flush=True)
+
+
+if __name__ == "__main__":
+    main()
+"#,
+    )?;
+
+    std::fs::write(
+        worker_dir.join("worker.bzl"),
+        r#"def _worker_action_impl(ctx):
+    out = ctx.actions.declare_file(ctx.label.name + ".sha256")
+    args = ctx.actions.args()
+    args.add("--out", out)
+    args.add_all(ctx.files.srcs)
+    args.use_param_file("@%s", use_always = True)
+    args.set_param_file_format("multiline")
+    ctx.actions.run(
+        executable = ctx.executable._worker,
+        arguments = [args],
+        inputs = ctx.files.srcs,
+        outputs = [out],
+        mnemonic = "BenchWork",
+        execution_requirements = {
+            "supports-workers": "1",
+            "requires-worker-protocol": "json",
+        },
+    )
+    return [DefaultInfo(files = depset([out]))]
+
+worker_action = rule(
+    implementation = _worker_action_impl,
+    attrs = {
+        "srcs": attr.label_list(allow_files = True),
+        "_worker": attr.label(
+            default = "//tools/worker:worker",
+            executable = True,
+            cfg = "exec",
+        ),
+    },
+)
+"#,
+    )?;
+    Ok(())
+}
+
+/// Writes a fake lint aspect propagating along `deps` that produces one report per target, enabled
+/// through the `--config=with-aspect` bazelrc config.
+pub fn write_lint_aspect(root_dir: &Path) -> anyhow::Result<()> {
+    let aspects_dir = root_dir.join("tools/aspects");
+    std::fs::create_dir_all(&aspects_dir)?;
+    std::fs::File::create(aspects_dir.join("BUILD.bazel"))?;
+
+    let mut f = std::fs::File::create(aspects_dir.join("lint.bzl"))?;
+    writeln!(
+        f,
+        r#"BenchLintInfo = provider(fields = ["reports"])
+
+def _bench_lint_aspect_impl(target, ctx):
+    srcs = [f for src in getattr(ctx.rule.attr, "srcs", []) for f in src.files.to_list()]
+    report = ctx.actions.declare_file(ctx.label.name + ".lint")
+    ctx.actions.run_shell(
+        inputs = srcs,
+        outputs = [report],
+        command = "wc -l {{}} /dev/null > {{}}".format(
+            " ".join([f.path for f in srcs]),
+            report.path,
+        ),
+        mnemonic = "BenchLint",
+    )
+    transitive = [
+        dep[BenchLintInfo].reports
+        for dep in getattr(ctx.rule.attr, "deps", [])
+        if BenchLintInfo in dep
+    ]
+    reports = depset([report], transitive = transitive)
+    return [
+        BenchLintInfo(reports = reports),
+        OutputGroupInfo(bench_lint = reports),
+    ]
+
+bench_lint_aspect = aspect(
+    implementation = _bench_lint_aspect_impl,
+    attr_aspects = ["deps"],
+)"#
+    )?;
+    Ok(())
+}
+
+/// Writes `per_level` `genquery` targets for every level of the graph into `//tools/queries`,
+/// alternating between `deps()` of a library of that level and a `somepath()` from it down to its
+/// first descendant on the last level.
+pub fn write_genqueries(args: &Args) -> anyhow::Result<()> {
+    let (targets_per_level, height) = (args.targets_per_level, args.height);
+    let queries_dir = args.output.join("tools/queries");
+    std::fs::create_dir_all(&queries_dir)?;
+
+    let node_at = |level: u32, index: u64| {
+        ID::new(
+            num_nodes_in_ntree(targets_per_level, level - 1) + index - 1,
+            targets_per_level,
+            height as u64,
+        )
+    };
+
+    let mut build = BuildFile::new();
+    for level in 1..=height {
+        let width = targets_per_level.pow(level);
+        for i in 1..=args.genquery_per_level {
+            let index = (i - 1) % width + 1;
+            let from = node_at(level, index);
+            let expression = if i % 2 == 1 {
+                format!("deps({})", label(&from, args))
+            } else {
+                let leaf_index =
+                    (level..height).fold(index, |j, _| targets_per_level * (j - 1) + 1);
+                format!(
+                    "somepath({}, {})",
+                    label(&from, args),
+                    label(&node_at(height, leaf_index), args)
+                )
+            };
+            build.add(
+                Rule::new("genquery", format!("level_{}_query_{}", level, i))
+                    .attr("expression", quote(&expression))
+                    .attr("scope", string_list([label(&from, args)])),
+            );
+        }
+    }
+    build.write(&queries_dir.join("BUILD.bazel"))?;
+    Ok(())
+}
@@ -1,461 +1,2186 @@
-#![feature(async_closure)]
-#![feature(int_log)]
-
-use anyhow::private::format_err;
-use anyhow::{format_err, Result};
-use clap::Parser;
-use futures::{stream, StreamExt};
-use itertools::Itertools;
-use std::fmt::Display;
-use std::future::Future;
-use std::io::{BufWriter, Write};
-use std::path::{Path, PathBuf};
-use tokio::sync::watch;
-use tokio_stream::iter;
+mod android;
+mod apple;
+mod archive;
+mod backend;
+mod bench;
+mod benchmarks;
+mod buck;
+mod budget;
+mod bzlmod;
+mod calibrate;
+mod churn;
+mod cmake;
+mod devenv;
+mod diff;
+mod diff_bench;
+mod exec_log;
+mod explain;
+mod fake;
+mod go;
+mod graph;
+mod graph_file;
+mod hooks;
+mod ide;
+mod image;
+mod inject;
+mod java;
+mod layers;
+mod matrix;
+mod metrics;
+mod mutate;
+mod presets;
+mod python;
+mod query_bench;
+mod rbe;
+mod regen;
+mod report;
+mod resume;
+mod rust;
+mod self_bench;
+mod spm;
+mod starlark;
+mod stream;
+mod summary;
+mod symbols;
+mod third_party;
+mod toolchain;
+mod validate;
+mod vendor;
+mod watchdog;
+mod xcode;
+
+use anyhow::{bail, format_err, Context};
+use clap::{Parser, Subcommand};
+use graph::{num_nodes_in_ntree, ID};
+use indicatif::{ProgressBar, ProgressStyle};
+use std::ffi::OsString;
+use std::io::{IsTerminal, Write};
+use std::path::PathBuf;
+use std::str::FromStr;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::Instant;
+use tracing::{debug, info, Level};
+
+#[derive(Parser, Debug)]
+#[clap(author, version, about, long_about = None)]
+struct Cli {
+    #[clap(subcommand)]
+    command: Command,
+}
+
+#[derive(Subcommand, Debug)]
+enum Command {
+    Generate(Args),
+    Validate(validate::ValidateArgs),
+    Report(report::ReportArgs),
+    DiffBench(diff_bench::DiffBenchArgs),
+    Calibrate(calibrate::CalibrateArgs),
+    QueryBench(query_bench::QueryBenchArgs),
+    Bench(bench::BenchArgs),
+    Churn(churn::ChurnArgs),
+    Regen(regen::RegenArgs),
+    Explain(explain::ExplainArgs),
+    Mutate(stream::MutateArgs),
+    Diff(diff::DiffArgs),
+    /// Print the JSON Schema of the `--emit-summary json` output
+    SummarySchema,
+}
 
 /// Generate a bazel benchmarking workspace. You can tweak various parameters to configure the
 /// topology of the build graph.
 ///
 /// Generally the amount of targets generated will be targets_per_level^height
 #[derive(Parser, Debug)]
-#[clap(author, version, about, long_about = None)]
 struct Args {
-    /// Directory to write the output to, existing content will be wiped
+    /// Directory to write the output to, existing content will be wiped unless resuming
     #[clap(long)]
     output: PathBuf,
 
+    /// What to write to `--output`: the workspace as a `dir`ectory, a `tar` or `tar.zst`
+    /// archive of it, or an `ext4-image` to mount, which `sparse-image` writes in the Android
+    /// sparse format without its unused blocks. Images need `mke2fs` from e2fsprogs
+    #[clap(long, default_value = "dir")]
+    output_format: archive::OutputFormat,
+
+    /// Size of the `--output-format` images, e.g. `4G`, by default what the workspace needs with
+    /// some room for the filesystem
+    #[clap(long)]
+    image_size: Option<image::ImageSize>,
+
+    /// Generate one workspace per combination of the values of some flags instead, into
+    /// `<output>/<flag>-<value>_...` directories listed in `<output>/index.json`. Given as
+    /// `<flag>=<values>` with comma separated values or inclusive `<first>..<last>` ranges, e.g.
+    /// `--matrix height=3..6 --matrix targets-per-level=4,8`
+    #[clap(long)]
+    matrix: Vec<String>,
+
+    /// The command line these arguments were parsed from, of the workspace of the matrix for
+    /// `--matrix`, before expanding presets
+    #[clap(skip)]
+    command_line: Vec<OsString>,
+
+    /// Named combination of graph shape and feature flags, explicitly given flags take
+    /// precedence. `--list-presets` shows the available ones
+    #[clap(long)]
+    preset: Option<String>,
+
     /// Height of the build graph
     #[clap(long)]
     height: u32,
 
-    /// The amount of targets to generate per level, each
+    /// The amount of targets to generate per level, each. With 1 the libraries form a single
+    /// chain, a narrow and deep graph
     #[clap(long)]
     targets_per_level: u64,
 
+    /// Number of source files of every library: a single count, a comma separated list of counts
+    /// per level starting at the first library level (the last one repeats for deeper levels), or
+    /// `root:<n>,mid:<n>,leaf:<n>` for the first, inner and last levels
     #[clap(long)]
-    files_per_target: u64,
-}
+    files_per_target: FilesPerTarget,
 
-async fn emit_build_file(
-    node_id: u64,
-    targets_per_level: u64,
-    files_per_target: u64,
-    root_dir: PathBuf,
-    max_depth: u64,
-) {
-    tokio::spawn(async move {
-        if node_id == 0 {
-            handle_root(targets_per_level, &root_dir);
-        } else {
-            let id = ID::new(node_id, targets_per_level, max_depth);
-            handle_node(&id, files_per_target, &root_dir);
-        }
-    })
-    .await
-    .unwrap();
-}
-
-const ALL_FRAMEWORKS: [&str; 135] = [
-    "ARKit",
-    "AVFAudio",
-    "AVFoundation",
-    "AVKit",
-    "Accelerate",
-    "Accessibility",
-    "Accounts",
-    "AdServices",
-    "AdSupport",
-    "AddressBook",
-    "AddressBookUI",
-    "AppClip",
-    "AppTrackingTransparency",
-    "AssetsLibrary",
-    "AudioToolbox",
-    "AudioUnit",
-    "AuthenticationServices",
-    "AutomaticAssessmentConfiguration",
-    "BackgroundTasks",
-    "BusinessChat",
-    "CFNetwork",
-    "CallKit",
-    "CarPlay",
-    "ClassKit",
-    "ClockKit",
-    "CloudKit",
-    "Contacts",
-    "ContactsUI",
-    "CoreAudio",
-    "CoreAudioKit",
-    "CoreAudioTypes",
-    "CoreBluetooth",
-    "CoreData",
-    "CoreFoundation",
-    "CoreGraphics",
-    "CoreHaptics",
-    "CoreImage",
-    "CoreLocation",
-    "CoreLocationUI",
-    "CoreMIDI",
-    "CoreML",
-    "CoreMedia",
-    "CoreMotion",
-    "CoreNFC",
-    "CoreServices",
-    "CoreSpotlight",
-    "CoreTelephony",
-    "CoreText",
-    "CoreVideo",
-    "DataDetection",
-    "DeviceCheck",
-    "EventKit",
-    "EventKitUI",
-    "ExposureNotification",
-    "ExternalAccessory",
-    "FileProvider",
-    "FileProviderUI",
-    "Foundation",
-    "GLKit",
-    "GSS",
-    "GameController",
-    "GameKit",
-    "GameplayKit",
-    "GroupActivities",
-    "HealthKit",
-    "HealthKitUI",
-    "HomeKit",
-    "IOSurface",
-    "IdentityLookup",
-    "IdentityLookupUI",
-    "ImageCaptureCore",
-    "ImageIO",
-    "Intents",
-    "IntentsUI",
-    "JavaScriptCore",
-    "LinkPresentation",
-    "LocalAuthentication",
-    "MapKit",
-    "MediaAccessibility",
-    "MediaPlayer",
-    "MediaToolbox",
-    "MessageUI",
-    "Messages",
-    "Metal",
-    "MetalKit",
-    "MetalPerformanceShaders",
-    "MetalPerformanceShadersGraph",
-    "MetricKit",
-    "MobileCoreServices",
-    "ModelIO",
-    "MultipeerConnectivity",
-    "NaturalLanguage",
-    "NearbyInteraction",
-    "Network",
-    "NetworkExtension",
-    "NewsstandKit",
-    "NotificationCenter",
-    "OSLog",
-    "OpenAL",
-    "OpenGLES",
-    "PDFKit",
-    "PHASE",
-    "PassKit",
-    "PencilKit",
-    "Photos",
-    "PhotosUI",
-    "PushKit",
-    "QuartzCore",
-    "QuickLook",
-    "QuickLookThumbnailing",
-    "ReplayKit",
-    "SafariServices",
-    "SceneKit",
-    "ScreenTime",
-    "Security",
-    "SensorKit",
-    "ShazamKit",
-    "Social",
-    "SoundAnalysis",
-    "Speech",
-    "SpriteKit",
-    "StoreKit",
-    "SwiftUI",
-    "SystemConfiguration",
-    "UIKit",
-    "UniformTypeIdentifiers",
-    "UserNotifications",
-    "UserNotificationsUI",
-    "VideoToolbox",
-    "Vision",
-    "VisionKit",
-    "WatchConnectivity",
-    "WebKit",
-    "WidgetKit",
-    "iAd",
-];
-
-fn handle_root(targets_per_level: u64, root_dir: &Path) {
-    let mut file = std::fs::File::create(root_dir.join("BUILD.bazel")).unwrap();
-    let deps: String = (1..=targets_per_level)
-        .map(|i| format!("\"//pkg_1/lib_{}\"", i))
-        .intersperse(", ".to_string())
-        .collect();
-
-    writeln!(
-        file,
-        r#"load("@build_bazel_rules_ios//rules:app.bzl", "ios_application")
-ios_application(
-    name = "root",
-    bundle_id = "com.bazel.benchmark",
-    families = [
-        "iphone",
-        "ipad",
-    ],
-    srcs = ["main.m"],
-    minimum_os_version = "15.0",
-    deps = [{}],
-)"#,
-        deps
-    )
-    .unwrap();
-}
-
-#[derive(Clone)]
-struct ID {
-    id: u64,
-    parents: Vec<ID>,
-    package_relative_index: u64,
-    targets_per_level: u64,
-    max_depth: u64,
-}
+    /// Leave the sources of the libraries empty, keeping their names, packages and `BUILD.bazel`
+    /// files, for benchmarks of loading, globbing and file watching rather than of compiling
+    #[clap(long)]
+    skeleton_only: bool,
 
-impl ID {
-    fn new(id: u64, targets_per_level: u64, max_depth: u64) -> Self {
-        let mut parents = vec![];
-        let mut parent_id = id;
+    /// Lay BUILD files out the way buildifier formats them: loads and their symbols sorted,
+    /// attributes in buildifier's order, and lists of labels sorted and one element per line with
+    /// trailing commas
+    #[clap(long)]
+    format: bool,
 
-        if id != 0 {
-            loop {
-                parent_id = (parent_id - 1) / targets_per_level as u64;
+    /// Run `buildifier -r` over the generated workspace when it's on the PATH, for the files and
+    /// expressions `--format` doesn't cover
+    #[clap(long)]
+    buildifier: bool,
 
-                parents.push(ID::new(parent_id, targets_per_level, max_depth));
+    /// Shell command to run over the generated workspace before its metadata is written, e.g.
+    /// a formatter or a generator of extra files, with `sh -c`. Its environment has
+    /// `BENCHMARK_OUTPUT`, `BENCHMARK_LANGUAGE`, `BENCHMARK_HEIGHT`, `BENCHMARK_TARGETS_PER_LEVEL`
+    /// and, for `--post-hook-scope package`, `BENCHMARK_PACKAGE`, `BENCHMARK_LABEL`,
+    /// `BENCHMARK_MODULE` and `BENCHMARK_LEVEL`. Can be repeated, the hooks run in order
+    #[clap(long)]
+    post_hook: Vec<String>,
 
-                if parent_id == 0 {
-                    break;
-                }
-            }
-        }
+    /// Run the `--post-hook` commands `once` in the workspace, or in the directory of every
+    /// `package` of a library
+    #[clap(long, default_value = "once")]
+    post_hook_scope: hooks::HookScope,
 
-        let package_relative_index = if id > 0 {
-            1 + id - num_nodes_in_ntree(targets_per_level, parents.len() as u32 - 1)
-        } else {
-            0
-        };
+    /// List library sources with `glob()` instead of explicit file lists
+    #[clap(long)]
+    use_globs: bool,
 
-        ID {
-            id,
-            parents,
-            package_relative_index,
-            targets_per_level,
-            max_depth,
-        }
-    }
+    /// Keep the dependencies of every library in a `DEPS` list of a `deps.bzl` next to its
+    /// `BUILD.bazel`, which loads it, the way repositories centralizing dependency lists do. It
+    /// moves the cost of parsing them from the BUILD files to loading the `.bzl` files
+    #[clap(long)]
+    deps_in_bzl: bool,
 
-    fn build_file(&self) -> PathBuf {
-        self.lib_path().join("BUILD.bazel")
-    }
+    /// Language backend to generate: `objc` (rules_ios frameworks), `android`, `java`, `go`,
+    /// `rust` or `python`
+    #[clap(long, default_value = "objc")]
+    language: Language,
+
+    /// Fraction of Android libraries generated in Kotlin instead of Java
+    #[clap(long, default_value = "0")]
+    kotlin_ratio: f64,
+
+    /// For the go backend, emit a plain Go module without BUILD files below the root so it can
+    /// be processed by Gazelle
+    #[clap(long)]
+    gazelle_compatible: bool,
+
+    /// Generate a test target for every library (python and objc backends)
+    #[clap(long)]
+    with_tests: bool,
+
+    /// Fraction of the `ios_unit_test`s of `--with-tests` that run inside the application, with it
+    /// as their `test_host`, so they can only build and run after it
+    #[clap(long, default_value = "0")]
+    test_hosted: f64,
+
+    /// Distribution of the `size` of the tests, e.g. `small@0.8,medium@0.2`
+    #[clap(long)]
+    test_sizes: Option<layers::Distribution<layers::TestSize>>,
+
+    /// Distribution of the `timeout` of the tests, e.g. `short@0.9,long@0.1`
+    #[clap(long)]
+    test_timeouts: Option<layers::Distribution<layers::TestTimeout>>,
+
+    /// Distribution of the `shard_count` of the tests, e.g. `1@0.9,4@0.1`
+    #[clap(long)]
+    test_shard_counts: Option<layers::Distribution<u64>>,
+
+    /// Number of data files in the runfiles of every test and of the root binary (java, go, rust
+    /// and python backends)
+    #[clap(long, default_value = "0")]
+    runfiles_per_test: u64,
+
+    /// Fraction of the tests that fail nondeterministically, listed in `flaky_tests.txt`
+    #[clap(long, default_value = "0")]
+    flaky_ratio: f64,
+
+    /// Probability of a flaky test to fail in a single run
+    #[clap(long, default_value = "0.5")]
+    flake_rate: f64,
+
+    /// Emit a fake lint aspect over the library graph, enabled with `--config=with-aspect`
+    #[clap(long)]
+    with_aspect: bool,
+
+    /// Number of `genquery` targets to emit for every level of the graph, computing `deps()` and
+    /// `somepath()` queries rooted at libraries of that level
+    #[clap(long, default_value = "0")]
+    genquery_per_level: u64,
+
+    /// Emit a `//tools/levels:level_<n>` filegroup of all libraries of every level of the graph,
+    /// to build exact horizontal slices of it without target patterns
+    #[clap(long)]
+    level_targets: bool,
+
+    /// Aggregate every level below the first into a `//umbrellas:level_<n>` library depending on
+    /// and re-exporting all of its libraries, which the libraries of the level above depend on
+    /// instead of their children. The applications keep depending on the first level directly
+    #[clap(long)]
+    umbrella_per_level: bool,
+
+    /// Pad package and target names to at least this many characters
+    #[clap(long, default_value = "0")]
+    label_length: usize,
+
+    /// Number of extra directories nested below every package level of the graph
+    #[clap(long, default_value = "0")]
+    path_depth_padding: u64,
+
+    /// Write an OWNERS file naming an owning team into every package
+    #[clap(long)]
+    emit_owners: bool,
+
+    /// Additional file to write into every package, as `<file name>=<template>`. `{package}`,
+    /// `{label}`, `{name}`, `{level}` and `{owner}` in the template are replaced with the
+    /// package path, its label, the target name, its level and the owner of `--emit-owners`,
+    /// `\n` with a newline
+    #[clap(long = "metadata-file")]
+    metadata_files: Vec<layers::MetadataFile>,
+
+    /// Number of files that aren't build inputs, Markdown, JSON and YAML, to scatter through every
+    /// library package and its `docs` and `config` subdirectories, for the loading, globbing and
+    /// file watching costs of a repository that holds more than sources
+    #[clap(long, default_value = "0")]
+    noise_files_per_package: u64,
+
+    /// Refuse to generate workspaces with paths longer than this many bytes, counted from the
+    /// file system root. The OS limit is always enforced
+    #[clap(long)]
+    max_path_length: Option<usize>,
+
+    /// Name package directories `p1`, `p2`, ... instead of `pkg_1`, `pkg_2`, ... when the longest
+    /// path would exceed `--max-path-length` or the OS limit otherwise
+    #[clap(long)]
+    shorten_packages: bool,
+
+    /// Naming of packages, targets and modules: `ascii`, `unicode` (non-ASCII letters), `mixed`
+    /// (also label-only punctuation in target names, objc and rust backends only),
+    /// `template:<template>` (e.g. `template:{package}_{index}`), `dictionary` (realistic names of
+    /// dictionary words) or `hashed` (hash names colliding in case, objc and java backends only)
+    #[clap(long, default_value = "ascii")]
+    naming_scheme: graph::NamingScheme,
+
+    /// Prefix of the module names of the libraries, e.g. `Core_`, to group them into namespaces.
+    /// Comma separated prefixes apply per level starting at the first library level, the last one
+    /// repeating for deeper levels
+    #[clap(long, use_delimiter = true)]
+    module_prefix: Vec<String>,
+
+    /// Number of `local_repository` repositories the libraries below the first level are spread
+    /// over, one level per repository (objc, android and java backends)
+    #[clap(long, default_value = "0")]
+    external_repos: u64,
+
+    /// Directory layout of the packages: `monolith`, `nested-workspaces` (every level below the
+    /// first a repository nested in the directory of the one above, objc, android and java
+    /// backends) or `symlinked` (every level a directory of its own, linked into the level above)
+    #[clap(long, default_value = "monolith")]
+    layout: layers::Layout,
+
+    /// Comma separated directories, e.g. on other disks, to distribute the library packages over
+    /// round-robin. The workspace stays in `--output` with a symlink to every library, which keeps
+    /// the generated files off its filesystem
+    #[clap(long, use_delimiter = true)]
+    shard_output: Vec<PathBuf>,
+
+    /// Have every library re-export its dependencies (`exports` for java and android, `pub use`
+    /// for rust), objc headers always re-export the modules of their dependencies
+    #[clap(long)]
+    reexport_deps: bool,
+
+    /// Rules the objc backend builds on: `rules_ios` frameworks and applications, or
+    /// `native-objc` for the builtin `objc_library` and the rules_apple `ios_application` alone,
+    /// to tell the overhead of the rules_ios macros apart from Bazel itself
+    #[clap(long, default_value = "rules_ios")]
+    rules: ObjcRules,
+
+    /// Rule the objc backend emits frameworks with, as `<load label>:<rule name>` or just the
+    /// name of a native rule like `objc_library`. It has to take the attributes the `--rules`
+    /// default sets, rules_ios' `apple_framework` or `objc_library`
+    #[clap(long)]
+    framework_rule: Option<starlark::RuleRef>,
+
+    /// Directory with `header.h.tpl`, `impl.m.tpl` and `BUILD.bazel.tpl` templates replacing the
+    /// generated files of every framework (objc backend only). `{module}`, `{name}`,
+    /// `{package}`, `{level}` and `{children}` are replaced with the module and target name,
+    /// package, level and imported modules, `{generated}` with the generated content. Sources
+    /// additionally get `{index}`, `{class}`, `{imports}` and `{include}`
+    #[clap(long = "source-template-dir")]
+    source_templates: Option<apple::SourceTemplates>,
+
+    /// Emit no-op stand-ins for the rules_ios rules, with the same attributes, so the workspace
+    /// loads and analyzes without Xcode or the Apple rule sets but builds nothing (objc backend
+    /// only)
+    #[clap(long)]
+    analysis_only: bool,
+
+    /// Milliseconds the single action of every framework takes with `--analysis-only`, as a
+    /// weighted choice like `100@0.9,2000@0.1`
+    #[clap(long)]
+    action_duration_ms: Option<layers::Distribution<u64>>,
+
+    /// Rule iOS applications are emitted with, like `--framework-rule`
+    #[clap(long)]
+    app_rule: Option<starlark::RuleRef>,
+
+    /// Root targets of the objc backend: `ios_application` for the applications of
+    /// `--platforms`, `macos_command_line` for a rules_apple macOS command line tool per
+    /// application, or `none` for just the frameworks and a `libraries` filegroup of the first
+    /// level, without any Apple application rules
+    #[clap(long, default_value = "ios_application")]
+    root_rule: RootRule,
+
+    /// Give every framework an explicit umbrella header and module.modulemap instead of the ones
+    /// rules_ios generates
+    #[clap(long)]
+    emit_modulemaps: bool,
+
+    /// Build the clang modules of frameworks explicitly from their module maps instead of through
+    /// the implicit module cache (objc backend only, implies `--emit-modulemaps`)
+    #[clap(long)]
+    explicit_modules: bool,
+
+    /// Number of Objective-C categories per framework, extending classes of its dependencies
+    /// (or `NSObject` for leaves)
+    #[clap(long, default_value = "0")]
+    categories_per_target: u64,
+
+    /// Number of SDK frameworks (from the iOS SDK) every framework imports in its headers and
+    /// links against, `sdk_frameworks` with rules_ios and `-framework` linkopts with native objc
+    /// rules, sampled per framework (objc backend only)
+    #[clap(long, default_value = "0")]
+    sdk_frameworks_per_target: usize,
+
+    /// Comma separated locales (e.g. `en,fr,pt-BR`) to localize the resources of every library
+    /// for: a `.strings` table and a storyboard in an `.lproj` per locale for objc frameworks, a
+    /// `res/values-*` strings file per locale for android libraries
+    #[clap(long, use_delimiter = true)]
+    locales: Vec<String>,
+
+    /// How objc sources import their dependencies: `module` (`@import`) or `textual` (`#import`
+    /// of their headers)
+    #[clap(long, default_value = "module")]
+    import_style: ImportStyle,
+
+    /// Prefix headers for frameworks: `none`, one `shared` by all of them or one `per-target`
+    #[clap(long, default_value = "none")]
+    pch: Pch,
+
+    /// Whether objc sources only import their direct dependencies (`strict`) or also reach into
+    /// the modules of their dependencies' dependencies (`loose`)
+    #[clap(long, default_value = "strict")]
+    header_discipline: HeaderDiscipline,
+
+    /// Probability of every edge of the tree from a library to a child on the level below to
+    /// exist; children left without one depend on a random library on any level above instead
+    #[clap(long, default_value = "1")]
+    dep_keep_probability: f64,
+
+    /// Fraction of the libraries below the first level to move to a random parent on a level
+    /// above, picked by `--seed`. Workspaces of the same flags and different seeds share most of
+    /// their graph, like a repository a month apart, to benchmark re-analysis after graph drift
+    #[clap(long, default_value = "0")]
+    shuffle_edges: f64,
+
+    /// Write the graph being generated, every node with its name, children and dependencies, as
+    /// JSON to this file, for `--from-graph`
+    #[clap(long)]
+    export_graph: Option<PathBuf>,
+
+    /// Generate the graph of an `--export-graph` file, possibly edited, instead of the tree of
+    /// `--height` and `--targets-per-level`, which have to match it. The libraries keep the
+    /// parents and names of the file, the other flags apply as usual. Its dependencies have to be
+    /// the ones the flags give the tree, so pass the dependency flags it was exported with
+    #[clap(long)]
+    from_graph: Option<PathBuf>,
+
+    /// Probability of a library to also depend directly on one descendant two or more levels
+    /// below it, skipping the levels in between
+    #[clap(long, default_value = "0")]
+    skip_level_deps: f64,
+
+    /// Number of libraries right before it on its level every library also depends on
+    #[clap(long, default_value = "0")]
+    sibling_deps: u64,
+
+    /// Fraction of dependency edges that are declared but never imported in sources
+    #[clap(long, default_value = "0")]
+    unused_deps: f64,
+
+    /// Fraction of libraries whose sources also import a grandchild they don't declare a
+    /// dependency on, listed in `undeclared_imports.txt`
+    #[clap(long, default_value = "0")]
+    undeclared_imports: f64,
+
+    /// Number of .proto files per package, compiled by a `proto_library` that the package's
+    /// library consumes through a language binding
+    #[clap(long, default_value = "0")]
+    protos_per_package: u64,
+
+    /// Proto binding rule used by the objc backend: `cc` or `swift`
+    #[clap(long, default_value = "cc")]
+    proto_bindings: ProtoBindings,
+
+    /// Fraction of libraries whose headers a genrule generates from checked-in `.h.in` files,
+    /// putting an action in front of the compiles of the library and its dependents (objc
+    /// backend only)
+    #[clap(long, default_value = "0")]
+    codegen_ratio: f64,
+
+    /// Fraction of the headers of every library, besides the first, that are private to it: left
+    /// out of its umbrella header and the imports of its dependents, and declared in
+    /// `private_headers`, or the `srcs` of an `objc_library` (objc backend only)
+    #[clap(long, default_value = "0")]
+    private_headers_ratio: f64,
+
+    /// Fraction of leaf frameworks replaced by imported prebuilt frameworks (objc backend only)
+    #[clap(long, default_value = "0")]
+    prebuilt_ratio: f64,
+
+    /// Number of generated compiler flags added to every library (objc, java, android and rust
+    /// backends)
+    #[clap(long, default_value = "0")]
+    copts_per_target: u64,
+
+    /// Number of generated preprocessor defines (objc), linker `x_defs` (go) or `--cfg` flags
+    /// (rust) added to every library
+    #[clap(long, default_value = "0")]
+    defines_per_target: u64,
+
+    /// Length the values of generated flags and defines are padded to
+    #[clap(long, default_value = "1")]
+    flag_length: u64,
+
+    /// Compile time of every source independent of the file count: `low`, `medium` or `high`
+    /// depth and breadth of generic instantiations (rust backend)
+    #[clap(long, default_value = "low")]
+    compile_cost: rust::CompileCost,
+
+    /// How generated frameworks are linked: `static`, `dynamic`, or `mixed:<ratio>` where ratio is
+    /// the fraction of frameworks linked dynamically
+    #[clap(long, default_value = "static")]
+    linkage: Linkage,
+
+    /// Give every dynamically linked framework an Info.plist, a bundle identifier and a bundled
+    /// resource, so the applications install on devices (objc backend with rules_ios only)
+    #[clap(long)]
+    framework_bundles: bool,
+
+    /// Number of genrules to emit in every package, each consuming the outputs of the previous one
+    #[clap(long, default_value = "0")]
+    genrules_per_package: u64,
+
+    /// Number of outputs produced by each generated genrule
+    #[clap(long, default_value = "1")]
+    genrule_outputs: u64,
+
+    /// Have the generated actions produce a tree artifact (declared directory) holding
+    /// `genrule_outputs` files instead of individual outputs
+    #[clap(long)]
+    genrule_tree_artifacts: bool,
+
+    /// Number of actions to emit in every package that run in a persistent worker, each hashing
+    /// the package's files and the output of the previous one
+    #[clap(long, default_value = "0")]
+    worker_actions_per_package: u64,
+
+    /// Number of filegroups to emit in every package, each of one exported data file. Every
+    /// library carries the filegroups of its dependencies and the first of their files as data
+    #[clap(long, default_value = "0")]
+    filegroups_per_package: u64,
+
+    /// Write a `REPO.bazel` and give every package a default visibility and a license of its own
+    /// as default package metadata, with a stub of the rules_license `license` rule (Bazel 7+)
+    #[clap(long)]
+    package_metadata: bool,
+
+    /// Number of vendored libraries to generate in `third_party/`, every library of the graph
+    /// depending on one of them (all backends but android)
+    #[clap(long, default_value = "0")]
+    third_party_packages: u64,
+
+    /// Depth of the chain of generated .bzl macro files that frameworks are created through, 0
+    /// uses the framework rule directly
+    #[clap(long, default_value = "0")]
+    bzl_depth: u64,
 
-    fn package_path(&self) -> PathBuf {
-        let res: String = (1..=self.parents.len())
-            .map(|i| format!("pkg_{}", i))
-            .intersperse("/".to_string())
-            .collect();
+    /// Number of .bzl files at every level of the macro chain, each loading all files of the next
+    /// level
+    #[clap(long, default_value = "1")]
+    bzl_fanout: u64,
 
-        PathBuf::from(res)
+    /// Number of generated `config_setting`s every framework selects on, deps and copts get
+    /// wrapped in `select()` expressions over them
+    #[clap(long, default_value = "0")]
+    selects_per_target: u64,
+
+    /// Number of chained `alias` targets interposed between consumers and every framework
+    #[clap(long, default_value = "0")]
+    alias_layers: u64,
+
+    /// Tag added to every library, can be repeated. With an `@<ratio>` suffix, e.g.
+    /// `no-remote@0.1`, only that fraction of the libraries gets it
+    #[clap(long = "tag")]
+    tags: Vec<layers::Stamp>,
+
+    /// `key=value` execution property added to every library, can be repeated and restricted to
+    /// a fraction of the libraries like `--tag`
+    #[clap(long = "exec-property")]
+    exec_properties: Vec<layers::Stamp>,
+
+    /// Restrict framework visibility to the level above via generated `package_group`s instead of
+    /// `//visibility:public`
+    #[clap(long)]
+    package_group_visibility: bool,
+
+    /// Visibility of the generated libraries: `public`, `package` (the packages below the level
+    /// above) or `parent-only` (exactly the packages depending on them)
+    #[clap(
+        long,
+        default_value = "public",
+        conflicts_with = "package-group-visibility"
+    )]
+    visibility: layers::Visibility,
+
+    /// List the outputs of `--also-emit` in the `.bazelignore`, so Bazel doesn't traverse them
+    #[clap(long)]
+    bazelignore_side_outputs: bool,
+
+    /// Have Bazel not create the `bazel-*` convenience symlinks in the workspace
+    #[clap(long)]
+    no_convenience_symlinks: bool,
+
+    /// Add `.bazelrc` configs building with debug info: `debug`, `dsym` additionally generating
+    /// dSYM bundles (objc backend) and `fission` additionally splitting the debug info of
+    /// C/C++ compiles into `.dwo` files. `bench --config-sweep debug,dsym` compares them
+    #[clap(long)]
+    debug_configs: bool,
+
+    /// Add a `--config=simulator` to the `.bazelrc` building the applications for the iOS
+    /// simulator, so `bazel run --config=simulator //:<app>` installs and launches them there
+    #[clap(long)]
+    with_run_target: bool,
+
+    /// Simulator device `bazel run` launches applications on with `--with-run-target`
+    #[clap(long, default_value = "iPhone 14")]
+    simulator_device: String,
+
+    /// iOS version of the simulator of `--with-run-target`
+    #[clap(long, default_value = "16.0")]
+    simulator_version: String,
+
+    /// Name of the workspace, declared with `workspace(name = ...)` at the top of the `WORKSPACE`
+    #[clap(long)]
+    workspace_name: Option<String>,
+
+    /// Also write a `<output>_bzlmod` sibling declaring the rulesets in a `MODULE.bazel` instead of
+    /// the `WORKSPACE`, with hardlinks to the same sources, to compare the dependency systems on
+    /// identical code. Editing a file in place changes it in both
+    #[clap(long)]
+    emit_both_dep_systems: bool,
+
+    /// Reverse DNS prefix of the bundle identifiers of the applications, extensions and
+    /// frameworks, and the package of the Android application
+    #[clap(long, default_value = "com.bazel.benchmark")]
+    bundle_id_prefix: String,
+
+    /// Target name of the application, `root` by default, or with several applications the
+    /// prefix of their `<name>_<n>` names, `app` by default (objc and android backends)
+    #[clap(long)]
+    app_name: Option<String>,
+
+    /// Number of disconnected subgraphs to generate, each with an application of its own. The
+    /// first-level libraries are split into contiguous blocks between them, and no dependency
+    /// crosses from one block to another
+    #[clap(long, default_value = "1")]
+    islands: u64,
+
+    /// Number of top-level applications, the first-level libraries are partitioned between them
+    #[clap(long, default_value = "1")]
+    apps: u64,
+
+    /// Fraction of the first-level libraries that every application depends on, on top of its
+    /// own partition
+    #[clap(long, default_value = "0")]
+    app_overlap: f64,
+
+    /// Appended to the sources of libraries outside of `--cache-hit-ratio`, so that workspaces
+    /// generated with different salts only partially share cache entries
+    #[clap(long, default_value = "")]
+    content_salt: String,
+
+    /// Fraction of libraries whose sources don't depend on `--content-salt`
+    #[clap(long, default_value = "0")]
+    cache_hit_ratio: f64,
+
+    /// Initialize a git repository in the output, with the generated workspace as first commit
+    #[clap(long)]
+    init_git: bool,
+
+    /// Number of synthetic commits to create on top of the initial one with `--init-git`. The
+    /// targets every commit impacts are written to the untracked `impacted_targets.json`
+    #[clap(long, default_value = "0")]
+    commits: u64,
+
+    /// Number of libraries mutated by every synthetic commit
+    #[clap(long, default_value = "1")]
+    mutations_per_commit: u64,
+
+    /// How synthetic commits and `diff-bench` edit a mutated library: a `comment`, an
+    /// `implementation` only change, an `interface` change or a `dep-edge` added or removed
+    #[clap(long, default_value = "comment")]
+    edit_kind: mutate::EditKind,
+
+    /// Number of libraries to break with a compile error, listed in `injected_errors.json`
+    #[clap(long, default_value = "0")]
+    inject_errors: u64,
+
+    /// Break the `--inject-errors` libraries with a dependency that fails their analysis instead
+    /// of a compile error
+    #[clap(long)]
+    inject_analysis_errors: bool,
+
+    /// Fraction of the libraries stamped with the workspace status of
+    /// `tools/workspace_status.sh`, through a stamped genrule and, for objc, a linkstamp. The
+    /// `stamp` config of the `.bazelrc` stamps the binaries as well
+    #[clap(long, default_value = "0")]
+    stamped_targets: f64,
+
+    /// Number of dependency cycles to close, each from a library below the first level back to
+    /// its ancestor on the first level, marked in the BUILD files and listed in
+    /// `injected_cycles.json`. Nothing depending on them builds
+    #[clap(long, default_value = "0")]
+    inject_cycles: u64,
+
+    /// Seed for the selection of mutated, `--inject-errors` and `--inject-cycles` libraries
+    #[clap(long, default_value = "0")]
+    seed: u64,
+
+    /// Number of app extensions embedded in every iOS application, each depending on a different
+    /// first-level library
+    #[clap(long, default_value = "0")]
+    extensions: u64,
+
+    /// Embed a watchOS application in every iOS application, requires `watchos` in `--platforms`
+    #[clap(long)]
+    watch_app: bool,
+
+    /// Emit a rules_xcodeproj `xcodeproj` target covering the applications and all libraries
+    /// (objc backend only)
+    #[clap(long)]
+    with_xcodeproj: bool,
+
+    /// Emit the hedron compile commands extractor setup, producing compile_commands.json for the
+    /// applications with `bazel run //:refresh_compile_commands` (objc backend only)
+    #[clap(long)]
+    emit_compile_commands: bool,
+
+    /// Provisioning profile to sign the iOS applications with, they are signed ad-hoc otherwise
+    #[clap(long)]
+    provisioning_profile: Option<PathBuf>,
+
+    /// Comma separated build systems to also describe the graph for, next to Bazel: `cmake`,
+    /// `xcodeproj`, `spm` and `buck2` (objc backend only)
+    #[clap(long, use_delimiter = true)]
+    also_emit: Vec<AlsoEmit>,
+
+    /// Comma separated IDE project files to generate: `intellij` (a `.bazelproject` project view)
+    /// and `vscode` (`.vscode/settings.json`)
+    #[clap(long, use_delimiter = true)]
+    ide: Vec<ide::Ide>,
+
+    /// Comma separated definitions of the environment to benchmark in to write, with Bazel
+    /// pinned through bazelisk and the tools of the workspace: `nix` (a `flake.nix` shell) and
+    /// `devcontainer` (`.devcontainer/`, a Linux image, so objc only with `--analysis-only`)
+    #[clap(long, use_delimiter = true)]
+    emit_env: Vec<devenv::EnvKind>,
+
+    /// Print a machine-readable summary of the generated workspace to stdout: `json`, following
+    /// the schema printed by `summary-schema`
+    #[clap(long)]
+    emit_summary: Option<summary::SummaryFormat>,
+
+    /// Log the generator's own throughput, files and bytes per second, and the wall time of its
+    /// phases, also adding them to the `--emit-summary` one: `graph` builds the graph model,
+    /// `targets` writes the packages, `metrics` walks the whole graph once more without writing
+    /// anything, which is about the share of `targets` that isn't I/O, and `workspace` writes the
+    /// rest
+    #[clap(long)]
+    self_bench: bool,
+
+    /// Continue an interrupted generation into the same output with the same flags, instead of
+    /// wiping it
+    #[clap(long)]
+    resume: bool,
+
+    /// Leave the partially written output in place when generation fails, e.g. on a full disk,
+    /// instead of removing it. `--resume` continues it once the cause is fixed. Outputs being
+    /// resumed are always kept
+    #[clap(long)]
+    keep_partial: bool,
+
+    /// Stop generating once the libraries make up more than this many files, after completing the
+    /// level that went over: the workspace gets the levels up to that one, and its
+    /// `BENCHMARK_METADATA.json` the cut height and the budget
+    #[clap(long)]
+    max_files: Option<u64>,
+
+    /// Like `--max-files`, for the bytes of the files of the libraries
+    #[clap(long)]
+    max_bytes: Option<u64>,
+
+    /// The `--height` asked for when `--max-files` or `--max-bytes` cut the graph lower
+    #[clap(skip)]
+    requested_height: Option<u32>,
+
+    /// Modification time of all generated files, in seconds since the epoch. With the normalized
+    /// permissions every file gets, it makes the output only depend on the flags. Given
+    /// explicitly, it is also the generation time `BENCHMARK_METADATA.json` records
+    #[clap(long, default_value = "315532800")]
+    mtime: u64,
+
+    /// Only log warnings, without progress bar
+    #[clap(long, short, conflicts_with = "verbose")]
+    quiet: bool,
+
+    /// Log every generated target instead of showing a progress bar
+    #[clap(long, short)]
+    verbose: bool,
+
+    /// Directory to vendor the external archives of the workspace into for offline builds: it gets
+    /// a `fetch.sh` to run on a machine with internet access, and the `.bazelrc` makes Bazel
+    /// download from it instead of the network
+    #[clap(long)]
+    vendor_deps: Option<PathBuf>,
+
+    /// Container image (e.g. `gcr.io/project/image@sha256:...`) to emit a remote execution
+    /// platform for, selected with `--config=rbe`
+    #[clap(long)]
+    rbe_platform: Option<String>,
+
+    /// Xcode version (e.g. `13.2.1`) to pin with a generated `xcode_config`, instead of the
+    /// one Bazel finds on the machine
+    #[clap(long)]
+    hermetic_apple_toolchain: Option<String>,
+
+    /// Directory with the compiler, linker and binutils (`cc`, `ld`, `ar`, ...) of a generated
+    /// Linux `cc_toolchain` to register instead of the autodetected one
+    #[clap(long)]
+    cc_toolchain_config: Option<PathBuf>,
+
+    /// Have the `main` of every application reference a class of each of its first-level
+    /// frameworks, so the linker has to pull in the whole graph
+    #[clap(long)]
+    link_all_roots: bool,
+
+    /// Comma separated Apple platforms to emit applications for, frameworks are built for all of
+    /// them
+    #[clap(long, use_delimiter = true, default_value = "ios")]
+    platforms: Vec<Platform>,
+
+    /// Comma separated iOS architectures (`arm64`, `arm64e`, `sim_arm64`, `x86_64`) to add
+    /// `.bazelrc` configs for: `arch_<arch>` building for one of them, `archs_simulator` and
+    /// `archs_device` for all simulator and device ones at once, which `--with-run-target`'s
+    /// `simulator` config uses too. rules_apple takes architectures from flags only, the rules
+    /// don't change. `bench --arch-sweep` or `--config-sweep` compares them (objc backend only)
+    #[clap(long, use_delimiter = true)]
+    archs: Vec<String>,
+}
+
+/// The name of a backend of `backend::BACKENDS`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct Language(&'static str);
+
+impl Language {
+    const OBJC: Language = Language("objc");
+    const RUST: Language = Language("rust");
+
+    fn as_str(&self) -> &'static str {
+        self.0
     }
 
-    fn lib_path(&self) -> PathBuf {
-        self.package_path()
-            .join(format!("lib_{}", self.package_relative_index))
+    /// The library rule the backend emits.
+    fn library_rule(&self, args: &Args) -> starlark::RuleRef {
+        backend::backend(*self).library_rule(args)
     }
+}
 
-    fn lib_name(&self) -> String {
-        let res: String = (1..=self.parents.len())
-            .map(|i| format!("Pkg{}", i))
-            .intersperse("_".to_string())
-            .collect();
+impl FromStr for Language {
+    type Err = anyhow::Error;
 
-        format!("{}_Lib{}", res, self.package_relative_index)
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match backend::BACKENDS.iter().find(|backend| backend.name() == s) {
+            Some(backend) => Ok(Language(backend.name())),
+            None => {
+                let names: Vec<_> = backend::BACKENDS.iter().map(|b| b.name()).collect();
+                bail!(
+                    "unknown language {}, expected {}",
+                    s,
+                    backend::enumerate(&names, "or")
+                )
+            }
+        }
     }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum ProtoBindings {
+    Cc,
+    Swift,
+}
+
+impl FromStr for ProtoBindings {
+    type Err = anyhow::Error;
 
-    fn children(&self) -> Vec<ID> {
-        if self.parents.len() >= self.max_depth as usize {
-            return vec![];
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "cc" => Ok(ProtoBindings::Cc),
+            "swift" => Ok(ProtoBindings::Swift),
+            _ => bail!("unknown proto bindings {}, expected cc or swift", s),
         }
+    }
+}
 
-        let mut result = vec![];
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum HeaderDiscipline {
+    Strict,
+    Loose,
+}
 
-        let mut parents = self.parents.clone();
-        parents.push(self.clone());
+impl FromStr for HeaderDiscipline {
+    type Err = anyhow::Error;
 
-        for i in 0..self.targets_per_level {
-            result.push(ID {
-                id: self.id * self.targets_per_level + i,
-                parents: parents.clone(),
-                package_relative_index: self.targets_per_level * (self.package_relative_index - 1)
-                    + i
-                    + 1,
-                targets_per_level: self.targets_per_level,
-                max_depth: self.max_depth,
-            })
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "strict" => Ok(HeaderDiscipline::Strict),
+            "loose" => Ok(HeaderDiscipline::Loose),
+            _ => bail!("unknown header discipline {}, expected strict or loose", s),
         }
-
-        result
     }
 }
 
-impl Display for ID {
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        write!(f, "{:?}", self.lib_path())
-    }
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum ObjcRules {
+    RulesIos,
+    NativeObjc,
 }
 
-fn handle_node(node: &ID, files_per_target: u64, root_dir: &Path) {
-    println!("handling {}", node);
-    let lib_dir = root_dir.join(node.lib_path());
-    std::fs::create_dir_all(&lib_dir).unwrap();
+impl FromStr for ObjcRules {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "rules_ios" => Ok(ObjcRules::RulesIos),
+            "native-objc" => Ok(ObjcRules::NativeObjc),
+            _ => bail!("unknown rules {}, expected rules_ios or native-objc", s),
+        }
+    }
+}
 
-    let mut f = std::fs::File::create(&lib_dir.join("BUILD.bazel")).unwrap();
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum RootRule {
+    IosApplication,
+    MacosCommandLine,
+    None,
+}
 
-    writeln!(
-        f,
-        r#"load("@build_bazel_rules_ios//rules:framework.bzl", "apple_framework")"#
-    )
-    .unwrap();
-    let deps_snippet: String = node
-        .children()
-        .iter()
-        .map(|node| format!(r#""//{}""#, node.lib_path().to_str().unwrap()))
-        .intersperse(", ".to_string())
-        .collect();
-
-    let srcs = (1..=files_per_target)
-        .into_iter()
-        .flat_map(|i| {
-            vec![
-                format!(r#""{}_Hdr{}.h""#, node.lib_name(), i),
-                format!(r#""{}_Src{}.m""#, node.lib_name(), i),
-            ]
-        })
-        .intersperse(", ".to_string());
-
-    let srcs_snippet = "";
-    writeln!(
-        f,
-        r#"apple_framework(name = "lib_{}",
-    module_name = "{}",
-    srcs = [
-        {}
-    ],
-    deps = [
-{}
-    ],
-    visibility = ["//visibility:public"])"#,
-        node.package_relative_index,
-        node.lib_name(),
-        srcs.collect::<String>(),
-        deps_snippet,
-    )
-    .unwrap();
-
-    for i in 1..=files_per_target {
-        write_objc_files(&lib_dir, node, files_per_target);
-    }
-}
-
-fn write_objc_files(lib_dir: &Path, node: &ID, files_per_target: u64) {
-    for i in 1..=files_per_target {
-        let mut hdr_file = BufWriter::new(
-            std::fs::File::create(&lib_dir.join(format!("{}_Hdr{}.h", node.lib_name(), i)))
-                .unwrap(),
-        );
+impl FromStr for RootRule {
+    type Err = anyhow::Error;
 
-        // for framework in ALL_FRAMEWORKS {
-        //     writeln!(hdr_file, "@import {};", framework).unwrap();
-        // }
-        writeln!(hdr_file, "@import Foundation;").unwrap();
-        for child in node.children() {
-            writeln!(hdr_file, "@import {};", child.lib_name()).unwrap();
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "ios_application" => Ok(RootRule::IosApplication),
+            "macos_command_line" => Ok(RootRule::MacosCommandLine),
+            "none" => Ok(RootRule::None),
+            _ => bail!(
+                "unknown root rule {}, expected ios_application, macos_command_line or none",
+                s
+            ),
         }
+    }
+}
 
-        writeln!(
-            hdr_file,
-            "@interface {}_Hdr{}_Class : NSObject",
-            node.lib_name(),
-            i
-        )
-        .unwrap();
-        writeln!(hdr_file, "@end").unwrap();
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum ImportStyle {
+    Module,
+    Textual,
+}
 
-        let mut m_file = BufWriter::new(
-            std::fs::File::create(&lib_dir.join(format!("{}_Src{}.m", node.lib_name(), i)))
-                .unwrap(),
-        );
+impl FromStr for ImportStyle {
+    type Err = anyhow::Error;
 
-        writeln!(
-            m_file,
-            "#include \"{}/{}_Hdr{}.h\"",
-            node.lib_name(),
-            node.lib_name(),
-            i
-        )
-        .unwrap();
-        writeln!(m_file, "@implementation {}_Hdr{}_Class", node.lib_name(), i).unwrap();
-        writeln!(m_file, "@end").unwrap();
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "module" => Ok(ImportStyle::Module),
+            "textual" => Ok(ImportStyle::Textual),
+            _ => bail!("unknown import style {}, expected module or textual", s),
+        }
     }
 }
 
-fn package(v: &Vec<u64>) -> String {
-    (1..=v.len())
-        .map(|i| format!("pkg_{}", i))
-        .intersperse("/".to_string())
-        .collect()
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Pch {
+    None,
+    Shared,
+    PerTarget,
 }
 
-fn num_nodes_in_ntree(targets_per_level: u64, height: u32) -> u64 {
-    (targets_per_level.pow(height + 1) - 1) / (targets_per_level - 1)
+impl FromStr for Pch {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "none" => Ok(Pch::None),
+            "shared" => Ok(Pch::Shared),
+            "per-target" => Ok(Pch::PerTarget),
+            _ => bail!(
+                "unknown pch mode {}, expected none, shared or per-target",
+                s
+            ),
+        }
+    }
 }
 
-#[tokio::main]
-async fn main() -> anyhow::Result<()> {
-    let args = Args::parse();
-
-    std::fs::remove_dir_all(&args.output).unwrap_or(());
-    std::fs::create_dir_all(&args.output)?;
-
-    let targets_per_level = args.targets_per_level as u64;
-
-    // k^{h+1} - 1) / (k - 1 )
-    let height = args.height;
-    let num_nodes = num_nodes_in_ntree(targets_per_level, height);
-    stream::iter(0..num_nodes)
-        .for_each_concurrent(64, |i| {
-            emit_build_file(
-                i,
-                args.targets_per_level,
-                args.files_per_target,
-                args.output.clone(),
-                height as u64,
-            )
-        })
-        .await;
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum AlsoEmit {
+    Cmake,
+    Xcodeproj,
+    Spm,
+    Buck2,
+}
 
-    std::fs::copy(Path::new("GEN_WORKSPACE"), args.output.join("WORKSPACE")).unwrap();
+impl FromStr for AlsoEmit {
+    type Err = anyhow::Error;
 
-    let mut f = std::fs::File::create(args.output.join(".bazelversion")).unwrap();
-    writeln!(f, "5.0.0.7").unwrap();
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "cmake" => Ok(AlsoEmit::Cmake),
+            "xcodeproj" => Ok(AlsoEmit::Xcodeproj),
+            "spm" => Ok(AlsoEmit::Spm),
+            "buck2" => Ok(AlsoEmit::Buck2),
+            _ => bail!(
+                "unknown build system {}, expected cmake, xcodeproj, spm or buck2",
+                s
+            ),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Platform {
+    Ios,
+    Macos,
+    Tvos,
+    Watchos,
+}
+
+impl Platform {
+    fn name(&self) -> &'static str {
+        match self {
+            Platform::Ios => "ios",
+            Platform::Macos => "macos",
+            Platform::Tvos => "tvos",
+            Platform::Watchos => "watchos",
+        }
+    }
 
-    let mut f = std::fs::File::create(args.output.join("main.m")).unwrap();
-    writeln!(f, "int main(int, char*[]){{return  0;}}").unwrap();
+    fn minimum_os_version(&self) -> &'static str {
+        match self {
+            Platform::Ios => "15.0",
+            Platform::Macos => "12.0",
+            Platform::Tvos => "15.0",
+            Platform::Watchos => "8.0",
+        }
+    }
+}
+
+impl FromStr for Platform {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "ios" => Ok(Platform::Ios),
+            "macos" => Ok(Platform::Macos),
+            "tvos" => Ok(Platform::Tvos),
+            "watchos" => Ok(Platform::Watchos),
+            _ => bail!(
+                "unknown platform {}, expected ios, macos, tvos or watchos",
+                s
+            ),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Linkage {
+    Static,
+    Dynamic,
+    Mixed(f64),
+}
+
+impl Linkage {
+    fn is_dynamic(&self, node: &ID) -> bool {
+        match *self {
+            Linkage::Static => false,
+            Linkage::Dynamic => true,
+            Linkage::Mixed(ratio) => node_fraction(node.id, LINKAGE_SALT) < ratio,
+        }
+    }
+}
+
+impl FromStr for Linkage {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "static" => Ok(Linkage::Static),
+            "dynamic" => Ok(Linkage::Dynamic),
+            _ => {
+                let ratio = s
+                    .strip_prefix("mixed:")
+                    .ok_or_else(|| format_err!("expected static, dynamic or mixed:<ratio>"))?;
+                let ratio: f64 = ratio.parse()?;
+                if !(0.0..=1.0).contains(&ratio) {
+                    bail!("mixed linkage ratio must be between 0 and 1, got {}", ratio);
+                }
+                Ok(Linkage::Mixed(ratio))
+            }
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum FilesPerTarget {
+    /// Counts of the first library levels, the last one repeating for all deeper levels.
+    PerLevel(Vec<u64>),
+    /// Counts of the first level, the levels between it and the last one, and the last level,
+    /// which takes precedence when the graph has a single library level.
+    Roles { root: u64, mid: u64, leaf: u64 },
+}
+
+impl FilesPerTarget {
+    /// The counts multiplied by `factor`, keeping at least one file per library.
+    fn scaled(&self, factor: f64) -> FilesPerTarget {
+        let scale = |count: &u64| ((*count as f64 * factor).round() as u64).max(1);
+        match self {
+            FilesPerTarget::PerLevel(counts) => {
+                FilesPerTarget::PerLevel(counts.iter().map(scale).collect())
+            }
+            FilesPerTarget::Roles { root, mid, leaf } => FilesPerTarget::Roles {
+                root: scale(root),
+                mid: scale(mid),
+                leaf: scale(leaf),
+            },
+        }
+    }
+
+    fn for_node(&self, node: &ID) -> u64 {
+        match self {
+            FilesPerTarget::PerLevel(counts) => {
+                counts[(node.level.max(1) as usize - 1).min(counts.len() - 1)]
+            }
+            FilesPerTarget::Roles { leaf, .. } if node.is_leaf() => *leaf,
+            FilesPerTarget::Roles { root, .. } if node.level <= 1 => *root,
+            FilesPerTarget::Roles { mid, .. } => *mid,
+        }
+    }
+}
+
+impl FromStr for FilesPerTarget {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if !s.contains(':') {
+            let counts = s
+                .split(',')
+                .map(|count| count.parse())
+                .collect::<Result<Vec<u64>, _>>()?;
+            return Ok(FilesPerTarget::PerLevel(counts));
+        }
+        let (mut root, mut mid, mut leaf) = (None, None, None);
+        for part in s.split(',') {
+            let (role, count) = part
+                .split_once(':')
+                .ok_or_else(|| format_err!("expected <role>:<count>, got {}", part))?;
+            let slot = match role {
+                "root" => &mut root,
+                "mid" => &mut mid,
+                "leaf" => &mut leaf,
+                _ => bail!("unknown level role {}, expected root, mid or leaf", role),
+            };
+            *slot = Some(count.parse()?);
+        }
+        match (root, mid, leaf) {
+            (Some(root), Some(mid), Some(leaf)) => Ok(FilesPerTarget::Roles { root, mid, leaf }),
+            _ => bail!("expected counts for all of root, mid and leaf, got {}", s),
+        }
+    }
+}
+
+impl Args {
+    /// Rejects graph shapes the generator can't lay out, before anything derived from the node
+    /// count gets computed or written.
+    fn validate_shape(&self) -> anyhow::Result<()> {
+        if self.targets_per_level == 0 {
+            bail!("--targets-per-level must be at least 1, 1 makes the libraries a chain");
+        }
+        if self.height == 0 {
+            bail!("--height must be at least 1, a graph of height 0 is only the root without libraries");
+        }
+        let zero_files = match &self.files_per_target {
+            FilesPerTarget::PerLevel(counts) => counts.contains(&0),
+            FilesPerTarget::Roles { root, mid, leaf } => [root, mid, leaf].contains(&&0),
+        };
+        if zero_files {
+            bail!(
+                "--files-per-target counts must be at least 1, every library needs a source file"
+            );
+        }
+        // num_nodes_in_ntree goes through targets_per_level^(height + 1) for wider trees.
+        if self
+            .targets_per_level
+            .checked_pow(self.height + 1)
+            .is_none()
+        {
+            bail!(
+                "--height {} with --targets-per-level {} makes more targets than fit in 64 bits, \
+                 lower either",
+                self.height,
+                self.targets_per_level
+            );
+        }
+        Ok(())
+    }
+}
+
+const LINKAGE_SALT: u64 = 1;
+
+/// Deterministically maps a node id to a value in `[0, 1)`. Per-node choices are derived from this
+/// rather than a shared RNG so they don't depend on the order nodes get generated in. `salt`
+/// keeps unrelated choices for the same node independent of each other.
+fn node_fraction(id: u64, salt: u64) -> f64 {
+    let mut z = id
+        .wrapping_add(salt.wrapping_mul(0x9E37_79B9_7F4A_7C15))
+        .wrapping_add(0x9E37_79B9_7F4A_7C15);
+    z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+    z ^= z >> 31;
+    (z >> 11) as f64 / (1u64 << 53) as f64
+}
+
+/// Writes the files of node `node_id`, failing with the library and its package in the error.
+/// Leftovers of an interrupted run get removed first when `resumed`.
+fn emit_build_file(node_id: u64, args: &Args, resumed: bool) -> anyhow::Result<()> {
+    let id = ID::new(node_id, args.targets_per_level, args.height as u64);
+    emit_node(&id, args, resumed).map_err(|e| {
+        let (name, dir) = match node_id {
+            0 => ("the root package".to_string(), args.output.clone()),
+            _ => (layers::label(&id, args), layers::lib_dir(&id, args)),
+        };
+        format_err!("writing {} into {}: {}", name, dir.display(), e)
+    })
+}
+
+fn emit_node(id: &ID, args: &Args, resumed: bool) -> anyhow::Result<()> {
+    let backend = backend::backend(args.language);
+    if id.id == 0 {
+        backend.emit_package(id, args)?;
+        layers::write_metadata_files(id, args)?;
+    } else {
+        debug!("handling {}", id);
+        if resumed {
+            std::fs::remove_dir_all(layers::lib_dir(id, args)).unwrap_or(());
+        }
+        layers::link_shard(id, args)?;
+        backend.emit_package(id, args)?;
+        layers::write_metadata_files(id, args)?;
+        layers::write_noise_files(id, args)?;
+        mutate::apply_content_salt(args, id)?;
+        if args.skeleton_only {
+            mutate::empty_sources(args, id)?;
+        }
+    }
+    Ok(())
+}
+
+/// Number of threads writing nodes. Generation is bound by file system latency rather than CPU.
+const WRITERS: usize = 64;
+
+/// Writes all nodes an earlier run didn't complete. The writers claim node ids in increasing
+/// order, which walks the graph level by level without ever holding more than the nodes being
+/// written in memory. The first node that fails stops all writers. Completed nodes go to the
+/// `packer` of a streamed archive.
+fn emit_build_files(
+    args: &Args,
+    num_nodes: u64,
+    progress: &ProgressBar,
+    checkpoint: &resume::Checkpoint,
+    packer: Option<&archive::Packer>,
+) -> anyhow::Result<()> {
+    let next = AtomicU64::new(0);
+    let error = std::sync::Mutex::new(None);
+    std::thread::scope(|scope| {
+        for _ in 0..WRITERS {
+            scope.spawn(|| loop {
+                let node_id = next.fetch_add(1, Ordering::Relaxed);
+                if node_id >= num_nodes || error.lock().unwrap().is_some() {
+                    break;
+                }
+                if checkpoint.is_completed(node_id) {
+                    continue;
+                }
+                match emit_build_file(node_id, args, checkpoint.resumed())
+                    .and_then(|()| checkpoint.complete(node_id))
+                {
+                    Ok(()) => {
+                        if let Some(packer) = packer {
+                            packer.completed(node_id);
+                        }
+                        progress.inc(1);
+                    }
+                    Err(e) => {
+                        error.lock().unwrap().get_or_insert(e);
+                    }
+                }
+            });
+        }
+    });
+    error.into_inner().unwrap().map_or(Ok(()), Err)
+}
+
+#[tokio::main]
+async fn main() -> anyhow::Result<()> {
+    let argv: Vec<_> = std::env::args_os().collect();
+    if argv.iter().any(|arg| arg == "--list-presets") {
+        presets::print_presets();
+        return Ok(());
+    }
+    if let Some((output, configs)) = matrix::expand(&argv)? {
+        for config in &configs {
+            match Cli::parse_from(presets::expand(config.argv.clone())?).command {
+                Command::Generate(mut args) => {
+                    args.command_line = config.argv.clone();
+                    run_generate(args).await?
+                }
+                _ => unreachable!("matrix::expand only expands generate"),
+            }
+        }
+        return matrix::write_index(&output, &configs);
+    }
+    match Cli::parse_from(presets::expand(argv.clone())?).command {
+        Command::Generate(mut args) => {
+            args.command_line = argv;
+            run_generate(args).await
+        }
+        Command::Validate(args) => validate::run(&args),
+        Command::Report(args) => report::run(&args),
+        Command::DiffBench(mut args) => {
+            args.generate.command_line = diff_bench::generate_command_line(&argv);
+            diff_bench::run(args).await
+        }
+        Command::Calibrate(mut args) => {
+            args.generate.command_line = calibrate::generate_command_line(&argv);
+            calibrate::run(args).await
+        }
+        Command::QueryBench(args) => query_bench::run(&args),
+        Command::Bench(args) => bench::run(&args),
+        Command::Churn(args) => churn::run(&args),
+        Command::Regen(args) => regen::run(&args),
+        Command::Explain(args) => explain::run(&args),
+        Command::Mutate(args) => stream::run(&args),
+        Command::Diff(args) => diff::run(&args),
+        Command::SummarySchema => {
+            println!("{}", summary::SCHEMA);
+            Ok(())
+        }
+    }
+}
+
+/// `args` cut to the `--max-files` and `--max-bytes` budget, if they have one. Every command
+/// generating a workspace from the command line goes through it before `generate`.
+fn budgeted(args: Args) -> anyhow::Result<Args> {
+    if args.max_files.is_some() || args.max_bytes.is_some() {
+        budget::fit(args)
+    } else {
+        Ok(args)
+    }
+}
+
+async fn run_generate(args: Args) -> anyhow::Result<()> {
+    let args = budgeted(args)?;
+    if args.output_format != archive::OutputFormat::Dir {
+        return archive::generate_archive(args).await;
+    }
+    let start = Instant::now();
+    let args = Arc::new(args);
+    generate(args.clone(), None).await?;
+    summary::emit(&args, &args.output, &args.output, start.elapsed())
+}
+
+/// Writes the `.bazelignore`, which has to hide the `--external-repos` repositories and can hide
+/// the outputs of `--also-emit`, and the `.gitignore` of the build outputs.
+fn write_ignore_files(args: &Args) -> anyhow::Result<()> {
+    let mut bazelignore = vec![benchmarks::CACHE_DIR];
+    let mut gitignore = vec!["/bazel-*", "/.cache"];
+    if args.external_repos > 0 {
+        bazelignore.push(layers::EXTERNAL_REPOS_DIR);
+    }
+    match args.layout {
+        layers::Layout::Monolith => {}
+        layers::Layout::NestedWorkspaces => bazelignore.push("nested_2"),
+        layers::Layout::Symlinked => bazelignore.push(layers::SYMLINKED_LEVELS_DIR),
+    }
+    for output in &args.also_emit {
+        let (side_outputs, build_dir): (&[&str], _) = match output {
+            AlsoEmit::Cmake => (&[cmake::HEADERS_DIR], Some("/build")),
+            AlsoEmit::Xcodeproj => (&[xcode::PROJECT_DIR, xcode::MODULEMAPS_DIR], None),
+            AlsoEmit::Spm => (&[".build"], Some("/.build")),
+            AlsoEmit::Buck2 => (&["buck-out"], Some("/buck-out")),
+        };
+        if args.bazelignore_side_outputs {
+            bazelignore.extend(side_outputs);
+        }
+        gitignore.extend(build_dir);
+    }
+    std::fs::write(
+        args.output.join(".bazelignore"),
+        format!("{}\n", bazelignore.join("\n")),
+    )?;
+    std::fs::write(
+        args.output.join(".gitignore"),
+        format!("{}\n", gitignore.join("\n")),
+    )?;
+    Ok(())
+}
+
+fn init_logging(args: &Args) {
+    let level = if args.quiet {
+        Level::WARN
+    } else if args.verbose {
+        Level::DEBUG
+    } else {
+        Level::INFO
+    };
+    // diff-bench generates through here too, keep whichever subscriber came first
+    tracing_subscriber::fmt()
+        .with_max_level(level)
+        .with_target(false)
+        .without_time()
+        .with_writer(std::io::stderr)
+        .with_ansi(std::io::stderr().is_terminal())
+        .try_init()
+        .unwrap_or(());
+}
+
+/// Progress of writing the targets. Hidden when quiet, verbose (the per target logs take its
+/// place) or not writing to a terminal.
+fn progress_bar(args: &Args, num_nodes: u64) -> ProgressBar {
+    if args.quiet || args.verbose {
+        return ProgressBar::hidden();
+    }
+    let progress = ProgressBar::new(num_nodes);
+    progress.set_style(
+        ProgressStyle::with_template(
+            "{bar:40} {pos}/{len} targets, {per_sec}, {elapsed} elapsed, ETA {eta}",
+        )
+        .unwrap(),
+    );
+    progress
+}
+
+/// Validates `args` and sets up the naming, topology and symbol pool of the graph, everything
+/// generating the files of a library needs.
+fn prepare(args: &Args) -> anyhow::Result<()> {
+    init_logging(args);
+    args.validate_shape()?;
+    let invalid_prefix = args.module_prefix.iter().find(|prefix| {
+        prefix.starts_with(|c: char| c.is_ascii_digit())
+            || !prefix
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || c == '_')
+    });
+    if let Some(prefix) = invalid_prefix {
+        bail!(
+            "--module-prefix {} must be letters, digits and underscores, not starting with a digit",
+            prefix
+        );
+    }
+    let naming = graph::Naming {
+        label_length: args.label_length,
+        path_depth_padding: args.path_depth_padding,
+        scheme: args.naming_scheme.clone(),
+        short_packages: false,
+        module_prefixes: args.module_prefix.clone(),
+    };
+    graph::set_naming(watchdog::check(args, naming)?);
+    starlark::set_format(args.format);
+    if !(0.0..=1.0).contains(&args.dep_keep_probability) {
+        bail!(
+            "--dep-keep-probability must be between 0 and 1, got {}",
+            args.dep_keep_probability
+        );
+    }
+    if !(0.0..=1.0).contains(&args.shuffle_edges) {
+        bail!(
+            "--shuffle-edges must be between 0 and 1, got {}",
+            args.shuffle_edges
+        );
+    }
+    if args.max_files == Some(0) || args.max_bytes == Some(0) {
+        bail!("--max-files and --max-bytes must be at least 1");
+    }
+    if (args.max_files.is_some() || args.max_bytes.is_some())
+        && (args.resume || args.from_graph.is_some() || !args.shard_output.is_empty())
+    {
+        bail!("--max-files and --max-bytes cut the height, they don't combine with --resume, --from-graph or --shard-output");
+    }
+    if let Some(path) = &args.from_graph {
+        if args.dep_keep_probability < 1.0 || args.shuffle_edges > 0.0 {
+            bail!(
+                "--from-graph brings its own tree, it doesn't combine with --dep-keep-probability or --shuffle-edges"
+            );
+        }
+        graph::set_topology(None);
+        let (topology, names, deps) = graph_file::import(args, path)?;
+        graph::set_topology(Some(topology));
+        symbols::set_symbol_pool(Some(symbols::SymbolPool::from_names(args, &names)?));
+        graph_file::check_deps(args, path, &deps)?;
+    } else {
+        graph::set_topology(
+            (args.dep_keep_probability < 1.0 || args.shuffle_edges > 0.0).then(|| {
+                graph::Topology::pruned(
+                    args.targets_per_level,
+                    args.height,
+                    args.dep_keep_probability,
+                    args.islands,
+                    args.shuffle_edges,
+                    args.seed,
+                )
+            }),
+        );
+        symbols::set_symbol_pool(Some(symbols::SymbolPool::build(args)));
+    }
+    if let Some(path) = &args.export_graph {
+        graph_file::export(args, path)?;
+    }
+
+    backend::require(args, "--external-repos", args.external_repos > 0)?;
+    if args.layout == layers::Layout::NestedWorkspaces {
+        backend::require(args, "--layout nested-workspaces", true)?;
+        if args.external_repos > 0 {
+            bail!("--layout nested-workspaces already puts the levels into repositories, it doesn't combine with --external-repos");
+        }
+    }
+    if !args.shard_output.is_empty()
+        && (args.external_repos > 0 || args.layout != layers::Layout::Monolith)
+    {
+        bail!("--shard-output links the libraries into the main repository, it needs --layout monolith and no --external-repos");
+    }
+    for shard in &args.shard_output {
+        std::fs::create_dir_all(shard)?;
+    }
+    if args.umbrella_per_level {
+        backend::require(args, "--umbrella-per-level", true)?;
+        if args.external_repos > 0 || args.layout != layers::Layout::Monolith {
+            bail!("--umbrella-per-level puts the umbrellas into the main repository, it needs --layout monolith and no --external-repos");
+        }
+        if args.visibility != layers::Visibility::Public || args.package_group_visibility {
+            bail!("--umbrella-per-level depends on every library of a level, it needs --visibility public");
+        }
+        if args.islands > 1 {
+            bail!("--umbrella-per-level would connect --islands");
+        }
+        if !args.also_emit.is_empty() {
+            bail!("--also-emit doesn't support --umbrella-per-level, the umbrellas are bazel only");
+        }
+    }
+    if args.watch_app && !args.platforms.contains(&Platform::Watchos) {
+        bail!("--watch-app needs frameworks built for watchos, add it to --platforms");
+    }
+    backend::require(args, "--with-xcodeproj", args.with_xcodeproj)?;
+    backend::require(args, "--emit-compile-commands", args.emit_compile_commands)?;
+    backend::require(args, "--also-emit", !args.also_emit.is_empty())?;
+    backend::require(args, "--locales", !args.locales.is_empty())?;
+    if let Some(locale) = args.locales.iter().find(|locale| {
+        locale.is_empty()
+            || !locale
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+    }) {
+        bail!(
+            "--locales {:?} must be letters, digits, dashes and underscores",
+            locale
+        );
+    }
+    backend::require(args, "--archs", !args.archs.is_empty())?;
+    if let Some(arch) = args
+        .archs
+        .iter()
+        .find(|arch| !apple::IOS_ARCHS.contains(&arch.as_str()))
+    {
+        bail!(
+            "unknown arch {}, expected {}",
+            arch,
+            apple::IOS_ARCHS.join(", ")
+        );
+    }
+    backend::require(
+        args,
+        "--sdk-frameworks-per-target",
+        args.sdk_frameworks_per_target > 0,
+    )?;
+    if args.sdk_frameworks_per_target > apple::ALL_FRAMEWORKS.len() {
+        bail!(
+            "--sdk-frameworks-per-target can be at most {}, the number of known SDK frameworks",
+            apple::ALL_FRAMEWORKS.len()
+        );
+    }
+    backend::require(args, "--explicit-modules", args.explicit_modules)?;
+    if args.explicit_modules && args.import_style != ImportStyle::Module {
+        bail!("--explicit-modules needs --import-style module");
+    }
+    let image = matches!(
+        args.output_format,
+        archive::OutputFormat::Ext4Image | archive::OutputFormat::SparseImage
+    );
+    if args.image_size.is_some() && !image {
+        bail!("--image-size needs --output-format ext4-image or sparse-image");
+    }
+    backend::require(args, "--emit-env", !args.emit_env.is_empty())?;
+    if args.emit_env.contains(&devenv::EnvKind::Devcontainer)
+        && args.language == Language::OBJC
+        && !args.analysis_only
+    {
+        bail!("--emit-env devcontainer is a Linux image without Xcode, the objc backend needs --analysis-only in it");
+    }
+    if args.post_hook_scope != hooks::HookScope::Once && args.post_hook.is_empty() {
+        bail!("--post-hook-scope needs --post-hook");
+    }
+    let test_flags = args.test_sizes.is_some()
+        || args.test_timeouts.is_some()
+        || args.test_shard_counts.is_some()
+        || args.flaky_ratio > 0.0
+        || args.test_hosted > 0.0;
+    if test_flags && !args.with_tests {
+        bail!("the --test-* and --flaky-ratio flags need --with-tests");
+    }
+    if args.with_tests && args.analysis_only {
+        bail!("--analysis-only has no fake ios_unit_test, it doesn't combine with --with-tests");
+    }
+    if !(0.0..=1.0).contains(&args.test_hosted) {
+        bail!(
+            "--test-hosted must be between 0 and 1, got {}",
+            args.test_hosted
+        );
+    }
+    if args.test_hosted > 0.0
+        && (!backend::backend(args.language).supports("--test-hosted")
+            || args.root_rule != RootRule::IosApplication
+            || !args.platforms.contains(&Platform::Ios))
+    {
+        bail!("--test-hosted needs an iOS application to host the tests, the objc backend with --root-rule ios_application");
+    }
+    backend::require(args, "--runfiles-per-test", args.runfiles_per_test > 0)?;
+    if let Some(counts) = &args.test_shard_counts {
+        if counts.values().any(|count| *count == 0) {
+            bail!("--test-shard-counts must be at least 1");
+        }
+    }
+    if !(0.0..=1.0).contains(&args.flake_rate) {
+        bail!(
+            "--flake-rate must be between 0 and 1, got {}",
+            args.flake_rate
+        );
+    }
+    backend::require(
+        args,
+        "--compile-cost",
+        args.compile_cost != rust::CompileCost::Low,
+    )?;
+    if args.rules == ObjcRules::NativeObjc {
+        backend::require(args, "--rules native-objc", true)?;
+        if args.pch != Pch::None {
+            bail!("--rules native-objc doesn't support --pch, objc_library dropped it");
+        }
+        if args.linkage != Linkage::Static {
+            bail!("--rules native-objc only builds static libraries, it needs --linkage static");
+        }
+        if args.external_repos > 0 || args.layout == layers::Layout::NestedWorkspaces {
+            bail!("--rules native-objc doesn't support --external-repos or --layout nested-workspaces");
+        }
+        if !args.also_emit.is_empty() {
+            bail!("--rules native-objc doesn't support --also-emit, its includes are bazel only");
+        }
+    }
+    backend::require(args, "--framework-bundles", args.framework_bundles)?;
+    if args.framework_bundles && args.linkage == Linkage::Static {
+        bail!("--framework-bundles bundles dynamically linked frameworks, it needs --linkage dynamic or mixed:<ratio>");
+    }
+    if matches!(
+        args.edit_kind,
+        mutate::EditKind::Implementation | mutate::EditKind::Interface
+    ) {
+        backend::require(
+            args,
+            &format!("--edit-kind {}", args.edit_kind.as_str()),
+            true,
+        )?;
+    }
+    if args.edit_kind == mutate::EditKind::DepEdge
+        && (args.visibility != layers::Visibility::Public || args.package_group_visibility)
+    {
+        bail!("--edit-kind dep-edge adds dependencies on arbitrary libraries, it needs --visibility public");
+    }
+    backend::require(
+        args,
+        "--source-template-dir",
+        args.source_templates.is_some(),
+    )?;
+    if args.root_rule != RootRule::IosApplication {
+        backend::require(args, "--root-rule", true)?;
+        let ios_only = [
+            ("--app-rule", args.app_rule.is_some()),
+            ("--extensions", args.extensions > 0),
+            ("--watch-app", args.watch_app),
+            (
+                "--provisioning-profile",
+                args.provisioning_profile.is_some(),
+            ),
+            ("--also-emit", !args.also_emit.is_empty()),
+        ];
+        if let Some((flag, _)) = ios_only.iter().find(|(_, set)| *set) {
+            bail!("{} needs --root-rule ios_application", flag);
+        }
+    }
+    if let Some(profile) = &args.provisioning_profile {
+        if !profile.is_file() {
+            bail!("--provisioning-profile {} isn't a file", profile.display());
+        }
+    }
+    if args.root_rule == RootRule::MacosCommandLine && !args.platforms.contains(&Platform::Macos) {
+        bail!("--root-rule macos_command_line needs frameworks built for macos, add it to --platforms");
+    }
+    if args.root_rule == RootRule::None {
+        let app_only = [
+            ("--link-all-roots", args.link_all_roots),
+            ("--with-xcodeproj", args.with_xcodeproj),
+            ("--emit-compile-commands", args.emit_compile_commands),
+        ];
+        if let Some((flag, _)) = app_only.iter().find(|(_, set)| *set) {
+            bail!(
+                "{} needs applications, it doesn't work with --root-rule none",
+                flag
+            );
+        }
+    }
+    if args.third_party_packages > 0 {
+        backend::require(args, "--third-party-packages", true)?;
+        if args.gazelle_compatible {
+            bail!("--third-party-packages needs hand-written BUILD files, it doesn't work with --gazelle-compatible");
+        }
+    }
+    if args.skeleton_only && !args.content_salt.is_empty() {
+        bail!("--skeleton-only empties the sources, --content-salt has nothing to salt");
+    }
+    if args.islands == 0 || args.islands > args.targets_per_level {
+        bail!(
+            "--islands must be between 1 and --targets-per-level, got {}",
+            args.islands
+        );
+    }
+    if args.apps == 0 || args.apps > args.targets_per_level {
+        bail!(
+            "--apps must be between 1 and --targets-per-level, got {}",
+            args.apps
+        );
+    }
+    if !(0.0..=1.0).contains(&args.app_overlap) {
+        bail!(
+            "--app-overlap must be between 0 and 1, got {}",
+            args.app_overlap
+        );
+    }
+    if args.islands > 1 {
+        backend::require(args, "--islands", true)?;
+        if args.apps > 1 || args.app_overlap > 0.0 {
+            bail!("--islands gives every island an application, it doesn't combine with --apps and --app-overlap");
+        }
+        if args.edit_kind == mutate::EditKind::DepEdge {
+            bail!("--edit-kind dep-edge would connect --islands");
+        }
+    }
+    backend::require(
+        args,
+        "--hermetic-apple-toolchain",
+        args.hermetic_apple_toolchain.is_some(),
+    )?;
+    backend::require(
+        args,
+        "--cc-toolchain-config",
+        args.cc_toolchain_config.is_some(),
+    )?;
+    if !(0.0..=1.0).contains(&args.stamped_targets) {
+        bail!(
+            "--stamped-targets must be between 0 and 1, got {}",
+            args.stamped_targets
+        );
+    }
+    backend::require(args, "--stamped-targets", args.stamped_targets > 0.0)?;
+    backend::require(
+        args,
+        "--filegroups-per-package",
+        args.filegroups_per_package > 0,
+    )?;
+    if args.emit_both_dep_systems {
+        backend::require(args, "--emit-both-dep-systems", true)?;
+        if args.output_format != archive::OutputFormat::Dir || args.init_git {
+            bail!("--emit-both-dep-systems writes a sibling directory, it doesn't combine with archive output formats or --init-git");
+        }
+        if !layers::repositories(args).is_empty()
+            || (args.protos_per_package > 0 && !backend::backend(args.language).has_proto_rules())
+            || args.with_xcodeproj
+            || args.emit_compile_commands
+            || args.cc_toolchain_config.is_some()
+            || args.vendor_deps.is_some()
+        {
+            bail!(
+                "--emit-both-dep-systems only knows the modules of the rulesets of the backends, it doesn't combine with external repositories, protos outside objc, --with-xcodeproj, --emit-compile-commands, --cc-toolchain-config or --vendor-deps"
+            );
+        }
+    }
+    if args.inject_cycles > 0 {
+        if args.height < 2 {
+            bail!("--inject-cycles needs a library below the first level, use --height 2 or more");
+        }
+        if args.visibility != layers::Visibility::Public || args.package_group_visibility {
+            bail!("--inject-cycles adds dependencies on libraries above, it needs --visibility public");
+        }
+    }
+    if args.inject_analysis_errors && args.inject_errors == 0 {
+        bail!("--inject-analysis-errors needs --inject-errors");
+    }
+    if args.inject_errors > 0
+        && !args.inject_analysis_errors
+        && !backend::backend(args.language).supports("--inject-errors")
+    {
+        bail!(
+            "the {} backend isn't compiled, --inject-errors needs --inject-analysis-errors with it",
+            args.language.as_str()
+        );
+    }
+    if !(0.0..=1.0).contains(&args.private_headers_ratio) {
+        bail!(
+            "--private-headers-ratio must be between 0 and 1, got {}",
+            args.private_headers_ratio
+        );
+    }
+    backend::require(
+        args,
+        "--private-headers-ratio",
+        args.private_headers_ratio > 0.0,
+    )?;
+    if args.codegen_ratio > 0.0 {
+        backend::require(args, "--codegen-ratio", true)?;
+        if !args.also_emit.is_empty() {
+            bail!("--also-emit doesn't support --codegen-ratio, generated headers are bazel only");
+        }
+    }
+    if args.action_duration_ms.is_some() && !args.analysis_only {
+        bail!("--action-duration-ms needs --analysis-only, the real rules run real actions");
+    }
+    if args.analysis_only {
+        if !backend::backend(args.language).supports("--analysis-only")
+            || args.rules != ObjcRules::RulesIos
+        {
+            bail!("--analysis-only only has stand-ins for the rules_ios rules of the objc backend");
+        }
+        if args.platforms != [Platform::Ios] || args.root_rule == RootRule::MacosCommandLine {
+            bail!(
+                "--analysis-only only has a stand-in for ios_application, it needs --platforms ios"
+            );
+        }
+        let real_rules_only = [
+            ("--framework-rule", args.framework_rule.is_some()),
+            ("--app-rule", args.app_rule.is_some()),
+            ("--extensions", args.extensions > 0),
+            ("--watch-app", args.watch_app),
+            ("--prebuilt-ratio", args.prebuilt_ratio > 0.0),
+            ("--protos-per-package", args.protos_per_package > 0),
+            ("--external-repos", args.external_repos > 0),
+            (
+                "--layout nested-workspaces",
+                args.layout == layers::Layout::NestedWorkspaces,
+            ),
+            ("--explicit-modules", args.explicit_modules),
+            ("--with-xcodeproj", args.with_xcodeproj),
+            ("--emit-compile-commands", args.emit_compile_commands),
+            ("--with-run-target", args.with_run_target),
+        ];
+        if let Some((flag, _)) = real_rules_only.iter().find(|(_, set)| *set) {
+            bail!(
+                "{} needs the real Apple rules, it doesn't work with --analysis-only",
+                flag
+            );
+        }
+    }
+    if args.with_run_target
+        && (!backend::backend(args.language).supports("--with-run-target")
+            || args.root_rule != RootRule::IosApplication
+            || !args.platforms.contains(&Platform::Ios))
+    {
+        bail!("--with-run-target needs iOS applications, with --root-rule ios_application and ios in --platforms");
+    }
+    if let Some(name) = &args.workspace_name {
+        if name.is_empty() || !name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_') {
+            bail!(
+                "--workspace-name {} may only contain letters, digits and underscores",
+                name
+            );
+        }
+    }
+    if args.bundle_id_prefix.is_empty()
+        || !args.bundle_id_prefix.split('.').all(|part| {
+            !part.is_empty() && part.chars().all(|c| c.is_ascii_alphanumeric() || c == '-')
+        })
+    {
+        bail!(
+            "--bundle-id-prefix {} is not a reverse DNS name like com.example.benchmark",
+            args.bundle_id_prefix
+        );
+    }
+    if let Some(name) = &args.app_name {
+        backend::require(args, "--app-name", true)?;
+        if name.is_empty()
+            || !name
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-')
+        {
+            bail!("--app-name {} is not a valid target name", name);
+        }
+    }
+    if args.undeclared_imports > 0.0 && args.header_discipline == HeaderDiscipline::Loose {
+        bail!("--undeclared-imports needs --header-discipline strict");
+    }
+    if args.also_emit.contains(&AlsoEmit::Spm) && args.prebuilt_ratio > 0.0 {
+        bail!("--also-emit spm doesn't support prebuilt frameworks");
+    }
+    if args.also_emit.contains(&AlsoEmit::Buck2) && args.naming_scheme == graph::NamingScheme::Mixed
+    {
+        bail!("--also-emit buck2 doesn't support --naming-scheme mixed");
+    }
+    if let Some(property) = args.exec_properties.iter().find(|p| !p.value.contains('=')) {
+        bail!(
+            "--exec-property {} is not of the form key=value",
+            property.value
+        );
+    }
+    backend::require(
+        args,
+        "--naming-scheme mixed",
+        args.naming_scheme == graph::NamingScheme::Mixed,
+    )?;
+    backend::require(
+        args,
+        "--naming-scheme hashed",
+        args.naming_scheme == graph::NamingScheme::Hashed,
+    )?;
+    Ok(())
+}
+
+/// Generates the workspace of `args`. When writing it fails the partial output is removed, unless
+/// it is kept for `--resume`.
+async fn generate(args: Arc<Args>, packer: Option<&archive::Packer>) -> anyhow::Result<()> {
+    let start = Instant::now();
+    self_bench::reset();
+    prepare(&args)?;
+    match write_output(args.clone(), start, packer).await {
+        Err(e) if !args.keep_partial && !args.resume => {
+            std::fs::remove_dir_all(&args.output).unwrap_or(());
+            bail!(
+                "{}\nremoved the partially generated {}, --keep-partial keeps it",
+                e,
+                args.output.display()
+            )
+        }
+        Err(e) => bail!(
+            "{}\nleft the partially generated {}, --resume continues it",
+            e,
+            args.output.display()
+        ),
+        Ok(()) => Ok(()),
+    }
+}
+
+async fn write_output(
+    args: Arc<Args>,
+    start: Instant,
+    packer: Option<&archive::Packer>,
+) -> anyhow::Result<()> {
+    let phase = self_bench::phase("graph", start);
+
+    // (k^{h+1} - 1) / (k - 1), or h + 1 for a chain
+    let num_nodes = num_nodes_in_ntree(args.targets_per_level, args.height);
+    let checkpoint = resume::Checkpoint::open(&args, num_nodes)?;
+    if checkpoint.resumed() {
+        info!(
+            "resuming, {} of {} targets already written",
+            checkpoint.num_completed(),
+            num_nodes
+        );
+    }
+    if let Some(preset) = &args.preset {
+        info!("using preset {}", preset);
+    }
+    info!(
+        "generating {} targets in {}",
+        num_nodes,
+        args.output.display()
+    );
+    if args.layout == layers::Layout::Symlinked {
+        layers::write_level_symlinks(&args).context("linking the levels of --layout symlinked")?;
+    }
+    let progress = progress_bar(&args, num_nodes);
+    progress.set_position(checkpoint.num_completed());
+    let written = tokio::task::block_in_place(|| {
+        emit_build_files(&args, num_nodes, &progress, &checkpoint, packer)
+    });
+    progress.finish_and_clear();
+    written?;
+    info!(
+        "wrote {} targets in {:.1}s",
+        num_nodes,
+        progress.elapsed().as_secs_f64()
+    );
+    let phase = self_bench::phase("targets", phase);
+
+    if args.undeclared_imports > 0.0 {
+        layers::write_undeclared_imports(&args).context("writing undeclared_imports.txt")?;
+    }
+
+    if args.flaky_ratio > 0.0 {
+        layers::write_flaky_tests(&args).context("writing flaky_tests.txt")?;
+    }
+
+    if args.genrules_per_package > 0 && args.genrule_tree_artifacts {
+        layers::write_tree_artifact_rule(&args.output)
+            .context("writing //tools:tree_artifact.bzl")?;
+    }
+
+    if args.worker_actions_per_package > 0 {
+        layers::write_worker(&args.output).context("writing //tools/worker")?;
+    }
+
+    if args.package_metadata {
+        layers::write_package_metadata(&args).context("writing the package metadata")?;
+    }
+
+    if args.third_party_packages > 0 {
+        third_party::write_packages(&args)?;
+    }
+
+    if args.selects_per_target > 0 {
+        layers::write_config_settings(&args.output, args.selects_per_target)
+            .context("writing //tools/config")?;
+    }
+
+    if args.package_group_visibility {
+        layers::write_package_groups(&args).context("writing //tools/visibility")?;
+    }
+
+    if args.bzl_depth > 0 {
+        let rule = args.language.library_rule(&args);
+        layers::write_bzl_macros(
+            &args.output,
+            args.bzl_depth,
+            args.bzl_fanout,
+            &rule.load,
+            &rule.name,
+        )
+        .context("writing //tools/macros")?;
+    }
+
+    if args.genquery_per_level > 0 {
+        layers::write_genqueries(&args).context("writing //tools/queries")?;
+    }
+
+    if args.level_targets {
+        layers::write_level_targets(&args).context("writing //tools/levels")?;
+    }
+
+    if args.umbrella_per_level {
+        layers::write_umbrellas(&args).context("writing the umbrellas")?;
+    }
+
+    if args.with_aspect {
+        layers::write_lint_aspect(&args.output).context("writing //tools/aspects")?;
+    }
+
+    info!("writing workspace files");
+    backend::backend(args.language)
+        .emit_workspace(&args)
+        .context("writing the WORKSPACE")?;
+
+    if let Some(name) = &args.workspace_name {
+        // The generated WORKSPACE may already declare a name, which has to make way.
+        let path = args.output.join("WORKSPACE");
+        let content = std::fs::read_to_string(&path)?;
+        let rest = content
+            .lines()
+            .filter(|line| !line.starts_with("workspace(name = "))
+            .collect::<Vec<_>>()
+            .join("\n");
+        let header = format!("workspace(name = {})\n", starlark::quote(name));
+        let rest = rest.trim();
+        std::fs::write(
+            &path,
+            if rest.is_empty() {
+                header
+            } else {
+                format!("{}\n{}\n", header, rest)
+            },
+        )?;
+    }
+    let mut workspace = std::fs::OpenOptions::new()
+        .append(true)
+        .open(args.output.join("WORKSPACE"))?;
+    if args.protos_per_package > 0 && !backend::backend(args.language).has_proto_rules() {
+        writeln!(workspace, "{}", layers::PROTO_WORKSPACE)?;
+    }
+    if args.with_xcodeproj {
+        writeln!(workspace, "{}", apple::XCODEPROJ_WORKSPACE)?;
+    }
+    if args.emit_compile_commands {
+        writeln!(workspace, "{}", apple::COMPILE_COMMANDS_WORKSPACE)?;
+    }
+    if !layers::repositories(&args).is_empty() {
+        writeln!(
+            workspace,
+            "{}",
+            layers::write_external_repos(&args).context("writing the external repositories")?
+        )?;
+    }
+    if let Some(tool_dir) = &args.cc_toolchain_config {
+        toolchain::write_cc_toolchain(&args, tool_dir).context("writing the C++ toolchain")?;
+        writeln!(workspace, "{}", toolchain::CC_TOOLCHAIN_WORKSPACE)?;
+    }
+    write_ignore_files(&args)?;
+
+    if args.also_emit.contains(&AlsoEmit::Xcodeproj) {
+        xcode::write_project(&args).context("writing the Xcode project")?;
+    }
+    if args.also_emit.contains(&AlsoEmit::Spm) {
+        spm::write_package(&args).context("writing Package.swift")?;
+    }
+
+    benchmarks::write_benchmark_script(&args).context("writing run_benchmarks.sh")?;
+    benchmarks::write_target_lists(&args).context("writing the target lists")?;
+    ide::write_ide_files(&args).context("writing the --ide files")?;
+    devenv::write(&args)?;
+
+    let mut f = std::fs::File::create(args.output.join(".bazelversion"))?;
+    writeln!(f, "5.0.0.7")?;
+
+    let mut bazelrc = vec![benchmarks::cache_bazelrc()];
+    if args.no_convenience_symlinks {
+        bazelrc.push("build --experimental_convenience_symlinks=ignore".to_string());
+    }
+    if args.debug_configs {
+        bazelrc.push(benchmarks::debug_bazelrc(&args));
+    }
+    if args.with_aspect {
+        bazelrc.push(
+            "build:with-aspect --aspects=//tools/aspects:lint.bzl%bench_lint_aspect\n\
+             build:with-aspect --output_groups=+bench_lint"
+                .to_string(),
+        );
+    }
+    if args.explicit_modules {
+        bazelrc.push(apple::EXPLICIT_MODULES_BAZELRC.to_string());
+    }
+    if !args.archs.is_empty() {
+        bazelrc.push(apple::archs_bazelrc(&args));
+    }
+    if args.with_run_target {
+        bazelrc.push(apple::simulator_bazelrc(&args));
+    }
+    if let Some(image) = &args.rbe_platform {
+        rbe::write_platforms(&args, image).context("writing //platforms")?;
+        bazelrc.push(rbe::bazelrc());
+    }
+    if let Some(version) = &args.hermetic_apple_toolchain {
+        toolchain::write_xcode_config(&args, version).context("writing the Xcode config")?;
+        bazelrc.push(toolchain::xcode_bazelrc(version));
+    }
+    if args.cc_toolchain_config.is_some() {
+        bazelrc.push(toolchain::cc_bazelrc());
+    }
+    if let Some(dir) = &args.vendor_deps {
+        bazelrc.push(vendor::write(&args, dir)?);
+    }
+    if args.stamped_targets > 0.0 {
+        layers::write_workspace_status(&args).context("writing tools/workspace_status.sh")?;
+        bazelrc.push(layers::stamping_bazelrc());
+    }
+    let mut f = std::fs::File::create(args.output.join(".bazelrc"))?;
+    writeln!(f, "{}", bazelrc.join("\n"))?;
+
+    if args.inject_errors > 0 {
+        inject::inject_errors(&args)?;
+    }
+    if args.inject_cycles > 0 {
+        inject::inject_cycles(&args)?;
+    }
+    if args.buildifier {
+        starlark::run_buildifier(std::slice::from_ref(&args.output))?;
+    }
+    hooks::run(&args)?;
+
+    summary::write_metadata(&args)?;
+    checkpoint.finish()?;
+
+    if args.init_git {
+        info!("creating git history");
+        mutate::init_git(&args)?;
+    }
+    if args.emit_both_dep_systems {
+        let sibling = bzlmod::write_sibling(&args)?;
+        archive::normalize_metadata(&sibling, args.mtime)?;
+    }
+    // The packer gives its entries their time and permissions, and removes the libraries it
+    // packed while this would walk them.
+    if packer.is_none() {
+        archive::normalize_metadata(&args.output, args.mtime)?;
+    }
+    self_bench::phase("workspace", phase);
 
     Ok(())
 }
@@ -0,0 +1,141 @@
+//! `--matrix` expansion: generates one workspace per combination of the values of some flags,
+//! e.g. `--matrix height=3..6 --matrix targets-per-level=4,8`, into subdirectories of `--output`
+//! with an `index.json` listing them. Sweeps then pay for process startup and argument handling
+//! once instead of for every workspace.
+
+use anyhow::{bail, format_err};
+use itertools::Itertools;
+use serde_json::json;
+use std::collections::BTreeMap;
+use std::ffi::OsString;
+use std::path::{Path, PathBuf};
+
+/// One workspace of the matrix: its subdirectory name, the flag values it is generated with and
+/// its full command line.
+pub struct Config {
+    pub name: String,
+    pub values: Vec<(String, String)>,
+    pub argv: Vec<OsString>,
+}
+
+/// Values of a `--matrix` axis: comma separated values or inclusive `<first>..<last>` ranges of
+/// integers.
+fn axis_values(values: &str) -> anyhow::Result<Vec<String>> {
+    let mut expanded = vec![];
+    for value in values.split(',') {
+        match value.split_once("..") {
+            Some((first, last)) => {
+                let (first, last): (u64, u64) = (first.parse()?, last.parse()?);
+                if first > last {
+                    bail!("empty --matrix range {}", value);
+                }
+                expanded.extend((first..=last).map(|v| v.to_string()));
+            }
+            None if value.is_empty() => bail!("empty --matrix value in {}", values),
+            None => expanded.push(value.to_string()),
+        }
+    }
+    Ok(expanded)
+}
+
+/// Splits the `--matrix <flag>=<values>` arguments off `argv`, returning `None` without them and
+/// the command line of every combination otherwise. Each one has the matrix flags and
+/// `--output <output>/<name>` appended, so matrix values take precedence over presets.
+pub fn expand(argv: &[OsString]) -> anyhow::Result<Option<(PathBuf, Vec<Config>)>> {
+    let mut axes: Vec<(String, Vec<String>)> = vec![];
+    let mut rest = vec![];
+    let mut output = None;
+    let mut args = argv.iter();
+    while let Some(arg) = args.next() {
+        let text = arg.to_str().unwrap_or_default();
+        let (flag, inline) = match text.split_once('=') {
+            Some((flag, value)) if flag.starts_with("--") => (flag, Some(value.to_string())),
+            _ => (text, None),
+        };
+        if flag != "--matrix" && flag != "--output" {
+            rest.push(arg.clone());
+            continue;
+        }
+        let value = match inline {
+            Some(value) => value,
+            None => args
+                .next()
+                .and_then(|value| value.to_str())
+                .ok_or_else(|| format_err!("{} needs a value", flag))?
+                .to_string(),
+        };
+        if flag == "--output" {
+            output = Some(PathBuf::from(value));
+            continue;
+        }
+        let (name, values) = value
+            .split_once('=')
+            .ok_or_else(|| format_err!("--matrix takes <flag>=<values>, got {}", value))?;
+        let name = name.trim_start_matches("--").to_string();
+        if matches!(name.as_str(), "output" | "matrix") {
+            bail!("--matrix can't vary --{}", name);
+        }
+        if axes.iter().any(|(axis, _)| *axis == name) {
+            bail!("--matrix {} given twice", name);
+        }
+        axes.push((name, axis_values(values)?));
+    }
+    if axes.is_empty() {
+        return Ok(None);
+    }
+    let output = output.ok_or_else(|| format_err!("--matrix needs --output"))?;
+    if !rest.iter().any(|arg| arg == "generate") {
+        bail!("--matrix is only supported by generate");
+    }
+    if rest.iter().any(|arg| {
+        arg.to_str()
+            .is_some_and(|arg| arg.starts_with("--emit-summary"))
+    }) {
+        bail!("--matrix writes index.json instead of --emit-summary");
+    }
+
+    let configs = axes
+        .iter()
+        .map(|(name, values)| {
+            values
+                .iter()
+                .map(move |value| (name.clone(), value.clone()))
+        })
+        .multi_cartesian_product()
+        .map(|values| {
+            let name = values
+                .iter()
+                .map(|(flag, value)| format!("{}-{}", flag, value))
+                .join("_");
+            let mut argv = rest.clone();
+            for (flag, value) in &values {
+                argv.push(format!("--{}", flag).into());
+                argv.push(value.into());
+            }
+            argv.push("--output".into());
+            argv.push(output.join(&name).into());
+            Config { name, values, argv }
+        })
+        .collect();
+    Ok(Some((output, configs)))
+}
+
+/// Writes `index.json` into the `output` of the matrix, listing every workspace with its
+/// directory and the flag values it was generated with.
+pub fn write_index(output: &Path, configs: &[Config]) -> anyhow::Result<()> {
+    let workspaces: Vec<_> = configs
+        .iter()
+        .map(|config| {
+            json!({
+                "name": config.name,
+                "output": config.name,
+                "parameters": config.values.iter().cloned().collect::<BTreeMap<_, _>>(),
+            })
+        })
+        .collect();
+    std::fs::write(
+        output.join("index.json"),
+        serde_json::to_string_pretty(&json!({ "workspaces": workspaces }))?,
+    )?;
+    Ok(())
+}
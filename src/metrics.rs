@@ -0,0 +1,112 @@
+//! Metrics of the generated dependency graph, computed from the generator's own model of it
+//! rather than by querying the workspace, which takes long for the graphs worth measuring.
+
+use crate::graph::{num_nodes_in_ntree, ID};
+use crate::{backend, Args};
+use serde_json::{json, Value};
+use tracing::info;
+
+pub struct Metrics {
+    libraries: u64,
+    edges: u64,
+    /// Targets on the longest dependency chain from the root, the root included.
+    longest_chain: u64,
+    max_fan_out: u64,
+    /// Average over the libraries with dependencies.
+    avg_fan_out: f64,
+    max_fan_in: u64,
+    /// Average over the libraries with dependents.
+    avg_fan_in: f64,
+    /// Libraries the root depends on transitively.
+    root_closure: u64,
+}
+
+/// The libraries `id` depends on, the first level for the root.
+pub fn dependencies(id: u64, args: &Args) -> Vec<u64> {
+    let node = ID::new(id, args.targets_per_level, args.height as u64);
+    if id == 0 {
+        node.children().iter().map(|child| child.id).collect()
+    } else {
+        backend::backend(args.language).dependency_ids(&node, args)
+    }
+}
+
+pub fn compute(args: &Args) -> Metrics {
+    let num_nodes = num_nodes_in_ntree(args.targets_per_level, args.height);
+    let deps: Vec<Vec<u64>> = (0..num_nodes).map(|id| dependencies(id, args)).collect();
+    let mut fan_in = vec![0u64; num_nodes as usize];
+    for dep in deps.iter().flatten() {
+        fan_in[*dep as usize] += 1;
+    }
+
+    // Dependencies are on deeper levels or earlier on the same level, so going up level by level
+    // and left to right within one finds every chain of a library's dependencies first.
+    let mut chain = vec![0u64; num_nodes as usize];
+    for level in (0..=args.height).rev() {
+        let first =
+            num_nodes_in_ntree(args.targets_per_level, level) - args.targets_per_level.pow(level);
+        for id in first..num_nodes_in_ntree(args.targets_per_level, level) {
+            chain[id as usize] = 1 + deps[id as usize]
+                .iter()
+                .map(|dep| chain[*dep as usize])
+                .max()
+                .unwrap_or(0);
+        }
+    }
+
+    let mut reached = vec![false; num_nodes as usize];
+    let mut stack = deps[0].clone();
+    let mut root_closure = 0;
+    while let Some(id) = stack.pop() {
+        if !std::mem::replace(&mut reached[id as usize], true) {
+            root_closure += 1;
+            stack.extend(&deps[id as usize]);
+        }
+    }
+
+    let libraries = &deps[1..];
+    let edges: u64 = libraries.iter().map(|deps| deps.len() as u64).sum();
+    let depending = libraries.iter().filter(|deps| !deps.is_empty()).count();
+    let depended_on = fan_in[1..].iter().filter(|fan_in| **fan_in > 0).count();
+    let library_fan_in: u64 = fan_in[1..].iter().sum();
+    Metrics {
+        libraries: num_nodes - 1,
+        edges,
+        longest_chain: chain[0],
+        max_fan_out: libraries
+            .iter()
+            .map(|deps| deps.len() as u64)
+            .max()
+            .unwrap_or(0),
+        avg_fan_out: edges as f64 / depending.max(1) as f64,
+        max_fan_in: fan_in[1..].iter().copied().max().unwrap_or(0),
+        avg_fan_in: library_fan_in as f64 / depended_on.max(1) as f64,
+        root_closure,
+    }
+}
+
+impl Metrics {
+    pub fn log(&self) {
+        info!(
+            "graph: {} libraries, {} edges between them, longest chain of {} targets, {} libraries below the root",
+            self.libraries, self.edges, self.longest_chain, self.root_closure
+        );
+        info!(
+            "fan-out {:.2} on average, {} at most; fan-in {:.2} on average, {} at most",
+            self.avg_fan_out, self.max_fan_out, self.avg_fan_in, self.max_fan_in
+        );
+    }
+
+    pub fn to_json(&self) -> Value {
+        json!({
+            "libraries": self.libraries,
+            "edges": self.edges,
+            "longest_chain": self.longest_chain,
+            "max_fan_out": self.max_fan_out,
+            "avg_fan_out": self.avg_fan_out,
+            "max_fan_in": self.max_fan_in,
+            "avg_fan_in": self.avg_fan_in,
+            "root_closure": self.root_closure,
+        })
+    }
+}
@@ -0,0 +1,385 @@
+//! Seeded source mutations of an already generated workspace, used to model incremental changes
+//! for incremental build and target determination benchmarks.
+
+use crate::graph::{num_nodes_in_ntree, ID};
+use crate::{apple, backend, layers, node_fraction, starlark, Args};
+use anyhow::{bail, Context};
+use itertools::Itertools;
+use serde_json::{json, Value};
+use std::collections::BTreeSet;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use std::str::FromStr;
+
+const MUTATION_SALT: u64 = 3;
+const CACHE_SALT: u64 = 5;
+
+const IMPACT_REPORT: &str = "impacted_targets.json";
+
+/// How a mutated library gets edited.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum EditKind {
+    /// A line comment, which changes the content of a source without changing what it compiles
+    /// to.
+    Comment,
+    /// A changed method body in the first implementation file, leaving the interface alone (objc
+    /// backend only).
+    Implementation,
+    /// A new method declared in the first header, changing the interface of the module (objc
+    /// backend only).
+    Interface,
+    /// Adds a dependency on a library of the last level to the library, or removes the one an
+    /// earlier edit added, which changes the action graph but no source.
+    DepEdge,
+}
+
+impl EditKind {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            EditKind::Comment => "comment",
+            EditKind::Implementation => "implementation",
+            EditKind::Interface => "interface",
+            EditKind::DepEdge => "dep-edge",
+        }
+    }
+}
+
+impl FromStr for EditKind {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "comment" => Ok(EditKind::Comment),
+            "implementation" => Ok(EditKind::Implementation),
+            "interface" => Ok(EditKind::Interface),
+            "dep-edge" => Ok(EditKind::DepEdge),
+            _ => bail!(
+                "unknown edit kind {}, expected comment, implementation, interface or dep-edge",
+                s
+            ),
+        }
+    }
+}
+
+/// Source files of the library in `lib_dir` that can take a line comment, including ones in
+/// subdirectories (e.g. headers of prebuilt frameworks) but not symlinks to them, sorted by path.
+pub fn source_files(lib_dir: &Path) -> anyhow::Result<Vec<PathBuf>> {
+    let mut files = vec![];
+    let mut dirs = vec![lib_dir.to_path_buf()];
+    while let Some(dir) = dirs.pop() {
+        for entry in
+            std::fs::read_dir(&dir).with_context(|| format!("listing {}", dir.display()))?
+        {
+            let entry = entry?;
+            let path = entry.path();
+            let file_type = entry.file_type()?;
+            if file_type.is_dir() {
+                dirs.push(path);
+            } else if file_type.is_file() && comment_prefix(&path).is_some() {
+                files.push(path);
+            }
+        }
+    }
+    files.sort();
+    Ok(files)
+}
+
+pub fn comment_prefix(path: &Path) -> Option<&'static str> {
+    match path.extension()?.to_str()? {
+        "h" | "m" | "java" | "kt" | "go" | "rs" => Some("//"),
+        "py" => Some("#"),
+        _ => None,
+    }
+}
+
+/// Picks `count` libraries to mutate in `round`, deterministically for a given `seed`.
+pub fn pick_nodes(args: &Args, seed: u64, round: u64, count: u64) -> Vec<ID> {
+    let libraries = num_nodes_in_ntree(args.targets_per_level, args.height) - 1;
+    (0..count)
+        .map(|k| {
+            let key = seed
+                .wrapping_mul(0x1000_0000_01B3)
+                .wrapping_add(round * count + k);
+            let id = 1 + (node_fraction(key, MUTATION_SALT) * libraries as f64) as u64;
+            ID::new(id, args.targets_per_level, args.height as u64)
+        })
+        .collect()
+}
+
+/// Edits `node` the `--edit-kind` way, mentioning `marker` in the edit. Returns the mutated files
+/// relative to the workspace.
+pub fn mutate_node(args: &Args, node: &ID, marker: &str) -> anyhow::Result<Vec<PathBuf>> {
+    mutate_node_as(args, node, args.edit_kind, marker)
+}
+
+/// Edits `node` the `kind` way, like `mutate_node`.
+pub fn mutate_node_as(
+    args: &Args,
+    node: &ID,
+    kind: EditKind,
+    marker: &str,
+) -> anyhow::Result<Vec<PathBuf>> {
+    let lib_dir = layers::lib_dir(node, args);
+    let files = match kind {
+        // Prebuilt frameworks have no sources to edit, their headers have to match the binary.
+        EditKind::Implementation | EditKind::Interface if apple::is_prebuilt(node, args) => {
+            vec![append_comment(&lib_dir, marker)?]
+        }
+        EditKind::Comment => vec![append_comment(&lib_dir, marker)?],
+        EditKind::Implementation => vec![edit_implementation(&lib_dir, node, marker)?],
+        EditKind::Interface => edit_interface(&lib_dir, node, marker)?,
+        EditKind::DepEdge => vec![toggle_dep_edge(&lib_dir, node, args)?],
+    };
+    Ok(files
+        .into_iter()
+        .map(|file| file.strip_prefix(&args.output).unwrap().to_path_buf())
+        .collect())
+}
+
+/// Appends a comment mentioning `marker` to the first source file of the library in `lib_dir`,
+/// which changes its content (and thus invalidates it) without changing what it compiles to.
+fn append_comment(lib_dir: &Path, marker: &str) -> anyhow::Result<PathBuf> {
+    let file = source_files(lib_dir)?.remove(0);
+    let mut f = std::fs::OpenOptions::new()
+        .append(true)
+        .open(&file)
+        .with_context(|| format!("opening {}", file.display()))?;
+    writeln!(f, "{} mutation {}", comment_prefix(&file).unwrap(), marker)?;
+    Ok(file)
+}
+
+/// Inserts `line` before the `@end` closing the class of an objc header or implementation.
+fn insert_before_end(file: &Path, line: &str) -> anyhow::Result<()> {
+    let content = std::fs::read_to_string(file)?;
+    let end = content
+        .rfind("@end")
+        .unwrap_or_else(|| panic!("{} has no @end", file.display()));
+    let content = format!("{}{}\n{}", &content[..end], line, &content[end..]);
+    std::fs::write(file, content)?;
+    Ok(())
+}
+
+/// Name of the method the `implementation` edit changes the body of.
+const MUTATED_METHOD: &str = "- (NSUInteger)benchMutation";
+
+/// Gives the class of the first implementation file of `node` a private method returning a value
+/// that depends on `marker`, or changes the value if an earlier edit added the method already.
+fn edit_implementation(lib_dir: &Path, node: &ID, marker: &str) -> anyhow::Result<PathBuf> {
+    let file = lib_dir.join(format!("{}_Src1.m", node.lib_name()));
+    let method = format!("{} {{ return @\"{}\".length; }}", MUTATED_METHOD, marker);
+    let content = std::fs::read_to_string(&file)?;
+    match content.lines().find(|l| l.starts_with(MUTATED_METHOD)) {
+        Some(existing) => std::fs::write(&file, content.replace(existing, &method))?,
+        None => insert_before_end(&file, &method)?,
+    }
+    Ok(file)
+}
+
+/// Declares a new method named after `marker` in the first header of `node` and implements it.
+fn edit_interface(lib_dir: &Path, node: &ID, marker: &str) -> anyhow::Result<Vec<PathBuf>> {
+    let name: String = marker
+        .chars()
+        .map(|c| if c.is_ascii_alphanumeric() { c } else { '_' })
+        .collect();
+    let method = format!("- (void)benchMutation_{}", name);
+    let mut header = lib_dir.join(format!("{}_Hdr1.h", node.lib_name()));
+    // The checked-in header of a `--codegen-ratio` library is the input of its genrule.
+    if !header.exists() {
+        header = header.with_extension("h.in");
+    }
+    let implementation = lib_dir.join(format!("{}_Src1.m", node.lib_name()));
+    insert_before_end(&header, &format!("{};", method))?;
+    insert_before_end(&implementation, &format!("{} {{}}", method))?;
+    Ok(vec![header, implementation])
+}
+
+/// The library `node` can get a new dependency on without a cycle: the first library of the last
+/// level not among its deps that depends on nothing `node` is a dependency of. Libraries only
+/// depend on deeper ones and earlier siblings, so for libraries above the last level that is any
+/// library of the last level, and for ones on it an earlier sibling.
+fn dep_edge_target(node: &ID, args: &Args) -> Option<ID> {
+    let first_leaf = num_nodes_in_ntree(args.targets_per_level, args.height - 1);
+    let end = if node.is_leaf() {
+        node.id
+    } else {
+        num_nodes_in_ntree(args.targets_per_level, args.height)
+    };
+    let deps = layers::direct_deps(node, args);
+    (first_leaf..end)
+        .map(|id| ID::new(id, args.targets_per_level, args.height as u64))
+        .find(|leaf| !deps.contains(leaf))
+}
+
+/// Adds the `dep_edge_target` to the `deps` of the library of `node`, as a separately
+/// concatenated list so that a later edit of the same library finds and removes it again.
+fn toggle_dep_edge(lib_dir: &Path, node: &ID, args: &Args) -> anyhow::Result<PathBuf> {
+    let file = lib_dir.join("BUILD.bazel");
+    let rule = layers::library_rule_name(&node.target_name(), args);
+    if !starlark::remove_appended_deps(&file, &rule)? {
+        match dep_edge_target(node, args) {
+            Some(target) => {
+                let list = starlark::string_list([layers::label(&target, args)]);
+                starlark::append_deps(&file, &rule, &list, "")?;
+            }
+            None => return append_comment(lib_dir, "dep-edge"),
+        }
+    }
+    Ok(file)
+}
+
+/// Libraries impacted by changing the sources of `mutated`: the libraries themselves and all of
+/// their transitive dependents, which in the generated tree are their parents and the later
+/// siblings of `--sibling-deps`. Edges to deeper descendants don't add dependents.
+pub fn impacted_libraries(mutated: &[ID], args: &Args) -> BTreeSet<String> {
+    let mut impacted = BTreeSet::new();
+    let mut stack = mutated.to_vec();
+    while let Some(node) = stack.pop() {
+        if node.id == 0 || !impacted.insert(node.id) {
+            continue;
+        }
+        stack.extend(node.parent());
+        stack.extend(layers::sibling_dependents(&node, args));
+    }
+    impacted
+        .into_iter()
+        .map(|id| {
+            let node = ID::new(id, args.targets_per_level, args.height as u64);
+            layers::label(&node, args)
+        })
+        .collect()
+}
+
+/// Entry of `impacted_targets.json` for the mutation `name` of the `mutated` libraries, changing
+/// `files`: the impacted libraries, their tests, and the root targets, which depend on
+/// everything.
+pub fn impact(name: &str, mutated: &[ID], files: &[PathBuf], args: &Args) -> Value {
+    let libraries = impacted_libraries(mutated, args);
+    let tests: Vec<_> = if args.with_tests && backend::backend(args.language).has_library_tests() {
+        libraries
+            .iter()
+            .map(|label| {
+                let name = label.rsplit('/').next().unwrap();
+                format!("{}:{}_test", label, name)
+            })
+            .collect()
+    } else {
+        vec![]
+    };
+    json!({
+        "name": name,
+        "changed_files": files,
+        "mutated": mutated.iter().map(|node| layers::label(node, args)).collect::<Vec<_>>(),
+        "impacted_libraries": libraries,
+        "impacted_tests": tests,
+        "impacted_root": !libraries.is_empty(),
+    })
+}
+
+/// Writes the `impact` entries of the mutations applied to the workspace to
+/// `impacted_targets.json`, the ground truth for target determination tools to compare against.
+pub fn write_impact_report(args: &Args, mutations: &[Value]) -> anyhow::Result<()> {
+    std::fs::write(
+        args.output.join(IMPACT_REPORT),
+        serde_json::to_string_pretty(&json!({ "mutations": mutations }))?,
+    )?;
+    Ok(())
+}
+
+/// The entries of the `impacted_targets.json` of the workspace, none if it has no report yet.
+pub fn read_impact_report(args: &Args) -> anyhow::Result<Vec<Value>> {
+    let path = args.output.join(IMPACT_REPORT);
+    if !path.exists() {
+        return Ok(vec![]);
+    }
+    let report: Value = serde_json::from_str(&std::fs::read_to_string(&path)?)
+        .with_context(|| format!("reading {}", path.display()))?;
+    Ok(report["mutations"].as_array().cloned().unwrap_or_default())
+}
+
+/// Appends `--content-salt` to the sources of `node`, unless it falls into the
+/// `--cache-hit-ratio` fraction of libraries whose content doesn't depend on the salt. Two
+/// workspaces generated with different salts then share the unsalted libraries, which bounds the
+/// cache hits one gets from the other.
+pub fn apply_content_salt(args: &Args, node: &ID) -> anyhow::Result<()> {
+    if args.content_salt.is_empty() || node_fraction(node.id, CACHE_SALT) < args.cache_hit_ratio {
+        return Ok(());
+    }
+    for file in source_files(&layers::lib_dir(node, args))? {
+        let mut f = std::fs::OpenOptions::new().append(true).open(&file)?;
+        writeln!(
+            f,
+            "{} salt {}",
+            comment_prefix(&file).unwrap(),
+            args.content_salt
+        )?;
+    }
+    Ok(())
+}
+
+/// Truncates the sources of `node` for `--skeleton-only`, leaving the files for globs and labels
+/// to find.
+pub fn empty_sources(args: &Args, node: &ID) -> anyhow::Result<()> {
+    for file in source_files(&layers::lib_dir(node, args))? {
+        std::fs::File::create(&file)?;
+    }
+    Ok(())
+}
+
+fn git(dir: &Path, git_args: &[&str]) -> anyhow::Result<()> {
+    let status = Command::new("git")
+        .args(git_args)
+        .current_dir(dir)
+        .env("GIT_AUTHOR_NAME", "gen_bazel_benchmark")
+        .env("GIT_AUTHOR_EMAIL", "gen_bazel_benchmark@localhost")
+        .env("GIT_COMMITTER_NAME", "gen_bazel_benchmark")
+        .env("GIT_COMMITTER_EMAIL", "gen_bazel_benchmark@localhost")
+        .status()?;
+    if !status.success() {
+        bail!("git {} failed with {}", git_args.join(" "), status);
+    }
+    Ok(())
+}
+
+/// Turns the generated workspace into a git repository with an initial commit followed by
+/// `args.commits` commits, each mutating `args.mutations_per_commit` libraries.
+pub fn init_git(args: &Args) -> anyhow::Result<()> {
+    let dir = &args.output;
+
+    git(dir, &["init", "--quiet"])?;
+    git(dir, &["add", "-A"])?;
+    git(dir, &["commit", "--quiet", "-m", "Generated workspace"])?;
+
+    let mut impacts = vec![];
+    for round in 1..=args.commits {
+        let nodes = pick_nodes(args, args.seed, round, args.mutations_per_commit);
+        let files = nodes
+            .iter()
+            .map(|node| mutate_node(args, node, &format!("commit {}", round)))
+            .collect::<anyhow::Result<Vec<_>>>()?
+            .concat();
+        let mut entry = impact(&format!("commit {}", round), &nodes, &files, args);
+        entry["revision"] = json!(format!("HEAD~{}", args.commits - round));
+        impacts.push(entry);
+        git(dir, &["add", "-A"])?;
+        let message = format!(
+            "Mutate {}\n\n{}",
+            nodes
+                .iter()
+                .map(|node| layers::label(node, args))
+                .join(", "),
+            files.iter().map(|f| f.to_str().unwrap()).join("\n")
+        );
+        git(dir, &["commit", "--quiet", "-m", &message])?;
+    }
+    if args.commits > 0 {
+        // The report describes the history, so it stays out of it.
+        write_impact_report(args, &impacts)?;
+        let mut exclude = std::fs::OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(dir.join(".git/info/exclude"))?;
+        writeln!(exclude, "/{}", IMPACT_REPORT)?;
+    }
+    Ok(())
+}
@@ -0,0 +1,142 @@
+//! Named flag combinations, so that benchmark results of different teams are comparable without
+//! everyone curating their own parameter sets.
+
+use anyhow::format_err;
+use std::ffi::OsString;
+
+pub struct Preset {
+    pub name: &'static str,
+    pub description: &'static str,
+    pub flags: &'static [(&'static str, &'static str)],
+}
+
+pub const PRESETS: &[Preset] = &[
+    Preset {
+        name: "mobile-medium",
+        description: "two iOS apps over ~1.5k frameworks",
+        flags: &[
+            ("--height", "4"),
+            ("--targets-per-level", "6"),
+            ("--files-per-target", "5"),
+            ("--apps", "2"),
+            ("--app-overlap", "0.25"),
+            ("--categories-per-target", "1"),
+        ],
+    },
+    Preset {
+        name: "mobile-large",
+        description: "iOS app family over ~56k frameworks, partially dynamic and prebuilt",
+        flags: &[
+            ("--height", "6"),
+            ("--targets-per-level", "6"),
+            ("--files-per-target", "10"),
+            ("--apps", "4"),
+            ("--app-overlap", "0.25"),
+            ("--linkage", "mixed:0.2"),
+            ("--prebuilt-ratio", "0.05"),
+            ("--categories-per-target", "1"),
+        ],
+    },
+    Preset {
+        name: "deep-chain",
+        description: "narrow graph with long dependency chains",
+        flags: &[
+            ("--height", "12"),
+            ("--targets-per-level", "2"),
+            ("--files-per-target", "2"),
+        ],
+    },
+    Preset {
+        name: "wide-flat",
+        description: "a single level of 5k independent libraries",
+        flags: &[
+            ("--height", "1"),
+            ("--targets-per-level", "5000"),
+            ("--files-per-target", "2"),
+        ],
+    },
+    Preset {
+        name: "java-large",
+        description: "~4k java libraries with protos",
+        flags: &[
+            ("--language", "java"),
+            ("--height", "5"),
+            ("--targets-per-level", "5"),
+            ("--files-per-target", "10"),
+            ("--protos-per-package", "1"),
+        ],
+    },
+    Preset {
+        name: "incremental",
+        description: "mobile-medium sized graph with a git history of 20 single library commits",
+        flags: &[
+            ("--height", "4"),
+            ("--targets-per-level", "6"),
+            ("--files-per-target", "5"),
+            ("--init-git", ""),
+            ("--commits", "20"),
+        ],
+    },
+];
+
+pub fn print_presets() {
+    for preset in PRESETS {
+        let flags: Vec<_> = preset
+            .flags
+            .iter()
+            .map(|(flag, value)| {
+                if value.is_empty() {
+                    flag.to_string()
+                } else {
+                    format!("{} {}", flag, value)
+                }
+            })
+            .collect();
+        println!(
+            "{}: {}\n    {}",
+            preset.name,
+            preset.description,
+            flags.join(" ")
+        );
+    }
+}
+
+/// Appends the flags of the `--preset` in `args` that aren't given explicitly, so that explicit
+/// flags override the preset.
+pub fn expand(mut args: Vec<OsString>) -> anyhow::Result<Vec<OsString>> {
+    let name = args.iter().enumerate().find_map(|(i, arg)| {
+        let arg = arg.to_str()?;
+        match arg.strip_prefix("--preset=") {
+            Some(name) => Some(name.to_string()),
+            None if arg == "--preset" => Some(args.get(i + 1)?.to_str()?.to_string()),
+            None => None,
+        }
+    });
+    let name = match name {
+        Some(name) => name,
+        None => return Ok(args),
+    };
+    let preset = PRESETS
+        .iter()
+        .find(|preset| preset.name == name)
+        .ok_or_else(|| format_err!("unknown preset {}, see --list-presets", name))?;
+
+    let given = |flag: &str| {
+        args.iter().any(|arg| {
+            arg.to_str()
+                .is_some_and(|arg| arg == flag || arg.starts_with(&format!("{}=", flag)))
+        })
+    };
+    let missing: Vec<_> = preset
+        .flags
+        .iter()
+        .filter(|(flag, _)| !given(flag))
+        .collect();
+    for (flag, value) in missing {
+        args.push(flag.into());
+        if !value.is_empty() {
+            args.push(value.into());
+        }
+    }
+    Ok(args)
+}
@@ -0,0 +1,248 @@
+//! Python backend: `py_library` packages importing each other through absolute imports, with
+//! an optional `py_test` per library.
+
+use crate::backend::EmitBackend;
+use crate::graph::ID;
+use crate::layers;
+use crate::starlark::{quote, string_list, BuildFile, Rule, RuleRef};
+use crate::{bzlmod, third_party, Args};
+use std::io::{BufWriter, Write};
+use std::path::Path;
+
+pub const LIBRARY_LOAD: &str = "@rules_python//python:defs.bzl";
+pub const LIBRARY_RULE: &str = "py_library";
+
+const WORKSPACE: &str = r#"load("@bazel_tools//tools/build_defs/repo:http.bzl", "http_archive")
+
+http_archive(
+    name = "rules_python",
+    strip_prefix = "rules_python-0.13.0",
+    url = "https://github.com/bazelbuild/rules_python/archive/refs/tags/0.13.0.tar.gz",
+)"#;
+
+fn module_path(node: &ID) -> String {
+    node.lib_path().to_str().unwrap().replace('/', ".")
+}
+
+fn import_alias(node: &ID) -> String {
+    format!("{}_src1", node.lib_name().to_lowercase())
+}
+
+fn write_imports(f: &mut impl Write, deps: &[ID]) -> anyhow::Result<()> {
+    for dep in deps {
+        writeln!(
+            f,
+            "from {} import src1 as {}",
+            module_path(dep),
+            import_alias(dep)
+        )?;
+    }
+    Ok(())
+}
+
+pub fn handle_root(args: &Args) -> anyhow::Result<()> {
+    let first_level: Vec<_> = (1..=args.targets_per_level)
+        .map(|i| ID::new(i, args.targets_per_level, args.height as u64))
+        .collect();
+
+    let mut build = BuildFile::new();
+    build.load(LIBRARY_LOAD, "py_binary");
+    let mut binary = Rule::new("py_binary", "root")
+        .attr("srcs", string_list(["main.py"]))
+        .attr("deps", string_list(first_level.iter().map(ID::label)));
+    if let Some(data) = layers::runfiles(&args.output, args)? {
+        binary.set("data", data);
+    }
+    build.add(binary);
+    build.write(&args.output.join("BUILD.bazel"))?;
+
+    let mut f = BufWriter::new(std::fs::File::create(args.output.join("main.py"))?);
+    write_imports(&mut f, &first_level)?;
+    write!(f, "\nif __name__ == \"__main__\":\n    print(0")?;
+    for dep in &first_level {
+        write!(f, " + {}.value1()", import_alias(dep))?;
+    }
+    writeln!(f, ")")?;
+    Ok(())
+}
+
+pub fn handle_node(node: &ID, args: &Args) -> anyhow::Result<()> {
+    let lib_dir = args.output.join(node.lib_path());
+    std::fs::create_dir_all(&lib_dir)?;
+
+    let deps = layers::direct_deps(node, args);
+    let name = node.target_name();
+
+    let mut srcs = vec!["__init__.py".to_string()];
+    srcs.extend((1..=args.files_per_target.for_node(node)).map(|i| format!("src{}.py", i)));
+    let rule = Rule::new(LIBRARY_RULE, &name)
+        .attr("srcs", layers::srcs(args, srcs, &["*.py"], &["*_test.py"]));
+
+    let labels = layers::dep_labels(node, &deps, args);
+
+    let mut build = BuildFile::new();
+    layers::add_library(&mut build, node, args, LIBRARY_LOAD, rule, labels)?;
+
+    if args.with_tests {
+        build.load(LIBRARY_LOAD, "py_test");
+        let mut test = Rule::new("py_test", format!("{}_test", name))
+            .attr("srcs", string_list([format!("{}_test.py", name)]))
+            .attr("main", quote(&format!("{}_test.py", name)))
+            .attr("deps", string_list([format!(":{}", name)]));
+        if let Some(data) = layers::runfiles(&lib_dir, args)? {
+            test.set("data", data);
+        }
+        layers::add_test_attrs(&mut test, node, args);
+        build.add(test);
+        write_test(&lib_dir, node, &name, args)?;
+    }
+
+    build.write(&lib_dir.join("BUILD.bazel"))?;
+
+    std::fs::File::create(lib_dir.join("__init__.py"))?;
+    let mut imported = layers::imported_deps(node, deps, args);
+    imported.extend(layers::undeclared_import(node, args));
+    for i in 1..=args.files_per_target.for_node(node) {
+        let mut f = BufWriter::new(std::fs::File::create(lib_dir.join(format!("src{}.py", i)))?);
+        write_imports(&mut f, &imported)?;
+        if !imported.is_empty() {
+            writeln!(f, "\n")?;
+        }
+        write!(f, "def value{}():\n    return {}", i, i)?;
+        for dep in &imported {
+            write!(f, " + {}.value1()", import_alias(dep))?;
+        }
+        writeln!(f)?;
+    }
+    Ok(())
+}
+
+/// Writes the test of `node`, with a test method per shard that only runs in its own shard.
+/// Flaky tests additionally fail with the `--flake-rate` probability.
+fn write_test(lib_dir: &Path, node: &ID, name: &str, args: &Args) -> anyhow::Result<()> {
+    let mut f = BufWriter::new(std::fs::File::create(
+        lib_dir.join(format!("{}_test.py", name)),
+    )?);
+    writeln!(
+        f,
+        r#"import os
+import random
+import unittest
+
+from {} import src1
+
+
+class {}Test(unittest.TestCase):"#,
+        module_path(node),
+        node.lib_name().replace('_', "")
+    )?;
+    for shard in 0..layers::shard_count(node, args) {
+        writeln!(
+            f,
+            "    def test_value_{}(self):\n        self.assertGreater(src1.value1(), 0)",
+            shard
+        )?;
+        // Only one method flakes, so the whole test fails at the flake rate
+        if shard == 0 && layers::is_flaky(node, args) {
+            writeln!(
+                f,
+                "        self.assertGreaterEqual(random.random(), {})",
+                args.flake_rate
+            )?;
+        }
+        writeln!(f)?;
+    }
+    writeln!(
+        f,
+        r#"
+if __name__ == "__main__":
+    # Bazel's test sharding protocol: acknowledge it and only run the methods of this shard
+    if "TEST_SHARD_STATUS_FILE" in os.environ:
+        open(os.environ["TEST_SHARD_STATUS_FILE"], "w").close()
+    shards = int(os.environ.get("TEST_TOTAL_SHARDS", "1"))
+    index = int(os.environ.get("TEST_SHARD_INDEX", "0"))
+    names = unittest.TestLoader().getTestCaseNames({}Test)
+    suite = unittest.TestSuite(
+        {}Test(name) for name in names if int(name.rsplit("_", 1)[1]) % shards == index
+    )
+    result = unittest.TextTestRunner().run(suite)
+    raise SystemExit(not result.wasSuccessful())"#,
+        node.lib_name().replace('_', ""),
+        node.lib_name().replace('_', "")
+    )?;
+    Ok(())
+}
+
+pub fn write_workspace(args: &Args) -> anyhow::Result<()> {
+    let mut f = std::fs::File::create(args.output.join("WORKSPACE"))?;
+    writeln!(f, "{}", WORKSPACE)?;
+    Ok(())
+}
+
+/// The language dependent flags the python backend implements.
+const SUPPORTED_FLAGS: &[&str] = &[
+    "--umbrella-per-level",
+    "--emit-env",
+    "--runfiles-per-test",
+    "--third-party-packages",
+    "--cc-toolchain-config",
+    "--stamped-targets",
+    "--filegroups-per-package",
+    "--emit-both-dep-systems",
+];
+
+pub struct Backend;
+
+impl EmitBackend for Backend {
+    fn name(&self) -> &'static str {
+        "python"
+    }
+
+    fn emit_workspace(&self, args: &Args) -> anyhow::Result<()> {
+        write_workspace(args)
+    }
+
+    fn emit_package(&self, node: &ID, args: &Args) -> anyhow::Result<()> {
+        match node.id {
+            0 => handle_root(args),
+            _ => handle_node(node, args),
+        }
+    }
+
+    fn library_rule(&self, _args: &Args) -> RuleRef {
+        RuleRef {
+            load: LIBRARY_LOAD.to_string(),
+            name: LIBRARY_RULE.to_string(),
+        }
+    }
+
+    fn supports(&self, flag: &str) -> bool {
+        SUPPORTED_FLAGS.contains(&flag)
+    }
+
+    fn has_library_tests(&self) -> bool {
+        true
+    }
+
+    fn third_party_library(&self, index: u64, _args: &Args) -> Option<third_party::Library> {
+        Some(third_party::Library {
+            src: "version.py".to_string(),
+            content: "VERSION = 1\n".to_string(),
+            load: LIBRARY_LOAD.to_string(),
+            rule: Rule::new(LIBRARY_RULE, third_party::name(index))
+                .attr("srcs", string_list(["version.py"])),
+        })
+    }
+
+    fn modules(&self, _args: &Args) -> Vec<bzlmod::Module> {
+        vec![("rules_python", "0.31.0", "rules_python")]
+    }
+
+    fn ide_languages(&self, _args: &Args) -> Vec<&'static str> {
+        vec!["python"]
+    }
+
+    fn env_tools(&self, _args: &Args) -> Vec<(&'static str, &'static str)> {
+        vec![("python3", "python3")]
+    }
+}
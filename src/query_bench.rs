@@ -0,0 +1,140 @@
+//! `query-bench` subcommand: times a suite of representative `query`, `cquery` and `aquery`
+//! invocations against a generated workspace. The query endpoints come from the
+//! `all_targets.txt` and `leaf_targets.txt` the generator writes, so they are known to span the
+//! whole graph.
+
+use crate::report::print_table;
+use anyhow::{bail, format_err};
+use clap::Parser;
+use serde_json::json;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use std::time::Instant;
+
+/// Time representative queries, cqueries and aqueries against a generated workspace and record
+/// their output sizes
+#[derive(Parser, Debug)]
+pub struct QueryBenchArgs {
+    /// Generated workspace to query
+    #[clap(long)]
+    workspace: PathBuf,
+
+    /// Bazel binary to run
+    #[clap(long, default_value = "bazel")]
+    bazel: String,
+
+    /// Number of timed runs of every query, after an untimed one warming up the server
+    #[clap(long, default_value = "3")]
+    runs: usize,
+
+    /// Also write the results as JSON to this file
+    #[clap(long)]
+    json: Option<PathBuf>,
+}
+
+fn read_labels(workspace: &Path, file: &str) -> anyhow::Result<Vec<String>> {
+    let labels: Vec<_> = std::fs::read_to_string(workspace.join(file))
+        .map_err(|e| format_err!("{}: {}, is this a generated workspace?", file, e))?
+        .lines()
+        .filter(|l| !l.is_empty())
+        .map(str::to_string)
+        .collect();
+    if labels.is_empty() {
+        bail!("{} lists no targets", file);
+    }
+    Ok(labels)
+}
+
+/// The queries of the suite by name, with the query command and its arguments. Paths run from the
+/// first library of the first level to the first leaf, which is one of its descendants. The
+/// configured queries output `jsonproto` like IDE tooling consumes, the action graph of the
+/// whole workspace is limited to the subgraph below the first library to keep its size sane.
+fn queries(workspace: &Path) -> anyhow::Result<Vec<(&'static str, Vec<String>)>> {
+    let all = read_labels(workspace, "all_targets.txt")?;
+    let leaves = read_labels(workspace, "leaf_targets.txt")?;
+    let (top, leaf) = (&all[0], &leaves[0]);
+    let package = top
+        .rsplit_once('/')
+        .map_or(top.as_str(), |(package, _)| package);
+    let query = |query: String| vec!["query".to_string(), "--output=label".to_string(), query];
+    let jsonproto = |command: &str, query: String| {
+        vec![command.to_string(), "--output=jsonproto".to_string(), query]
+    };
+    Ok(vec![
+        ("deps", query("deps(//:all)".to_string())),
+        ("rdeps", query(format!("rdeps(//..., {})", leaf))),
+        ("somepath", query(format!("somepath({}, {})", top, leaf))),
+        ("allpaths", query(format!("allpaths({}, {})", top, leaf))),
+        ("pattern", query(format!("{}/...", package))),
+        ("kind", query("kind(\".*_library\", //...)".to_string())),
+        (
+            "cquery deps",
+            jsonproto("cquery", "deps(//:all)".to_string()),
+        ),
+        ("aquery deps", jsonproto("aquery", format!("deps({})", top))),
+    ])
+}
+
+/// Runs `bazel_args` once, returning its wall time in milliseconds and the number of lines and
+/// bytes it output.
+fn run_query(args: &QueryBenchArgs, bazel_args: &[String]) -> anyhow::Result<(f64, usize, usize)> {
+    let start = Instant::now();
+    let output = Command::new(&args.bazel)
+        .args(bazel_args)
+        .current_dir(&args.workspace)
+        .output()?;
+    let elapsed = start.elapsed().as_secs_f64() * 1000.0;
+    if !output.status.success() {
+        bail!(
+            "{} {} failed with {}:\n{}",
+            args.bazel,
+            bazel_args.join(" "),
+            output.status,
+            String::from_utf8_lossy(&output.stderr)
+        );
+    }
+    let lines = output.stdout.iter().filter(|b| **b == b'\n').count();
+    Ok((elapsed, lines, output.stdout.len()))
+}
+
+pub fn run(args: &QueryBenchArgs) -> anyhow::Result<()> {
+    if args.runs == 0 {
+        bail!("--runs must be at least 1");
+    }
+    let mut rows = vec![];
+    let mut results = vec![];
+    for (name, query) in queries(&args.workspace)? {
+        println!("running {} {}", args.bazel, query.join(" "));
+        run_query(args, &query)?;
+        let mut times = vec![];
+        let (mut lines, mut bytes) = (0, 0);
+        for _ in 0..args.runs {
+            let (time, output_lines, output_bytes) = run_query(args, &query)?;
+            times.push(time);
+            (lines, bytes) = (output_lines, output_bytes);
+        }
+        times.sort_by(|a, b| a.partial_cmp(b).unwrap());
+        let (min, median) = (times[0], times[times.len() / 2]);
+        rows.push(vec![
+            name.to_string(),
+            format!("{:.0}", min),
+            format!("{:.0}", median),
+            lines.to_string(),
+            bytes.to_string(),
+        ]);
+        results.push(json!({
+            "name": name,
+            "command": query,
+            "times_ms": times,
+            "lines": lines,
+            "bytes": bytes,
+        }));
+    }
+
+    let header = ["query", "min (ms)", "median (ms)", "lines", "bytes"].map(str::to_string);
+    print_table(&header, &rows);
+    if let Some(path) = &args.json {
+        std::fs::write(path, serde_json::to_string_pretty(&results)?)?;
+    }
+    Ok(())
+}
@@ -0,0 +1,50 @@
+//! Platform definitions and `.bazelrc` config for building the generated workspace with remote
+//! execution, so a workspace is RBE-ready without copying boilerplate from another repository.
+
+use crate::starlark::quote;
+use crate::Args;
+
+/// Label of the platform remote actions run on.
+const PLATFORM: &str = "//platforms:rbe_platform";
+
+/// Writes `platforms/BUILD` with a Linux platform whose actions run in `image`.
+pub fn write_platforms(args: &Args, image: &str) -> anyhow::Result<()> {
+    let dir = args.output.join("platforms");
+    std::fs::create_dir_all(&dir)?;
+    std::fs::write(
+        dir.join("BUILD.bazel"),
+        format!(
+            r#"package(default_visibility = ["//visibility:public"])
+
+platform(
+    name = "rbe_platform",
+    constraint_values = [
+        "@platforms//os:linux",
+        "@platforms//cpu:x86_64",
+    ],
+    exec_properties = {{
+        "container-image": {},
+        "OSFamily": "Linux",
+    }},
+)
+"#,
+            quote(&format!("docker://{}", image))
+        ),
+    )?;
+    Ok(())
+}
+
+/// `--config=rbe` section of the `.bazelrc`. The remote executor itself is left to the command
+/// line (`--remote_executor=grpcs://...`) since it differs between users of the same workspace.
+pub fn bazelrc() -> String {
+    [
+        format!("build:rbe --host_platform={}", PLATFORM),
+        format!("build:rbe --platforms={}", PLATFORM),
+        format!("build:rbe --extra_execution_platforms={}", PLATFORM),
+        "build:rbe --spawn_strategy=remote".to_string(),
+        "build:rbe --jobs=200".to_string(),
+        "build:rbe --remote_timeout=3600".to_string(),
+        "build:rbe --incompatible_strict_action_env".to_string(),
+    ]
+    .join("\n")
+}
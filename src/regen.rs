@@ -0,0 +1,214 @@
+//! `regen` subcommand: regenerates the libraries of part of a generated workspace with some flags
+//! changed, leaving every other file as it is, so that Bazel keeps its caches for the rest. The
+//! workspace is rebuilt from the command line in its `BENCHMARK_METADATA.json`.
+
+use crate::graph::{num_nodes_in_ntree, ID};
+use crate::summary::{self, METADATA_FILE};
+use crate::{archive, emit_build_file, layers, prepare, starlark, Args, Cli, Command};
+use anyhow::{bail, format_err};
+use clap::{App, ArgSettings, IntoApp, Parser};
+use serde_json::json;
+use std::ffi::OsString;
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+use tracing::info;
+
+/// Regenerate part of a generated workspace with changed generate flags
+#[derive(Parser, Debug)]
+pub struct RegenArgs {
+    /// Generated workspace to regenerate part of, with its `BENCHMARK_METADATA.json`
+    #[clap(long)]
+    workspace: PathBuf,
+
+    /// Libraries to regenerate: `//<dir>/...` for every library in a directory, e.g.
+    /// `//pkg_1/pkg_2/...` for the levels from the second down, or the label of a library for it
+    /// and all libraries below it in the tree
+    #[clap(long)]
+    package: String,
+
+    /// Generate flags to change, after `--`, e.g. `-- --files-per-target 20`. Dependents outside
+    /// of the regenerated part keep what they were generated with, so flags they depend on, like
+    /// `--files-per-target` with `--import-style textual`, can leave them stale
+    #[clap(last = true)]
+    flags: Vec<OsString>,
+}
+
+/// Flags deciding the shape, names or places of all libraries, which a part can't change alone.
+const FIXED_FLAGS: [&str; 17] = [
+    "output",
+    "output-format",
+    "image-size",
+    "matrix",
+    "preset",
+    "height",
+    "targets-per-level",
+    "language",
+    "rules",
+    "naming-scheme",
+    "module-prefix",
+    "label-length",
+    "path-depth-padding",
+    "layout",
+    "external-repos",
+    "dep-keep-probability",
+    "shuffle-edges",
+];
+
+/// Long name of the flag `arg`, `None` for values.
+fn flag_name(arg: &OsString) -> Option<&str> {
+    let flag = arg.to_str()?.strip_prefix("--")?;
+    Some(flag.split_once('=').map_or(flag, |(name, _)| name))
+}
+
+/// `argv` without the flags named in `names`, with their values.
+pub fn without_flags(argv: &[OsString], names: &[&str]) -> Vec<OsString> {
+    without_flags_of(&Args::into_app(), argv, names)
+}
+
+/// `without_flags` for a command line of the subcommand `app`.
+pub fn without_flags_of(app: &App, argv: &[OsString], names: &[&str]) -> Vec<OsString> {
+    let takes_value = |name: &str| {
+        app.get_arguments()
+            .any(|arg| arg.get_long() == Some(name) && arg.is_set(ArgSettings::TakesValue))
+    };
+    let mut kept = vec![];
+    let mut args = argv.iter();
+    while let Some(arg) = args.next() {
+        match flag_name(arg) {
+            Some(name) if names.contains(&name) => {
+                if !arg.to_str().unwrap().contains('=') && takes_value(name) {
+                    args.next();
+                }
+            }
+            _ => kept.push(arg.clone()),
+        }
+    }
+    kept
+}
+
+/// The generate flags of the workspace at `workspace`, from the command line in its metadata,
+/// with the output pointing at it.
+pub fn workspace_args(workspace: &Path) -> anyhow::Result<Args> {
+    let (_, original) = summary::read_metadata(workspace)?;
+    let mut argv = without_flags(
+        &original,
+        &["output", "output-format", "image-size", "resume"],
+    );
+    argv.push("--output".into());
+    argv.push(workspace.into());
+    let mut args = match Cli::try_parse_from(crate::presets::expand(argv.clone())?)?.command {
+        Command::Generate(args) => args,
+        _ => bail!("{} wasn't written by generate", workspace.display()),
+    };
+    args.command_line = argv;
+    Ok(args)
+}
+
+/// Ids of the libraries `pattern` selects.
+fn select(pattern: &str, args: &Args) -> anyhow::Result<Vec<u64>> {
+    let path = pattern
+        .strip_prefix("//")
+        .ok_or_else(|| format_err!("--package {} has to start with //", pattern))?;
+    let node = |id| ID::new(id, args.targets_per_level, args.height as u64);
+    let libraries = 1..num_nodes_in_ntree(args.targets_per_level, args.height);
+    let selected: Vec<u64> = match path.strip_suffix("...") {
+        Some(dir) => {
+            let dir = Path::new(dir.trim_end_matches('/'));
+            libraries
+                .filter(|id| node(*id).lib_path().starts_with(dir))
+                .collect()
+        }
+        None => match libraries
+            .clone()
+            .find(|id| node(*id).lib_path() == Path::new(path))
+        {
+            Some(id) => {
+                let mut selected = vec![];
+                let mut stack = vec![node(id)];
+                while let Some(node) = stack.pop() {
+                    selected.push(node.id);
+                    stack.extend(node.children());
+                }
+                selected.sort_unstable();
+                selected
+            }
+            None => vec![],
+        },
+    };
+    if selected.is_empty() {
+        bail!("--package {} matches no library", pattern);
+    }
+    Ok(selected)
+}
+
+pub fn run(regen: &RegenArgs) -> anyhow::Result<()> {
+    let metadata_path = regen.workspace.join(METADATA_FILE);
+    let (mut metadata, original) = summary::read_metadata(&regen.workspace)?;
+
+    let changed: Vec<&str> = regen.flags.iter().filter_map(flag_name).collect();
+    if let Some(flag) = changed.iter().find(|flag| FIXED_FLAGS.contains(flag)) {
+        bail!(
+            "regen can't change --{}, it applies to the whole workspace",
+            flag
+        );
+    }
+    let mut argv = without_flags(&original, &changed);
+    argv = without_flags(&argv, &["output", "output-format", "image-size", "resume"]);
+    argv.extend(regen.flags.iter().cloned());
+    argv.push("--output".into());
+    argv.push(regen.workspace.clone().into());
+
+    let mut args = match Cli::try_parse_from(crate::presets::expand(argv.clone())?)?.command {
+        Command::Generate(args) => args,
+        _ => bail!("{} wasn't written by generate", metadata_path.display()),
+    };
+    args.command_line = argv;
+    if args.shuffle_edges > 0.0 && changed.contains(&"seed") {
+        bail!("regen can't change --seed, it picks the --shuffle-edges of the whole workspace");
+    }
+    prepare(&args)?;
+
+    let selected = select(&regen.package, &args)?;
+    info!(
+        "regenerating {} libraries of {}",
+        selected.len(),
+        regen.workspace.display()
+    );
+    let dirs: Vec<PathBuf> = selected
+        .iter()
+        .map(|id| {
+            emit_build_file(*id, &args, true)?;
+            Ok(layers::lib_dir(
+                &ID::new(*id, args.targets_per_level, args.height as u64),
+                &args,
+            ))
+        })
+        .collect::<anyhow::Result<_>>()?;
+    if args.buildifier {
+        starlark::run_buildifier(&dirs)?;
+    }
+    for dir in &dirs {
+        archive::normalize_metadata(dir, args.mtime)?;
+    }
+
+    let regenerated_at = SystemTime::now().duration_since(UNIX_EPOCH)?.as_secs();
+    let regenerations = metadata
+        .as_object_mut()
+        .ok_or_else(|| format_err!("{} isn't an object", metadata_path.display()))?
+        .entry("regenerations")
+        .or_insert_with(|| json!([]));
+    if let Some(regenerations) = regenerations.as_array_mut() {
+        regenerations.push(json!({
+            "package": regen.package,
+            "flags": regen
+                .flags
+                .iter()
+                .map(|flag| flag.to_string_lossy())
+                .collect::<Vec<_>>(),
+            "libraries": selected.len(),
+            "regenerated_at_unix": regenerated_at,
+        }));
+    }
+    std::fs::write(&metadata_path, serde_json::to_string_pretty(&metadata)?)?;
+    Ok(())
+}
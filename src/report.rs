@@ -0,0 +1,265 @@
+//! `report` subcommand: summarizes Bazel JSON trace profiles (`--profile`) of several runs side by
+//! side, e.g. the same generated workspace built with two versions of a rule set, and optionally
+//! the build event protocol streams (`--build_event_json_file`) of the same runs.
+
+use anyhow::{bail, format_err};
+use clap::Parser;
+use serde_json::Value;
+use std::collections::{BTreeMap, BTreeSet};
+use std::io::{BufRead, Read};
+use std::path::{Path, PathBuf};
+
+/// Compare Bazel JSON trace profiles: phase times, action counts, critical path and cache hits
+#[derive(Parser, Debug)]
+pub struct ReportArgs {
+    /// Profiles to compare, optionally gzipped
+    #[clap(required = true)]
+    profiles: Vec<PathBuf>,
+
+    /// Build event JSON file of the run of every profile, in the same order
+    #[clap(long)]
+    bep: Vec<PathBuf>,
+}
+
+/// Phases in the order Bazel marks them, with the column title used for them.
+const PHASES: [(&str, &str); 6] = [
+    ("Launch Blaze", "launch"),
+    ("Initialize command", "init"),
+    ("Load and analyze dependencies", "analysis"),
+    ("Prepare for build", "prepare"),
+    ("Build artifacts", "execution"),
+    ("Complete build", "finish"),
+];
+
+/// What gets extracted from a single profile. Times are in milliseconds.
+pub struct Summary {
+    pub phases: Vec<(&'static str, f64)>,
+    pub total: f64,
+    pub actions: u64,
+    pub executed_actions: u64,
+    pub critical_path: f64,
+}
+
+impl Summary {
+    /// Fraction of actions that didn't have to execute a spawn, i.e. were served from a (disk or
+    /// remote) cache. This is an approximation: actions without spawns count as cache hits.
+    pub fn cache_hit_rate(&self) -> f64 {
+        if self.actions == 0 {
+            return 0.0;
+        }
+        1.0 - self.executed_actions.min(self.actions) as f64 / self.actions as f64
+    }
+}
+
+fn read_profile(path: &Path) -> anyhow::Result<Value> {
+    let mut data = String::new();
+    let file = std::fs::File::open(path)?;
+    if path.extension().is_some_and(|e| e == "gz") {
+        flate2::read::GzDecoder::new(file).read_to_string(&mut data)?;
+    } else {
+        std::io::BufReader::new(file).read_to_string(&mut data)?;
+    }
+    Ok(serde_json::from_str(&data)?)
+}
+
+fn category(event: &Value) -> &str {
+    event.get("cat").and_then(Value::as_str).unwrap_or("")
+}
+
+pub fn summarize(path: &Path) -> anyhow::Result<Summary> {
+    let profile = read_profile(path)?;
+    let events = profile
+        .get("traceEvents")
+        .and_then(Value::as_array)
+        .ok_or_else(|| format_err!("{}: not a JSON trace profile", path.display()))?;
+
+    let micros = |e: &Value, field: &str| e.get(field).and_then(Value::as_f64).unwrap_or(0.0);
+
+    let end = events
+        .iter()
+        .map(|e| micros(e, "ts") + micros(e, "dur"))
+        .fold(0.0, f64::max);
+
+    let mut markers: Vec<(&str, f64)> = events
+        .iter()
+        .filter(|e| category(e) == "build phase marker")
+        .filter_map(|e| Some((e.get("name")?.as_str()?, micros(e, "ts"))))
+        .collect();
+    markers.sort_by(|a, b| a.1.partial_cmp(&b.1).unwrap());
+
+    let phases = PHASES
+        .iter()
+        .map(|(marker, title)| {
+            let duration = match markers.iter().position(|(name, _)| name == marker) {
+                Some(i) => markers.get(i + 1).map_or(end, |next| next.1) - markers[i].1,
+                None => 0.0,
+            };
+            (*title, duration / 1000.0)
+        })
+        .collect();
+
+    let count = |categories: &[&str]| {
+        events
+            .iter()
+            .filter(|e| categories.contains(&category(e)))
+            .count() as u64
+    };
+    let critical_path: f64 = events
+        .iter()
+        .filter(|e| category(e) == "critical path component")
+        .map(|e| micros(e, "dur"))
+        .sum();
+
+    Ok(Summary {
+        phases,
+        total: (end - markers.first().map_or(0.0, |m| m.1)) / 1000.0,
+        actions: count(&["action processing"]),
+        executed_actions: count(&["local action execution", "remote action execution"]),
+        critical_path: critical_path / 1000.0,
+    })
+}
+
+/// What gets extracted from a build event JSON file.
+pub struct BepSummary {
+    /// Number of events by the kind of their id, e.g. `targetCompleted`.
+    pub events: BTreeMap<String, u64>,
+    pub bytes: u64,
+    /// Milliseconds from the start of the build to the latest time an event reports, the finish
+    /// of the build. `None` if the stream has no times.
+    pub to_last_event: Option<f64>,
+}
+
+pub fn summarize_bep(path: &Path) -> anyhow::Result<BepSummary> {
+    let file = std::io::BufReader::new(std::fs::File::open(path)?);
+    let mut events = BTreeMap::new();
+    let mut bytes = 0;
+    let (mut start, mut last) = (None, None::<f64>);
+    for line in file.lines() {
+        let line = line?;
+        bytes += line.len() as u64 + 1;
+        if line.trim().is_empty() {
+            continue;
+        }
+        let event: Value = serde_json::from_str(&line)
+            .map_err(|e| format_err!("{}: not a build event JSON file: {}", path.display(), e))?;
+        let kind = event
+            .get("id")
+            .and_then(Value::as_object)
+            .and_then(|id| id.keys().next())
+            .ok_or_else(|| format_err!("{}: build event without id", path.display()))?;
+        *events.entry(kind.clone()).or_insert(0) += 1;
+
+        let millis = |payload: &str, field: &str| {
+            let value = event.get(payload)?.get(field)?;
+            // int64 fields are strings in the JSON mapping of protobuf.
+            value
+                .as_f64()
+                .or_else(|| value.as_str().and_then(|s| s.parse().ok()))
+        };
+        if let Some(time) = millis("started", "startTimeMillis") {
+            start = Some(time);
+        }
+        if let Some(time) = millis("finished", "finishTimeMillis") {
+            last = Some(last.map_or(time, |last| last.max(time)));
+        }
+    }
+    Ok(BepSummary {
+        events,
+        bytes,
+        to_last_event: start.zip(last).map(|(start, last)| last - start),
+    })
+}
+
+/// Prints `rows` as a table with one column per entry of `header`, the first column left aligned.
+pub fn print_table(header: &[String], rows: &[Vec<String>]) {
+    let widths: Vec<_> = (0..header.len())
+        .map(|c| {
+            rows.iter()
+                .map(|r| r[c].len())
+                .chain([header[c].len()])
+                .max()
+                .unwrap()
+        })
+        .collect();
+    for row in std::iter::once(header).chain(rows.iter().map(Vec::as_slice)) {
+        let line: Vec<_> = row
+            .iter()
+            .enumerate()
+            .map(|(c, cell)| {
+                if c == 0 {
+                    format!("{:<width$}", cell, width = widths[c])
+                } else {
+                    format!("{:>width$}", cell, width = widths[c])
+                }
+            })
+            .collect();
+        println!("{}", line.join("  "));
+    }
+}
+
+pub fn run(args: &ReportArgs) -> anyhow::Result<()> {
+    if !args.bep.is_empty() && args.bep.len() != args.profiles.len() {
+        bail!(
+            "got {} build event files for {} profiles, --bep has to be given once per profile",
+            args.bep.len(),
+            args.profiles.len()
+        );
+    }
+    let summaries = args
+        .profiles
+        .iter()
+        .map(|p| summarize(p))
+        .collect::<anyhow::Result<Vec<_>>>()?;
+
+    let mut header = vec!["".to_string()];
+    header.extend(args.profiles.iter().map(|p| p.display().to_string()));
+
+    let mut rows = vec![];
+    let mut row = |title: &str, value: &dyn Fn(&Summary) -> String| {
+        let mut row = vec![title.to_string()];
+        row.extend(summaries.iter().map(value));
+        rows.push(row);
+    };
+    for (i, (_, title)) in PHASES.iter().enumerate() {
+        row(&format!("{} (ms)", title), &|s| {
+            format!("{:.0}", s.phases[i].1)
+        });
+    }
+    row("total (ms)", &|s| format!("{:.0}", s.total));
+    row("critical path (ms)", &|s| format!("{:.0}", s.critical_path));
+    row("actions", &|s| s.actions.to_string());
+    row("executed actions", &|s| s.executed_actions.to_string());
+    row("cache hit rate", &|s| {
+        format!("{:.1}%", s.cache_hit_rate() * 100.0)
+    });
+
+    let beps = args
+        .bep
+        .iter()
+        .map(|p| summarize_bep(p))
+        .collect::<anyhow::Result<Vec<_>>>()?;
+    let mut row = |title: &str, value: &dyn Fn(&BepSummary) -> String| {
+        let mut row = vec![title.to_string()];
+        row.extend(beps.iter().map(value));
+        rows.push(row);
+    };
+    if !beps.is_empty() {
+        row("BEP events", &|b| {
+            b.events.values().sum::<u64>().to_string()
+        });
+        row("BEP bytes", &|b| b.bytes.to_string());
+        row("BEP to last event (ms)", &|b| {
+            b.to_last_event
+                .map_or(String::new(), |t| format!("{:.0}", t))
+        });
+        let kinds: BTreeSet<_> = beps.iter().flat_map(|b| b.events.keys()).collect();
+        for kind in kinds {
+            row(&format!("  {}", kind), &|b| {
+                b.events.get(kind).copied().unwrap_or(0).to_string()
+            });
+        }
+    }
+
+    print_table(&header, &rows);
+    Ok(())
+}
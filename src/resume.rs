@@ -0,0 +1,150 @@
+//! Checkpointing of generation progress, so that `--resume` can continue an interrupted run
+//! instead of wiping the output and starting over.
+
+use crate::Args;
+use anyhow::{bail, Context};
+use std::io::{BufRead, LineWriter, Write};
+use std::path::PathBuf;
+use std::sync::Mutex;
+use tracing::info;
+
+/// Written to the output while generating and removed once generation completes. The first line
+/// identifies the flags, every following line is the id of a node whose files are all written.
+const CHECKPOINT: &str = ".gen_bazel_benchmark_checkpoint";
+
+pub struct Checkpoint {
+    path: PathBuf,
+    file: Mutex<LineWriter<std::fs::File>>,
+    completed: Vec<u64>,
+    resumed: bool,
+}
+
+/// Identifies the flags generation ran with, ignoring the ones that don't change the output and
+/// the command line they were given on, which `--resume` itself changes.
+fn fingerprint(args: &Args) -> String {
+    format!("{:?}", args)
+        .replace(&format!("command_line: {:?}, ", args.command_line), "")
+        .replace("resume: true", "resume: false")
+        .replace("keep_partial: true", "keep_partial: false")
+        .replace("quiet: true", "quiet: false")
+        .replace("verbose: true", "verbose: false")
+}
+
+impl Checkpoint {
+    /// Starts generating into `args.output`. With `--resume` an existing checkpoint for the same
+    /// flags is continued, otherwise the output is wiped.
+    pub fn open(args: &Args, num_nodes: u64) -> anyhow::Result<Self> {
+        let path = args.output.join(CHECKPOINT);
+        let mut completed = vec![0; (num_nodes as usize).div_ceil(64)];
+
+        let resumed = args.resume && path.exists();
+        if resumed {
+            let file = std::io::BufReader::new(std::fs::File::open(&path)?);
+            let mut lines = file.lines();
+            if lines.next().transpose()?.as_deref() != Some(&fingerprint(args)) {
+                bail!(
+                    "{} was generated with different flags, can't resume",
+                    args.output.display()
+                );
+            }
+            // An interrupted write can leave a truncated last line, that node just gets redone
+            for id in lines
+                .map_while(Result::ok)
+                .filter_map(|l| l.parse::<u64>().ok())
+            {
+                if id < num_nodes {
+                    completed[id as usize / 64] |= 1 << (id % 64);
+                }
+            }
+        } else {
+            if args.resume {
+                info!("no checkpoint in {}, starting over", args.output.display());
+            }
+            std::fs::remove_dir_all(&args.output).unwrap_or(());
+            std::fs::create_dir_all(&args.output)?;
+        }
+
+        let mut file = LineWriter::new(
+            std::fs::OpenOptions::new()
+                .create(true)
+                .append(true)
+                .open(&path)?,
+        );
+        if !resumed {
+            writeln!(file, "{}", fingerprint(args))?;
+        }
+        Ok(Checkpoint {
+            path,
+            file: Mutex::new(file),
+            completed,
+            resumed,
+        })
+    }
+
+    /// Whether an earlier run fully wrote node `id`.
+    pub fn is_completed(&self, id: u64) -> bool {
+        self.completed[id as usize / 64] & (1 << (id % 64)) != 0
+    }
+
+    /// Number of nodes an earlier run fully wrote.
+    pub fn num_completed(&self) -> u64 {
+        self.completed.iter().map(|w| w.count_ones() as u64).sum()
+    }
+
+    /// Whether this run continues an earlier one, whose partially written nodes have to be
+    /// cleaned up before they are written again.
+    pub fn resumed(&self) -> bool {
+        self.resumed
+    }
+
+    pub fn complete(&self, id: u64) -> anyhow::Result<()> {
+        writeln!(self.file.lock().unwrap(), "{}", id)
+            .with_context(|| format!("writing the checkpoint {}", self.path.display()))
+    }
+
+    /// Removes the checkpoint once all nodes are written.
+    pub fn finish(self) -> anyhow::Result<()> {
+        drop(self.file);
+        std::fs::remove_file(&self.path)?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use clap::Parser;
+
+    fn args(output: &std::path::Path, height: &str, extra: &[&str]) -> Args {
+        let mut argv = vec!["generate", "--output", output.to_str().unwrap()];
+        argv.extend(["--height", height, "--targets-per-level", "2"]);
+        argv.extend(["--files-per-target", "1"]);
+        argv.extend(extra);
+        let mut args = Args::parse_from(&argv);
+        args.command_line = argv.into_iter().map(Into::into).collect();
+        args
+    }
+
+    #[test]
+    fn resumes_an_interrupted_run() {
+        let output = std::env::temp_dir().join(format!("resume_test_{}", std::process::id()));
+        let checkpoint = Checkpoint::open(&args(&output, "2", &[]), 7).unwrap();
+        checkpoint.complete(0).unwrap();
+        checkpoint.complete(3).unwrap();
+        // Interrupted: the checkpoint is left behind without being finished.
+        drop(checkpoint);
+
+        let checkpoint = Checkpoint::open(&args(&output, "2", &["--resume"]), 7).unwrap();
+        assert!(checkpoint.resumed());
+        assert!(checkpoint.is_completed(0) && checkpoint.is_completed(3));
+        assert_eq!(checkpoint.num_completed(), 2);
+        checkpoint.finish().unwrap();
+
+        let checkpoint = Checkpoint::open(&args(&output, "2", &[]), 7).unwrap();
+        checkpoint.complete(1).unwrap();
+        drop(checkpoint);
+        let changed = Checkpoint::open(&args(&output, "3", &["--resume"]), 15);
+        assert!(changed.is_err());
+        std::fs::remove_dir_all(&output).unwrap();
+    }
+}
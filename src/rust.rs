@@ -0,0 +1,352 @@
+//! Rust backend: `rust_library` crates built with rules_rust. A matching cargo workspace is
+//! written alongside so the same sources can be built with cargo for comparison.
+
+use crate::backend::EmitBackend;
+use crate::graph::{level_path, ID};
+use crate::layers;
+use crate::starlark::{quote, string_list, BuildFile, Rule, RuleRef};
+use crate::{bzlmod, third_party, Args};
+use anyhow::bail;
+use std::io::{BufWriter, Write};
+use std::path::Path;
+use std::str::FromStr;
+
+pub const LIBRARY_LOAD: &str = "@rules_rust//rust:defs.bzl";
+pub const LIBRARY_RULE: &str = "rust_library";
+
+const WORKSPACE: &str = r#"load("@bazel_tools//tools/build_defs/repo:http.bzl", "http_archive")
+
+http_archive(
+    name = "rules_rust",
+    urls = ["https://github.com/bazelbuild/rules_rust/releases/download/0.13.0/rules_rust-v0.13.0.tar.gz"],
+)
+
+load("@rules_rust//rust:repositories.bzl", "rules_rust_dependencies", "rust_register_toolchains")
+
+rules_rust_dependencies()
+
+rust_register_toolchains()"#;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum CompileCost {
+    Low,
+    Medium,
+    High,
+}
+
+impl CompileCost {
+    /// Nesting depth of the generic types every module instantiates, and how many distinct
+    /// chains of them it instantiates.
+    fn instantiations(self) -> (u64, u64) {
+        match self {
+            CompileCost::Low => (0, 0),
+            CompileCost::Medium => (32, 4),
+            CompileCost::High => (128, 16),
+        }
+    }
+}
+
+impl FromStr for CompileCost {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "low" => Ok(CompileCost::Low),
+            "medium" => Ok(CompileCost::Medium),
+            "high" => Ok(CompileCost::High),
+            _ => bail!("unknown compile cost {}, expected low, medium or high", s),
+        }
+    }
+}
+
+fn crate_name(node: &ID) -> String {
+    node.lib_name().to_lowercase()
+}
+
+pub fn handle_root(args: &Args) -> anyhow::Result<()> {
+    let first_level: Vec<_> = (1..=args.targets_per_level)
+        .map(|i| ID::new(i, args.targets_per_level, args.height as u64))
+        .collect();
+
+    let mut build = BuildFile::new();
+    build.load(LIBRARY_LOAD, "rust_binary");
+    let mut binary = Rule::new("rust_binary", "root")
+        .attr("srcs", string_list(["main.rs"]))
+        .attr("deps", string_list(first_level.iter().map(ID::label)));
+    if let Some(data) = layers::runfiles(&args.output, args)? {
+        binary.set("data", data);
+    }
+    build.add(binary);
+    build.write(&args.output.join("BUILD.bazel"))?;
+
+    let mut f = BufWriter::new(std::fs::File::create(args.output.join("main.rs"))?);
+    for dep in &first_level {
+        writeln!(
+            f,
+            "use {}::src1::value1 as {}_value1;",
+            crate_name(dep),
+            crate_name(dep)
+        )?;
+    }
+    write!(f, "\nfn main() {{\n    println!(\"{{}}\", 0")?;
+    for dep in &first_level {
+        write!(f, " + {}_value1()", crate_name(dep))?;
+    }
+    writeln!(f, ");\n}}")?;
+
+    let mut f = BufWriter::new(std::fs::File::create(args.output.join("Cargo.toml"))?);
+    writeln!(
+        f,
+        r#"[package]
+name = "root"
+version = "0.1.0"
+edition = "2021"
+
+[[bin]]
+name = "root"
+path = "main.rs"
+
+[dependencies]"#
+    )?;
+    for dep in &first_level {
+        writeln!(
+            f,
+            r#"{} = {{ path = "{}" }}"#,
+            crate_name(dep),
+            dep.lib_path().to_str().unwrap()
+        )?;
+    }
+
+    // One glob per level keeps the member list small no matter how large the graph is.
+    writeln!(f, "\n[workspace]\nmembers = [")?;
+    for level in 1..=args.height {
+        writeln!(
+            f,
+            r#"    "{}/lib_*","#,
+            level_path(level as usize).display()
+        )?;
+    }
+    writeln!(f, "]")?;
+    Ok(())
+}
+
+pub fn handle_node(node: &ID, args: &Args) -> anyhow::Result<()> {
+    let lib_dir = args.output.join(node.lib_path());
+    std::fs::create_dir_all(&lib_dir)?;
+
+    let deps = layers::direct_deps(node, args);
+
+    let mut srcs = vec!["lib.rs".to_string()];
+    srcs.extend((1..=args.files_per_target.for_node(node)).map(|i| format!("src{}.rs", i)));
+    let mut rule = Rule::new(LIBRARY_RULE, node.target_name())
+        .attr("crate_name", quote(&crate_name(node)))
+        .attr("srcs", layers::srcs(args, srcs, &["*.rs"], &[]))
+        .attr("edition", quote("2021"));
+    let mut rustc_flags = layers::generated_flags(args, args.copts_per_target, |i, value| {
+        format!("--cfg=bench_copt_{}_{}", i, value)
+    });
+    rustc_flags.extend(layers::generated_flags(
+        args,
+        args.defines_per_target,
+        |i, value| format!("--cfg=bench_define_{}=\"{}\"", i, value),
+    ));
+    if !rustc_flags.is_empty() {
+        rule.set("rustc_flags", string_list(rustc_flags));
+    }
+
+    let labels = deps.iter().map(ID::label).collect();
+
+    let mut build = BuildFile::new();
+    layers::add_library(&mut build, node, args, LIBRARY_LOAD, rule, labels)?;
+    build.write(&lib_dir.join("BUILD.bazel"))?;
+
+    let mut f = BufWriter::new(std::fs::File::create(lib_dir.join("lib.rs"))?);
+    if args.compile_cost == CompileCost::High {
+        // Trait resolution recurses once per level of nesting
+        writeln!(f, "#![recursion_limit = \"512\"]\n")?;
+    }
+    for i in 1..=args.files_per_target.for_node(node) {
+        writeln!(f, "pub mod src{};", i)?;
+    }
+    let mut imported = layers::imported_deps(node, deps.clone(), args);
+    imported.extend(layers::undeclared_import(node, args));
+    if args.reexport_deps {
+        for dep in &imported {
+            writeln!(f, "pub use {};", crate_name(dep))?;
+        }
+    }
+
+    for i in 1..=args.files_per_target.for_node(node) {
+        write_module(&lib_dir, &imported, i, args.compile_cost)?;
+    }
+
+    write_cargo_manifest(&lib_dir, node, &deps)?;
+    Ok(())
+}
+
+/// Every module uses the first function of each dependency crate, so the crate graph is real and
+/// not just declared. Above `--compile-cost low` it also monomorphizes chains of nested generic
+/// types, each level a separate non-inlined function.
+fn write_module(
+    lib_dir: &Path,
+    deps: &[ID],
+    i: u64,
+    compile_cost: CompileCost,
+) -> anyhow::Result<()> {
+    let mut f = BufWriter::new(std::fs::File::create(lib_dir.join(format!("src{}.rs", i)))?);
+    for dep in deps {
+        writeln!(
+            f,
+            "use {}::src1::value1 as {}_value1;",
+            crate_name(dep),
+            crate_name(dep)
+        )?;
+    }
+    if !deps.is_empty() {
+        writeln!(f)?;
+    }
+
+    let (depth, breadth) = compile_cost.instantiations();
+    if depth > 0 {
+        writeln!(
+            f,
+            r#"pub trait Cost {{
+    fn cost() -> u64;
+}}
+
+impl<const K: usize> Cost for [u8; K] {{
+    fn cost() -> u64 {{
+        K as u64
+    }}
+}}
+
+pub struct Layer<T>(std::marker::PhantomData<T>);
+
+impl<T: Cost> Cost for Layer<T> {{
+    #[inline(never)]
+    fn cost() -> u64 {{
+        (T::cost() * 31 + {}) % 1_000_003
+    }}
+}}
+"#,
+            i
+        )?;
+    }
+
+    write!(f, "pub fn value{}() -> u64 {{\n    {}", i, i)?;
+    for dep in deps {
+        write!(f, " + {}_value1()", crate_name(dep))?;
+    }
+    for k in 0..breadth {
+        let ty = format!("[u8; {}]", k);
+        let ty = (0..depth).fold(ty, |ty, _| format!("Layer<{}>", ty));
+        write!(f, "\n        + <{} as Cost>::cost()", ty)?;
+    }
+    writeln!(f, "\n}}")?;
+    Ok(())
+}
+
+fn write_cargo_manifest(lib_dir: &Path, node: &ID, deps: &[ID]) -> anyhow::Result<()> {
+    let mut f = BufWriter::new(std::fs::File::create(lib_dir.join("Cargo.toml"))?);
+    writeln!(
+        f,
+        r#"[package]
+name = "{}"
+version = "0.1.0"
+edition = "2021"
+
+[lib]
+path = "lib.rs"
+
+[dependencies]"#,
+        crate_name(node)
+    )?;
+
+    let to_root = "../".repeat(node.lib_path().components().count());
+    for dep in deps {
+        writeln!(
+            f,
+            r#"{} = {{ path = "{}{}" }}"#,
+            crate_name(dep),
+            to_root,
+            dep.lib_path().to_str().unwrap()
+        )?;
+    }
+    Ok(())
+}
+
+pub fn write_workspace(args: &Args) -> anyhow::Result<()> {
+    let mut f = std::fs::File::create(args.output.join("WORKSPACE"))?;
+    writeln!(f, "{}", WORKSPACE)?;
+    Ok(())
+}
+
+/// The language dependent flags the rust backend implements.
+const SUPPORTED_FLAGS: &[&str] = &[
+    "--emit-env",
+    "--runfiles-per-test",
+    "--compile-cost",
+    "--third-party-packages",
+    "--cc-toolchain-config",
+    "--stamped-targets",
+    "--filegroups-per-package",
+    "--emit-both-dep-systems",
+    "--inject-errors",
+    "--naming-scheme mixed",
+];
+
+pub struct Backend;
+
+impl EmitBackend for Backend {
+    fn name(&self) -> &'static str {
+        "rust"
+    }
+
+    fn emit_workspace(&self, args: &Args) -> anyhow::Result<()> {
+        write_workspace(args)
+    }
+
+    fn emit_package(&self, node: &ID, args: &Args) -> anyhow::Result<()> {
+        match node.id {
+            0 => handle_root(args),
+            _ => handle_node(node, args),
+        }
+    }
+
+    fn library_rule(&self, _args: &Args) -> RuleRef {
+        RuleRef {
+            load: LIBRARY_LOAD.to_string(),
+            name: LIBRARY_RULE.to_string(),
+        }
+    }
+
+    fn supports(&self, flag: &str) -> bool {
+        SUPPORTED_FLAGS.contains(&flag)
+    }
+
+    fn third_party_library(&self, index: u64, _args: &Args) -> Option<third_party::Library> {
+        Some(third_party::Library {
+            src: "lib.rs".to_string(),
+            content: "pub const VERSION: u32 = 1;\n".to_string(),
+            load: LIBRARY_LOAD.to_string(),
+            rule: Rule::new(LIBRARY_RULE, third_party::name(index))
+                .attr("crate_name", quote(&format!("third_party_{}", index)))
+                .attr("srcs", string_list(["lib.rs"]))
+                .attr("edition", quote("2021")),
+        })
+    }
+
+    fn modules(&self, _args: &Args) -> Vec<bzlmod::Module> {
+        vec![("rules_rust", "0.40.0", "rules_rust")]
+    }
+
+    fn module_extensions(&self, _args: &Args) -> &'static str {
+        r#"
+rust = use_extension("@rules_rust//rust:extensions.bzl", "rust")
+rust.toolchain()
+use_repo(rust, "rust_toolchains")
+
+register_toolchains("@rust_toolchains//:all")
+"#
+    }
+}
@@ -0,0 +1,51 @@
+//! `--self-bench`: how long the generator itself takes, phase by phase, and how fast it writes
+//! files, to tell a slow generator or disk apart from a slow build before blaming Bazel.
+
+use serde_json::{json, Map, Value};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+use tracing::info;
+
+/// Phases of the current generation in the order they ran, with their wall time. One process
+/// generates several workspaces with `--matrix`, so `reset` starts every generation over.
+static PHASES: Mutex<Vec<(&'static str, Duration)>> = Mutex::new(vec![]);
+
+pub fn reset() {
+    PHASES.lock().unwrap().clear();
+}
+
+/// Records phase `name` as running from `start` until now and returns now as the start of the
+/// next one.
+pub fn phase(name: &'static str, start: Instant) -> Instant {
+    let now = Instant::now();
+    PHASES.lock().unwrap().push((name, now - start));
+    now
+}
+
+/// Logs the phases and the write throughput of a generation of `files` files of `bytes` bytes
+/// in `elapsed`, and returns them for the summary.
+pub fn report(files: u64, bytes: u64, elapsed: Duration) -> Value {
+    let phases = PHASES.lock().unwrap();
+    let seconds = elapsed.as_secs_f64().max(f64::EPSILON);
+    let files_per_second = files as f64 / seconds;
+    let bytes_per_second = bytes as f64 / seconds;
+    info!(
+        "self-bench: {:.0} files/s, {:.1} MB/s, {}",
+        files_per_second,
+        bytes_per_second / 1e6,
+        phases
+            .iter()
+            .map(|(name, time)| format!("{} {:.2}s", name, time.as_secs_f64()))
+            .collect::<Vec<_>>()
+            .join(", ")
+    );
+    let mut phases_seconds = Map::new();
+    for (name, time) in phases.iter() {
+        phases_seconds.insert(name.to_string(), json!(time.as_secs_f64()));
+    }
+    json!({
+        "files_per_second": files_per_second,
+        "bytes_per_second": bytes_per_second,
+        "phases_seconds": phases_seconds,
+    })
+}
@@ -0,0 +1,118 @@
+//! Swift Package Manager manifest for the objc library graph, so the same sources can be built
+//! with `swift build` for comparison. There is no Swift backend, SwiftPM builds the Objective-C
+//! libraries as C language targets.
+
+use crate::graph::{num_nodes_in_ntree, ID};
+use crate::starlark::quote;
+use crate::{apple, layers, Args};
+use itertools::Itertools;
+use std::io::{BufWriter, Write};
+use std::path::Path;
+
+/// Public headers directory of every target, relative to its library directory. Holds a
+/// `<Module>/` directory linking the headers, matching how the sources include them.
+const INCLUDE_DIR: &str = "include";
+
+fn write_includes(node: &ID, lib_dir: &Path) -> anyhow::Result<()> {
+    let module_dir = lib_dir.join(INCLUDE_DIR).join(node.lib_name());
+    // Left over by an interrupted run when resuming
+    std::fs::remove_dir_all(&module_dir).unwrap_or(());
+    std::fs::create_dir_all(&module_dir)?;
+    for header in layers::files_with_extension(lib_dir, "h")? {
+        let file_name = header.file_name().unwrap();
+        std::os::unix::fs::symlink(
+            Path::new("../..").join(file_name),
+            module_dir.join(file_name),
+        )?;
+    }
+    std::fs::write(
+        lib_dir.join(INCLUDE_DIR).join("module.modulemap"),
+        format!(
+            "module {0} {{\n    umbrella \"{0}\"\n    export *\n    module * {{ export * }}\n}}\n",
+            node.lib_name()
+        ),
+    )?;
+    Ok(())
+}
+
+fn swift_list<I, S>(items: I) -> String
+where
+    I: IntoIterator<Item = S>,
+    S: AsRef<str>,
+{
+    format!(
+        "[{}]",
+        items.into_iter().map(|s| quote(s.as_ref())).join(", ")
+    )
+}
+
+fn write_target(f: &mut impl Write, id: u64, args: &Args) -> anyhow::Result<()> {
+    let node = ID::new(id, args.targets_per_level, args.height as u64);
+    let lib_dir = layers::lib_dir(&node, args);
+    write_includes(&node, &lib_dir)?;
+
+    let mut exclude: Vec<_> = std::fs::read_dir(&lib_dir)
+        .unwrap()
+        .map(|entry| entry.unwrap().path())
+        .filter(|path| path.is_file() && !path.extension().is_some_and(|e| e == "h" || e == "m"))
+        .map(|path| path.file_name().unwrap().to_str().unwrap().to_string())
+        .collect();
+    exclude.sort();
+
+    let deps = apple::dependency_ids(id, args)
+        .into_iter()
+        .map(|dep| ID::new(dep, args.targets_per_level, args.height as u64).lib_name());
+
+    let mut settings: Vec<_> =
+        layers::generated_flags(args, args.defines_per_target, |i, value| {
+            format!(".define(\"BENCH_DEFINE_{}\", to: \"{}\")", i, value)
+        });
+    let copts = layers::generated_flags(args, args.copts_per_target, |i, value| {
+        format!("-DBENCH_COPT_{}={}", i, value)
+    });
+    if !copts.is_empty() {
+        settings.push(format!(".unsafeFlags({})", swift_list(copts)));
+    }
+
+    writeln!(
+        f,
+        "        .target(\n            name: {},\n            dependencies: {},\n            path: {},\n            exclude: {},\n            publicHeadersPath: {},\n            cSettings: [{}]\n        ),",
+        quote(&node.lib_name()),
+        swift_list(deps),
+        quote(lib_dir.strip_prefix(&args.output).unwrap().to_str().unwrap()),
+        swift_list(exclude),
+        quote(INCLUDE_DIR),
+        settings.join(", ")
+    )
+    ?;
+    Ok(())
+}
+
+/// Writes `Package.swift` with a target for every library and a library product for every
+/// application, has to run after all libraries are written. Prefix headers are not supported by
+/// SwiftPM and get left out.
+pub fn write_package(args: &Args) -> anyhow::Result<()> {
+    let mut f = BufWriter::new(std::fs::File::create(args.output.join("Package.swift"))?);
+    writeln!(
+        f,
+        "// swift-tools-version:5.5\nimport PackageDescription\n\nlet package = Package(\n    name: \"Benchmark\",\n    platforms: [.iOS(.v15), .macOS(.v12)],\n    products: ["
+    )
+    ?;
+    for app in 1..=apple::num_apps(args) {
+        let (name, _) = apple::app_identity(app, args);
+        let targets = apple::app_deps(app, args)
+            .map(|i| ID::new(i, args.targets_per_level, args.height as u64).lib_name());
+        writeln!(
+            f,
+            "        .library(name: {}, targets: {}),",
+            quote(&name),
+            swift_list(targets)
+        )?;
+    }
+    writeln!(f, "    ],\n    targets: [")?;
+    for id in 1..num_nodes_in_ntree(args.targets_per_level, args.height) {
+        write_target(&mut f, id, args)?;
+    }
+    writeln!(f, "    ]\n)")?;
+    Ok(())
+}
@@ -0,0 +1,446 @@
+//! Small helpers for assembling generated BUILD files out of rule instantiations.
+
+use anyhow::bail;
+use itertools::Itertools;
+use std::cmp::Ordering;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use std::str::FromStr;
+use std::sync::atomic::{AtomicBool, Ordering as AtomicOrdering};
+use tracing::warn;
+
+/// Quotes `s` as a Starlark string literal.
+pub fn quote(s: &str) -> String {
+    format!("\"{}\"", s.replace('\\', "\\\\").replace('"', "\\\""))
+}
+
+/// Renders `items` as a Starlark list of strings.
+pub fn string_list<I, S>(items: I) -> String
+where
+    I: IntoIterator<Item = S>,
+    S: AsRef<str>,
+{
+    format!(
+        "[{}]",
+        items.into_iter().map(|s| quote(s.as_ref())).join(", ")
+    )
+}
+
+/// Whether rules and loads are laid out the way buildifier formats BUILD files. Like the topology
+/// it can change between generations in one process.
+static FORMAT: AtomicBool = AtomicBool::new(false);
+
+/// Makes `BuildFile` and `Rule` render buildifier-clean with `--format`.
+pub fn set_format(format: bool) {
+    FORMAT.store(format, AtomicOrdering::Relaxed);
+}
+
+fn format() -> bool {
+    FORMAT.load(AtomicOrdering::Relaxed)
+}
+
+/// Attributes buildifier moves ahead of (negative) or behind (positive) all others, keeping the
+/// order of the ones of the same priority.
+fn attr_priority(name: &str) -> i32 {
+    match name {
+        "size" => -95,
+        "timeout" => -94,
+        "testonly" => -93,
+        "src" => -92,
+        "srcs" => -90,
+        "out" => -89,
+        "outs" => -88,
+        "hdrs" => -87,
+        "exports" => 2,
+        "runtime_deps" => 3,
+        "deps" => 4,
+        _ => 0,
+    }
+}
+
+/// Attributes whose lists buildifier sorts.
+const SORTED_ATTRS: [&str; 12] = [
+    "deps",
+    "srcs",
+    "hdrs",
+    "textual_hdrs",
+    "data",
+    "resources",
+    "resource_files",
+    "exports",
+    "runtime_deps",
+    "visibility",
+    "sdk_frameworks",
+    "exported_headers",
+];
+
+/// The strings of `value` if it's a plain list of string literals, unquoted.
+fn parse_string_list(value: &str) -> Option<Vec<String>> {
+    let inner = value.strip_prefix('[')?.strip_suffix(']')?.trim();
+    let mut items = vec![];
+    let mut chars = inner.chars().peekable();
+    while chars.peek().is_some() {
+        if chars.next()? != '"' {
+            return None;
+        }
+        let mut item = String::new();
+        loop {
+            match chars.next()? {
+                '"' => break,
+                '\\' => item.push(chars.next()?),
+                c => item.push(c),
+            }
+        }
+        items.push(item);
+        while chars.peek() == Some(&' ') {
+            chars.next();
+        }
+        match chars.next() {
+            None => break,
+            Some(',') => {
+                while chars.peek() == Some(&' ') {
+                    chars.next();
+                }
+            }
+            Some(_) => return None,
+        }
+    }
+    Some(items)
+}
+
+/// Order buildifier sorts labels in: ones of the package first, then the ones of the main
+/// repository, then the external ones, and plain strings last.
+fn compare_labels(a: &str, b: &str) -> Ordering {
+    let phase = |label: &str| match label {
+        _ if label.starts_with(':') => 0,
+        _ if label.starts_with("//") => 1,
+        _ if label.starts_with('@') => 2,
+        _ => 3,
+    };
+    let parts = |label: &str| {
+        label
+            .split([':', '.'])
+            .map(str::to_string)
+            .collect::<Vec<_>>()
+    };
+    phase(a)
+        .cmp(&phase(b))
+        .then_with(|| parts(a).cmp(&parts(b)))
+}
+
+/// Order buildifier sorts loads in: ones with an explicit repository first, then by package and
+/// file.
+fn compare_loads(a: &str, b: &str) -> Ordering {
+    let module = |label: &str| -> (bool, String, String) {
+        let (package, file) = label.split_once(':').unwrap_or((label, ""));
+        (
+            !label.starts_with('@'),
+            package.to_string(),
+            file.to_string(),
+        )
+    };
+    module(a).cmp(&module(b))
+}
+
+/// Value `value` of attribute `name` as buildifier formats it with `--format`: string lists of
+/// more than one element one per line with trailing commas, sorted for the attributes that get
+/// sorted. Other expressions stay as they are.
+pub fn format_value(name: &str, value: &str) -> String {
+    if !format() {
+        return value.to_string();
+    }
+    let mut items = match parse_string_list(value) {
+        Some(items) if items.len() > 1 => items,
+        Some(items) => return string_list(items),
+        None => return value.to_string(),
+    };
+    if SORTED_ATTRS.contains(&name) {
+        items.sort_by(|a, b| compare_labels(a, b));
+        items.dedup();
+    }
+    format!(
+        "[\n{}    ]",
+        items
+            .iter()
+            .map(|item| format!("        {},\n", quote(item)))
+            .join("")
+    )
+}
+
+/// `value` as `format_value` formats it for a top level assignment, one indentation level less.
+pub fn format_assigned_value(name: &str, value: &str) -> String {
+    format_value(name, value).replace("\n    ", "\n")
+}
+
+/// Index of the last line of the attribute on line `start` of a rendered rule, `start` itself
+/// unless `--format` split its list over several lines or it is a `select()` over several.
+fn attr_end(lines: &[String], start: usize) -> usize {
+    let close = if lines[start].ends_with('[') {
+        "    ]"
+    } else if lines[start].ends_with("select({") {
+        "    })"
+    } else {
+        return start;
+    };
+    start
+        + lines[start..]
+            .iter()
+            .position(|l| l.starts_with(close))
+            .unwrap()
+}
+
+/// Lines of a BUILD file `file` with the line range of the rule named `rule`, from its `name` to
+/// its closing parenthesis, and the last line of its `deps` if it has any.
+fn deps_of(file: &Path, rule: &str) -> anyhow::Result<(Vec<String>, usize, Option<usize>)> {
+    let content = std::fs::read_to_string(file)?;
+    let lines: Vec<_> = content.lines().map(str::to_string).collect();
+    let name = format!("    name = {},", quote(rule));
+    let start = match lines.iter().position(|l| *l == name) {
+        Some(start) => start,
+        None => bail!("no rule {} in {}", rule, file.display()),
+    };
+    let end = start + lines[start..].iter().position(|l| l == ")").unwrap();
+    let deps = (start..end)
+        .find(|i| lines[*i].starts_with("    deps = "))
+        .map(|i| attr_end(&lines, i));
+    Ok((lines, end, deps))
+}
+
+/// The value of the last line of an attribute, without its trailing comma, and the comment
+/// following it.
+fn split_comment(line: &str) -> (&str, &str) {
+    let (value, comment) = line.split_at(line.find("  #").unwrap_or(line.len()));
+    (value.strip_suffix(',').unwrap_or(value), comment)
+}
+
+/// Concatenates the list `list` to the `deps` of the rule `rule` in the BUILD file `file`, or sets
+/// them to it if the rule has none, followed by `comment` on the line.
+pub fn append_deps(file: &Path, rule: &str, list: &str, comment: &str) -> anyhow::Result<()> {
+    let (mut lines, end, deps) = deps_of(file, rule)?;
+    match deps {
+        Some(i) => {
+            let (value, line_comment) = split_comment(&lines[i]);
+            lines[i] = format!("{} + {},{}{}", value, list, comment, line_comment);
+        }
+        None => lines.insert(end, format!("    deps = [] + {},{}", list, comment)),
+    }
+    std::fs::write(file, lines.join("\n") + "\n")?;
+    Ok(())
+}
+
+/// Removes the list `append_deps` last concatenated to the `deps` of the rule `rule` in `file`,
+/// returning whether there was one.
+pub fn remove_appended_deps(file: &Path, rule: &str) -> anyhow::Result<bool> {
+    let (mut lines, _, deps) = deps_of(file, rule)?;
+    let i = match deps {
+        Some(i) => i,
+        None => return Ok(false),
+    };
+    let (value, line_comment) = split_comment(&lines[i]);
+    match value.rfind(" + [") {
+        Some(at) => {
+            lines[i] = format!("{},{}", &value[..at], line_comment);
+            std::fs::write(file, lines.join("\n") + "\n")?;
+            Ok(true)
+        }
+        None => Ok(false),
+    }
+}
+
+/// Runs `buildifier -r` over `paths` for `--buildifier`, skipping it with a warning when it isn't
+/// installed.
+pub fn run_buildifier(paths: &[PathBuf]) -> anyhow::Result<()> {
+    match Command::new("buildifier").arg("-r").args(paths).status() {
+        Ok(status) if status.success() => Ok(()),
+        Ok(status) => bail!("buildifier failed with {}", status),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+            warn!("buildifier isn't on the PATH, leaving the BUILD files as generated");
+            Ok(())
+        }
+        Err(e) => bail!("running buildifier: {}", e),
+    }
+}
+
+/// A rule given on the command line as `<load label>:<rule name>`, e.g.
+/// `@build_bazel_rules_ios//rules:framework.bzl:apple_framework`, or as just a name for native
+/// rules like `objc_library`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct RuleRef {
+    /// Empty for native rules.
+    pub load: String,
+    pub name: String,
+}
+
+impl FromStr for RuleRef {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (load, name) = match s.rsplit_once(':') {
+            Some((load, name)) if load.ends_with(".bzl") => (load, name),
+            Some(_) => bail!(
+                "expected <load label>:<rule name> with a .bzl label, got {}",
+                s
+            ),
+            None => ("", s),
+        };
+        if name.is_empty() || !name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_') {
+            bail!("invalid rule name {}", name);
+        }
+        Ok(RuleRef {
+            load: load.to_string(),
+            name: name.to_string(),
+        })
+    }
+}
+
+/// A single rule (or macro) instantiation. Attribute values are Starlark expressions and are
+/// emitted verbatim, in insertion order after `name`.
+pub struct Rule {
+    pub kind: String,
+    pub name: String,
+    attrs: Vec<(String, String)>,
+}
+
+impl Rule {
+    pub fn new(kind: impl Into<String>, name: impl Into<String>) -> Self {
+        Rule {
+            kind: kind.into(),
+            name: name.into(),
+            attrs: vec![],
+        }
+    }
+
+    pub fn attr(mut self, name: &str, value: impl Into<String>) -> Self {
+        self.set(name, value);
+        self
+    }
+
+    pub fn set(&mut self, name: &str, value: impl Into<String>) {
+        self.attrs.push((name.to_string(), value.into()));
+    }
+
+    /// Concatenates the list `value` to attribute `name`, setting it if it isn't yet.
+    pub fn append(&mut self, name: &str, value: impl Into<String>) {
+        match self.attrs.iter_mut().find(|(n, _)| n == name) {
+            Some((_, current)) => *current = format!("{} + {}", current, value.into()),
+            None => self.set(name, value),
+        }
+    }
+
+    pub fn render(&self) -> String {
+        let mut out = format!("{}(\n    name = {},\n", self.kind, quote(&self.name));
+        let mut attrs: Vec<_> = self.attrs.iter().collect();
+        if format() {
+            attrs.sort_by_key(|(name, _)| attr_priority(name));
+        }
+        for (name, value) in attrs {
+            out.push_str(&format!("    {} = {},\n", name, format_value(name, value)));
+        }
+        out.push(')');
+        out
+    }
+}
+
+/// A BUILD file under construction. Loads are collected separately so that they always end up at
+/// the top of the file, no matter in which order the rules using them get added.
+#[derive(Default)]
+pub struct BuildFile {
+    comments: Vec<String>,
+    loads: Vec<(String, Vec<String>)>,
+    /// Attributes of the `package()` call, which has to precede all rules.
+    package: Vec<(String, String)>,
+    body: Vec<String>,
+}
+
+impl BuildFile {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Adds a `#` comment line to the top of the file, e.g. for tool directives.
+    pub fn comment(&mut self, text: &str) {
+        self.comments.push(text.to_string());
+    }
+
+    /// Loads `symbol` from `label`. Native rules have nothing to load, an empty `label` is
+    /// ignored.
+    pub fn load(&mut self, label: &str, symbol: &str) {
+        if label.is_empty() {
+            return;
+        }
+        match self.loads.iter_mut().find(|(l, _)| l == label) {
+            Some((_, symbols)) => {
+                if !symbols.iter().any(|s| s == symbol) {
+                    symbols.push(symbol.to_string());
+                }
+            }
+            None => self
+                .loads
+                .push((label.to_string(), vec![symbol.to_string()])),
+        }
+    }
+
+    /// Sets the attribute `name` of the `package()` call of the file.
+    pub fn package(&mut self, name: &str, value: impl Into<String>) {
+        self.package.push((name.to_string(), value.into()));
+    }
+
+    pub fn add(&mut self, rule: Rule) {
+        self.body.push(rule.render());
+    }
+
+    /// Adds an `exports_files` of the string list `files` with the list `visibility`.
+    pub fn exports_files(&mut self, files: &str, visibility: &str) {
+        self.body.push(format!(
+            "exports_files(\n    {},\n    visibility = {},\n)",
+            format_value("srcs", files),
+            format_value("visibility", visibility)
+        ));
+    }
+
+    pub fn render(&self) -> String {
+        let mut out = String::new();
+        for comment in &self.comments {
+            out.push_str(&format!("# {}\n", comment));
+        }
+        if !self.comments.is_empty() {
+            out.push('\n');
+        }
+        let mut loads: Vec<_> = self.loads.to_vec();
+        if format() {
+            loads.sort_by(|(a, _), (b, _)| compare_loads(a, b));
+            for (_, symbols) in &mut loads {
+                symbols.sort();
+            }
+        }
+        for (label, symbols) in &loads {
+            out.push_str(&format!(
+                "load({}, {})\n",
+                quote(label),
+                symbols.iter().map(|s| quote(s)).join(", ")
+            ));
+        }
+        if !self.package.is_empty() {
+            if !self.loads.is_empty() {
+                out.push('\n');
+            }
+            out.push_str("package(\n");
+            for (name, value) in &self.package {
+                out.push_str(&format!("    {} = {},\n", name, format_value(name, value)));
+            }
+            out.push_str(")\n");
+        }
+        for (i, rule) in self.body.iter().enumerate() {
+            if i > 0 || !self.loads.is_empty() || !self.package.is_empty() {
+                out.push('\n');
+            }
+            out.push_str(rule);
+            out.push('\n');
+        }
+        out
+    }
+
+    pub fn write(&self, path: &Path) -> std::io::Result<()> {
+        std::fs::write(path, self.render())
+    }
+}
@@ -0,0 +1,202 @@
+//! `mutate` subcommand: a seeded stream of edits to the libraries of a generated workspace, for
+//! soak tests of `bazel build --watchfs` loops and the background builds of IDEs. Unlike `churn`,
+//! which appends to random files, the edits are the ones of `--edit-kind`, applied to libraries on
+//! weighted levels of the graph, so the stream can mix cheap implementation changes of leaves with
+//! interface changes near the root the way real development does. The same seed and weights make
+//! the same edits in the same order, every one logged as a JSON line on stdout and its impact
+//! added to `impacted_targets.json` as soon as it is made.
+
+use crate::graph::{num_nodes_in_ntree, ID};
+use crate::layers::{self, Distribution};
+use crate::mutate::{self, EditKind};
+use crate::{backend, node_fraction, prepare, regen, Args};
+use anyhow::{bail, format_err};
+use clap::Parser;
+use serde_json::json;
+use std::path::PathBuf;
+use std::str::FromStr;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+const STREAM_SALT: u64 = 22;
+
+/// A duration given as a number with a unit, `ms`, `s`, `m` or `h`, e.g. `500ms` or `2h`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Span(Duration);
+
+impl FromStr for Span {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let split = s
+            .find(|c: char| !c.is_ascii_digit() && c != '.')
+            .unwrap_or(s.len());
+        let (value, unit) = s.split_at(split);
+        let value: f64 = value
+            .parse()
+            .map_err(|_| format_err!("{} isn't a duration, e.g. 30s or 2h", s))?;
+        let seconds = match unit {
+            "ms" => value / 1000.0,
+            "s" => value,
+            "m" => value * 60.0,
+            "h" => value * 3600.0,
+            _ => bail!("unknown unit of duration {}, expected ms, s, m or h", s),
+        };
+        Ok(Span(Duration::from_secs_f64(seconds)))
+    }
+}
+
+/// Where in the graph an edited library is: the `root` libraries of the first level, the `leaf`
+/// ones of the last level, or the `mid` ones between them.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Tier {
+    Root,
+    Mid,
+    Leaf,
+}
+
+impl Tier {
+    fn as_str(self) -> &'static str {
+        match self {
+            Tier::Root => "root",
+            Tier::Mid => "mid",
+            Tier::Leaf => "leaf",
+        }
+    }
+
+    /// Range of the ids of the libraries of the tier.
+    fn ids(self, args: &Args) -> std::ops::Range<u64> {
+        let k = args.targets_per_level;
+        match self {
+            Tier::Root => 1..num_nodes_in_ntree(k, 1),
+            Tier::Mid => num_nodes_in_ntree(k, 1)..num_nodes_in_ntree(k, args.height - 1),
+            Tier::Leaf => {
+                num_nodes_in_ntree(k, args.height - 1)..num_nodes_in_ntree(k, args.height)
+            }
+        }
+    }
+}
+
+impl FromStr for Tier {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "root" => Ok(Tier::Root),
+            "mid" => Ok(Tier::Mid),
+            "leaf" => Ok(Tier::Leaf),
+            _ => bail!("unknown level {}, expected root, mid or leaf", s),
+        }
+    }
+}
+
+/// Apply a seeded stream of edits to a generated workspace, e.g. while a watcher builds it
+#[derive(Parser, Debug)]
+pub struct MutateArgs {
+    /// Generated workspace to edit, with its `BENCHMARK_METADATA.json`
+    #[clap(long)]
+    workspace: PathBuf,
+
+    /// Keep editing every `--interval` until `--duration` is over, or until interrupted without
+    /// one, instead of making a single edit
+    #[clap(long)]
+    stream: bool,
+
+    /// Time between the edits of `--stream`, e.g. `30s`
+    #[clap(long, default_value = "30s")]
+    interval: Span,
+
+    /// How long to stream edits for, e.g. `2h`
+    #[clap(long)]
+    duration: Option<Span>,
+
+    /// Weights of the levels of the edited libraries: `root` for the first one, `leaf` for the
+    /// last, `mid` for the ones between
+    #[clap(long, default_value = "leaf@6,mid@3,root@1")]
+    levels: Distribution<Tier>,
+
+    /// Weights of the edits, out of the `--edit-kind` ones. `implementation` and `interface`
+    /// are objc only, the other backends take `comment` and `dep-edge`
+    #[clap(long, default_value = "implementation@3,interface@1")]
+    edit_kinds: Distribution<EditKind>,
+
+    /// Seed of the edited libraries and kinds of edits. The same seed repeats the same edits, and
+    /// an `interface` edit repeated on the same workspace declares its method twice, so editing a
+    /// workspace again takes another one
+    #[clap(long, default_value = "0")]
+    seed: u64,
+}
+
+pub fn run(mutate: &MutateArgs) -> anyhow::Result<()> {
+    let args = regen::workspace_args(&mutate.workspace)?;
+    prepare(&args)?;
+
+    if mutate.interval.0.is_zero() {
+        bail!("--interval must be longer than 0");
+    }
+    if let Some(tier) = mutate
+        .levels
+        .values()
+        .find(|tier| tier.ids(&args).is_empty())
+    {
+        bail!(
+            "the graph of height {} has no {} level, leave it out of --levels",
+            args.height,
+            tier.as_str()
+        );
+    }
+    for kind in mutate.edit_kinds.values() {
+        match kind {
+            EditKind::Implementation | EditKind::Interface => {
+                backend::require(&args, &format!("--edit-kind {}", kind.as_str()), true)?
+            }
+            EditKind::DepEdge
+                if args.visibility != layers::Visibility::Public
+                    || args.package_group_visibility =>
+            {
+                bail!("--edit-kinds dep-edge adds dependencies on arbitrary libraries, it needs a workspace with --visibility public")
+            }
+            _ => {}
+        }
+    }
+
+    let start = Instant::now();
+    let end = mutate.duration.map(|duration| start + duration.0);
+    // Edits of earlier runs, e.g. the `--commits` of the workspace, stay in the report.
+    let mut impacts = mutate::read_impact_report(&args)?;
+    for event in 0u64.. {
+        // Every edit has its own slot, so slow ones don't lower the rate.
+        let due = start + mutate.interval.0.mul_f64(event as f64);
+        if end.is_some_and(|end| due >= end) || (!mutate.stream && event > 0) {
+            break;
+        }
+        std::thread::sleep(due.saturating_duration_since(Instant::now()));
+
+        let key = event ^ mutate.seed.wrapping_mul(0x1000_0000_01B3);
+        let tier = *mutate.levels.pick(node_fraction(key, STREAM_SALT));
+        let kind = *mutate
+            .edit_kinds
+            .pick(node_fraction(key | 1 << 62, STREAM_SALT));
+        let ids = tier.ids(&args);
+        let pick = node_fraction(key | 1 << 63, STREAM_SALT);
+        let id = ids.start + (pick * (ids.end - ids.start) as f64) as u64;
+        let node = ID::new(id, args.targets_per_level, args.height as u64);
+        let name = format!("stream {} {}", mutate.seed, event);
+        let files = mutate::mutate_node_as(&args, &node, kind, &name)?;
+        // Rewritten after every edit, an interrupted stream leaves the report of its edits.
+        impacts.push(mutate::impact(&name, &[node], &files, &args));
+        mutate::write_impact_report(&args, &impacts)?;
+
+        let time = SystemTime::now().duration_since(UNIX_EPOCH)?;
+        let entry = json!({
+            "event": event,
+            "time_unix": time.as_secs_f64(),
+            "elapsed_seconds": start.elapsed().as_secs_f64(),
+            "library": layers::label(&node, &args),
+            "level": tier.as_str(),
+            "edit_kind": kind.as_str(),
+            "changed_files": files,
+        });
+        println!("{}", entry);
+    }
+    Ok(())
+}
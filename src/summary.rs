@@ -0,0 +1,363 @@
+//! Machine-readable summary of a generation run, printed to stdout with `--emit-summary json` for
+//! benchmark orchestration to capture what exactly got generated.
+
+use crate::{budget, metrics, presets, self_bench, Args, Cli};
+use anyhow::{bail, format_err};
+use clap::{ArgMatches, ArgSettings, IntoApp};
+use serde_json::{json, Map, Value};
+use std::collections::BTreeMap;
+use std::ffi::OsString;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum SummaryFormat {
+    Json,
+}
+
+impl FromStr for SummaryFormat {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "json" => Ok(SummaryFormat::Json),
+            _ => bail!("unknown summary format {}, expected json", s),
+        }
+    }
+}
+
+/// File in the output root recording how the workspace got generated.
+pub const METADATA_FILE: &str = "BENCHMARK_METADATA.json";
+
+/// Version of the summary layout, bumped on incompatible changes of `SCHEMA`.
+const SCHEMA_VERSION: u64 = 1;
+
+/// JSON Schema of the summary, printed by the `summary-schema` subcommand.
+pub const SCHEMA: &str = r#"{
+  "$schema": "https://json-schema.org/draft/2020-12/schema",
+  "title": "gen_bazel_benchmark generation summary",
+  "type": "object",
+  "required": ["schema_version", "version", "output", "output_format", "parameters", "preset",
+               "seed", "counts", "elapsed_seconds", "manifest_digest"],
+  "properties": {
+    "schema_version": { "const": 1 },
+    "version": { "type": "string" },
+    "output": { "type": "string" },
+    "output_format": { "enum": ["dir", "tar", "tar.zst", "ext4-image", "sparse-image"] },
+    "parameters": {
+      "description": "Every generate flag by long name, including defaults and preset flags",
+      "type": "object",
+      "additionalProperties": {
+        "type": ["string", "boolean", "null", "array"],
+        "items": { "type": "string" }
+      }
+    },
+    "preset": { "type": ["string", "null"] },
+    "seed": { "type": "integer", "minimum": 0 },
+    "counts": {
+      "type": "object",
+      "required": ["targets", "files", "symlinks", "build_files", "bytes"],
+      "properties": {
+        "targets": { "type": "integer", "minimum": 0 },
+        "files": { "type": "integer", "minimum": 0 },
+        "symlinks": { "type": "integer", "minimum": 0 },
+        "build_files": { "type": "integer", "minimum": 0 },
+        "bytes": { "type": "integer", "minimum": 0 }
+      }
+    },
+    "graph": {
+      "description": "Metrics of the dependency graph between the libraries",
+      "type": "object",
+      "properties": {
+        "libraries": { "type": "integer", "minimum": 0 },
+        "edges": { "type": "integer", "minimum": 0 },
+        "longest_chain": { "type": "integer", "minimum": 1 },
+        "max_fan_out": { "type": "integer", "minimum": 0 },
+        "avg_fan_out": { "type": "number", "minimum": 0 },
+        "max_fan_in": { "type": "integer", "minimum": 0 },
+        "avg_fan_in": { "type": "number", "minimum": 0 },
+        "root_closure": { "type": "integer", "minimum": 0 }
+      }
+    },
+    "elapsed_seconds": { "type": "number", "minimum": 0 },
+    "self_bench": {
+      "description": "Throughput and phases of the generator with --self-bench",
+      "type": "object",
+      "required": ["files_per_second", "bytes_per_second", "phases_seconds"],
+      "properties": {
+        "files_per_second": { "type": "number", "minimum": 0 },
+        "bytes_per_second": { "type": "number", "minimum": 0 },
+        "phases_seconds": {
+          "type": "object",
+          "additionalProperties": { "type": "number", "minimum": 0 }
+        }
+      }
+    },
+    "budget": {
+      "description": "The --max-files and --max-bytes budgets and the height they cut the graph to",
+      "type": "object",
+      "required": ["max_files", "max_bytes", "requested_height", "height", "truncated", "libraries"],
+      "properties": {
+        "max_files": { "type": ["integer", "null"], "minimum": 1 },
+        "max_bytes": { "type": ["integer", "null"], "minimum": 1 },
+        "requested_height": { "type": "integer", "minimum": 1 },
+        "height": { "type": "integer", "minimum": 1 },
+        "truncated": { "type": "boolean" },
+        "libraries": { "type": "integer", "minimum": 0 }
+      }
+    },
+    "manifest_digest": {
+      "description": "FNV-1a 64 of the sorted paths and contents (link targets for symlinks)",
+      "type": "string",
+      "pattern": "^[0-9a-f]{16}$"
+    }
+  }
+}"#;
+
+/// The matches of the generate flags of the command line of `args`, with presets expanded.
+fn generate_matches(args: &Args) -> anyhow::Result<ArgMatches> {
+    let argv = presets::expand(args.command_line.clone())?;
+    let matches = Cli::into_app().try_get_matches_from(argv)?;
+    match matches.subcommand_matches("generate") {
+        Some(matches) => Ok(matches.clone()),
+        None => bail!("summaries are only emitted by generate"),
+    }
+}
+
+/// The generate flags of `matches` by long name, with defaults filled in.
+fn parameters(matches: &ArgMatches) -> Value {
+    let mut parameters = Map::new();
+    for arg in Args::into_app().get_arguments() {
+        let name = arg.get_name();
+        if name == "help" || name == "version" {
+            continue;
+        }
+        let value = if !arg.is_set(ArgSettings::TakesValue) {
+            Value::Bool(matches.is_present(name))
+        } else if arg.is_set(ArgSettings::MultipleOccurrences)
+            || arg.is_set(ArgSettings::UseValueDelimiter)
+        {
+            Value::Array(
+                matches
+                    .values_of(name)
+                    .into_iter()
+                    .flatten()
+                    .map(|value| Value::String(value.to_string()))
+                    .collect(),
+            )
+        } else {
+            matches
+                .value_of(name)
+                .map_or(Value::Null, |value| Value::String(value.to_string()))
+        };
+        parameters.insert(name.replace('_', "-"), value);
+    }
+    Value::Object(parameters)
+}
+
+#[derive(Default)]
+struct Counts {
+    files: u64,
+    symlinks: u64,
+    build_files: u64,
+    bytes: u64,
+}
+
+/// Counts the files of `workspace` and digests them, leaving out the `.git` history of
+/// `--init-git` and the timestamped `METADATA_FILE`.
+fn scan(workspace: &Path) -> anyhow::Result<(Counts, u64)> {
+    let mut entries = vec![];
+    let mut dirs = vec![workspace.to_path_buf()];
+    while let Some(dir) = dirs.pop() {
+        for entry in std::fs::read_dir(dir)? {
+            let entry = entry?;
+            let file_type = entry.file_type()?;
+            if file_type.is_dir() {
+                if entry.file_name() != ".git" {
+                    dirs.push(entry.path());
+                }
+            } else if entry.path() != workspace.join(METADATA_FILE) {
+                entries.push((entry.path(), file_type.is_symlink()));
+            }
+        }
+    }
+    entries.sort();
+
+    let mut counts = Counts::default();
+    let mut digest = Fnv::default();
+    for (path, symlink) in entries {
+        digest.update(path.strip_prefix(workspace)?.to_str().unwrap().as_bytes());
+        digest.update(&[0]);
+        if symlink {
+            counts.symlinks += 1;
+            digest.update(std::fs::read_link(&path)?.to_str().unwrap().as_bytes());
+        } else {
+            let content = std::fs::read(&path)?;
+            counts.files += 1;
+            counts.bytes += content.len() as u64;
+            if path
+                .file_name()
+                .is_some_and(|n| n == "BUILD.bazel" || n == "BUILD")
+            {
+                counts.build_files += 1;
+            }
+            digest.update(&content);
+        }
+        digest.update(&[0]);
+    }
+    Ok((counts, digest.0))
+}
+
+/// Digests of the files of `workspace` by their path relative to it, symlinks by their target,
+/// leaving out the same as `scan`.
+pub fn file_digests(workspace: &Path) -> anyhow::Result<BTreeMap<PathBuf, u64>> {
+    let mut digests = BTreeMap::new();
+    let mut dirs = vec![workspace.to_path_buf()];
+    while let Some(dir) = dirs.pop() {
+        for entry in std::fs::read_dir(dir)? {
+            let entry = entry?;
+            let file_type = entry.file_type()?;
+            if file_type.is_dir() {
+                if entry.file_name() != ".git" {
+                    dirs.push(entry.path());
+                }
+            } else if entry.path() != workspace.join(METADATA_FILE) {
+                let mut digest = Fnv::default();
+                if file_type.is_symlink() {
+                    digest.update(
+                        std::fs::read_link(entry.path())?
+                            .to_str()
+                            .unwrap()
+                            .as_bytes(),
+                    );
+                } else {
+                    digest.update(&std::fs::read(entry.path())?);
+                }
+                let path = entry.path().strip_prefix(workspace)?.to_path_buf();
+                digests.insert(path, digest.0);
+            }
+        }
+    }
+    Ok(digests)
+}
+
+/// Writes `METADATA_FILE` into the output: the version and commit of the generator, when it ran,
+/// its command line and every generate flag. With `--mtime` given, it is the time recorded, so
+/// the workspace stays the same between runs.
+pub fn write_metadata(args: &Args) -> anyhow::Result<()> {
+    let matches = generate_matches(args)?;
+    let generated_at = if matches.occurrences_of("mtime") > 0 {
+        args.mtime
+    } else {
+        SystemTime::now().duration_since(UNIX_EPOCH)?.as_secs()
+    };
+    let mut metadata = json!({
+        "version": env!("CARGO_PKG_VERSION"),
+        "commit": env!("GIT_COMMIT"),
+        "generated_at_unix": generated_at,
+        "command_line": args
+            .command_line
+            .iter()
+            .map(|arg| arg.to_string_lossy())
+            .collect::<Vec<_>>(),
+        "preset": args.preset,
+        "seed": args.seed,
+        "parameters": parameters(&matches),
+    });
+    if let Some(budget) = budget::manifest(args) {
+        metadata["budget"] = budget;
+    }
+    std::fs::write(
+        args.output.join(METADATA_FILE),
+        serde_json::to_string_pretty(&metadata)?,
+    )?;
+    Ok(())
+}
+
+/// Reads the `METADATA_FILE` of the generated `workspace`, returning it and the command line it
+/// records.
+pub fn read_metadata(workspace: &Path) -> anyhow::Result<(Value, Vec<OsString>)> {
+    let path = workspace.join(METADATA_FILE);
+    let content = std::fs::read_to_string(&path).map_err(|e| {
+        format_err!(
+            "{}: {}, the workspace has to be generated with its metadata",
+            path.display(),
+            e
+        )
+    })?;
+    let metadata: Value = serde_json::from_str(&content)?;
+    let command_line = metadata["command_line"]
+        .as_array()
+        .ok_or_else(|| format_err!("{} has no command line", path.display()))?
+        .iter()
+        .filter_map(|arg| arg.as_str().map(OsString::from))
+        .collect();
+    Ok((metadata, command_line))
+}
+
+/// 64-bit FNV-1a, a stable hash that doesn't need another dependency.
+struct Fnv(u64);
+
+impl Default for Fnv {
+    fn default() -> Self {
+        Fnv(0xcbf2_9ce4_8422_2325)
+    }
+}
+
+impl Fnv {
+    fn update(&mut self, bytes: &[u8]) {
+        for &byte in bytes {
+            self.0 = (self.0 ^ byte as u64).wrapping_mul(0x0100_0000_01b3);
+        }
+    }
+}
+
+/// Prints the summary of generating `workspace` with `args` if `--emit-summary` asks for one.
+/// `output` is where the result ended up, the archive for archive output formats.
+pub fn emit(args: &Args, workspace: &Path, output: &Path, elapsed: Duration) -> anyhow::Result<()> {
+    if args.emit_summary.is_none() && !args.self_bench {
+        return Ok(());
+    }
+    // The metrics hold the dependencies of every library, so they are only computed for the
+    // reports rather than on every generation.
+    let computing = Instant::now();
+    let metrics = metrics::compute(args);
+    metrics.log();
+    self_bench::phase("metrics", computing);
+    let (counts, digest) = scan(workspace)?;
+    let self_bench = args
+        .self_bench
+        .then(|| self_bench::report(counts.files, counts.bytes, elapsed));
+    if args.emit_summary.is_none() {
+        return Ok(());
+    }
+    let parameters = parameters(&generate_matches(args)?);
+    let mut summary = json!({
+        "schema_version": SCHEMA_VERSION,
+        "version": env!("CARGO_PKG_VERSION"),
+        "output": output.display().to_string(),
+        "output_format": parameters["output-format"],
+        "preset": args.preset,
+        "seed": args.seed,
+        "counts": {
+            "targets": crate::num_nodes_in_ntree(args.targets_per_level, args.height),
+            "files": counts.files,
+            "symlinks": counts.symlinks,
+            "build_files": counts.build_files,
+            "bytes": counts.bytes,
+        },
+        "graph": metrics.to_json(),
+        "elapsed_seconds": elapsed.as_secs_f64(),
+        "manifest_digest": format!("{:016x}", digest),
+        "parameters": parameters,
+    });
+    if let Some(self_bench) = self_bench {
+        summary["self_bench"] = self_bench;
+    }
+    if let Some(budget) = budget::manifest(args) {
+        summary["budget"] = budget;
+    }
+    println!("{}", serde_json::to_string_pretty(&summary)?);
+    Ok(())
+}
@@ -0,0 +1,104 @@
+//! Registry of the module names of the generated libraries, which every class, category, crate
+//! and package name of the backends is derived from. Naming schemes and module prefixes make
+//! collisions possible, which break linking or fail the build in ways that have nothing to do
+//! with what is being benchmarked, so the registry checks every name once before generating and
+//! renames the later library of every colliding pair.
+
+use crate::graph::{num_nodes_in_ntree, NamingScheme, ID};
+use crate::Args;
+use anyhow::bail;
+use itertools::Itertools;
+use std::collections::hash_map::DefaultHasher;
+use std::collections::{HashMap, HashSet};
+use std::hash::{Hash, Hasher};
+use std::sync::{Arc, RwLock};
+use tracing::warn;
+
+/// Module names that replace colliding ones, by library id.
+pub struct SymbolPool {
+    renamed: HashMap<u64, String>,
+}
+
+/// Hash of `name` as the pool compares it. Only the hashes are kept, which bounds the memory of
+/// the registry for graphs of millions of libraries; a false positive only renames a library
+/// that didn't need it. Crate, package and file names fold case, so names differing only in case
+/// collide, except with the `hashed` scheme, which generates such pairs on purpose.
+fn key(name: &str, scheme: &NamingScheme) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    if *scheme == NamingScheme::Hashed {
+        name.hash(&mut hasher);
+    } else {
+        name.to_lowercase().hash(&mut hasher);
+    }
+    hasher.finish()
+}
+
+impl SymbolPool {
+    /// Registers the module names of all libraries of the graph of `args` in id order, after the
+    /// names of the `--third-party-packages`. Names derived from the position of the library are
+    /// unique by construction, also behind a prefix all levels share, so their graph isn't walked.
+    pub fn build(args: &Args) -> SymbolPool {
+        set_symbol_pool(None);
+        if !args.naming_scheme.replaces_names() && args.module_prefix.iter().all_equal() {
+            return SymbolPool {
+                renamed: HashMap::new(),
+            };
+        }
+        let mut seen: HashSet<u64> = (1..=args.third_party_packages)
+            .map(|index| key(&format!("ThirdParty{}", index), &args.naming_scheme))
+            .collect();
+        let mut renamed = HashMap::new();
+        for id in 1..num_nodes_in_ntree(args.targets_per_level, args.height) {
+            let name = ID::new(id, args.targets_per_level, args.height as u64).lib_name();
+            if seen.insert(key(&name, &args.naming_scheme)) {
+                continue;
+            }
+            let unique = (1..)
+                .map(|attempt| match attempt {
+                    1 => format!("{}_N{}", name, id),
+                    _ => format!("{}_N{}_{}", name, id, attempt),
+                })
+                .find(|candidate| seen.insert(key(candidate, &args.naming_scheme)))
+                .unwrap();
+            warn!(
+                "module name {} of library {} is taken, naming it {}",
+                name, id, unique
+            );
+            renamed.insert(id, unique);
+        }
+        SymbolPool { renamed }
+    }
+
+    /// The pool giving every library its name in `names`, which are indexed by id, e.g. the ones
+    /// of a `--from-graph` file. They have to be unique like the generated ones, nothing gets
+    /// renamed.
+    pub fn from_names(args: &Args, names: &[String]) -> anyhow::Result<SymbolPool> {
+        set_symbol_pool(None);
+        let mut seen: HashMap<u64, u64> = HashMap::new();
+        let mut renamed = HashMap::new();
+        for (id, name) in names.iter().enumerate().skip(1) {
+            let id = id as u64;
+            if let Some(other) = seen.insert(key(name, &args.naming_scheme), id) {
+                bail!("libraries {} and {} are both named {}", other, id, name);
+            }
+            if *name != ID::new(id, args.targets_per_level, args.height as u64).lib_name() {
+                renamed.insert(id, name.clone());
+            }
+        }
+        Ok(SymbolPool { renamed })
+    }
+}
+
+static SYMBOL_POOL: RwLock<Option<Arc<SymbolPool>>> = RwLock::new(None);
+
+/// Makes `ID::lib_name` use the names of `pool`. Like the topology it can change between
+/// generations in one process.
+pub fn set_symbol_pool(pool: Option<SymbolPool>) {
+    *SYMBOL_POOL.write().unwrap() = pool.map(Arc::new);
+}
+
+/// The name the pool gave library `id` instead of its colliding one.
+pub fn renamed(id: u64) -> Option<String> {
+    let pool = SYMBOL_POOL.read().unwrap();
+    pool.as_ref()?.renamed.get(&id).cloned()
+}
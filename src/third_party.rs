@@ -0,0 +1,97 @@
+//! `--third-party-packages`: a `third_party/` tree of vendored leaf libraries every library of the
+//! graph depends on one of, like the handful of widely used dependencies of a real code base.
+//! The `third_party_bump` scenario of `run_benchmarks.sh` bumps the version of the first one, and
+//! `third_party_bump.json` lists the libraries the bump is expected to invalidate.
+
+use crate::graph::{num_nodes_in_ntree, ID};
+use crate::starlark::{string_list, BuildFile, Rule};
+use crate::{apple, backend, layers, mutate, node_fraction, Args};
+use serde_json::json;
+use std::path::PathBuf;
+
+const THIRD_PARTY_SALT: u64 = 16;
+
+pub const DIR: &str = "third_party";
+
+pub const BUMP_REPORT: &str = "third_party_bump.json";
+
+/// Third-party library `index` (1-based) of `node`, picked per library so that every third-party
+/// library ends up with about the same number of dependents.
+fn dependency(node: &ID, args: &Args) -> Option<u64> {
+    if args.third_party_packages == 0 || apple::is_prebuilt(node, args) {
+        return None;
+    }
+    let pick = node_fraction(node.id, THIRD_PARTY_SALT) * args.third_party_packages as f64;
+    Some(1 + pick as u64)
+}
+
+/// Label of the third-party library `node` depends on, if any.
+pub fn dependency_label(node: &ID, args: &Args) -> Option<String> {
+    dependency(node, args)
+        .map(|index| format!("{}//{}/{}", layers::main_repo(node, args), DIR, name(index)))
+}
+
+/// A third-party library of a backend: its single source, which spells the version as
+/// `VERSION... = 1` for the bump scenario to rewrite, and its library rule with the load of it.
+pub struct Library {
+    pub src: String,
+    pub content: String,
+    pub load: String,
+    pub rule: Rule,
+}
+
+/// Target name, and directory under `DIR`, of third-party library `index`.
+pub fn name(index: u64) -> String {
+    format!("tp_{}", index)
+}
+
+/// Third-party library `index` of the `--language` backend, which `prepare` checks supports them.
+fn library(index: u64, args: &Args) -> Library {
+    backend::backend(args.language)
+        .third_party_library(index, args)
+        .expect("--third-party-packages is checked to be supported")
+}
+
+/// The source the bump scenario rewrites, relative to the workspace.
+pub fn bumped_file(args: &Args) -> PathBuf {
+    PathBuf::from(DIR).join(name(1)).join(library(1, args).src)
+}
+
+/// The libraries depending on the third-party library the bump scenario bumps.
+pub fn bump_dependents(args: &Args) -> Vec<ID> {
+    (1..num_nodes_in_ntree(args.targets_per_level, args.height))
+        .map(|id| ID::new(id, args.targets_per_level, args.height as u64))
+        .filter(|node| dependency(node, args) == Some(1))
+        .collect()
+}
+
+/// Writes the `--third-party-packages` libraries and `third_party_bump.json`.
+pub fn write_packages(args: &Args) -> anyhow::Result<()> {
+    for index in 1..=args.third_party_packages {
+        let dir = args.output.join(DIR).join(name(index));
+        std::fs::create_dir_all(&dir)?;
+        let library = library(index, args);
+        std::fs::write(dir.join(&library.src), library.content)?;
+        let mut build = BuildFile::new();
+        build.load(&library.load, &library.rule.kind);
+        build.add(
+            library
+                .rule
+                .attr("visibility", string_list(["//visibility:public"])),
+        );
+        build.write(&dir.join("BUILD.bazel"))?;
+    }
+
+    let mut impact = mutate::impact(
+        "bump third_party/tp_1",
+        &bump_dependents(args),
+        &[bumped_file(args)],
+        args,
+    );
+    impact["mutated"] = json!([format!("//{}/tp_1", DIR)]);
+    std::fs::write(
+        args.output.join(BUMP_REPORT),
+        serde_json::to_string_pretty(&impact)?,
+    )?;
+    Ok(())
+}
@@ -0,0 +1,171 @@
+//! Pinned toolchain definitions and their `.bazelrc` lines, so a fresh CI machine builds the
+//! generated workspace with the toolchain given on the command line instead of whatever Bazel
+//! autodetects there: an `xcode_config` for the objc backend and a `cc_toolchain` for the others.
+
+use crate::starlark::quote;
+use crate::Args;
+use itertools::Itertools;
+use std::path::Path;
+
+const XCODE_DIR: &str = "tools/xcode";
+const CC_TOOLCHAIN_DIR: &str = "tools/cc_toolchain";
+
+/// Writes `tools/xcode/BUILD.bazel` with an `xcode_config` whose only Xcode is `version`, also
+/// matching its shorter aliases (`13.2` and `13` for `13.2.1`).
+pub fn write_xcode_config(args: &Args, version: &str) -> anyhow::Result<()> {
+    let dir = args.output.join(XCODE_DIR);
+    std::fs::create_dir_all(&dir)?;
+    let components: Vec<_> = version.split('.').collect();
+    let aliases = (1..components.len())
+        .rev()
+        .map(|n| components[..n].join("."));
+    std::fs::write(
+        dir.join("BUILD.bazel"),
+        format!(
+            r#"package(default_visibility = ["//visibility:public"])
+
+xcode_version(
+    name = "xcode",
+    version = {},
+    aliases = [{}],
+)
+
+xcode_config(
+    name = "host_xcodes",
+    default = ":xcode",
+    versions = [":xcode"],
+)
+"#,
+            quote(version),
+            aliases.map(|alias| quote(&alias)).join(", ")
+        ),
+    )?;
+    Ok(())
+}
+
+/// `.bazelrc` lines selecting the Xcode of `write_xcode_config`.
+pub fn xcode_bazelrc(version: &str) -> String {
+    [
+        format!("build --xcode_version_config=//{}:host_xcodes", XCODE_DIR),
+        format!("build --xcode_version={}", version),
+    ]
+    .join("\n")
+}
+
+/// Tools of the generated C++ toolchain by the name Bazel knows them under, with the binary in
+/// the tool directory they are.
+const CC_TOOLS: [(&str, &str); 8] = [
+    ("gcc", "cc"),
+    ("cpp", "cpp"),
+    ("ld", "ld"),
+    ("ar", "ar"),
+    ("nm", "nm"),
+    ("objdump", "objdump"),
+    ("strip", "strip"),
+    ("gcov", "gcov"),
+];
+
+const CC_TOOLCHAIN_CONFIG_BZL: &str = r#"load("@bazel_tools//tools/cpp:cc_toolchain_config_lib.bzl", "tool_path")
+
+def _bench_cc_toolchain_config_impl(ctx):
+    return cc_common.create_cc_toolchain_config_info(
+        ctx = ctx,
+        toolchain_identifier = "bench-linux",
+        host_system_name = "local",
+        target_system_name = "local",
+        target_cpu = "k8",
+        target_libc = "unknown",
+        compiler = "cc",
+        abi_version = "unknown",
+        abi_libc_version = "unknown",
+        tool_paths = [
+            tool_path(name = name, path = ctx.attr.tool_dir + "/" + tool)
+            for name, tool in ctx.attr.tools.items()
+        ],
+        cxx_builtin_include_directories = ctx.attr.builtin_include_directories,
+    )
+
+bench_cc_toolchain_config = rule(
+    implementation = _bench_cc_toolchain_config_impl,
+    attrs = {
+        "builtin_include_directories": attr.string_list(),
+        "tool_dir": attr.string(mandatory = True),
+        "tools": attr.string_dict(mandatory = True),
+    },
+    provides = [CcToolchainConfigInfo],
+)
+"#;
+
+/// Writes `tools/cc_toolchain` with a Linux `cc_toolchain` running the compiler, linker and
+/// binutils in `tool_dir`, registered by `CC_TOOLCHAIN_WORKSPACE`.
+pub fn write_cc_toolchain(args: &Args, tool_dir: &Path) -> anyhow::Result<()> {
+    let dir = args.output.join(CC_TOOLCHAIN_DIR);
+    std::fs::create_dir_all(&dir)?;
+    std::fs::write(dir.join("cc_toolchain_config.bzl"), CC_TOOLCHAIN_CONFIG_BZL)?;
+    let tools = CC_TOOLS
+        .iter()
+        .map(|(name, tool)| format!("        {}: {},", quote(name), quote(tool)))
+        .join("\n");
+    std::fs::write(
+        dir.join("BUILD.bazel"),
+        format!(
+            r#"load(":cc_toolchain_config.bzl", "bench_cc_toolchain_config")
+
+package(default_visibility = ["//visibility:public"])
+
+filegroup(name = "empty")
+
+bench_cc_toolchain_config(
+    name = "linux_config",
+    builtin_include_directories = ["/usr/include", "/usr/lib", "/usr/local/include"],
+    tool_dir = {},
+    tools = {{
+{}
+    }},
+)
+
+cc_toolchain(
+    name = "linux_cc",
+    all_files = ":empty",
+    compiler_files = ":empty",
+    dwp_files = ":empty",
+    linker_files = ":empty",
+    objcopy_files = ":empty",
+    strip_files = ":empty",
+    supports_param_files = 0,
+    toolchain_config = ":linux_config",
+)
+
+toolchain(
+    name = "cc_toolchain",
+    exec_compatible_with = [
+        "@platforms//os:linux",
+        "@platforms//cpu:x86_64",
+    ],
+    target_compatible_with = [
+        "@platforms//os:linux",
+        "@platforms//cpu:x86_64",
+    ],
+    toolchain = ":linux_cc",
+    toolchain_type = "@bazel_tools//tools/cpp:toolchain_type",
+)
+"#,
+            quote(&tool_dir.display().to_string()),
+            tools
+        ),
+    )?;
+    Ok(())
+}
+
+pub const CC_TOOLCHAIN_WORKSPACE: &str =
+    r#"register_toolchains("//tools/cc_toolchain:cc_toolchain")"#;
+
+/// `.bazelrc` lines resolving the C++ toolchain through toolchain resolution, and keeping Bazel
+/// from configuring the autodetected one next to it.
+pub fn cc_bazelrc() -> String {
+    [
+        "build --incompatible_enable_cc_toolchain_resolution",
+        "build --repo_env=BAZEL_DO_NOT_DETECT_CPP_TOOLCHAIN=1",
+    ]
+    .join("\n")
+}
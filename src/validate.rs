@@ -0,0 +1,102 @@
+//! `validate` subcommand: runs a few cheap Bazel invocations over a generated workspace to catch
+//! flag combinations that produce workspaces failing to load or analyze.
+
+use anyhow::bail;
+use clap::Parser;
+use std::path::PathBuf;
+use std::process::Command;
+
+/// Check that a generated workspace loads and analyzes, and optionally builds a sample of it
+#[derive(Parser, Debug)]
+pub struct ValidateArgs {
+    /// Generated workspace to validate
+    #[clap(long)]
+    workspace: PathBuf,
+
+    /// Bazel binary to run
+    #[clap(long, default_value = "bazel")]
+    bazel: String,
+
+    /// Number of targets, spread evenly over `bazel query //...`, to also fully build
+    #[clap(long, default_value = "0")]
+    build_sample: usize,
+}
+
+/// Output of a single Bazel invocation.
+struct Invocation {
+    success: bool,
+    stdout: String,
+    errors: Vec<String>,
+}
+
+fn run_bazel(args: &ValidateArgs, bazel_args: &[&str]) -> anyhow::Result<Invocation> {
+    println!("running {} {}", args.bazel, bazel_args.join(" "));
+    let output = Command::new(&args.bazel)
+        .args(bazel_args)
+        .current_dir(&args.workspace)
+        .output()?;
+
+    let errors = String::from_utf8_lossy(&output.stderr)
+        .lines()
+        .filter(|l| l.starts_with("ERROR:"))
+        .map(str::to_string)
+        .collect();
+    Ok(Invocation {
+        success: output.status.success(),
+        stdout: String::from_utf8_lossy(&output.stdout).into_owned(),
+        errors,
+    })
+}
+
+/// Picks `count` targets spread evenly over `targets`, skipping the generated tooling packages.
+fn sample(targets: &[&str], count: usize) -> Vec<String> {
+    let candidates: Vec<_> = targets
+        .iter()
+        .filter(|t| !t.starts_with("//tools"))
+        .collect();
+    if count == 0 || candidates.is_empty() {
+        return vec![];
+    }
+    let step = (candidates.len() / count).max(1);
+    candidates
+        .iter()
+        .step_by(step)
+        .take(count)
+        .map(|t| t.to_string())
+        .collect()
+}
+
+pub fn run(args: &ValidateArgs) -> anyhow::Result<()> {
+    let mut failures = vec![];
+    let mut check = |name: &str, invocation: &Invocation| {
+        if invocation.success {
+            println!("{}: ok", name);
+        } else {
+            println!("{}: failed", name);
+            for error in &invocation.errors {
+                println!("  {}", error);
+            }
+            failures.push(name.to_string());
+        }
+    };
+
+    let query = run_bazel(args, &["query", "//..."])?;
+    check("query", &query);
+
+    let analysis = run_bazel(args, &["build", "--nobuild", "//:all"])?;
+    check("analysis", &analysis);
+
+    let targets: Vec<_> = query.stdout.lines().collect();
+    let sampled = sample(&targets, args.build_sample);
+    if !sampled.is_empty() {
+        let mut build_args = vec!["build", "--"];
+        build_args.extend(sampled.iter().map(String::as_str));
+        let build = run_bazel(args, &build_args)?;
+        check("build", &build);
+    }
+
+    if !failures.is_empty() {
+        bail!("validation failed: {}", failures.join(", "));
+    }
+    Ok(())
+}
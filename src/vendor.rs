@@ -0,0 +1,121 @@
+//! `--vendor-deps`: a directory holding the external archives of the generated workspace, for
+//! benchmark machines without internet access. Generating doesn't download anything: it lists
+//! the archives of the WORKSPACE and writes a `fetch.sh` that downloads them, and everything they
+//! pull in transitively, on a machine with access. The `.bazelrc` of the workspace points Bazel
+//! at the directory, so once it is copied to the same path on the offline machines builds find
+//! every archive there.
+
+use crate::Args;
+use itertools::Itertools;
+use std::os::unix::fs::PermissionsExt;
+use std::path::Path;
+use tracing::{info, warn};
+
+/// URLs of the quoted `http(s)://` strings of `content`, in order of appearance.
+fn urls(content: &str) -> Vec<String> {
+    content
+        .split('"')
+        .skip(1)
+        .step_by(2)
+        .filter(|s| s.starts_with("https://") || s.starts_with("http://"))
+        .map(str::to_string)
+        .collect()
+}
+
+/// Writes the vendor directory `dir` for the workspace of `args`, whose WORKSPACE has to be
+/// complete, and returns the `.bazelrc` lines using it.
+pub fn write(args: &Args, dir: &Path) -> anyhow::Result<String> {
+    std::fs::create_dir_all(dir.join("mirror"))?;
+    std::fs::create_dir_all(dir.join("distdir"))?;
+    let dir = dir.canonicalize()?;
+    let workspace = args.output.canonicalize()?;
+
+    // Git repositories are cloned rather than downloaded, which the downloader config doesn't
+    // cover.
+    let (git, direct): (Vec<_>, Vec<_>) =
+        urls(&std::fs::read_to_string(args.output.join("WORKSPACE"))?)
+            .into_iter()
+            .unique()
+            .partition(|url| url.ends_with(".git"));
+    for remote in &git {
+        warn!(
+            "{} is a git repository, which --vendor-deps can't mirror, point --override_repository at a clone of it offline",
+            remote
+        );
+    }
+    std::fs::write(dir.join("urls.txt"), direct.join("\n") + "\n")?;
+
+    // Archives are mirrored by host and path, the same as the rewritten URLs of the downloader
+    // config. `sync` fetches with an empty config, the mirror is what it fills.
+    std::fs::write(
+        dir.join("downloader.cfg"),
+        format!("rewrite (.*) file://{}/mirror/$1\n", dir.display()),
+    )?;
+    std::fs::write(dir.join("direct.cfg"), "")?;
+    let fetch = dir.join("fetch.sh");
+    std::fs::write(
+        &fetch,
+        format!(
+            r#"#!/bin/sh
+# Downloads the external archives of the workspace into this directory. Needs internet access
+# and curl, and Bazel (or $BAZEL) for the archives the rulesets pull in transitively.
+set -eu
+vendor="$(cd "$(dirname "$0")" && pwd)"
+workspace="${{1:-{workspace}}}"
+bazel="${{BAZEL:-bazel}}"
+cp "$vendor/urls.txt" "$vendor/all_urls.txt"
+if command -v "$bazel" >/dev/null 2>&1; then
+    (cd "$workspace" && "$bazel" sync \
+        --experimental_downloader_config="$vendor/direct.cfg" \
+        --experimental_repository_resolved_file="$vendor/resolved.bzl")
+    grep -o 'https\?://[^"]*' "$vendor/resolved.bzl" >> "$vendor/all_urls.txt" || true
+else
+    echo "$bazel not found, only fetching the archives of the WORKSPACE" >&2
+fi
+sort -u "$vendor/all_urls.txt" | while read -r url; do
+    path="$vendor/mirror/${{url#*://}}"
+    mkdir -p "$(dirname "$path")"
+    [ -f "$path" ] || curl -fL --retry 3 -o "$path" "$url"
+    ln -sf "$path" "$vendor/distdir/$(basename "$url")"
+done
+"#,
+            workspace = workspace.display()
+        ),
+    )?;
+    std::fs::set_permissions(&fetch, std::fs::Permissions::from_mode(0o755))?;
+    std::fs::write(
+        dir.join("README.md"),
+        format!(
+            "# Vendored dependencies of {workspace}\n\n\
+             1. On a machine with internet access, run `{dir}/fetch.sh`, optionally with the path \
+             of a copy of the workspace. It downloads the archives of `urls.txt` and, through \
+             `bazel sync`, the ones the rulesets load in turn into `mirror/`, with links named \
+             for `--distdir` in `distdir/`.\n\
+             2. Copy `{dir}` to the same path on the offline machines.\n\n\
+             The `.bazelrc` of the workspace rewrites every download to `mirror/` with \
+             `downloader.cfg` and searches `distdir/` for the archives that have a checksum.\n",
+            workspace = workspace.display(),
+            dir = dir.display()
+        ),
+    )?;
+    info!(
+        "listed {} external archives in {}, run its fetch.sh online to vendor them",
+        direct.len(),
+        dir.display()
+    );
+
+    Ok(["build", "fetch", "query", "sync"]
+        .iter()
+        .flat_map(|command| {
+            [
+                format!(
+                    "{} --experimental_downloader_config={}/downloader.cfg",
+                    command,
+                    dir.display()
+                ),
+                format!("{} --distdir={}/distdir", command, dir.display()),
+            ]
+        })
+        .collect::<Vec<_>>()
+        .join("\n"))
+}
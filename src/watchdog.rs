@@ -0,0 +1,120 @@
+//! Checks that the workspace about to be generated fits the file system it goes to: its longest
+//! path against `--max-path-length` and the OS limits, and its estimated number of files against
+//! the free inodes. Deeply nested packages otherwise only fail halfway through writing them, or
+//! later in tools with lower limits than the OS.
+
+use crate::graph::{num_nodes_in_ntree, Naming, ID};
+use crate::{layers, Args};
+use anyhow::bail;
+use std::ffi::CString;
+use std::os::unix::ffi::OsStrExt;
+use std::path::{Path, PathBuf};
+use tracing::{info, warn};
+
+/// Room for the longest file name any backend writes into a library directory beyond the module
+/// name of the library, e.g. `<module>_Umbrella.h` or `<module>_Src100.java`.
+const FILE_NAME_RESERVE: usize = 16;
+
+/// Longest path the OS accepts, `PATH_MAX` counts the terminating NUL.
+const PATH_MAX: usize = libc::PATH_MAX as usize - 1;
+const NAME_MAX: usize = 255;
+
+/// The longest path of the workspace generated with `naming`, in the directory of the last
+/// library of the deepest level, whose index is the longest.
+fn longest_path(args: &Args, naming: &Naming) -> PathBuf {
+    let last = ID::new(
+        num_nodes_in_ntree(args.targets_per_level, args.height) - 1,
+        args.targets_per_level,
+        args.height as u64,
+    );
+    let mut path = std::env::current_dir()
+        .unwrap_or_default()
+        .join(&args.output);
+    if let Some(dir) = layers::repo_dir(&last, args) {
+        path = path.join(dir);
+    }
+    path.join(naming.level_path(last.level as usize))
+        .join(naming.target_name(&last))
+        .join(format!(
+            "{}{}",
+            naming.lib_name(&last),
+            "x".repeat(FILE_NAME_RESERVE)
+        ))
+}
+
+/// Rough upper bound of the files the workspace consists of: the objc backend writes a header
+/// and an implementation per source and category, and every library has a few more files.
+fn estimated_files(args: &Args) -> u64 {
+    let k = args.targets_per_level;
+    (1..=args.height)
+        .map(|level| {
+            let first = ID::new(num_nodes_in_ntree(k, level - 1), k, args.height as u64);
+            let sources = args.files_per_target.for_node(&first) + args.categories_per_target;
+            k.pow(level) * (2 * sources + 4)
+        })
+        .sum()
+}
+
+/// Free inodes of the file system `path` is (or will be) on, `None` if it doesn't limit them.
+fn free_inodes(path: &Path) -> Option<u64> {
+    let dir = path.ancestors().find(|dir| dir.exists())?;
+    let dir = CString::new(dir.as_os_str().as_bytes()).ok()?;
+    let mut stat = std::mem::MaybeUninit::<libc::statvfs>::uninit();
+    // SAFETY: `dir` is NUL terminated and statvfs only writes to `stat`.
+    if unsafe { libc::statvfs(dir.as_ptr(), stat.as_mut_ptr()) } != 0 {
+        return None;
+    }
+    // SAFETY: statvfs succeeded, so it filled in `stat`.
+    let stat = unsafe { stat.assume_init() };
+    // Some file systems, like btrfs, report no inodes at all instead of a limit.
+    #[allow(clippy::unnecessary_cast)] // fsfilcnt_t is narrower than u64 on macOS
+    (stat.f_files > 0).then_some(stat.f_favail as u64)
+}
+
+/// Checks the workspace generated with `naming` fits, switching to short package directories
+/// with `--shorten-packages` if it otherwise wouldn't. Returns the naming to generate with.
+pub fn check(args: &Args, mut naming: Naming) -> anyhow::Result<Naming> {
+    let limit = args.max_path_length.unwrap_or(PATH_MAX).min(PATH_MAX);
+    let mut path = longest_path(args, &naming);
+    if path.as_os_str().len() > limit && args.shorten_packages {
+        info!(
+            "shortening package directories, the longest path would have {} bytes",
+            path.as_os_str().len()
+        );
+        naming.short_packages = true;
+        path = longest_path(args, &naming);
+    }
+    if path.as_os_str().len() > limit {
+        bail!(
+            "the longest generated path would have {} bytes, over the limit of {}{}: {}",
+            path.as_os_str().len(),
+            limit,
+            if naming.short_packages {
+                ""
+            } else {
+                ", try --shorten-packages"
+            },
+            path.display()
+        );
+    }
+    if let Some(component) = path.components().find(|c| c.as_os_str().len() > NAME_MAX) {
+        bail!(
+            "generated file names would be longer than {} bytes: {}",
+            NAME_MAX,
+            component.as_os_str().to_string_lossy()
+        );
+    }
+
+    let output = std::env::current_dir()
+        .unwrap_or_default()
+        .join(&args.output);
+    let files = estimated_files(args);
+    match free_inodes(&output) {
+        Some(free) if files > free => warn!(
+            "the workspace takes up to {} files, but its file system only has {} free inodes",
+            files, free
+        ),
+        _ => {}
+    }
+    Ok(naming)
+}
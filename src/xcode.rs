@@ -0,0 +1,580 @@
+//! Plain Xcode project for the objc library graph, building the same sources with xcodebuild for
+//! comparison with Bazel. Every library becomes a framework target of a single
+//! `Benchmark.xcodeproj`, every application an application target.
+
+use crate::graph::{num_nodes_in_ntree, ID};
+use crate::starlark::quote;
+use crate::{apple, layers, Args, Pch};
+use itertools::Itertools;
+use std::collections::BTreeMap;
+use std::io::{BufWriter, Write};
+use std::path::Path;
+
+/// Directory, relative to the output, holding the framework module maps of the targets.
+pub const MODULEMAPS_DIR: &str = "xcode_modulemaps";
+pub const PROJECT_DIR: &str = "Benchmark.xcodeproj";
+
+const FRAMEWORK_TYPE: &str = "com.apple.product-type.framework";
+const APPLICATION_TYPE: &str = "com.apple.product-type.application";
+
+// Object kinds, making up the first part of the object identifiers.
+const PROJECT: u32 = 0;
+const TARGET: u32 = 1;
+const SOURCES_PHASE: u32 = 2;
+const HEADERS_PHASE: u32 = 3;
+const FRAMEWORKS_PHASE: u32 = 4;
+const CONFIGURATION_LIST: u32 = 5;
+const CONFIGURATION: u32 = 6;
+const PRODUCT: u32 = 7;
+const GROUP: u32 = 8;
+const FILE: u32 = 9;
+const BUILD_FILE: u32 = 10;
+const DEPENDENCY: u32 = 11;
+const PROXY: u32 = 12;
+const LINK_FILE: u32 = 13;
+
+/// A target of the project, or with `product_type` `None` a prebuilt framework that only gets
+/// linked.
+struct Target {
+    name: String,
+    product_type: Option<&'static str>,
+    product: String,
+    headers: Vec<String>,
+    sources: Vec<String>,
+    other_files: Vec<String>,
+    deps: Vec<usize>,
+    links: Vec<usize>,
+    settings: Vec<(&'static str, String)>,
+}
+
+fn object_id(kind: u32, target: usize, index: usize) -> String {
+    format!("{:08X}{:08X}{:08X}", kind, target, index)
+}
+
+fn list<I, S>(items: I) -> String
+where
+    I: IntoIterator<Item = S>,
+    S: AsRef<str>,
+{
+    format!(
+        "({})",
+        items
+            .into_iter()
+            .map(|item| format!("{}, ", item.as_ref()))
+            .join("")
+    )
+}
+
+fn relative(path: &Path, args: &Args) -> String {
+    path.strip_prefix(&args.output)
+        .unwrap()
+        .to_str()
+        .unwrap()
+        .to_string()
+}
+
+/// Index of the target of node `id`, the root has none.
+fn target_index(id: u64) -> usize {
+    id as usize - 1
+}
+
+fn library_target(id: u64, args: &Args) -> anyhow::Result<Target> {
+    let node = ID::new(id, args.targets_per_level, args.height as u64);
+    let lib_dir = layers::lib_dir(&node, args);
+    let name = node.lib_name();
+
+    if apple::is_prebuilt(&node, args) {
+        return Ok(Target {
+            product: relative(&lib_dir.join(format!("{}.framework", name)), args),
+            name,
+            product_type: None,
+            headers: vec![],
+            sources: vec![],
+            other_files: vec![],
+            deps: vec![],
+            links: vec![],
+            settings: vec![],
+        });
+    }
+
+    let module_map = Path::new(MODULEMAPS_DIR).join(format!("{}.modulemap", name));
+    std::fs::write(
+        args.output.join(&module_map),
+        format!(
+            "framework module {} {{\n    umbrella \"Headers\"\n    export *\n    module * {{ export * }}\n}}\n",
+            name
+        ),
+    )
+    ?;
+
+    let files = |extension| -> anyhow::Result<_> {
+        Ok(layers::files_with_extension(&lib_dir, extension)?
+            .iter()
+            .map(|f| relative(f, args))
+            .collect())
+    };
+    let deps: Vec<_> = apple::dependency_ids(id, args)
+        .into_iter()
+        .map(target_index)
+        .collect();
+    let dynamic = args.linkage.is_dynamic(&node);
+
+    let mut settings = vec![
+        ("PRODUCT_NAME", quote(&name)),
+        (
+            "PRODUCT_BUNDLE_IDENTIFIER",
+            quote(&format!(
+                "{}.{}",
+                args.bundle_id_prefix,
+                name.replace('_', "-")
+            )),
+        ),
+        ("DEFINES_MODULE", "YES".to_string()),
+        (
+            "MODULEMAP_FILE",
+            quote(&format!("$(SRCROOT)/{}", module_map.to_str().unwrap())),
+        ),
+        ("GENERATE_INFOPLIST_FILE", "YES".to_string()),
+        ("SKIP_INSTALL", "YES".to_string()),
+    ];
+    if !dynamic {
+        settings.push(("MACH_O_TYPE", "staticlib".to_string()));
+    }
+    match args.pch {
+        Pch::None => {}
+        Pch::Shared => settings.push((
+            "GCC_PREFIX_HEADER",
+            quote(&format!("$(SRCROOT)/tools/pch/{}", apple::SHARED_PCH)),
+        )),
+        Pch::PerTarget => settings.push((
+            "GCC_PREFIX_HEADER",
+            quote(&format!(
+                "$(SRCROOT)/{}",
+                relative(&lib_dir.join(format!("{}_Prefix.pch", name)), args)
+            )),
+        )),
+    }
+    let copts = layers::generated_flags(args, args.copts_per_target, |i, value| {
+        format!("-DBENCH_COPT_{}={}", i, value)
+    });
+    if !copts.is_empty() {
+        settings.push(("OTHER_CFLAGS", list(copts.iter().map(|c| quote(c)))));
+    }
+    let defines = layers::generated_flags(args, args.defines_per_target, |i, value| {
+        format!("BENCH_DEFINE_{}={}", i, value)
+    });
+    if !defines.is_empty() {
+        settings.push((
+            "GCC_PREPROCESSOR_DEFINITIONS",
+            list(defines.iter().map(|d| quote(d))),
+        ));
+    }
+
+    Ok(Target {
+        product: format!("{}.framework", name),
+        name,
+        product_type: Some(FRAMEWORK_TYPE),
+        headers: files("h")?,
+        sources: files("m")?,
+        other_files: vec![],
+        // Static frameworks get linked into the applications only, a dynamic framework has to
+        // link its own dependencies
+        links: if dynamic { deps.clone() } else { vec![] },
+        deps,
+        settings,
+    })
+}
+
+/// All libraries the first-level libraries `roots` depend on, including them.
+fn transitive_libraries(roots: impl Iterator<Item = u64>, args: &Args) -> Vec<u64> {
+    let mut result = vec![];
+    let mut stack: Vec<_> = roots.collect();
+    while let Some(id) = stack.pop() {
+        result.push(id);
+        stack.extend(apple::dependency_ids(id, args));
+    }
+    result.sort();
+    result.dedup();
+    result
+}
+
+fn app_target(app: u64, args: &Args) -> Target {
+    let (name, bundle_id) = apple::app_identity(app, args);
+    let first_level: Vec<_> = apple::app_deps(app, args).collect();
+    Target {
+        product: format!("{}.app", name),
+        product_type: Some(APPLICATION_TYPE),
+        headers: vec![],
+        sources: vec![apple::main_source(&name, args)],
+        other_files: vec!["Info.plist".to_string(), "app.entitlements".to_string()],
+        deps: first_level.iter().map(|&i| target_index(i)).collect(),
+        links: transitive_libraries(first_level.into_iter(), args)
+            .into_iter()
+            .map(target_index)
+            .collect(),
+        settings: vec![
+            ("PRODUCT_NAME", quote(&name)),
+            ("PRODUCT_BUNDLE_IDENTIFIER", quote(&bundle_id)),
+            ("INFOPLIST_FILE", quote("Info.plist")),
+            ("CODE_SIGN_ENTITLEMENTS", quote("app.entitlements")),
+        ],
+        name,
+    }
+}
+
+/// Objects of the project by section, which appear in alphabetical order.
+#[derive(Default)]
+struct Objects(BTreeMap<&'static str, Vec<String>>);
+
+impl Objects {
+    fn add(&mut self, isa: &'static str, id: String, fields: &[(&str, String)]) {
+        let fields = fields
+            .iter()
+            .map(|(name, value)| format!("{} = {}; ", name, value))
+            .join("");
+        self.0
+            .entry(isa)
+            .or_default()
+            .push(format!("\t\t{} = {{isa = {}; {}}};", id, isa, fields));
+    }
+}
+
+fn add_file(objects: &mut Objects, id: String, path: &str, source_tree: &str) {
+    let file_type = match Path::new(path).extension().and_then(|e| e.to_str()) {
+        Some("h") => "sourcecode.c.h",
+        Some("m") => "sourcecode.c.objc",
+        Some("framework") => "wrapper.framework",
+        Some("app") => "wrapper.application",
+        Some("plist") => "text.plist.xml",
+        _ => "text",
+    };
+    objects.add(
+        "PBXFileReference",
+        id,
+        &[
+            ("lastKnownFileType", file_type.to_string()),
+            ("path", quote(path)),
+            ("sourceTree", quote(source_tree)),
+        ],
+    );
+}
+
+fn add_configurations(
+    objects: &mut Objects,
+    list_id: String,
+    kind: u32,
+    target: usize,
+    settings: &[(&str, String)],
+) {
+    let settings = format!(
+        "{{{}}}",
+        settings
+            .iter()
+            .map(|(name, value)| format!("{} = {}; ", name, value))
+            .join("")
+    );
+    let ids: Vec<_> = ["Debug", "Release"]
+        .iter()
+        .enumerate()
+        .map(|(i, name)| {
+            let id = object_id(kind, target, i);
+            objects.add(
+                "XCBuildConfiguration",
+                id.clone(),
+                &[
+                    ("buildSettings", settings.clone()),
+                    ("name", name.to_string()),
+                ],
+            );
+            id
+        })
+        .collect();
+    objects.add(
+        "XCConfigurationList",
+        list_id,
+        &[
+            ("buildConfigurations", list(ids)),
+            ("defaultConfigurationName", "Release".to_string()),
+        ],
+    );
+}
+
+fn add_target(objects: &mut Objects, index: usize, target: &Target, targets: &[Target]) -> String {
+    let product_id = object_id(PRODUCT, index, 0);
+    let mut group = vec![];
+    let product_type = match target.product_type {
+        Some(product_type) => {
+            add_file(
+                objects,
+                product_id.clone(),
+                &target.product,
+                "BUILT_PRODUCTS_DIR",
+            );
+            product_type
+        }
+        None => {
+            add_file(objects, product_id.clone(), &target.product, "SOURCE_ROOT");
+            group.push(product_id);
+            objects.add(
+                "PBXGroup",
+                object_id(GROUP, index, 0),
+                &[
+                    ("children", list(group)),
+                    ("name", quote(&target.name)),
+                    ("sourceTree", quote("<group>")),
+                ],
+            );
+            return object_id(GROUP, index, 0);
+        }
+    };
+
+    let mut phase_files = |files: &[String], offset: usize, attributes: Option<&str>| {
+        files
+            .iter()
+            .enumerate()
+            .map(|(i, path)| {
+                let file_id = object_id(FILE, index, offset + i);
+                add_file(objects, file_id.clone(), path, "SOURCE_ROOT");
+                group.push(file_id.clone());
+                let build_file_id = object_id(BUILD_FILE, index, offset + i);
+                let mut fields = vec![("fileRef", file_id)];
+                if let Some(attributes) = attributes {
+                    fields.push(("settings", format!("{{ATTRIBUTES = ({}, ); }}", attributes)));
+                }
+                objects.add("PBXBuildFile", build_file_id.clone(), &fields);
+                build_file_id
+            })
+            .collect::<Vec<_>>()
+    };
+    let headers = phase_files(&target.headers, 0, Some("Public"));
+    let sources = phase_files(&target.sources, target.headers.len(), None);
+    for (i, path) in target.other_files.iter().enumerate() {
+        let file_id = object_id(FILE, index, target.headers.len() + target.sources.len() + i);
+        add_file(objects, file_id.clone(), path, "SOURCE_ROOT");
+        group.push(file_id);
+    }
+
+    let links: Vec<_> = target
+        .links
+        .iter()
+        .enumerate()
+        .map(|(i, &link)| {
+            let id = object_id(LINK_FILE, index, i);
+            objects.add(
+                "PBXBuildFile",
+                id.clone(),
+                &[("fileRef", object_id(PRODUCT, link, 0))],
+            );
+            id
+        })
+        .collect();
+    let dependencies: Vec<_> = target
+        .deps
+        .iter()
+        .enumerate()
+        .filter(|(_, &dep)| targets[dep].product_type.is_some())
+        .map(|(i, &dep)| {
+            let proxy_id = object_id(PROXY, index, i);
+            objects.add(
+                "PBXContainerItemProxy",
+                proxy_id.clone(),
+                &[
+                    ("containerPortal", object_id(PROJECT, 0, 0)),
+                    ("proxyType", "1".to_string()),
+                    ("remoteGlobalIDString", object_id(TARGET, dep, 0)),
+                    ("remoteInfo", quote(&targets[dep].name)),
+                ],
+            );
+            let id = object_id(DEPENDENCY, index, i);
+            objects.add(
+                "PBXTargetDependency",
+                id.clone(),
+                &[
+                    ("target", object_id(TARGET, dep, 0)),
+                    ("targetProxy", proxy_id),
+                ],
+            );
+            id
+        })
+        .collect();
+
+    let mut phases = vec![];
+    if !headers.is_empty() {
+        let id = object_id(HEADERS_PHASE, index, 0);
+        objects.add(
+            "PBXHeadersBuildPhase",
+            id.clone(),
+            &[("files", list(headers))],
+        );
+        phases.push(id);
+    }
+    let id = object_id(SOURCES_PHASE, index, 0);
+    objects.add(
+        "PBXSourcesBuildPhase",
+        id.clone(),
+        &[("files", list(sources))],
+    );
+    phases.push(id);
+    let id = object_id(FRAMEWORKS_PHASE, index, 0);
+    objects.add(
+        "PBXFrameworksBuildPhase",
+        id.clone(),
+        &[("files", list(links))],
+    );
+    phases.push(id);
+
+    add_configurations(
+        objects,
+        object_id(CONFIGURATION_LIST, index, 0),
+        CONFIGURATION,
+        index,
+        &target.settings,
+    );
+    objects.add(
+        "PBXNativeTarget",
+        object_id(TARGET, index, 0),
+        &[
+            (
+                "buildConfigurationList",
+                object_id(CONFIGURATION_LIST, index, 0),
+            ),
+            ("buildPhases", list(phases)),
+            ("dependencies", list(dependencies)),
+            ("name", quote(&target.name)),
+            ("productName", quote(&target.name)),
+            ("productReference", object_id(PRODUCT, index, 0)),
+            ("productType", quote(product_type)),
+        ],
+    );
+    objects.add(
+        "PBXGroup",
+        object_id(GROUP, index, 0),
+        &[
+            ("children", list(group)),
+            ("name", quote(&target.name)),
+            ("sourceTree", quote("<group>")),
+        ],
+    );
+    object_id(GROUP, index, 0)
+}
+
+/// Writes `Benchmark.xcodeproj` for the whole graph, has to run after all libraries are written.
+/// The frameworks are only built for iOS.
+pub fn write_project(args: &Args) -> anyhow::Result<()> {
+    std::fs::create_dir_all(args.output.join(MODULEMAPS_DIR))?;
+    let num_nodes = num_nodes_in_ntree(args.targets_per_level, args.height);
+    let mut targets = (1..num_nodes)
+        .map(|id| library_target(id, args))
+        .collect::<anyhow::Result<Vec<_>>>()?;
+    targets.extend((1..=apple::num_apps(args)).map(|app| app_target(app, args)));
+
+    let mut objects = Objects::default();
+    let groups: Vec<_> = targets
+        .iter()
+        .enumerate()
+        .map(|(i, target)| add_target(&mut objects, i, target, &targets))
+        .collect();
+
+    let products_group = object_id(GROUP, targets.len(), 0);
+    objects.add(
+        "PBXGroup",
+        products_group.clone(),
+        &[
+            (
+                "children",
+                list(
+                    targets
+                        .iter()
+                        .enumerate()
+                        .filter(|(_, target)| target.product_type.is_some())
+                        .map(|(i, _)| object_id(PRODUCT, i, 0)),
+                ),
+            ),
+            ("name", quote("Products")),
+            ("sourceTree", quote("<group>")),
+        ],
+    );
+    let main_group = object_id(GROUP, targets.len(), 1);
+    objects.add(
+        "PBXGroup",
+        main_group.clone(),
+        &[
+            ("children", list(groups.iter().chain([&products_group]))),
+            ("sourceTree", quote("<group>")),
+        ],
+    );
+
+    let prebuilt_dirs = targets
+        .iter()
+        .filter(|target| target.product_type.is_none())
+        .map(|target| {
+            quote(&format!(
+                "$(SRCROOT)/{}",
+                Path::new(&target.product)
+                    .parent()
+                    .unwrap()
+                    .to_str()
+                    .unwrap()
+            ))
+        });
+    add_configurations(
+        &mut objects,
+        object_id(CONFIGURATION_LIST, targets.len(), 0),
+        CONFIGURATION,
+        targets.len(),
+        &[
+            ("SDKROOT", "iphoneos".to_string()),
+            ("IPHONEOS_DEPLOYMENT_TARGET", "15.0".to_string()),
+            ("CLANG_ENABLE_MODULES", "YES".to_string()),
+            ("CLANG_ENABLE_OBJC_ARC", "YES".to_string()),
+            ("CODE_SIGN_STYLE", "Automatic".to_string()),
+            (
+                "FRAMEWORK_SEARCH_PATHS",
+                list(std::iter::once(quote("$(inherited)")).chain(prebuilt_dirs)),
+            ),
+        ],
+    );
+    objects.add(
+        "PBXProject",
+        object_id(PROJECT, 0, 0),
+        &[
+            (
+                "buildConfigurationList",
+                object_id(CONFIGURATION_LIST, targets.len(), 0),
+            ),
+            ("compatibilityVersion", quote("Xcode 13.0")),
+            ("mainGroup", main_group),
+            ("productRefGroup", products_group),
+            ("projectDirPath", quote("")),
+            ("projectRoot", quote("")),
+            (
+                "targets",
+                list(
+                    targets
+                        .iter()
+                        .enumerate()
+                        .filter(|(_, target)| target.product_type.is_some())
+                        .map(|(i, _)| object_id(TARGET, i, 0)),
+                ),
+            ),
+        ],
+    );
+
+    let project_dir = args.output.join(PROJECT_DIR);
+    std::fs::create_dir_all(&project_dir)?;
+    let mut f = BufWriter::new(std::fs::File::create(project_dir.join("project.pbxproj"))?);
+    writeln!(
+        f,
+        "// !$*UTF8*$!\n{{\n\tarchiveVersion = 1;\n\tclasses = {{\n\t}};\n\tobjectVersion = 55;\n\tobjects = {{"
+    )
+    ?;
+    for (isa, section) in &objects.0 {
+        writeln!(f, "\n/* Begin {} section */", isa)?;
+        for object in section {
+            writeln!(f, "{}", object)?;
+        }
+        writeln!(f, "/* End {} section */", isa)?;
+    }
+    writeln!(f, "\t}};\n\trootObject = {};\n}}", object_id(PROJECT, 0, 0))?;
+    Ok(())
+}